@@ -12,6 +12,36 @@ struct TestStruct {
     e: bool,
 }
 
+#[derive(Marshal, UnMarshal, Viewable, Clone)]
+struct Payload {
+    id: u64,
+    name: String,
+    samples: Vec<u32>,
+    blob: Vec<u8>,
+}
+
+#[derive(Marshal, UnMarshal, Clone)]
+enum Message {
+    Ping(u32),
+    Move { x: f32, y: f32 },
+    Say(String),
+    Sample(TestStruct),
+}
+
+/// `Message` marshalled the way derived enums used to be, with a boxed iterator per value
+fn marshal_boxed(message: &Message) -> MarshalIterator<'_> {
+    match message {
+        Message::Ping(n) => MarshalIterator(Box::new(0u8.marshal().chain(n.marshal_ref()))),
+        Message::Move { x, y } => MarshalIterator(Box::new(
+            1u8.marshal().chain(x.marshal_ref()).chain(y.marshal_ref()),
+        )),
+        Message::Say(text) => MarshalIterator(Box::new(2u8.marshal().chain(text.marshal_ref()))),
+        Message::Sample(sample) => {
+            MarshalIterator(Box::new(3u8.marshal().chain(sample.marshal_ref())))
+        }
+    }
+}
+
 #[inline(never)]
 fn marshal_u64(num: &str) -> u8 {
     unsafe { num.marshal().next().unwrap_unchecked() }
@@ -23,7 +53,60 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("marshal_u8", |b| {
         b.iter(|| black_box(marshal_u64(black_box(Default::default()))))
     });
-    // c.bench_function("Marshalling", |b| b.iter(|| black_box(s1).marshal()));
+    c.bench_function("Marshalling", |b| {
+        b.iter(|| black_box(s1).marshal().collect::<Vec<_>>())
+    });
+    c.bench_function("Marshalling by reference", |b| {
+        b.iter(|| black_box(&s1).marshal_ref().collect::<Vec<_>>())
+    });
+
+    let messages = (0..1000)
+        .map(|i| match i % 4 {
+            0 => Message::Ping(i),
+            1 => Message::Move { x: 1.0, y: 2.0 },
+            2 => Message::Say("hello".to_string()),
+            _ => Message::Sample(TestStruct::default()),
+        })
+        .collect::<Vec<_>>();
+    c.bench_function("Marshalling enums", |b| {
+        b.iter(|| {
+            black_box(&messages)
+                .iter()
+                .flat_map(|m| m.marshal_ref())
+                .collect::<Vec<_>>()
+        })
+    });
+    c.bench_function("Marshalling enums with boxed iterators", |b| {
+        b.iter(|| {
+            black_box(&messages)
+                .iter()
+                .flat_map(marshal_boxed)
+                .collect::<Vec<_>>()
+        })
+    });
+
+    let payload = Payload {
+        id: 7,
+        name: "payload".to_string(),
+        samples: (0..4096).collect(),
+        blob: vec![0xab; 64 * 1024],
+    };
+    c.bench_function("Marshalling a large payload", |b| {
+        b.iter(|| black_box(&payload).marshal_ref().collect::<Vec<_>>())
+    });
+    c.bench_function("Marshalling a large payload with marshal_to_vec", |b| {
+        b.iter(|| black_box(&payload).marshal_to_vec())
+    });
+    let mut out = Vec::new();
+    c.bench_function("Marshalling a large payload into a reused buffer", |b| {
+        b.iter(|| {
+            out.clear();
+            black_box(&payload).marshal_ref_into(&mut out).unwrap();
+        })
+    });
+    c.bench_function("Marshalling into", |b| {
+        b.iter(|| black_box(s1).marshal_to_vec())
+    });
 
     // c.bench_function("UnMarshalling", |b| {
     //     b.iter(|| {
@@ -31,6 +114,20 @@ fn criterion_benchmark(c: &mut Criterion) {
     //         TestStruct::unmarshal(black_box(&mut b)).unwrap();
     //     })
     // });
+
+    let bytes = payload.marshal_to_vec();
+    c.bench_function("UnMarshalling a large payload", |b| {
+        b.iter(|| Payload::unmarshal(&mut black_box(&bytes).iter().cloned()).unwrap())
+    });
+    c.bench_function("UnMarshalling a large payload from a slice", |b| {
+        b.iter(|| Payload::unmarshal_from(&mut &black_box(&bytes)[..]).unwrap())
+    });
+    c.bench_function("Viewing one sample of a large payload", |b| {
+        b.iter(|| {
+            let view = Payload::view_from(&mut &black_box(&bytes)[..]).unwrap();
+            view.samples().unwrap().get(4000).unwrap()
+        })
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
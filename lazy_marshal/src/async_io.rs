@@ -0,0 +1,140 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    config::{Config, DefaultConfig},
+    error::MarshalError,
+    limits::Limit,
+    traits::{Decoded, IncrementalUnMarshal, Marshal, PushDecoder},
+};
+
+/// Unmarshal a `T` from an async reader.
+///
+/// Only the bytes that make up the value are read, so the reader is left at the start of whatever
+/// comes next. Reads are sized by what the decoder still needs, which means small values can cause
+/// many small reads. Wrap unbuffered readers (like a `TcpStream`) in a
+/// [`BufReader`](tokio::io::BufReader) to avoid a syscall for every field.
+///
+/// # Errors
+/// Errors with [`MarshalError::EarlyStreamEnd`] if the reader ends before the value is complete,
+/// [`MarshalError::Io`] if reading fails, or any error produced while decoding. Match on its
+/// [`kind()`](MarshalError::kind), since errors from inside the value come with a
+/// [`Context`](MarshalError::Context).
+///
+/// ```
+/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+/// use lazy_marshal::prelude::*;
+///
+/// let bytes: Vec<u8> = (7u16, "seven").marshal().collect();
+/// let decoded: (u16, String) = lazy_marshal::read_unmarshal(&mut &bytes[..]).await.unwrap();
+/// assert_eq!(decoded, (7, "seven".to_string()));
+/// # })
+/// ```
+pub async fn read_unmarshal<T: IncrementalUnMarshal>(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<T, MarshalError> {
+    read_unmarshal_with::<T, DefaultConfig>(reader).await
+}
+
+/// Most bytes read at once, so a hostile length prefix can't make the buffer huge
+const MAX_READ: usize = 64 * 1024;
+
+/// [`read_unmarshal`] using the wire format and [`DecodeLimits`](crate::DecodeLimits) of `C`.
+/// `max_bytes` caps everything read for the value, including the outermost fields.
+///
+/// ```
+/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+/// use lazy_marshal::prelude::*;
+///
+/// struct Small;
+///
+/// impl Config for Small {
+///     const LIMITS: DecodeLimits = DecodeLimits::NONE.max_bytes(16);
+/// }
+///
+/// let bytes: Vec<u8> = "Far too long for the limit".marshal().collect();
+/// let decoded = lazy_marshal::read_unmarshal_with::<String, Small>(&mut &bytes[..]).await;
+/// let err = decoded.unwrap_err();
+/// assert!(matches!(err.kind(), MarshalError::LimitExceeded { limit: Limit::Bytes, .. }));
+/// # })
+/// ```
+pub async fn read_unmarshal_with<T: IncrementalUnMarshal, C: Config>(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<T, MarshalError> {
+    let mut decoder = T::decoder();
+    let mut buf = Vec::new();
+    let mut total = 0usize;
+    loop {
+        match decoder.feed_with::<C>(&mut &buf[..])? {
+            Decoded::Done(v) => return Ok(v),
+            Decoded::NeedMore(n) => {
+                // Never more than the decoder needs, so nothing past the value is read
+                let n = n.min(MAX_READ);
+                total = total.saturating_add(n);
+                if total > C::LIMITS.max_bytes {
+                    Err(MarshalError::LimitExceeded {
+                        limit: Limit::Bytes,
+                        max: C::LIMITS.max_bytes,
+                    })?
+                }
+                buf.resize(n, 0);
+                reader
+                    .read_exact(&mut buf)
+                    .await
+                    .map_err(|e| match e.kind() {
+                        std::io::ErrorKind::UnexpectedEof => MarshalError::EarlyStreamEnd,
+                        _ => e.into(),
+                    })?;
+            }
+        }
+    }
+}
+
+/// Marshal `value` and write all of it to an async writer.
+///
+/// The writer isn't flushed, so call [`flush`](tokio::io::AsyncWriteExt::flush) when it's buffered.
+///
+/// # Errors
+/// Errors with [`MarshalError::Io`] if writing fails
+///
+/// ```
+/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+/// use lazy_marshal::prelude::*;
+///
+/// let mut out = Vec::new();
+/// lazy_marshal::write_marshal(&mut out, "Hello, World!").await.unwrap();
+/// assert_eq!(out, "Hello, World!".marshal().collect::<Vec<_>>());
+/// # })
+/// ```
+pub async fn write_marshal(
+    writer: &mut (impl AsyncWrite + Unpin),
+    value: impl Marshal,
+) -> Result<(), MarshalError> {
+    write_marshal_with::<DefaultConfig>(writer, value).await
+}
+
+/// [`write_marshal`] using the wire format of `C`.
+///
+/// ```
+/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+/// use lazy_marshal::prelude::*;
+///
+/// let mut out = Vec::new();
+/// lazy_marshal::write_marshal_with::<Varints>(&mut out, vec![1u8, 2, 3]).await.unwrap();
+/// assert_eq!(out, vec![3, 1, 2, 3]);
+/// # })
+/// ```
+pub async fn write_marshal_with<C: Config>(
+    writer: &mut (impl AsyncWrite + Unpin),
+    value: impl Marshal,
+) -> Result<(), MarshalError> {
+    let len = value.encoded_len_hint_with::<C>();
+    let mut data = Vec::with_capacity(len.unwrap_or(0));
+    value
+        .marshal_into_with::<C, _>(&mut data)
+        .expect("writing to a Vec doesn't fail");
+    writer.write_all(&data).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests;
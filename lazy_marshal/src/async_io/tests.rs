@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use tokio::io::{duplex, AsyncWriteExt};
+
+use crate::{prelude::*, read_unmarshal, read_unmarshal_with, write_marshal, write_marshal_with};
+
+#[tokio::test]
+async fn test_round_trip() {
+    let (mut client, mut server) = duplex(8);
+
+    let mut hmap = HashMap::new();
+    hmap.insert("one".to_string(), vec![Some(1i32), None]);
+    hmap.insert("two".to_string(), vec![Some(2), Some(-2)]);
+
+    let sent = hmap.clone();
+    let writer = async move {
+        write_marshal(&mut client, &sent).await.unwrap();
+        write_marshal(&mut client, 42u64).await.unwrap();
+    };
+    let reader = async {
+        let decoded: HashMap<String, Vec<Option<i32>>> = read_unmarshal(&mut server).await.unwrap();
+        let next: u64 = read_unmarshal(&mut server).await.unwrap();
+        (decoded, next)
+    };
+
+    let ((), (decoded, next)) = tokio::join!(writer, reader);
+    assert_eq!(decoded, hmap);
+    assert_eq!(next, 42);
+}
+
+#[tokio::test]
+async fn test_round_trip_with_config() {
+    struct Compact;
+    impl Config for Compact {
+        const ENDIAN: Endian = Endian::Big;
+        const INT_ENCODING: IntEncoding = IntEncoding::Varint;
+        const LENGTH_PREFIX: LengthPrefix = LengthPrefix::Varint;
+    }
+
+    let (mut client, mut server) = duplex(8);
+
+    let sent = vec![
+        (300u32, "three hundred".to_string()),
+        (7, "seven".to_string()),
+    ];
+    let expected = sent.clone();
+    let writer = async move {
+        write_marshal_with::<Compact>(&mut client, &sent)
+            .await
+            .unwrap();
+        write_marshal_with::<Compact>(&mut client, -1i64)
+            .await
+            .unwrap();
+    };
+    let reader = async {
+        let decoded: Vec<(u32, String)> = read_unmarshal_with::<_, Compact>(&mut server)
+            .await
+            .unwrap();
+        let next: i64 = read_unmarshal_with::<_, Compact>(&mut server)
+            .await
+            .unwrap();
+        (decoded, next)
+    };
+
+    let ((), (decoded, next)) = tokio::join!(writer, reader);
+    assert_eq!(decoded, expected);
+    assert_eq!(next, -1);
+
+    // Varints make it shorter than the default wire format
+    let mut compact = Vec::new();
+    write_marshal_with::<Compact>(&mut compact, &expected)
+        .await
+        .unwrap();
+    let mut default = Vec::new();
+    write_marshal(&mut default, &expected).await.unwrap();
+    assert!(compact.len() < default.len());
+}
+
+#[tokio::test]
+async fn test_early_end() {
+    let (mut client, mut server) = duplex(64);
+
+    let bytes = "cut short".marshal().collect::<Vec<_>>();
+    client.write_all(&bytes[..12]).await.unwrap();
+    drop(client);
+
+    let err = read_unmarshal::<String>(&mut server).await.unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::EarlyStreamEnd));
+}
+
+#[tokio::test]
+async fn test_hostile_length() {
+    struct Limited;
+    impl Config for Limited {
+        const LIMITS: DecodeLimits = DecodeLimits::NONE.max_string_len(1024);
+    }
+
+    // Claims to be u64::MAX bytes long, which must not be allocated up front
+    let mut bytes = vec![0xff; 8];
+    bytes.extend(b"abc");
+
+    let err = read_unmarshal::<String>(&mut &bytes[..]).await.unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::EarlyStreamEnd));
+    let err = read_unmarshal_with::<String, Limited>(&mut &bytes[..])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.kind(),
+        MarshalError::LimitExceeded {
+            limit: Limit::StringLen,
+            max: 1024
+        }
+    ));
+}
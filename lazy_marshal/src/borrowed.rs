@@ -0,0 +1,192 @@
+//! [`UnMarshalBorrowed`] for the types in this crate. Strings and bytes can point into the input,
+//! and everything else is copied out of it like [`UnMarshal::unmarshal_from_with()`] does.
+
+use std::{borrow::Cow, collections::HashMap, fmt::Debug, hash::Hash, marker::PhantomData};
+
+use crate::{
+    config::Config,
+    error::{MarshalError, PathSegment},
+    limits::{consume, Counted, Nested},
+    source::BorrowedSource,
+    traits::{UnMarshal, UnMarshalBorrowed},
+    utils::{position, unmarshal_len},
+    varint::VarInt,
+};
+
+/// Types that own all of their data, which are read the same way as with [`UnMarshal`]
+macro_rules! owned {
+    ($($ty:ty),*) => {$(
+        impl<'de> UnMarshalBorrowed<'de> for $ty {
+            #[inline]
+            fn unmarshal_borrowed_with<C: Config>(
+                data: &mut impl BorrowedSource<'de>,
+            ) -> Result<Self, MarshalError> {
+                Self::unmarshal_from_with::<C>(data)
+            }
+
+            fn unmarshal_borrowed_vec_with<C: Config>(
+                data: &mut impl BorrowedSource<'de>,
+                len: usize,
+                out: &mut Vec<Self>,
+            ) -> Result<(), MarshalError> {
+                Self::unmarshal_vec_from_with::<C>(data, len, out)
+            }
+        }
+    )*};
+}
+
+owned! { bool, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, char }
+owned! { String, Box<[u8]> }
+owned! {
+    VarInt<u16>, VarInt<u32>, VarInt<u64>, VarInt<u128>, VarInt<usize>,
+    VarInt<i16>, VarInt<i32>, VarInt<i64>, VarInt<i128>, VarInt<isize>
+}
+
+impl<'de, T> UnMarshalBorrowed<'de> for PhantomData<T> {
+    fn unmarshal_borrowed_with<C: Config>(
+        _data: &mut impl BorrowedSource<'de>,
+    ) -> Result<Self, MarshalError> {
+        Ok(PhantomData)
+    }
+}
+
+/// The bytes of a string or byte slice, after its length
+fn borrow_len_prefixed<'de, C: Config>(
+    data: &mut impl BorrowedSource<'de>,
+    check: fn(usize) -> Result<usize, MarshalError>,
+) -> Result<&'de [u8], MarshalError> {
+    let _counted = Counted::enter::<C>();
+    let len = check(unmarshal_len::<C>(data)?)?;
+    consume::<C>(len)?;
+    data.borrow_bytes(len)
+}
+
+impl<'a, 'de: 'a> UnMarshalBorrowed<'de> for &'a [u8] {
+    fn unmarshal_borrowed_with<C: Config>(
+        data: &mut impl BorrowedSource<'de>,
+    ) -> Result<Self, MarshalError> {
+        borrow_len_prefixed::<C>(data, |len| C::LIMITS.check_collection_len(len))
+    }
+}
+
+impl<'a, 'de: 'a> UnMarshalBorrowed<'de> for &'a str {
+    fn unmarshal_borrowed_with<C: Config>(
+        data: &mut impl BorrowedSource<'de>,
+    ) -> Result<Self, MarshalError> {
+        let bytes = borrow_len_prefixed::<C>(data, |len| C::LIMITS.check_string_len(len))?;
+        std::str::from_utf8(bytes).map_err(|e| match e.error_len() {
+            Some(l) => MarshalError::InvalidSizedDecode(l),
+            None => MarshalError::InvalidDecode,
+        })
+    }
+}
+
+impl<'a, 'de: 'a, T> UnMarshalBorrowed<'de> for Cow<'a, T>
+where
+    T: ToOwned + ?Sized,
+    &'a T: UnMarshalBorrowed<'de>,
+{
+    fn unmarshal_borrowed_with<C: Config>(
+        data: &mut impl BorrowedSource<'de>,
+    ) -> Result<Self, MarshalError> {
+        <&T>::unmarshal_borrowed_with::<C>(data).map(Cow::Borrowed)
+    }
+}
+
+impl<'de, T: UnMarshalBorrowed<'de>> UnMarshalBorrowed<'de> for Vec<T> {
+    fn unmarshal_borrowed_with<C: Config>(
+        data: &mut impl BorrowedSource<'de>,
+    ) -> Result<Self, MarshalError> {
+        let start = position(data);
+        let _nested = Nested::enter::<C>().map_err(|e| e.within("Vec", &[], start, start))?;
+        let len = unmarshal_len::<C>(data)
+            .and_then(|len| C::LIMITS.check_collection_len(len))
+            .map_err(|e| e.within("Vec", &[], start, position(data)))?;
+        let mut val = Vec::new();
+        T::unmarshal_borrowed_vec_with::<C>(data, len, &mut val).map_err(|e| {
+            e.within(
+                "Vec",
+                &[PathSegment::Index(val.len())],
+                start,
+                position(data),
+            )
+        })?;
+        Ok(val)
+    }
+}
+
+impl<'de, K, V> UnMarshalBorrowed<'de> for HashMap<K, V>
+where
+    K: UnMarshalBorrowed<'de> + Hash + Eq,
+    V: UnMarshalBorrowed<'de> + Debug,
+{
+    fn unmarshal_borrowed_with<C: Config>(
+        data: &mut impl BorrowedSource<'de>,
+    ) -> Result<Self, MarshalError> {
+        let start = position(data);
+        let _nested = Nested::enter::<C>().map_err(|e| e.within("HashMap", &[], start, start))?;
+        let len = unmarshal_len::<C>(data)
+            .and_then(|len| C::LIMITS.check_collection_len(len))
+            .map_err(|e| e.within("HashMap", &[], start, position(data)))?;
+        let mut val = Self::new();
+        for i in 0..len {
+            let key = K::unmarshal_borrowed_with::<C>(data)
+                .map_err(|e| e.within("HashMap", &[PathSegment::Key(i)], start, position(data)))?;
+            let value = V::unmarshal_borrowed_with::<C>(data).map_err(|e| {
+                e.within("HashMap", &[PathSegment::Value(i)], start, position(data))
+            })?;
+            if let Some(a) = val.insert(key, value) {
+                Err(MarshalError::InvalidData(format!(
+                    "Duplicate Key while decoding HashMap: {a:#?}"
+                ))
+                .within("HashMap", &[PathSegment::Key(i)], start, position(data)))?
+            }
+        }
+        Ok(val)
+    }
+}
+
+impl<'de, T: UnMarshalBorrowed<'de>> UnMarshalBorrowed<'de> for Option<T> {
+    fn unmarshal_borrowed_with<C: Config>(
+        data: &mut impl BorrowedSource<'de>,
+    ) -> Result<Self, MarshalError> {
+        let _counted = Counted::enter::<C>();
+        Ok(match u8::unmarshal_from_with::<C>(data)? {
+            0 => None,
+            1 => Some(T::unmarshal_borrowed_with::<C>(data)?),
+            other => Err(MarshalError::InvalidData(format!(
+                "Found '{other}' when unmarshalling the option. Should be either 0 or 1"
+            )))?,
+        })
+    }
+}
+
+#[cfg(feature = "tuples")]
+macro_rules! tuple_borrowed {
+    ($($($t:ident)+;)+) => {$(
+        #[cfg_attr(docsrs, doc(hidden))]
+        impl<'de, $($t: UnMarshalBorrowed<'de>),+> UnMarshalBorrowed<'de> for ($($t,)+) {
+            fn unmarshal_borrowed_with<C: Config>(
+                data: &mut impl BorrowedSource<'de>,
+            ) -> Result<Self, MarshalError> {
+                let _counted = Counted::enter::<C>();
+                Ok(($($t::unmarshal_borrowed_with::<C>(data)?,)+))
+            }
+        }
+    )+};
+}
+
+#[cfg(feature = "tuples")]
+tuple_borrowed!(
+    T0;
+    T0 T1;
+    T0 T1 T2;
+    T0 T1 T2 T3;
+    T0 T1 T2 T3 T4;
+    T0 T1 T2 T3 T4 T5;
+    T0 T1 T2 T3 T4 T5 T6;
+    T0 T1 T2 T3 T4 T5 T6 T7;
+);
+
+#[cfg(test)]
+mod tests;
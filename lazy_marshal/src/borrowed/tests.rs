@@ -0,0 +1,74 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use crate::prelude::*;
+
+/// Whether `inner` points into `outer`
+fn points_into(inner: &[u8], outer: &[u8]) -> bool {
+    outer.as_ptr_range().contains(&inner.as_ptr())
+}
+
+#[test]
+fn test_borrowed_strings() {
+    let bytes = ("borrowed", vec![1u8, 2, 3], "cow").marshal_to_vec();
+    let mut data = &bytes[..];
+    let (s, b, cow) = <(&str, &[u8], Cow<str>)>::unmarshal_borrowed(&mut data).unwrap();
+    assert_eq!((s, b, &*cow), ("borrowed", &[1, 2, 3][..], "cow"));
+    assert!(data.is_empty());
+    assert!(points_into(s.as_bytes(), &bytes));
+    assert!(points_into(b, &bytes));
+    assert!(matches!(cow, Cow::Borrowed(c) if points_into(c.as_bytes(), &bytes)));
+
+    // The same bytes as the owned types
+    let (owned, _, _) = <(String, Vec<u8>, String)>::unmarshal_from(&mut &bytes[..]).unwrap();
+    assert_eq!(owned, s);
+    assert_eq!(
+        Cow::<str>::Owned(owned).marshal_to_vec(),
+        s.marshal_to_vec()
+    );
+    assert_eq!(cow.encoded_len(), 8 + 3);
+
+    let bytes = "varints".marshal_with::<Varints>().collect::<Vec<_>>();
+    assert_eq!(
+        <&str>::unmarshal_borrowed_with::<Varints>(&mut &bytes[..]).unwrap(),
+        "varints"
+    );
+}
+
+#[test]
+fn test_borrowed_collections() {
+    let map = HashMap::from([("a", vec![Some("x"), None]), ("b", vec![])]);
+    let bytes = map.marshal_ref().collect::<Vec<_>>();
+    let decoded = HashMap::<&str, Vec<Option<&str>>>::unmarshal_borrowed(&mut &bytes[..]).unwrap();
+    assert_eq!(decoded, map);
+
+    let bytes = (vec![1u16, 2], VarInt(300u32), -1i8).marshal_to_vec();
+    assert_eq!(
+        <(Vec<u16>, VarInt<u32>, i8)>::unmarshal_borrowed(&mut &bytes[..]).unwrap(),
+        (vec![1, 2], VarInt(300), -1)
+    );
+}
+
+#[test]
+fn test_borrowed_errors() {
+    let bytes = vec!["ok", "cut short"].marshal_to_vec();
+    let err = Vec::<&str>::unmarshal_borrowed(&mut &bytes[..30]).unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::InvalidSizedDecode(4)));
+    assert_eq!(err.path().as_deref(), Some("Vec[1]"));
+    assert_eq!(err.offset(), Some(30));
+
+    let bytes = [1, 0, 0, 0, 0, 0, 0, 0, 0xff];
+    assert!(matches!(
+        <&str>::unmarshal_borrowed(&mut &bytes[..]),
+        Err(MarshalError::InvalidSizedDecode(1))
+    ));
+
+    struct Short;
+
+    impl Config for Short {
+        const LIMITS: DecodeLimits = DecodeLimits::NONE.max_string_len(4);
+    }
+
+    let bytes = "too long".marshal_to_vec();
+    let err = <&str>::unmarshal_borrowed_with::<Short>(&mut &bytes[..]).unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::LimitExceeded { .. }));
+}
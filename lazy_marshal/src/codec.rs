@@ -0,0 +1,187 @@
+use std::marker::PhantomData;
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{
+    config::{Config, DefaultConfig, Endian},
+    error::MarshalError,
+    traits::{Marshal, UnMarshal},
+};
+
+/// Width of the length prefix written before every frame, which is in the byte order of the codec's [`Config`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameLength {
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl FrameLength {
+    fn width(self) -> usize {
+        match self {
+            FrameLength::U8 => 1,
+            FrameLength::U16 => 2,
+            FrameLength::U32 => 4,
+            FrameLength::U64 => 8,
+        }
+    }
+
+    /// Largest frame the prefix can describe
+    fn max(self) -> u64 {
+        match self {
+            FrameLength::U8 => u8::MAX as u64,
+            FrameLength::U16 => u16::MAX as u64,
+            FrameLength::U32 => u32::MAX as u64,
+            FrameLength::U64 => u64::MAX,
+        }
+    }
+}
+
+/// A [`tokio_util::codec`] codec that sends every marshalled value in its own length prefixed frame.
+///
+/// Decoding waits for a whole frame before unmarshalling it, and a frame has to be used up
+/// completely by the value it holds. Frames bigger than [`max_frame_len`](Self::max_frame_len)
+/// are rejected in both directions with [`MarshalError::FrameTooLarge`].
+///
+/// Any [`Marshal`] value can be encoded, so both `T` and `&T` (for `T: MarshalRef`) can be sent.
+///
+/// Values are written with the wire format selected by `C`, and the length prefix in its byte order.
+/// ```
+/// use bytes::BytesMut;
+/// use lazy_marshal::{FrameLength, LazyMarshalCodec, Varints};
+/// use tokio_util::codec::{Decoder, Encoder};
+///
+/// let mut codec = LazyMarshalCodec::<String>::new()
+///     .length_prefix(FrameLength::U16)
+///     .max_frame_len(1024);
+///
+/// let mut buf = BytesMut::new();
+/// codec.encode("framed", &mut buf).unwrap();
+/// assert_eq!(buf.len(), 2 + 8 + 6);
+/// assert_eq!(codec.decode(&mut buf).unwrap(), Some("framed".to_string()));
+///
+/// let mut codec = LazyMarshalCodec::<u32, Varints>::new().length_prefix(FrameLength::U8);
+/// codec.encode(300u32, &mut buf).unwrap();
+/// assert_eq!(&buf[..], [2, 0xac, 0x02]);
+/// assert_eq!(codec.decode(&mut buf).unwrap(), Some(300));
+/// ```
+#[derive(Debug)]
+pub struct LazyMarshalCodec<T, C = DefaultConfig> {
+    length_prefix: FrameLength,
+    max_frame_len: usize,
+    _ty: PhantomData<fn() -> (T, C)>,
+}
+
+// Derived impls would need `T` and `C` to be `Clone` too
+impl<T, C> Clone for LazyMarshalCodec<T, C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, C> Copy for LazyMarshalCodec<T, C> {}
+
+impl<T, C: Config> LazyMarshalCodec<T, C> {
+    /// A codec with a 4 byte length prefix and an 8 MiB maximum frame size
+    pub fn new() -> Self {
+        Self {
+            length_prefix: FrameLength::U32,
+            max_frame_len: 8 * 1024 * 1024,
+            _ty: PhantomData,
+        }
+    }
+
+    /// Set the width of the length prefix
+    pub fn length_prefix(mut self, length_prefix: FrameLength) -> Self {
+        self.length_prefix = length_prefix;
+        self
+    }
+
+    /// Set the largest frame (not counting the length prefix) that will be sent or accepted
+    pub fn max_frame_len(mut self, max_frame_len: usize) -> Self {
+        self.max_frame_len = max_frame_len;
+        self
+    }
+
+    fn check_len(&self, len: u64) -> Result<usize, MarshalError> {
+        let max = (self.max_frame_len as u64).min(self.length_prefix.max());
+        if len > max {
+            Err(MarshalError::FrameTooLarge {
+                len: usize::try_from(len).unwrap_or(usize::MAX),
+                max: max as usize,
+            })?
+        }
+        Ok(len as usize)
+    }
+}
+
+impl<T, C: Config> Default for LazyMarshalCodec<T, C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, C: Config, I: Marshal> Encoder<I> for LazyMarshalCodec<T, C> {
+    type Error = MarshalError;
+
+    fn encode(&mut self, item: I, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let width = self.length_prefix.width();
+        let start = dst.len();
+        dst.resize(start + width, 0);
+        item.marshal_into_with::<C, _>(&mut (&mut *dst).writer())?;
+
+        let len = match self.check_len((dst.len() - start - width) as u64) {
+            Ok(len) => len,
+            Err(e) => {
+                dst.truncate(start);
+                Err(e)?
+            }
+        };
+        let prefix = &mut dst[start..start + width];
+        prefix.copy_from_slice(&(len as u64).to_le_bytes()[..width]);
+        if C::ENDIAN == Endian::Big {
+            prefix.reverse();
+        }
+        Ok(())
+    }
+}
+
+impl<T: UnMarshal, C: Config> Decoder for LazyMarshalCodec<T, C> {
+    type Item = T;
+    type Error = MarshalError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let width = self.length_prefix.width();
+        if src.len() < width {
+            return Ok(None);
+        }
+        let mut prefix = [0; 8];
+        prefix[..width].copy_from_slice(&src[..width]);
+        if C::ENDIAN == Endian::Big {
+            prefix[..width].reverse();
+        }
+        let len = self.check_len(u64::from_le_bytes(prefix))?;
+
+        if src.len() < width + len {
+            src.reserve(width + len - src.len());
+            return Ok(None);
+        }
+        src.advance(width);
+        let frame = src.split_to(len);
+
+        let mut data = &frame[..];
+        let value = T::unmarshal_from_with::<C>(&mut data)?;
+        if !data.is_empty() {
+            Err(MarshalError::InvalidData(format!(
+                "{} unused bytes at the end of a {len} byte frame",
+                data.len()
+            )))?
+        }
+        Ok(Some(value))
+    }
+}
+
+#[cfg(test)]
+mod tests;
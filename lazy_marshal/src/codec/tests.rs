@@ -0,0 +1,89 @@
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{prelude::*, FrameLength, LazyMarshalCodec};
+
+#[test]
+fn test_frames() {
+    let mut codec = LazyMarshalCodec::<Vec<u32>>::new();
+    let mut buf = BytesMut::new();
+
+    let first = vec![1, 2, 3];
+    let second = vec![u32::MAX];
+    codec.encode(&first, &mut buf).unwrap();
+    codec.encode(second.clone(), &mut buf).unwrap();
+    assert_eq!(buf.len(), (4 + 8 + 12) + (4 + 8 + 4));
+
+    // Frames only decode once they've fully arrived
+    let mut partial = buf.split_to(10);
+    assert_eq!(codec.decode(&mut partial).unwrap(), None);
+    partial.unsplit(buf);
+    let mut buf = partial;
+
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some(first));
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some(second));
+    assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn test_oversized() {
+    let mut codec = LazyMarshalCodec::<String>::new().max_frame_len(10);
+    let mut buf = BytesMut::from(&b"keep"[..]);
+
+    assert!(matches!(
+        codec.encode("too long to fit", &mut buf),
+        Err(MarshalError::FrameTooLarge { len: 23, max: 10 })
+    ));
+    assert_eq!(&buf[..], b"keep");
+
+    // The prefix's width also limits the frame size
+    let mut codec = LazyMarshalCodec::<Vec<u8>>::new().length_prefix(FrameLength::U8);
+    assert!(matches!(
+        codec.encode(vec![0u8; 300], &mut BytesMut::new()),
+        Err(MarshalError::FrameTooLarge { len: 308, max: 255 })
+    ));
+
+    let mut buf = BytesMut::from(&[11u8, 0, 0, 0][..]);
+    assert!(matches!(
+        LazyMarshalCodec::<String>::new()
+            .max_frame_len(10)
+            .decode(&mut buf),
+        Err(MarshalError::FrameTooLarge { len: 11, max: 10 })
+    ));
+}
+
+#[test]
+fn test_corrupt() {
+    let mut codec = LazyMarshalCodec::<u16>::new().length_prefix(FrameLength::U8);
+
+    let mut buf = BytesMut::from(&[3u8, 1, 2, 3][..]);
+    assert!(matches!(
+        codec.decode(&mut buf),
+        Err(MarshalError::InvalidData(_))
+    ));
+
+    let mut buf = BytesMut::from(&[1u8, 1][..]);
+    assert!(codec.decode(&mut buf).is_err());
+}
+
+#[test]
+fn test_config() {
+    struct Network;
+
+    impl Config for Network {
+        const ENDIAN: Endian = Endian::Big;
+        const LENGTH_PREFIX: LengthPrefix = LengthPrefix::U16;
+    }
+
+    let mut codec =
+        LazyMarshalCodec::<(u16, String), Network>::new().length_prefix(FrameLength::U64);
+    let mut buf = BytesMut::new();
+    codec.encode((260u16, "hi"), &mut buf).unwrap();
+    assert_eq!(&buf[..], [0, 0, 0, 0, 0, 0, 0, 6, 1, 4, 0, 2, b'h', b'i']);
+    assert_eq!(
+        codec.decode(&mut buf).unwrap(),
+        Some((260, "hi".to_string()))
+    );
+    assert!(buf.is_empty());
+}
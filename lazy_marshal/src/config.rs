@@ -0,0 +1,86 @@
+use std::marker::PhantomData;
+
+use crate::limits::DecodeLimits;
+
+/// Byte order used for fixed width numbers and length prefixes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+/// How integers wider than a byte are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntEncoding {
+    /// Every integer takes its full width (a `u64` is always 8 bytes)
+    Fixed,
+    /// LEB128, with signed integers zigzag encoded first, so small values take fewer bytes.
+    /// `u8`, `i8`, `bool` and floats are always written as they are.
+    Varint,
+}
+
+/// How the length in front of strings, slices, vectors and maps is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthPrefix {
+    U8,
+    U16,
+    U32,
+    U64,
+    Varint,
+}
+
+/// Selects the wire format used by the `*_with` methods, like [`Marshal::marshal_with()`](crate::Marshal::marshal_with)
+/// and [`UnMarshal::unmarshal_with()`](crate::UnMarshal::unmarshal_with).
+///
+/// Every option has a default matching [`DefaultConfig`], so a config only needs to override what it changes.
+/// Both sides have to agree on the config for the data to make sense.
+///
+/// Marshalling a length that doesn't fit in the chosen [`LengthPrefix`] panics, since there's no way to
+/// report an error from the byte iterator.
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// struct Network;
+///
+/// impl Config for Network {
+///     const ENDIAN: Endian = Endian::Big;
+///     const LENGTH_PREFIX: LengthPrefix = LengthPrefix::U16;
+/// }
+///
+/// let bytes: Vec<u8> = (260u32, "hi").marshal_with::<Network>().collect();
+/// assert_eq!(bytes, vec![0, 0, 1, 4, 0, 2, b'h', b'i']);
+///
+/// let decoded = <(u32, String)>::unmarshal_with::<Network>(&mut bytes.into_iter()).unwrap();
+/// assert_eq!(decoded, (260, "hi".to_string()));
+/// ```
+pub trait Config: 'static {
+    const ENDIAN: Endian = Endian::Little;
+    const INT_ENCODING: IntEncoding = IntEncoding::Fixed;
+    const LENGTH_PREFIX: LengthPrefix = LengthPrefix::U64;
+    /// Only checked when unmarshalling
+    const LIMITS: DecodeLimits = DecodeLimits::NONE;
+}
+
+/// The format used by [`Marshal::marshal()`](crate::Marshal::marshal) and friends:
+/// little endian, fixed width integers and `u64` length prefixes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultConfig;
+
+impl Config for DefaultConfig {}
+
+/// `C` with every integer and length prefix written as a varint.
+/// `Varints` on its own is the compact version of [`DefaultConfig`].
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// let bytes: Vec<u8> = vec![1u8, 2, 3].marshal_with::<Varints>().collect();
+/// assert_eq!(bytes, vec![3, 1, 2, 3]);
+/// ```
+pub struct Varints<C = DefaultConfig>(PhantomData<C>);
+
+impl<C: Config> Config for Varints<C> {
+    const ENDIAN: Endian = C::ENDIAN;
+    const INT_ENCODING: IntEncoding = IntEncoding::Varint;
+    const LENGTH_PREFIX: LengthPrefix = LengthPrefix::Varint;
+    const LIMITS: DecodeLimits = C::LIMITS;
+}
@@ -0,0 +1,598 @@
+use std::{collections::HashMap, fmt::Debug, hash::Hash, marker::PhantomData};
+
+use crate::{
+    config::{Config, DefaultConfig, Endian, LengthPrefix, Varints},
+    error::{MarshalError, PathSegment},
+    limits::{DecodeLimits, Nested},
+    seq::StreamedSeq,
+    traits::{Decoded, IncrementalUnMarshal, PushDecoder},
+    utils::varint_step,
+    varint::VarInt,
+};
+
+/// Collects the bytes of a number
+pub struct PrimitiveDecoder<T> {
+    buf: [u8; 16],
+    filled: usize,
+    varint: u128,
+    _ty: PhantomData<T>,
+}
+
+impl<T> Default for PrimitiveDecoder<T> {
+    fn default() -> Self {
+        Self {
+            buf: [0; 16],
+            filled: 0,
+            varint: 0,
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<T> PrimitiveDecoder<T> {
+    /// Copy bytes out of `data` until `N` bytes have been collected.
+    /// The bytes are returned in little endian order.
+    pub(crate) fn fixed<C: Config, const N: usize>(
+        &mut self,
+        data: &mut &[u8],
+    ) -> Decoded<[u8; N]> {
+        let n = (N - self.filled).min(data.len());
+        self.buf[self.filled..self.filled + n].copy_from_slice(&data[..n]);
+        *data = &data[n..];
+        self.filled += n;
+
+        if self.filled < N {
+            return Decoded::NeedMore(N - self.filled);
+        }
+        self.filled = 0;
+        let mut d: [u8; N] = self.buf[..N].try_into().unwrap();
+        if C::ENDIAN == Endian::Big {
+            d.reverse();
+        }
+        Decoded::Done(d)
+    }
+
+    /// Read a LEB128 number one byte at a time
+    pub(crate) fn varint(&mut self, data: &mut &[u8]) -> Result<Decoded<u128>, MarshalError> {
+        while let Some((&b, rest)) = data.split_first() {
+            *data = rest;
+            let done = varint_step(&mut self.varint, self.filled, b)?;
+            self.filled += 1;
+            if done {
+                self.filled = 0;
+                return Ok(Decoded::Done(std::mem::take(&mut self.varint)));
+            }
+        }
+        Ok(Decoded::NeedMore(1))
+    }
+}
+
+/// Reads a [`VarInt`], whatever the config says about integers
+pub struct VarIntDecoder<T>(PrimitiveDecoder<T>);
+
+impl<T> Default for VarIntDecoder<T> {
+    fn default() -> Self {
+        Self(PrimitiveDecoder::default())
+    }
+}
+
+impl<T> PushDecoder for VarIntDecoder<T>
+where
+    PrimitiveDecoder<T>: PushDecoder<Output = T>,
+{
+    type Output = VarInt<T>;
+
+    fn feed(&mut self, data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError> {
+        self.feed_with::<DefaultConfig>(data)
+    }
+
+    fn feed_with<C: Config>(
+        &mut self,
+        data: &mut &[u8],
+    ) -> Result<Decoded<Self::Output>, MarshalError> {
+        Ok(self.0.feed_with::<Varints<C>>(data)?.map(VarInt))
+    }
+}
+
+/// Reads the length prefix in front of a collection
+#[derive(Default)]
+pub struct LenDecoder(PrimitiveDecoder<usize>);
+
+impl PushDecoder for LenDecoder {
+    type Output = usize;
+
+    fn feed(&mut self, data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError> {
+        self.feed_with::<DefaultConfig>(data)
+    }
+
+    fn feed_with<C: Config>(
+        &mut self,
+        data: &mut &[u8],
+    ) -> Result<Decoded<Self::Output>, MarshalError> {
+        let len = match C::LENGTH_PREFIX {
+            LengthPrefix::U8 => self
+                .0
+                .fixed::<C, 1>(data)
+                .map(|d| u8::from_le_bytes(d) as u128),
+            LengthPrefix::U16 => self
+                .0
+                .fixed::<C, 2>(data)
+                .map(|d| u16::from_le_bytes(d) as u128),
+            LengthPrefix::U32 => self
+                .0
+                .fixed::<C, 4>(data)
+                .map(|d| u32::from_le_bytes(d) as u128),
+            LengthPrefix::U64 => self
+                .0
+                .fixed::<C, 8>(data)
+                .map(|d| u64::from_le_bytes(d) as u128),
+            LengthPrefix::Varint => {
+                return Ok(match self.0.varint(data)? {
+                    Decoded::Done(len) => {
+                        Decoded::Done(len.try_into().map_err(|_| MarshalError::VarintOverflow)?)
+                    }
+                    Decoded::NeedMore(n) => Decoded::NeedMore(n),
+                })
+            }
+        };
+        Ok(match len {
+            Decoded::Done(len) => {
+                Decoded::Done(len.try_into().map_err(|_| MarshalError::InvalidDecode)?)
+            }
+            Decoded::NeedMore(n) => Decoded::NeedMore(n),
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct BoolDecoder(PrimitiveDecoder<u8>);
+
+impl PushDecoder for BoolDecoder {
+    type Output = bool;
+
+    fn feed(&mut self, data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError> {
+        self.feed_with::<DefaultConfig>(data)
+    }
+
+    fn feed_with<C: Config>(
+        &mut self,
+        data: &mut &[u8],
+    ) -> Result<Decoded<Self::Output>, MarshalError> {
+        Ok(match self.0.feed_with::<C>(data)? {
+            Decoded::NeedMore(n) => Decoded::NeedMore(n),
+            Decoded::Done(0) => Decoded::Done(false),
+            Decoded::Done(1) => Decoded::Done(true),
+            Decoded::Done(b) => Err(MarshalError::InvalidData(format!(
+                "Found '{b}' when unmarshalling a bool. Should be either 0 (false) or 1 (true)"
+            )))?,
+        })
+    }
+}
+
+/// Decodes a [`PhantomData`], which takes up no bytes
+pub struct PhantomDataDecoder<T>(PhantomData<fn() -> T>);
+
+impl<T> Default for PhantomDataDecoder<T> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T> PushDecoder for PhantomDataDecoder<T> {
+    type Output = PhantomData<T>;
+
+    fn feed(&mut self, _data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError> {
+        Ok(Decoded::Done(PhantomData))
+    }
+
+    fn feed_with<C: Config>(
+        &mut self,
+        _data: &mut &[u8],
+    ) -> Result<Decoded<Self::Output>, MarshalError> {
+        Ok(Decoded::Done(PhantomData))
+    }
+}
+
+/// Decodes a length prefixed run of bytes
+#[derive(Default)]
+pub struct ByteVecDecoder {
+    len: LenDecoder,
+    remaining: Option<usize>,
+    buf: Vec<u8>,
+}
+
+impl ByteVecDecoder {
+    /// Like [`PushDecoder::feed_with()`], but with the length prefix checked by `check_len`
+    fn feed_checked<C: Config>(
+        &mut self,
+        data: &mut &[u8],
+        check_len: fn(&DecodeLimits, usize) -> Result<usize, MarshalError>,
+    ) -> Result<Decoded<Vec<u8>>, MarshalError> {
+        let remaining = match self.remaining {
+            Some(r) => r,
+            None => match self.len.feed_with::<C>(data)? {
+                Decoded::Done(len) => check_len(&C::LIMITS, len)?,
+                Decoded::NeedMore(n) => return Ok(Decoded::NeedMore(n)),
+            },
+        };
+
+        // The length prefix can't be trusted, so only reserve what has actually arrived
+        let n = remaining.min(data.len());
+        self.buf.extend_from_slice(&data[..n]);
+        *data = &data[n..];
+
+        if remaining > n {
+            self.remaining = Some(remaining - n);
+            return Ok(Decoded::NeedMore(remaining - n));
+        }
+        self.remaining = None;
+        Ok(Decoded::Done(std::mem::take(&mut self.buf)))
+    }
+}
+
+impl PushDecoder for ByteVecDecoder {
+    type Output = Vec<u8>;
+
+    fn feed(&mut self, data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError> {
+        self.feed_with::<DefaultConfig>(data)
+    }
+
+    fn feed_with<C: Config>(
+        &mut self,
+        data: &mut &[u8],
+    ) -> Result<Decoded<Self::Output>, MarshalError> {
+        self.feed_checked::<C>(data, DecodeLimits::check_collection_len)
+    }
+}
+
+/// Collects a number of bytes known up front, like the size of a union
+#[derive(Default)]
+pub struct FixedBytesDecoder(Vec<u8>);
+
+impl FixedBytesDecoder {
+    /// Copy bytes out of `data` until there are `n` of them
+    pub fn feed(&mut self, n: usize, data: &mut &[u8]) -> Decoded<Vec<u8>> {
+        let take = (n - self.0.len()).min(data.len());
+        self.0.extend_from_slice(&data[..take]);
+        *data = &data[take..];
+        match self.0.len() < n {
+            true => Decoded::NeedMore(n - self.0.len()),
+            false => Decoded::Done(std::mem::take(&mut self.0)),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct StringDecoder(ByteVecDecoder);
+
+impl PushDecoder for StringDecoder {
+    type Output = String;
+
+    fn feed(&mut self, data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError> {
+        self.feed_with::<DefaultConfig>(data)
+    }
+
+    fn feed_with<C: Config>(
+        &mut self,
+        data: &mut &[u8],
+    ) -> Result<Decoded<Self::Output>, MarshalError> {
+        let check_len = DecodeLimits::check_string_len;
+        Ok(match self.0.feed_checked::<C>(data, check_len)? {
+            Decoded::NeedMore(n) => Decoded::NeedMore(n),
+            Decoded::Done(d) => Decoded::Done(String::from_utf8(d)?),
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct BoxedBytesDecoder(ByteVecDecoder);
+
+impl PushDecoder for BoxedBytesDecoder {
+    type Output = Box<[u8]>;
+
+    fn feed(&mut self, data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError> {
+        self.feed_with::<DefaultConfig>(data)
+    }
+
+    fn feed_with<C: Config>(
+        &mut self,
+        data: &mut &[u8],
+    ) -> Result<Decoded<Self::Output>, MarshalError> {
+        Ok(match self.0.feed_with::<C>(data)? {
+            Decoded::NeedMore(n) => Decoded::NeedMore(n),
+            Decoded::Done(d) => Decoded::Done(d.into_boxed_slice()),
+        })
+    }
+}
+
+/// Decodes a collection or derived type with `D`, checking how deeply they're nested and how many
+/// bytes the outermost one has been fed against the config's [`DecodeLimits`]. Errors say how many
+/// bytes into the value they happened.
+#[derive(Default)]
+pub struct NestedDecoder<D> {
+    inner: D,
+    /// Bytes fed to the current value so far
+    fed: usize,
+}
+
+impl<D: PushDecoder> PushDecoder for NestedDecoder<D> {
+    type Output = D::Output;
+
+    fn feed(&mut self, data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError> {
+        self.feed_with::<DefaultConfig>(data)
+    }
+
+    fn feed_with<C: Config>(
+        &mut self,
+        data: &mut &[u8],
+    ) -> Result<Decoded<Self::Output>, MarshalError> {
+        let len = data.len();
+        let decoded = Nested::feed::<C, _>(self.fed, data, |data| self.inner.feed_with::<C>(data));
+        let fed = self.fed + (len - data.len());
+        self.fed = match decoded {
+            Ok(Decoded::Done(_)) => 0,
+            Ok(Decoded::NeedMore(_)) => fed,
+            Err(e) => return Err(e.fed(fed)),
+        };
+        decoded
+    }
+}
+
+pub struct VecDecoder<T: IncrementalUnMarshal> {
+    len: LenDecoder,
+    remaining: Option<usize>,
+    items: Vec<T>,
+    // Boxed so a type can contain a `Vec` of itself
+    item: Box<T::Decoder>,
+}
+
+impl<T: IncrementalUnMarshal> Default for VecDecoder<T> {
+    fn default() -> Self {
+        Self {
+            len: Default::default(),
+            remaining: None,
+            items: Vec::new(),
+            item: Default::default(),
+        }
+    }
+}
+
+impl<T: IncrementalUnMarshal> PushDecoder for VecDecoder<T> {
+    type Output = Vec<T>;
+
+    fn feed(&mut self, data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError> {
+        self.feed_with::<DefaultConfig>(data)
+    }
+
+    fn feed_with<C: Config>(
+        &mut self,
+        data: &mut &[u8],
+    ) -> Result<Decoded<Self::Output>, MarshalError> {
+        let mut remaining = match self.remaining {
+            Some(r) => r,
+            None => match self.len.feed_with::<C>(data)? {
+                Decoded::Done(len) => C::LIMITS.check_collection_len(len)?,
+                Decoded::NeedMore(n) => return Ok(Decoded::NeedMore(n)),
+            },
+        };
+
+        while remaining > 0 {
+            let index = PathSegment::Index(self.items.len());
+            match self
+                .item
+                .feed_with::<C>(data)
+                .map_err(|e| e.within("Vec", &[index], None, None))?
+            {
+                Decoded::Done(v) => {
+                    self.items.push(v);
+                    remaining -= 1;
+                }
+                Decoded::NeedMore(n) => {
+                    self.remaining = Some(remaining);
+                    return Ok(Decoded::NeedMore(n));
+                }
+            }
+        }
+        self.remaining = None;
+        Ok(Decoded::Done(std::mem::take(&mut self.items)))
+    }
+}
+
+pub struct StreamedSeqDecoder<T: IncrementalUnMarshal> {
+    len: LenDecoder,
+    remaining: Option<usize>,
+    items: Vec<T>,
+    // Boxed so a type can contain a `StreamedSeq` of itself
+    item: Box<T::Decoder>,
+}
+
+impl<T: IncrementalUnMarshal> Default for StreamedSeqDecoder<T> {
+    fn default() -> Self {
+        Self {
+            len: Default::default(),
+            remaining: None,
+            items: Vec::new(),
+            item: Default::default(),
+        }
+    }
+}
+
+impl<T: IncrementalUnMarshal> PushDecoder for StreamedSeqDecoder<T> {
+    type Output = StreamedSeq<Vec<T>>;
+
+    fn feed(&mut self, data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError> {
+        self.feed_with::<DefaultConfig>(data)
+    }
+
+    fn feed_with<C: Config>(
+        &mut self,
+        data: &mut &[u8],
+    ) -> Result<Decoded<Self::Output>, MarshalError> {
+        loop {
+            let mut remaining = match self.remaining {
+                Some(r) => r,
+                None => match self.len.feed_with::<C>(data)? {
+                    Decoded::Done(0) => {
+                        let items = std::mem::take(&mut self.items);
+                        return Ok(Decoded::Done(StreamedSeq::new(items)));
+                    }
+                    Decoded::Done(len) => {
+                        C::LIMITS.check_collection_len(self.items.len().saturating_add(len))?;
+                        len
+                    }
+                    Decoded::NeedMore(n) => return Ok(Decoded::NeedMore(n)),
+                },
+            };
+
+            while remaining > 0 {
+                let index = PathSegment::Index(self.items.len());
+                match self
+                    .item
+                    .feed_with::<C>(data)
+                    .map_err(|e| e.within("StreamedSeq", &[index], None, None))?
+                {
+                    Decoded::Done(v) => {
+                        self.items.push(v);
+                        remaining -= 1;
+                    }
+                    Decoded::NeedMore(n) => {
+                        self.remaining = Some(remaining);
+                        return Ok(Decoded::NeedMore(n));
+                    }
+                }
+            }
+            self.remaining = None;
+        }
+    }
+}
+
+pub struct HashMapDecoder<K: IncrementalUnMarshal, V: IncrementalUnMarshal> {
+    len: LenDecoder,
+    remaining: Option<usize>,
+    map: HashMap<K, V>,
+    key: Option<K>,
+    // Boxed so a type can contain a `HashMap` of itself
+    key_decoder: Box<K::Decoder>,
+    value_decoder: Box<V::Decoder>,
+}
+
+impl<K: IncrementalUnMarshal, V: IncrementalUnMarshal> Default for HashMapDecoder<K, V> {
+    fn default() -> Self {
+        Self {
+            len: Default::default(),
+            remaining: None,
+            map: HashMap::new(),
+            key: None,
+            key_decoder: Default::default(),
+            value_decoder: Default::default(),
+        }
+    }
+}
+
+impl<K, V> PushDecoder for HashMapDecoder<K, V>
+where
+    K: IncrementalUnMarshal + Hash + Eq,
+    V: IncrementalUnMarshal + Debug,
+{
+    type Output = HashMap<K, V>;
+
+    fn feed(&mut self, data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError> {
+        self.feed_with::<DefaultConfig>(data)
+    }
+
+    fn feed_with<C: Config>(
+        &mut self,
+        data: &mut &[u8],
+    ) -> Result<Decoded<Self::Output>, MarshalError> {
+        let mut remaining = match self.remaining {
+            Some(r) => r,
+            None => match self.len.feed_with::<C>(data)? {
+                Decoded::Done(len) => C::LIMITS.check_collection_len(len)?,
+                Decoded::NeedMore(n) => return Ok(Decoded::NeedMore(n)),
+            },
+        };
+        self.remaining = Some(remaining);
+
+        while remaining > 0 {
+            let i = self.map.len();
+            let key = match self.key.take() {
+                Some(k) => k,
+                None => match self
+                    .key_decoder
+                    .feed_with::<C>(data)
+                    .map_err(|e| e.within("HashMap", &[PathSegment::Key(i)], None, None))?
+                {
+                    Decoded::Done(k) => k,
+                    Decoded::NeedMore(n) => return Ok(Decoded::NeedMore(n)),
+                },
+            };
+            let value = match self
+                .value_decoder
+                .feed_with::<C>(data)
+                .map_err(|e| e.within("HashMap", &[PathSegment::Value(i)], None, None))?
+            {
+                Decoded::Done(v) => v,
+                Decoded::NeedMore(n) => {
+                    self.key = Some(key);
+                    return Ok(Decoded::NeedMore(n));
+                }
+            };
+            if let Some(a) = self.map.insert(key, value) {
+                Err(MarshalError::InvalidData(format!(
+                    "Duplicate Key while decoding HashMap: {a:#?}"
+                ))
+                .within("HashMap", &[PathSegment::Key(i)], None, None))?
+            }
+            remaining -= 1;
+            self.remaining = Some(remaining);
+        }
+        self.remaining = None;
+        Ok(Decoded::Done(std::mem::take(&mut self.map)))
+    }
+}
+
+pub enum OptionDecoder<T: IncrementalUnMarshal> {
+    Tag(PrimitiveDecoder<u8>),
+    Some(T::Decoder),
+}
+
+impl<T: IncrementalUnMarshal> Default for OptionDecoder<T> {
+    fn default() -> Self {
+        Self::Tag(Default::default())
+    }
+}
+
+impl<T: IncrementalUnMarshal> PushDecoder for OptionDecoder<T> {
+    type Output = Option<T>;
+
+    fn feed(&mut self, data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError> {
+        self.feed_with::<DefaultConfig>(data)
+    }
+
+    fn feed_with<C: Config>(
+        &mut self,
+        data: &mut &[u8],
+    ) -> Result<Decoded<Self::Output>, MarshalError> {
+        if let Self::Tag(tag) = self {
+            match tag.feed_with::<C>(data)? {
+                Decoded::NeedMore(n) => return Ok(Decoded::NeedMore(n)),
+                Decoded::Done(0) => return Ok(Decoded::Done(None)),
+                Decoded::Done(1) => *self = Self::Some(Default::default()),
+                Decoded::Done(other) => Err(MarshalError::InvalidData(format!(
+                    "Found '{other}' when unmarshalling the option. Should be either 0 or 1"
+                )))?,
+            }
+        }
+
+        match self {
+            Self::Some(d) => Ok(match d.feed_with::<C>(data)? {
+                Decoded::NeedMore(n) => Decoded::NeedMore(n),
+                Decoded::Done(v) => {
+                    *self = Self::default();
+                    Decoded::Done(Some(v))
+                }
+            }),
+            Self::Tag(_) => unreachable!(),
+        }
+    }
+}
@@ -1,18 +1,218 @@
-use std::{error::Error, fmt::Display, string::FromUtf8Error};
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    io,
+    string::FromUtf8Error,
+    sync::Arc,
+};
 
+use crate::limits::Limit;
+
+/// Why marshalling or unmarshalling failed.
+///
+/// Errors from inside a collection or a derived type are wrapped in a
+/// [`Context`](MarshalError::Context) saying where they happened, so match on
+/// [`kind()`](MarshalError::kind) rather than on the error itself:
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// let bytes = vec![1u32, 2].marshal_to_vec();
+/// let err = Vec::<u32>::unmarshal(&mut bytes[..10].iter().copied()).unwrap_err();
+/// assert!(!matches!(err, MarshalError::InvalidSizedDecode(_)));
+/// assert!(matches!(err.kind(), MarshalError::InvalidSizedDecode(2)));
+/// ```
 #[derive(Debug, Clone)]
 pub enum MarshalError {
     EarlyStreamEnd,
     InvalidDecode,
     InvalidSizedDecode(usize),
     InvalidData(String),
+    Io(Arc<io::Error>),
+    FrameTooLarge {
+        len: usize,
+        max: usize,
+    },
+    /// A varint is too big for the type being decoded
+    VarintOverflow,
+    /// A varint has trailing zero groups, so the same number has a shorter encoding
+    VarintOverlong,
+    /// The input went over one of the config's [`DecodeLimits`](crate::DecodeLimits)
+    LimitExceeded {
+        limit: Limit,
+        max: usize,
+    },
+    /// Where in the input another error happened. Added by collections and derived types
+    /// as the error passes through them; use [`MarshalError::kind()`] to get the error itself.
+    Context(Box<ErrorContext>),
 }
 
-impl Error for MarshalError {}
+impl MarshalError {
+    /// The error without any [`Context`](MarshalError::Context)
+    /// ```
+    /// use lazy_marshal::prelude::*;
+    ///
+    /// let err = Vec::<String>::unmarshal(&mut [1, 0, 0, 0, 0, 0, 0, 0].into_iter()).unwrap_err();
+    /// assert!(matches!(err.kind(), MarshalError::InvalidSizedDecode(0)));
+    /// assert_eq!(err.path().as_deref(), Some("Vec[0]"));
+    /// assert_eq!(err.offset(), Some(8));
+    /// ```
+    pub fn kind(&self) -> &MarshalError {
+        match self {
+            Self::Context(context) => &context.source,
+            other => other,
+        }
+    }
+
+    /// The field the error happened in, like `Deal.salesman.email` or `Vec[3].name`
+    pub fn path(&self) -> Option<String> {
+        match self {
+            Self::Context(context) => Some(context.path()),
+            _ => None,
+        }
+    }
+
+    /// How many bytes into the value decoding stopped, for errors that have a [`path()`](Self::path)
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Self::Context(context) => context.offset,
+            _ => None,
+        }
+    }
+
+    /// Record that the error happened at `path` (outermost segment first) inside a `root`. `start` and
+    /// `now` are the [`ByteSource::position()`](crate::ByteSource::position) when `root` started
+    /// decoding and when the error came back.
+    #[doc(hidden)]
+    pub fn within(
+        self,
+        root: &'static str,
+        path: &[PathSegment],
+        start: Option<usize>,
+        now: Option<usize>,
+    ) -> Self {
+        let mut context = match self {
+            Self::Context(context) => context,
+            source => Box::new(ErrorContext {
+                source,
+                root,
+                path: Vec::new(),
+                offset: None,
+                at: now,
+            }),
+        };
+        context.root = root;
+        context.path.extend(path.iter().rev());
+        if let (Some(start), Some(at)) = (start, context.at) {
+            context.offset = at.checked_sub(start);
+        }
+        Self::Context(context)
+    }
+
+    /// Set how many bytes into the value a push decoder was fed before the error
+    pub(crate) fn fed(self, offset: usize) -> Self {
+        match self {
+            Self::Context(mut context) => {
+                context.offset = Some(offset);
+                Self::Context(context)
+            }
+            other => other,
+        }
+    }
+
+    /// The same error, but for the input ending before the value did, keeping where it happened
+    pub(crate) fn ended_early(self) -> Self {
+        match self {
+            Self::Context(mut context) => {
+                context.source = Self::EarlyStreamEnd;
+                Self::Context(context)
+            }
+            _ => Self::EarlyStreamEnd,
+        }
+    }
+}
+
+/// See [`MarshalError::Context`]
+#[derive(Debug, Clone)]
+pub struct ErrorContext {
+    source: MarshalError,
+    root: &'static str,
+    /// Innermost segment first
+    path: Vec<PathSegment>,
+    offset: Option<usize>,
+    /// The position of the input when the error happened
+    at: Option<usize>,
+}
+
+impl ErrorContext {
+    fn path(&self) -> String {
+        let mut path = self.root.to_string();
+        for segment in self.path.iter().rev() {
+            path += &segment.to_string();
+        }
+        path
+    }
+}
+
+/// One step of the path to where an error happened
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub enum PathSegment {
+    Field(&'static str),
+    Variant(&'static str),
+    Index(usize),
+    /// The key of the `n`th entry of a map
+    Key(usize),
+    /// The value of the `n`th entry of a map
+    Value(usize),
+}
+
+impl Display for PathSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Field(name) => write!(f, ".{name}"),
+            Self::Variant(name) => write!(f, "::{name}"),
+            Self::Index(i) => write!(f, "[{i}]"),
+            Self::Key(i) => write!(f, "[key {i}]"),
+            Self::Value(i) => write!(f, "{{key #{i}}}"),
+        }
+    }
+}
+
+impl Error for MarshalError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e.as_ref()),
+            // Context is part of how the error is displayed, so skip straight past it
+            Self::Context(context) => context.source.source(),
+            _ => None,
+        }
+    }
+}
 
 impl Display for MarshalError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{self:#?}")
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EarlyStreamEnd => write!(f, "the input ended before the value was complete"),
+            Self::InvalidDecode => write!(f, "the input isn't a valid value"),
+            Self::InvalidSizedDecode(n) => write!(f, "invalid or missing data after {n} bytes"),
+            Self::InvalidData(msg) => write!(f, "{msg}"),
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::FrameTooLarge { len, max } => {
+                write!(f, "a frame of {len} bytes is over the maximum of {max}")
+            }
+            Self::VarintOverflow => write!(f, "a varint is too big for its type"),
+            Self::VarintOverlong => write!(f, "a varint is longer than it needs to be"),
+            Self::LimitExceeded { limit, max } => {
+                write!(f, "the {limit} limit of {max} was exceeded")
+            }
+            Self::Context(context) => {
+                write!(f, "{} at {}", context.source, context.path())?;
+                match context.offset {
+                    Some(offset) => write!(f, " (byte {offset})"),
+                    None => Ok(()),
+                }
+            }
+        }
     }
 }
 
@@ -24,3 +224,12 @@ impl From<FromUtf8Error> for MarshalError {
         }
     }
 }
+
+impl From<io::Error> for MarshalError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(Arc::new(value))
+    }
+}
+
+#[cfg(test)]
+mod tests;
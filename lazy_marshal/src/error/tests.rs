@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+struct Salesman {
+    id: u32,
+    email: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+struct Deal {
+    id: u32,
+    salesman: Salesman,
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+enum Event {
+    Closed(Deal),
+    Lost,
+}
+
+fn deal(id: u32) -> Deal {
+    Deal {
+        id,
+        salesman: Salesman {
+            id,
+            email: "ab".to_string(),
+        },
+    }
+}
+
+#[test]
+fn test_field_path() {
+    let mut bytes = deal(1).marshal().collect::<Vec<_>>();
+    // The last byte of the email isn't valid utf8
+    *bytes.last_mut().unwrap() = 0xff;
+
+    let err = Deal::unmarshal(&mut bytes.iter().cloned()).unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::InvalidSizedDecode(1)));
+    assert_eq!(err.path().as_deref(), Some("Deal.salesman.email"));
+    assert_eq!(err.offset(), Some(bytes.len()));
+    assert_eq!(
+        err.to_string(),
+        "invalid or missing data after 1 bytes at Deal.salesman.email (byte 18)"
+    );
+
+    // Cut off in the middle of the salesman's id
+    let err = Deal::unmarshal(&mut bytes[..6].iter().cloned()).unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::InvalidSizedDecode(2)));
+    assert_eq!(err.path().as_deref(), Some("Deal.salesman.id"));
+    assert_eq!(err.offset(), Some(6));
+}
+
+#[test]
+fn test_collection_path() {
+    let deals = vec![deal(1), deal(2), deal(3), deal(4)];
+    let bytes = deals.marshal_ref().collect::<Vec<_>>();
+
+    let err = Vec::<Deal>::unmarshal(&mut bytes[..bytes.len() - 1].iter().cloned()).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Vec[3].salesman.email"));
+    assert_eq!(err.offset(), Some(bytes.len() - 1));
+
+    let mut events = HashMap::new();
+    events.insert("closed".to_string(), Event::Closed(deal(1)));
+    let mut bytes = events.marshal_ref().collect::<Vec<_>>();
+    // Cut off in the middle of the salesman's id
+    let err = HashMap::<String, Event>::unmarshal(&mut bytes[..30].iter().cloned()).unwrap_err();
+    assert_eq!(
+        err.path().as_deref(),
+        Some("HashMap{key #0}::Closed.0.salesman.id")
+    );
+    assert_eq!(err.offset(), Some(30));
+
+    // A bad variant tag
+    bytes[22] = 9;
+    let err = HashMap::<String, Event>::unmarshal(&mut bytes.iter().cloned()).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("HashMap{key #0}"));
+    assert!(matches!(err.kind(), MarshalError::InvalidData(_)));
+    let err = HashMap::<String, Event>::decoder()
+        .feed(&mut &bytes[..])
+        .unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("HashMap{key #0}"));
+    assert_eq!(err.offset(), Some(23));
+
+    // A key that isn't valid utf8
+    bytes[8 + 8] = 0xff;
+    let err = HashMap::<String, Event>::unmarshal(&mut bytes.iter().cloned()).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("HashMap[key 0]"));
+}
+
+#[test]
+fn test_decoder_path() {
+    let deals = vec![deal(1), deal(2)];
+    let mut bytes = deals.marshal_ref().collect::<Vec<_>>();
+    *bytes.last_mut().unwrap() = 0xff;
+
+    let mut decoder = Vec::<Deal>::decoder();
+    let err = decoder.feed(&mut &bytes[..]).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Vec[1].salesman.email"));
+    // Push decoders count what they've been fed
+    assert_eq!(err.offset(), Some(bytes.len()));
+
+    let mut decoder = Vec::<Deal>::decoder();
+    let (first, rest) = bytes.split_at(10);
+    assert!(matches!(
+        decoder.feed(&mut &first[..]).unwrap(),
+        Decoded::NeedMore(_)
+    ));
+    let err = decoder.feed(&mut &rest[..]).unwrap_err();
+    assert_eq!(err.offset(), Some(bytes.len()));
+}
+
+#[test]
+fn test_display() {
+    assert_eq!(
+        MarshalError::EarlyStreamEnd.to_string(),
+        "the input ended before the value was complete"
+    );
+    assert_eq!(
+        MarshalError::LimitExceeded {
+            limit: Limit::CollectionLen,
+            max: 3
+        }
+        .to_string(),
+        "the collection length limit of 3 was exceeded"
+    );
+
+    // Iterators that don't know their length count what they've read
+    let bytes = deal(1).marshal().collect::<Vec<_>>();
+    let mut data = bytes[..bytes.len() - 1].iter().cloned().filter(|_| true);
+    let err = Deal::unmarshal(&mut data).unwrap_err();
+    assert_eq!(err.offset(), Some(bytes.len() - 1));
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "invalid or missing data after 1 bytes at Deal.salesman.email (byte {})",
+            bytes.len() - 1
+        )
+    );
+}
@@ -1,27 +1,172 @@
-use std::{collections::HashMap, fmt::Debug, hash::Hash, marker::PhantomData, u64};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Debug,
+    hash::Hash,
+    io::{self, Write},
+    marker::PhantomData,
+};
 
 use crate::{
-    error::MarshalError,
-    traits::{Marshal, UnMarshal},
-    utils::readn_to_vec,
+    config::{Config, DefaultConfig, IntEncoding},
+    decoders::{
+        BoolDecoder, BoxedBytesDecoder, HashMapDecoder, NestedDecoder, OptionDecoder,
+        PhantomDataDecoder, PrimitiveDecoder, StringDecoder, VecDecoder,
+    },
+    error::{MarshalError, PathSegment},
+    limits::{consume, Counted, Nested},
+    source::{ByteSource, IterSource},
+    traits::{Decoded, IncrementalUnMarshal, Marshal, MarshalRef, PushDecoder, UnMarshal},
+    utils::{
+        int_len, len_prefix_len, marshal_len, position, read_fixed, read_varint, readn_to_vec,
+        unmarshal_len, unzigzag, zigzag, IntBytes, KnownLen, OwnedLen,
+    },
     Either,
 };
 
 impl Marshal for bool {
     #[inline]
     fn marshal(self) -> impl Iterator<Item = u8> {
+        self.marshal_with::<DefaultConfig>()
+    }
+
+    #[inline]
+    fn marshal_with<C: Config>(self) -> impl Iterator<Item = u8> {
         let d: u8 = match self {
             true => 1,
             false => 0,
         };
-        d.marshal()
+        d.marshal_with::<C>()
+    }
+
+    #[inline]
+    fn encoded_len_hint_with<C: Config>(&self) -> Option<usize> {
+        Some(self.encoded_len_with::<C>())
+    }
+
+    #[inline]
+    fn marshal_into<W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.marshal_into_with::<DefaultConfig, W>(w)
+    }
+
+    #[inline]
+    fn marshal_into_with<C: Config, W: Write>(self, w: &mut W) -> io::Result<()> {
+        w.write_all(&[self as u8])
+    }
+}
+
+impl MarshalRef for bool {
+    #[inline]
+    fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+        (*self).marshal()
+    }
+
+    #[inline]
+    fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
+        (*self).marshal_with::<C>()
+    }
+
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn encoded_len_with<C: Config>(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (*self).marshal_into(w)
+    }
+
+    #[inline]
+    fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (*self).marshal_into_with::<C, W>(w)
+    }
+}
+
+impl<T: MarshalRef + ?Sized> Marshal for &T {
+    #[inline]
+    fn marshal(self) -> impl Iterator<Item = u8> {
+        self.marshal_ref()
+    }
+
+    #[inline]
+    fn marshal_with<C: Config>(self) -> impl Iterator<Item = u8> {
+        self.marshal_ref_with::<C>()
+    }
+
+    fn encoded_len_hint_with<C: Config>(&self) -> Option<usize> {
+        Some(self.encoded_len_with::<C>())
+    }
+
+    #[inline]
+    fn marshal_into<W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.marshal_ref_into(w)
+    }
+
+    #[inline]
+    fn marshal_into_with<C: Config, W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.marshal_ref_into_with::<C, W>(w)
+    }
+
+    fn marshal_to_vec(self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.encoded_len());
+        self.marshal_ref_into(&mut out)
+            .expect("writing to a Vec doesn't fail");
+        out
+    }
+}
+
+impl<T: MarshalRef + ?Sized> MarshalRef for &T {
+    #[inline]
+    fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+        (**self).marshal_ref()
+    }
+
+    #[inline]
+    fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
+        (**self).marshal_ref_with::<C>()
+    }
+
+    #[inline]
+    fn encoded_len(&self) -> usize {
+        (**self).encoded_len()
+    }
+
+    #[inline]
+    fn encoded_len_with<C: Config>(&self) -> usize {
+        (**self).encoded_len_with::<C>()
+    }
+
+    #[inline]
+    fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (**self).marshal_ref_into(w)
+    }
+
+    #[inline]
+    fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (**self).marshal_ref_into_with::<C, W>(w)
     }
 }
 
 impl UnMarshal for bool {
     #[inline]
     fn unmarshal(data: &mut impl Iterator<Item = u8>) -> Result<Self, MarshalError> {
-        Ok(match u8::unmarshal(data)? {
+        Self::unmarshal_with::<DefaultConfig>(data)
+    }
+
+    #[inline]
+    fn unmarshal_with<C: Config>(
+        data: &mut impl Iterator<Item = u8>,
+    ) -> Result<Self, MarshalError> {
+        Self::unmarshal_from_with::<C>(&mut IterSource::new(data))
+    }
+
+    fn unmarshal_from_with<C: Config>(data: &mut impl ByteSource) -> Result<Self, MarshalError> {
+        Ok(match u8::unmarshal_from_with::<C>(data)? {
             0 => false,
             1 => true,
             b => Err(MarshalError::InvalidData(format!(
@@ -31,79 +176,410 @@ impl UnMarshal for bool {
     }
 }
 
+impl IncrementalUnMarshal for bool {
+    type Decoder = BoolDecoder;
+}
+
+/// Writes a number as its `repr` type. Single bytes and floats are always fixed width,
+/// other integers follow [`Config::INT_ENCODING`].
+macro_rules! encode_num {
+    (unsigned, $c:ident, $repr:ident, $v:expr) => {
+        match $c::INT_ENCODING {
+            IntEncoding::Fixed => encode_num!(fixed, $c, $repr, $v),
+            IntEncoding::Varint => IntBytes::varint($v as u128),
+        }
+    };
+    (signed, $c:ident, $repr:ident, $v:expr) => {
+        match $c::INT_ENCODING {
+            IntEncoding::Fixed => encode_num!(fixed, $c, $repr, $v),
+            IntEncoding::Varint => IntBytes::varint(zigzag($v as i128)),
+        }
+    };
+    ($kind:ident, $c:ident, $repr:ident, $v:expr) => {
+        IntBytes::fixed::<$c, { std::mem::size_of::<$repr>() }>($v.to_le_bytes())
+    };
+}
+
+/// How many bytes [`encode_num`] writes a number in
+macro_rules! num_len {
+    (unsigned, $c:ident, $repr:ident, $v:expr) => {
+        int_len::<$c>(std::mem::size_of::<$repr>(), $v as u128)
+    };
+    (signed, $c:ident, $repr:ident, $v:expr) => {
+        int_len::<$c>(std::mem::size_of::<$repr>(), zigzag($v as i128))
+    };
+    ($kind:ident, $c:ident, $repr:ident, $v:expr) => {
+        std::mem::size_of::<$repr>()
+    };
+}
+
+/// Writes a slice of numbers with [`encode_num`], all at once if they're bytes
+macro_rules! write_slice {
+    (byte, $c:ident, $items:expr, $w:expr) => {
+        // SAFETY: `u8` and `i8` have the same layout
+        $w.write_all(unsafe {
+            std::slice::from_raw_parts($items.as_ptr().cast::<u8>(), $items.len())
+        })
+    };
+    ($kind:ident, $c:ident, $items:expr, $w:expr) => {
+        $items
+            .iter()
+            .try_for_each(|item| item.marshal_ref_into_with::<$c, _>($w))
+    };
+}
+
+/// Reads a number written by [`encode_num`] from an iterator
+macro_rules! decode_num {
+    (unsigned, $c:ident, $repr:ident, $data:expr) => {
+        match $c::INT_ENCODING {
+            IntEncoding::Fixed => decode_num!(fixed, $c, $repr, $data),
+            IntEncoding::Varint => $repr::try_from(read_varint::<$c>($data)?)
+                .map_err(|_| MarshalError::VarintOverflow)?,
+        }
+    };
+    (signed, $c:ident, $repr:ident, $data:expr) => {
+        match $c::INT_ENCODING {
+            IntEncoding::Fixed => decode_num!(fixed, $c, $repr, $data),
+            IntEncoding::Varint => $repr::try_from(unzigzag(read_varint::<$c>($data)?))
+                .map_err(|_| MarshalError::VarintOverflow)?,
+        }
+    };
+    ($kind:ident, $c:ident, $repr:ident, $data:expr) => {
+        $repr::from_le_bytes(read_fixed::<$c, { std::mem::size_of::<$repr>() }>($data)?)
+    };
+}
+
+/// Reads `$len` numbers with [`decode_num`] onto the end of `$out`, all at once if they're bytes
+macro_rules! read_vec {
+    (byte, $c:ident, $data:expr, $len:expr, $out:expr) => {{
+        consume::<$c>($len)?;
+        let mut bytes = Vec::new();
+        let read = $data.read_to_vec(&mut bytes, $len);
+        $out.extend(bytes.into_iter().map(|b| b as Self));
+        // Like reading them one at a time, the first byte that's missing is the one that failed
+        read.map_err(|e| match e {
+            MarshalError::InvalidSizedDecode(_) => MarshalError::InvalidSizedDecode(0),
+            e => e,
+        })
+    }};
+    ($kind:ident, $c:ident, $data:expr, $len:expr, $out:expr) => {{
+        for _ in 0..$len {
+            $out.push(Self::unmarshal_from_with::<$c>($data)?);
+        }
+        Ok(())
+    }};
+}
+
+/// Pushes bytes of a number written by [`encode_num`] into a [`PrimitiveDecoder`]
+macro_rules! feed_num {
+    (unsigned, $c:ident, $repr:ident, $decoder:expr, $data:expr) => {
+        match $c::INT_ENCODING {
+            IntEncoding::Fixed => feed_num!(fixed, $c, $repr, $decoder, $data),
+            IntEncoding::Varint => match $decoder.varint($data)? {
+                Decoded::Done(v) => {
+                    Decoded::Done($repr::try_from(v).map_err(|_| MarshalError::VarintOverflow)?)
+                }
+                Decoded::NeedMore(n) => Decoded::NeedMore(n),
+            },
+        }
+    };
+    (signed, $c:ident, $repr:ident, $decoder:expr, $data:expr) => {
+        match $c::INT_ENCODING {
+            IntEncoding::Fixed => feed_num!(fixed, $c, $repr, $decoder, $data),
+            IntEncoding::Varint => match $decoder.varint($data)? {
+                Decoded::Done(v) => Decoded::Done(
+                    $repr::try_from(unzigzag(v)).map_err(|_| MarshalError::VarintOverflow)?,
+                ),
+                Decoded::NeedMore(n) => Decoded::NeedMore(n),
+            },
+        }
+    };
+    ($kind:ident, $c:ident, $repr:ident, $decoder:expr, $data:expr) => {
+        $decoder
+            .fixed::<$c, { std::mem::size_of::<$repr>() }>($data)
+            .map($repr::from_le_bytes)
+    };
+}
+
+/// `$ty` goes over the wire as `$repr`, which is encoded according to `$kind`
 macro_rules! primative_nums {
-    ($ty:ident) => {
+    ($ty:ident as $repr:ident: $kind:ident) => {
         impl Marshal for $ty {
             #[inline]
             fn marshal(self) -> impl Iterator<Item = u8> {
-                self.to_le_bytes().into_iter()
+                self.marshal_with::<DefaultConfig>()
             }
-        }
 
-        impl UnMarshal for $ty {
-            fn unmarshal(data: &mut impl Iterator<Item = u8>) -> Result<Self, MarshalError> {
-                const TY_SIZE: usize = std::mem::size_of::<$ty>();
-                #[allow(invalid_value)]
-                let mut d: [u8; TY_SIZE] = unsafe { std::mem::MaybeUninit::uninit().assume_init() };
-                for i in 0..TY_SIZE {
-                    d[i] = match data.next() {
-                        Some(val) => val,
-                        None => Err(MarshalError::InvalidSizedDecode(i))?,
-                    }
-                }
-                Ok(Self::from_le_bytes(d))
+            #[inline]
+            fn marshal_with<C: Config>(self) -> impl Iterator<Item = u8> {
+                encode_num!($kind, C, $repr, self as $repr)
+            }
+
+            #[inline]
+            fn encoded_len_hint_with<C: Config>(&self) -> Option<usize> {
+                Some(self.encoded_len_with::<C>())
+            }
+
+            #[inline]
+            fn marshal_into<W: Write>(self, w: &mut W) -> io::Result<()> {
+                self.marshal_into_with::<DefaultConfig, W>(w)
+            }
+
+            #[inline]
+            fn marshal_into_with<C: Config, W: Write>(self, w: &mut W) -> io::Result<()> {
+                w.write_all(encode_num!($kind, C, $repr, self as $repr).as_slice())
+            }
+
+            fn marshal_vec_into_with<C: Config, W: Write>(
+                items: Vec<Self>,
+                w: &mut W,
+            ) -> io::Result<()> {
+                Self::marshal_slice_into_with::<C, W>(&items, w)
             }
         }
-    };
-    ($ty:ident, $cast:ident) => {
-        impl Marshal for $ty {
+
+        impl MarshalRef for $ty {
             #[inline]
-            fn marshal(self) -> impl Iterator<Item = u8> {
-                (self as $cast).to_le_bytes().into_iter()
+            fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+                (*self).marshal()
+            }
+
+            #[inline]
+            fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
+                (*self).marshal_with::<C>()
+            }
+
+            #[inline]
+            fn encoded_len(&self) -> usize {
+                self.encoded_len_with::<DefaultConfig>()
+            }
+
+            #[inline]
+            fn encoded_len_with<C: Config>(&self) -> usize {
+                num_len!($kind, C, $repr, *self as $repr)
+            }
+
+            #[inline]
+            fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+                (*self).marshal_into(w)
+            }
+
+            #[inline]
+            fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+                (*self).marshal_into_with::<C, W>(w)
+            }
+
+            fn marshal_slice_into_with<C: Config, W: Write>(
+                items: &[Self],
+                w: &mut W,
+            ) -> io::Result<()> {
+                write_slice!($kind, C, items, w)
             }
         }
 
         impl UnMarshal for $ty {
             fn unmarshal(data: &mut impl Iterator<Item = u8>) -> Result<Self, MarshalError> {
-                let d = match readn_to_vec(data, std::mem::size_of::<Self>()) {
-                    Ok(a) => a,
-                    Err(e) => Err(MarshalError::InvalidSizedDecode(e))?,
-                };
-                Ok($cast::from_le_bytes(d.try_into().unwrap())
-                    .try_into()
-                    .map_err(|_| MarshalError::InvalidDecode)?)
+                Self::unmarshal_with::<DefaultConfig>(data)
+            }
+
+            fn unmarshal_with<C: Config>(
+                data: &mut impl Iterator<Item = u8>,
+            ) -> Result<Self, MarshalError> {
+                Self::unmarshal_from_with::<C>(&mut IterSource::new(data))
+            }
+
+            fn unmarshal_from_with<C: Config>(
+                data: &mut impl ByteSource,
+            ) -> Result<Self, MarshalError> {
+                let repr: $repr = decode_num!($kind, C, $repr, data);
+                repr.try_into().map_err(|_| MarshalError::InvalidDecode)
+            }
+
+            fn unmarshal_vec_from_with<C: Config>(
+                data: &mut impl ByteSource,
+                len: usize,
+                out: &mut Vec<Self>,
+            ) -> Result<(), MarshalError> {
+                read_vec!($kind, C, data, len, out)
+            }
+        }
+
+        impl PushDecoder for PrimitiveDecoder<$ty> {
+            type Output = $ty;
+
+            fn feed(&mut self, data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError> {
+                self.feed_with::<DefaultConfig>(data)
             }
+
+            fn feed_with<C: Config>(
+                &mut self,
+                data: &mut &[u8],
+            ) -> Result<Decoded<Self::Output>, MarshalError> {
+                Ok(match feed_num!($kind, C, $repr, self, data) {
+                    Decoded::Done(repr) => {
+                        Decoded::Done(repr.try_into().map_err(|_| MarshalError::InvalidDecode)?)
+                    }
+                    Decoded::NeedMore(n) => Decoded::NeedMore(n),
+                })
+            }
+        }
+
+        impl IncrementalUnMarshal for $ty {
+            type Decoder = PrimitiveDecoder<$ty>;
         }
     };
 }
 
-primative_nums!(usize, u64);
-primative_nums!(u8);
-primative_nums!(u16);
-primative_nums!(u32);
-primative_nums!(u64);
-primative_nums!(u128);
-primative_nums!(isize, i64);
-primative_nums!(i8);
-primative_nums!(i16);
-primative_nums!(i32);
-primative_nums!(i64);
-primative_nums!(i128);
-primative_nums!(f32);
-primative_nums!(f64);
-primative_nums!(char, u32);
-
-impl Marshal for &str {
-    fn marshal(self) -> impl Iterator<Item = u8> {
+primative_nums!(usize as u64: unsigned);
+primative_nums!(u8 as u8: byte);
+primative_nums!(u16 as u16: unsigned);
+primative_nums!(u32 as u32: unsigned);
+primative_nums!(u64 as u64: unsigned);
+primative_nums!(u128 as u128: unsigned);
+primative_nums!(isize as i64: signed);
+primative_nums!(i8 as i8: byte);
+primative_nums!(i16 as i16: signed);
+primative_nums!(i32 as i32: signed);
+primative_nums!(i64 as i64: signed);
+primative_nums!(i128 as i128: signed);
+primative_nums!(f32 as f32: float);
+primative_nums!(f64 as f64: float);
+primative_nums!(char as u32: unsigned);
+
+impl MarshalRef for str {
+    fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+        self.marshal_ref_with::<DefaultConfig>()
+    }
+
+    fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
         let d = self.as_bytes();
-        d.len().marshal().chain(d.into_iter().cloned())
+        marshal_len::<C>(d.len()).chain(d.iter().cloned())
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.encoded_len_with::<DefaultConfig>()
+    }
+
+    fn encoded_len_with<C: Config>(&self) -> usize {
+        len_prefix_len::<C>(self.len()) + self.len()
+    }
+
+    fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.marshal_ref_into_with::<DefaultConfig, W>(w)
+    }
+
+    fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(marshal_len::<C>(self.len()).as_slice())?;
+        w.write_all(self.as_bytes())
     }
 }
 
 impl Marshal for String {
     fn marshal(self) -> impl Iterator<Item = u8> {
+        self.marshal_with::<DefaultConfig>()
+    }
+
+    fn marshal_with<C: Config>(self) -> impl Iterator<Item = u8> {
         let d = self.into_bytes();
-        d.len().marshal().chain(d.into_iter())
+        marshal_len::<C>(d.len()).chain(d)
+    }
+
+    fn encoded_len_hint_with<C: Config>(&self) -> Option<usize> {
+        Some(self.encoded_len_with::<C>())
+    }
+
+    fn marshal_into<W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.as_str().marshal_ref_into(w)
+    }
+
+    fn marshal_into_with<C: Config, W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.as_str().marshal_ref_into_with::<C, W>(w)
+    }
+}
+
+impl MarshalRef for String {
+    fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+        self.as_str().marshal_ref()
+    }
+
+    fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
+        self.as_str().marshal_ref_with::<C>()
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.as_str().encoded_len()
+    }
+
+    fn encoded_len_with<C: Config>(&self) -> usize {
+        self.as_str().encoded_len_with::<C>()
+    }
+
+    fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.as_str().marshal_ref_into(w)
+    }
+
+    fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.as_str().marshal_ref_into_with::<C, W>(w)
+    }
+}
+
+/// Written the same as `T`, so `Cow<str>` reads as a `String` or a borrowed `&str`
+impl<T> Marshal for Cow<'_, T>
+where
+    T: MarshalRef + ToOwned + ?Sized,
+    T::Owned: Marshal,
+{
+    fn marshal(self) -> impl Iterator<Item = u8> {
+        self.marshal_with::<DefaultConfig>()
+    }
+
+    fn marshal_with<C: Config>(self) -> impl Iterator<Item = u8> {
+        match self {
+            Cow::Borrowed(v) => Either::Left(v.marshal_ref_with::<C>()),
+            Cow::Owned(v) => Either::Right(v.marshal_with::<C>()),
+        }
+    }
+
+    // Owned or not, it's written the same as `T`
+    fn encoded_len_hint_with<C: Config>(&self) -> Option<usize> {
+        Some((**self).encoded_len_with::<C>())
+    }
+
+    fn marshal_into<W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.marshal_into_with::<DefaultConfig, W>(w)
+    }
+
+    fn marshal_into_with<C: Config, W: Write>(self, w: &mut W) -> io::Result<()> {
+        match self {
+            Cow::Borrowed(v) => v.marshal_ref_into_with::<C, W>(w),
+            Cow::Owned(v) => v.marshal_into_with::<C, W>(w),
+        }
+    }
+}
+
+impl<T: MarshalRef + ToOwned + ?Sized> MarshalRef for Cow<'_, T> {
+    fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+        (**self).marshal_ref()
+    }
+
+    fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
+        (**self).marshal_ref_with::<C>()
+    }
+
+    fn encoded_len(&self) -> usize {
+        (**self).encoded_len()
+    }
+
+    fn encoded_len_with<C: Config>(&self) -> usize {
+        (**self).encoded_len_with::<C>()
+    }
+
+    fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (**self).marshal_ref_into(w)
+    }
+
+    fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+        (**self).marshal_ref_into_with::<C, W>(w)
     }
 }
 
@@ -111,75 +587,350 @@ impl<T> Marshal for PhantomData<T> {
     fn marshal(self) -> impl Iterator<Item = u8> {
         std::iter::empty()
     }
+
+    fn encoded_len_hint_with<C: Config>(&self) -> Option<usize> {
+        Some(0)
+    }
+
+    fn marshal_into<W: Write>(self, _w: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<T> MarshalRef for PhantomData<T> {
+    fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+        std::iter::empty()
+    }
+
+    fn encoded_len(&self) -> usize {
+        0
+    }
+
+    fn encoded_len_with<C: Config>(&self) -> usize {
+        0
+    }
+
+    fn marshal_ref_into<W: Write>(&self, _w: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<T> UnMarshal for PhantomData<T> {
+    fn unmarshal(_data: &mut impl Iterator<Item = u8>) -> Result<Self, MarshalError> {
+        Ok(PhantomData)
+    }
+
+    fn unmarshal_from_with<C: Config>(_data: &mut impl ByteSource) -> Result<Self, MarshalError> {
+        Ok(PhantomData)
+    }
+}
+
+impl<T> IncrementalUnMarshal for PhantomData<T> {
+    type Decoder = PhantomDataDecoder<T>;
 }
 
 impl UnMarshal for String {
     fn unmarshal(data: &mut impl Iterator<Item = u8>) -> Result<Self, MarshalError> {
-        let len = usize::unmarshal(data)?;
-        let d = match readn_to_vec(data, len) {
-            Ok(v) => v,
-            Err(e) => Err(MarshalError::InvalidSizedDecode(e))?,
-        };
-        Ok(String::from_utf8(d)?)
+        Self::unmarshal_with::<DefaultConfig>(data)
+    }
+
+    fn unmarshal_with<C: Config>(
+        data: &mut impl Iterator<Item = u8>,
+    ) -> Result<Self, MarshalError> {
+        Self::unmarshal_from_with::<C>(&mut IterSource::new(data))
+    }
+
+    fn unmarshal_from_with<C: Config>(data: &mut impl ByteSource) -> Result<Self, MarshalError> {
+        let _counted = Counted::enter::<C>();
+        let len = unmarshal_len::<C>(data)?;
+        let len = C::LIMITS.check_string_len(len)?;
+        Ok(String::from_utf8(readn_to_vec::<C>(data, len)?)?)
     }
 }
 
-impl<T: Marshal + Clone> Marshal for &[T] {
-    fn marshal(self) -> impl Iterator<Item = u8> {
+impl IncrementalUnMarshal for String {
+    type Decoder = StringDecoder;
+}
+
+impl<T: MarshalRef> MarshalRef for [T] {
+    fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+        self.marshal_ref_with::<DefaultConfig>()
+    }
+
+    fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
         let len = self.len();
-        let d = self.into_iter().cloned().map(|v| v.marshal()).flatten();
-        len.marshal().chain(d)
+        let d = self.iter().flat_map(|v| v.marshal_ref_with::<C>());
+        KnownLen::new(marshal_len::<C>(len).chain(d), || {
+            self.encoded_len_with::<C>()
+        })
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.encoded_len_with::<DefaultConfig>()
+    }
+
+    fn encoded_len_with<C: Config>(&self) -> usize {
+        let items = self
+            .iter()
+            .map(|v| v.encoded_len_with::<C>())
+            .sum::<usize>();
+        len_prefix_len::<C>(self.len()) + items
+    }
+
+    fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.marshal_ref_into_with::<DefaultConfig, W>(w)
+    }
+
+    fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(marshal_len::<C>(self.len()).as_slice())?;
+        T::marshal_slice_into_with::<C, W>(self, w)
     }
 }
 
 impl Marshal for Box<[u8]> {
     fn marshal(self) -> impl Iterator<Item = u8> {
+        self.marshal_with::<DefaultConfig>()
+    }
+
+    fn marshal_with<C: Config>(self) -> impl Iterator<Item = u8> {
         let len = self.len();
-        len.marshal().chain(IntoIterator::into_iter(self))
+        marshal_len::<C>(len).chain(IntoIterator::into_iter(self))
+    }
+
+    fn encoded_len_hint_with<C: Config>(&self) -> Option<usize> {
+        Some(self.encoded_len_with::<C>())
+    }
+
+    fn marshal_into<W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.marshal_ref_into(w)
+    }
+
+    fn marshal_into_with<C: Config, W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.marshal_ref_into_with::<C, W>(w)
+    }
+}
+
+impl MarshalRef for Box<[u8]> {
+    fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+        self.marshal_ref_with::<DefaultConfig>()
+    }
+
+    fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
+        let len = self.len();
+        marshal_len::<C>(len).chain(self.iter().cloned())
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.encoded_len_with::<DefaultConfig>()
+    }
+
+    fn encoded_len_with<C: Config>(&self) -> usize {
+        len_prefix_len::<C>(self.len()) + self.len()
+    }
+
+    fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.marshal_ref_into_with::<DefaultConfig, W>(w)
+    }
+
+    fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(marshal_len::<C>(self.len()).as_slice())?;
+        w.write_all(self)
     }
 }
 
 impl UnMarshal for Box<[u8]> {
     fn unmarshal(data: &mut impl Iterator<Item = u8>) -> Result<Self, MarshalError> {
-        let len = usize::unmarshal(data)?;
-        let d = match readn_to_vec(data, len) {
-            Ok(v) => v,
-            Err(e) => Err(MarshalError::InvalidSizedDecode(e))?,
-        };
-        Ok(d.into_boxed_slice())
+        Self::unmarshal_with::<DefaultConfig>(data)
+    }
+
+    fn unmarshal_with<C: Config>(
+        data: &mut impl Iterator<Item = u8>,
+    ) -> Result<Self, MarshalError> {
+        Self::unmarshal_from_with::<C>(&mut IterSource::new(data))
+    }
+
+    fn unmarshal_from_with<C: Config>(data: &mut impl ByteSource) -> Result<Self, MarshalError> {
+        let _counted = Counted::enter::<C>();
+        let len = unmarshal_len::<C>(data)?;
+        let len = C::LIMITS.check_collection_len(len)?;
+        Ok(readn_to_vec::<C>(data, len)?.into_boxed_slice())
     }
 }
 
+impl IncrementalUnMarshal for Box<[u8]> {
+    type Decoder = BoxedBytesDecoder;
+}
+
 impl<T: Marshal> Marshal for Vec<T> {
     fn marshal(self) -> impl Iterator<Item = u8> {
-        let len = self.len();
-        let d = self.into_iter().map(|v| v.marshal()).flatten();
-        len.marshal().chain(d)
+        self.marshal_with::<DefaultConfig>()
+    }
+
+    fn marshal_with<C: Config>(self) -> impl Iterator<Item = u8> {
+        let len = marshal_len::<C>(self.len());
+        OwnedLen::new(len, self, Self::encoded_len_hint_with::<C>, |items| {
+            items.into_iter().flat_map(|v| v.marshal_with::<C>())
+        })
+    }
+
+    fn encoded_len_hint_with<C: Config>(&self) -> Option<usize> {
+        let items = self
+            .iter()
+            .map(|v| v.encoded_len_hint_with::<C>())
+            .sum::<Option<usize>>()?;
+        Some(len_prefix_len::<C>(self.len()) + items)
+    }
+
+    fn marshal_into<W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.marshal_into_with::<DefaultConfig, W>(w)
+    }
+
+    fn marshal_into_with<C: Config, W: Write>(self, w: &mut W) -> io::Result<()> {
+        w.write_all(marshal_len::<C>(self.len()).as_slice())?;
+        T::marshal_vec_into_with::<C, W>(self, w)
+    }
+}
+
+impl<T: MarshalRef> MarshalRef for Vec<T> {
+    fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+        self.as_slice().marshal_ref()
+    }
+
+    fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
+        self.as_slice().marshal_ref_with::<C>()
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.as_slice().encoded_len()
+    }
+
+    fn encoded_len_with<C: Config>(&self) -> usize {
+        self.as_slice().encoded_len_with::<C>()
+    }
+
+    fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.as_slice().marshal_ref_into(w)
+    }
+
+    fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.as_slice().marshal_ref_into_with::<C, W>(w)
     }
 }
 
 impl<T: UnMarshal> UnMarshal for Vec<T> {
     fn unmarshal(data: &mut impl Iterator<Item = u8>) -> Result<Self, MarshalError> {
-        let len = usize::unmarshal(data)?;
-        let val = (0..len)
-            .map(|_| T::unmarshal(data))
-            .collect::<Result<Vec<_>, _>>()?;
+        Self::unmarshal_with::<DefaultConfig>(data)
+    }
+
+    fn unmarshal_with<C: Config>(
+        data: &mut impl Iterator<Item = u8>,
+    ) -> Result<Self, MarshalError> {
+        Self::unmarshal_from_with::<C>(&mut IterSource::new(data))
+    }
+
+    fn unmarshal_from_with<C: Config>(data: &mut impl ByteSource) -> Result<Self, MarshalError> {
+        let start = position(data);
+        let _nested = Nested::enter::<C>().map_err(|e| e.within("Vec", &[], start, start))?;
+        let len = unmarshal_len::<C>(data)
+            .and_then(|len| C::LIMITS.check_collection_len(len))
+            .map_err(|e| e.within("Vec", &[], start, position(data)))?;
+        let mut val = Vec::new();
+        T::unmarshal_vec_from_with::<C>(data, len, &mut val).map_err(|e| {
+            e.within(
+                "Vec",
+                &[PathSegment::Index(val.len())],
+                start,
+                position(data),
+            )
+        })?;
         Ok(val)
     }
 }
 
+impl<T: IncrementalUnMarshal> IncrementalUnMarshal for Vec<T> {
+    type Decoder = NestedDecoder<VecDecoder<T>>;
+}
+
 impl<K, V> Marshal for HashMap<K, V>
 where
     K: Marshal,
     V: Marshal,
 {
     fn marshal(self) -> impl Iterator<Item = u8> {
+        self.marshal_with::<DefaultConfig>()
+    }
+
+    fn marshal_with<C: Config>(self) -> impl Iterator<Item = u8> {
+        let len = marshal_len::<C>(self.len());
+        OwnedLen::new(len, self, Self::encoded_len_hint_with::<C>, |entries| {
+            entries
+                .into_iter()
+                .flat_map(|(k, v)| k.marshal_with::<C>().chain(v.marshal_with::<C>()))
+        })
+    }
+
+    fn encoded_len_hint_with<C: Config>(&self) -> Option<usize> {
+        let entries = self
+            .iter()
+            .map(|(k, v)| Some(k.encoded_len_hint_with::<C>()? + v.encoded_len_hint_with::<C>()?))
+            .sum::<Option<usize>>()?;
+        Some(len_prefix_len::<C>(self.len()) + entries)
+    }
+
+    fn marshal_into<W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.marshal_into_with::<DefaultConfig, W>(w)
+    }
+
+    fn marshal_into_with<C: Config, W: Write>(self, w: &mut W) -> io::Result<()> {
+        w.write_all(marshal_len::<C>(self.len()).as_slice())?;
+        self.into_iter().try_for_each(|(k, v)| {
+            k.marshal_into_with::<C, W>(w)?;
+            v.marshal_into_with::<C, W>(w)
+        })
+    }
+}
+
+impl<K, V> MarshalRef for HashMap<K, V>
+where
+    K: MarshalRef,
+    V: MarshalRef,
+{
+    fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+        self.marshal_ref_with::<DefaultConfig>()
+    }
+
+    fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
         let len = self.len();
         let data = self
-            .into_iter()
-            .map(|(k, v)| k.marshal().chain(v.marshal()))
-            .flatten();
-        len.marshal().chain(data)
+            .iter()
+            .flat_map(|(k, v)| k.marshal_ref_with::<C>().chain(v.marshal_ref_with::<C>()));
+        KnownLen::new(marshal_len::<C>(len).chain(data), || {
+            self.encoded_len_with::<C>()
+        })
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.encoded_len_with::<DefaultConfig>()
+    }
+
+    fn encoded_len_with<C: Config>(&self) -> usize {
+        let entries = self
+            .iter()
+            .map(|(k, v)| k.encoded_len_with::<C>() + v.encoded_len_with::<C>())
+            .sum::<usize>();
+        len_prefix_len::<C>(self.len()) + entries
+    }
+
+    fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.marshal_ref_into_with::<DefaultConfig, W>(w)
+    }
+
+    fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(marshal_len::<C>(self.len()).as_slice())?;
+        self.iter().try_for_each(|(k, v)| {
+            k.marshal_ref_into_with::<C, W>(w)?;
+            v.marshal_ref_into_with::<C, W>(w)
+        })
     }
 }
 
@@ -189,38 +940,138 @@ where
     V: UnMarshal + Debug,
 {
     fn unmarshal(data: &mut impl Iterator<Item = u8>) -> Result<Self, MarshalError> {
-        let len = usize::unmarshal(data)?;
-        let mut val = Self::with_capacity(len);
-        for _ in 0..len {
-            let key = K::unmarshal(data)?;
-            let value = V::unmarshal(data)?;
-            match val.insert(key, value) {
-                Some(a) => Err(MarshalError::InvalidData(format!(
+        Self::unmarshal_with::<DefaultConfig>(data)
+    }
+
+    fn unmarshal_with<C: Config>(
+        data: &mut impl Iterator<Item = u8>,
+    ) -> Result<Self, MarshalError> {
+        Self::unmarshal_from_with::<C>(&mut IterSource::new(data))
+    }
+
+    fn unmarshal_from_with<C: Config>(data: &mut impl ByteSource) -> Result<Self, MarshalError> {
+        let start = position(data);
+        let _nested = Nested::enter::<C>().map_err(|e| e.within("HashMap", &[], start, start))?;
+        let len = unmarshal_len::<C>(data)
+            .and_then(|len| C::LIMITS.check_collection_len(len))
+            .map_err(|e| e.within("HashMap", &[], start, position(data)))?;
+        // The length prefix can't be trusted, so let the map grow as entries actually arrive
+        let mut val = Self::new();
+        for i in 0..len {
+            let key = K::unmarshal_from_with::<C>(data)
+                .map_err(|e| e.within("HashMap", &[PathSegment::Key(i)], start, position(data)))?;
+            let value = V::unmarshal_from_with::<C>(data).map_err(|e| {
+                e.within("HashMap", &[PathSegment::Value(i)], start, position(data))
+            })?;
+            if let Some(a) = val.insert(key, value) {
+                Err(MarshalError::InvalidData(format!(
                     "Duplicate Key while decoding HashMap: {a:#?}"
-                )))?,
-                None => (),
-            };
+                ))
+                .within("HashMap", &[PathSegment::Key(i)], start, position(data)))?
+            }
         }
         Ok(val)
     }
 }
 
+impl<K, V> IncrementalUnMarshal for HashMap<K, V>
+where
+    K: IncrementalUnMarshal + Hash + Eq,
+    V: IncrementalUnMarshal + Debug,
+{
+    type Decoder = NestedDecoder<HashMapDecoder<K, V>>;
+}
+
 impl<T: Marshal> Marshal for Option<T> {
     fn marshal(self) -> impl Iterator<Item = u8> {
+        self.marshal_with::<DefaultConfig>()
+    }
+
+    fn marshal_with<C: Config>(self) -> impl Iterator<Item = u8> {
         match self {
-            Some(v) => Either::Left(1u8.marshal().chain(v.marshal())),
+            Some(v) => Either::Left(1u8.marshal().chain(v.marshal_with::<C>())),
             None => Either::Right(0u8.marshal().chain(std::iter::empty())),
         }
     }
+
+    fn encoded_len_hint_with<C: Config>(&self) -> Option<usize> {
+        match self {
+            Some(v) => v.encoded_len_hint_with::<C>().map(|len| 1 + len),
+            None => Some(1),
+        }
+    }
+
+    fn marshal_into<W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.marshal_into_with::<DefaultConfig, W>(w)
+    }
+
+    fn marshal_into_with<C: Config, W: Write>(self, w: &mut W) -> io::Result<()> {
+        match self {
+            Some(v) => {
+                w.write_all(&[1])?;
+                v.marshal_into_with::<C, W>(w)
+            }
+            None => w.write_all(&[0]),
+        }
+    }
+}
+
+impl<T: MarshalRef> MarshalRef for Option<T> {
+    fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+        self.marshal_ref_with::<DefaultConfig>()
+    }
+
+    fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
+        match self {
+            Some(v) => Either::Left(1u8.marshal().chain(v.marshal_ref_with::<C>())),
+            None => Either::Right(0u8.marshal().chain(std::iter::empty())),
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.encoded_len_with::<DefaultConfig>()
+    }
+
+    fn encoded_len_with<C: Config>(&self) -> usize {
+        match self {
+            Some(v) => 1 + v.encoded_len_with::<C>(),
+            None => 1,
+        }
+    }
+
+    fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.marshal_ref_into_with::<DefaultConfig, W>(w)
+    }
+
+    fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+        match self {
+            Some(v) => {
+                w.write_all(&[1])?;
+                v.marshal_ref_into_with::<C, W>(w)
+            }
+            None => w.write_all(&[0]),
+        }
+    }
 }
 
 impl<T: UnMarshal> UnMarshal for Option<T> {
     fn unmarshal(data: &mut impl Iterator<Item = u8>) -> Result<Self, MarshalError> {
-        let variant = u8::unmarshal(data)?;
+        Self::unmarshal_with::<DefaultConfig>(data)
+    }
+
+    fn unmarshal_with<C: Config>(
+        data: &mut impl Iterator<Item = u8>,
+    ) -> Result<Self, MarshalError> {
+        Self::unmarshal_from_with::<C>(&mut IterSource::new(data))
+    }
+
+    fn unmarshal_from_with<C: Config>(data: &mut impl ByteSource) -> Result<Self, MarshalError> {
+        let _counted = Counted::enter::<C>();
+        let variant = u8::unmarshal_from_with::<C>(data)?;
 
         Ok(match variant {
             0 => None,
-            1 => Some(T::unmarshal(data)?),
+            1 => Some(T::unmarshal_from_with::<C>(data)?),
             other => Err(MarshalError::InvalidData(format!(
                 "Found '{other}' when unmarshalling the option. Should be either 0 or 1"
             )))?,
@@ -228,22 +1079,29 @@ impl<T: UnMarshal> UnMarshal for Option<T> {
     }
 }
 
+impl<T: IncrementalUnMarshal> IncrementalUnMarshal for Option<T> {
+    type Decoder = OptionDecoder<T>;
+}
+
 #[cfg(feature = "tuples")]
 mod tuples {
-    use super::{Marshal, MarshalError, UnMarshal};
+    use super::{
+        io, ByteSource, Config, Counted, Decoded, DefaultConfig, IncrementalUnMarshal, IterSource,
+        Marshal, MarshalError, MarshalRef, PushDecoder, UnMarshal, Write,
+    };
 
     macro_rules! tuple_marshal_inner {
-        ($self:ident) => {};
-        ($self:ident, $first:tt, $($rest:tt,)+) => {
-            $self.$first.marshal().chain(tuple_marshal_inner!($self, $($rest,)+))
+        ($self:ident, $method:ident) => {};
+        ($self:ident, $method:ident, $first:tt, $($rest:tt,)+) => {
+            $self.$first.$method::<C>().chain(tuple_marshal_inner!($self, $method, $($rest,)+))
         };
-        ($self:ident, $first:tt,) => {
-            $self.$first.marshal()
+        ($self:ident, $method:ident, $first:tt,) => {
+            $self.$first.$method::<C>()
         };
     }
 
     macro_rules! tuple_impl {
-        ($([$($n:tt),+])*) => {
+        ($($decoder:ident[$($n:tt),+])*) => {
             $(
                 paste::item! {
                     #[cfg_attr(docsrs, doc(hidden))]
@@ -252,7 +1110,56 @@ mod tuples {
                     $([<T $n>]: Marshal,)+
                     {
                         fn marshal(self) -> impl Iterator<Item = u8> {
-                            tuple_marshal_inner!(self, $($n,)*)
+                            self.marshal_with::<DefaultConfig>()
+                        }
+
+                        fn marshal_with<C: Config>(self) -> impl Iterator<Item = u8> {
+                            tuple_marshal_inner!(self, marshal_with, $($n,)*)
+                        }
+
+                        fn encoded_len_hint_with<C: Config>(&self) -> Option<usize> {
+                            Some(0 $(+ self.$n.encoded_len_hint_with::<C>()?)+)
+                        }
+
+                        fn marshal_into<W: Write>(self, w: &mut W) -> io::Result<()> {
+                            self.marshal_into_with::<DefaultConfig, W>(w)
+                        }
+
+                        fn marshal_into_with<C: Config, W: Write>(self, w: &mut W) -> io::Result<()> {
+                            $(self.$n.marshal_into_with::<C, W>(w)?;)+
+                            Ok(())
+                        }
+                    }
+                }
+                paste::item! {
+                    #[cfg_attr(docsrs, doc(hidden))]
+                    impl<$([<T $n>]),+> MarshalRef for ($([<T $n>],)+)
+                    where
+                    $([<T $n>]: MarshalRef,)+
+                    {
+                        fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+                            self.marshal_ref_with::<DefaultConfig>()
+                        }
+
+                        fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
+                            tuple_marshal_inner!(self, marshal_ref_with, $($n,)*)
+                        }
+
+                        fn encoded_len(&self) -> usize {
+                            self.encoded_len_with::<DefaultConfig>()
+                        }
+
+                        fn encoded_len_with<C: Config>(&self) -> usize {
+                            0 $(+ self.$n.encoded_len_with::<C>())+
+                        }
+
+                        fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+                            self.marshal_ref_into_with::<DefaultConfig, W>(w)
+                        }
+
+                        fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+                            $(self.$n.marshal_ref_into_with::<C, W>(w)?;)+
+                            Ok(())
                         }
                     }
                 }
@@ -263,25 +1170,94 @@ mod tuples {
                     $([<T $n>]: UnMarshal,)+
                     {
                         fn unmarshal(data: &mut impl Iterator<Item = u8>) -> Result<Self, MarshalError> {
+                            Self::unmarshal_with::<DefaultConfig>(data)
+                        }
+
+                        fn unmarshal_with<C: Config>(
+                            data: &mut impl Iterator<Item = u8>,
+                        ) -> Result<Self, MarshalError> {
+                            Self::unmarshal_from_with::<C>(&mut IterSource::new(data))
+                        }
+
+                        fn unmarshal_from_with<C: Config>(data: &mut impl ByteSource) -> Result<Self, MarshalError> {
+                            let _counted = Counted::enter::<C>();
                             Ok(($(
-                                [<T $n>]::unmarshal(data)?,
+                                [<T $n>]::unmarshal_from_with::<C>(data)?,
                             )+))
                         }
                     }
                 }
+                paste::item! {
+                    pub struct $decoder<$([<T $n>]),+>
+                    where
+                    $([<T $n>]: IncrementalUnMarshal,)+
+                    {
+                        stage: usize,
+                        decoders: ($([<T $n>]::Decoder,)+),
+                        values: ($(Option<[<T $n>]>,)+),
+                    }
+
+                    impl<$([<T $n>]),+> Default for $decoder<$([<T $n>]),+>
+                    where
+                    $([<T $n>]: IncrementalUnMarshal,)+
+                    {
+                        fn default() -> Self {
+                            Self {
+                                stage: 0,
+                                decoders: Default::default(),
+                                values: Default::default(),
+                            }
+                        }
+                    }
+
+                    impl<$([<T $n>]),+> PushDecoder for $decoder<$([<T $n>]),+>
+                    where
+                    $([<T $n>]: IncrementalUnMarshal,)+
+                    {
+                        type Output = ($([<T $n>],)+);
+
+                        fn feed(&mut self, data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError> {
+                            self.feed_with::<DefaultConfig>(data)
+                        }
+
+                        fn feed_with<C: Config>(&mut self, data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError> {
+                            $(
+                                if self.stage == $n {
+                                    match self.decoders.$n.feed_with::<C>(data)? {
+                                        Decoded::Done(v) => {
+                                            self.values.$n = Some(v);
+                                            self.stage += 1;
+                                        }
+                                        Decoded::NeedMore(n) => return Ok(Decoded::NeedMore(n)),
+                                    }
+                                }
+                            )+
+                            self.stage = 0;
+                            Ok(Decoded::Done(($(self.values.$n.take().unwrap(),)+)))
+                        }
+                    }
+
+                    #[cfg_attr(docsrs, doc(hidden))]
+                    impl<$([<T $n>]),+> IncrementalUnMarshal for ($([<T $n>],)+)
+                    where
+                    $([<T $n>]: IncrementalUnMarshal,)+
+                    {
+                        type Decoder = $decoder<$([<T $n>]),+>;
+                    }
+                }
             )*
         };
     }
 
     tuple_impl!(
-        [0]
-        [0, 1]
-        [0, 1, 2]
-        [0, 1, 2, 3]
-        [0, 1, 2, 3, 4]
-        [0, 1, 2, 3, 4, 5]
-        [0, 1, 2, 3, 4, 5, 6]
-        [0, 1, 2, 3, 4, 5, 6, 7]
+        Tuple1Decoder[0]
+        Tuple2Decoder[0, 1]
+        Tuple3Decoder[0, 1, 2]
+        Tuple4Decoder[0, 1, 2, 3]
+        Tuple5Decoder[0, 1, 2, 3, 4]
+        Tuple6Decoder[0, 1, 2, 3, 4, 5]
+        Tuple7Decoder[0, 1, 2, 3, 4, 5, 6]
+        Tuple8Decoder[0, 1, 2, 3, 4, 5, 6, 7]
     );
 }
 
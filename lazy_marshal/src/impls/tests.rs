@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{cell::Cell, collections::HashMap};
 
 use crate::prelude::*;
 
@@ -66,6 +66,7 @@ fn test_vec() {
 }
 
 #[test]
+#[allow(clippy::useless_format)]
 fn test_option() {
     let d = Some(format!("Tesing"));
     let mut m = d.clone().marshal();
@@ -99,6 +100,7 @@ struct Deal {
 }
 
 #[test]
+#[allow(clippy::partialeq_to_none)]
 fn test_hashmap() {
     let s1 = Salesman {
         id: 1,
@@ -148,3 +150,372 @@ fn test_hashmap() {
     assert!(hmap == new_hmap);
     assert!(iter.next() == None);
 }
+
+#[test]
+fn test_hashmap_ref() {
+    let salesman = Salesman {
+        id: 1,
+        name: "John Smith".to_string(),
+        email: "abc@company.com".to_string(),
+    };
+    let deal = Deal {
+        id: 1,
+        name: "Corp 1".to_string(),
+        salesman: salesman.clone(),
+    };
+    let hmap = HashMap::from([(salesman, vec![deal])]);
+
+    let mut iter = hmap.marshal_ref();
+    let new_hmap = HashMap::<Salesman, Vec<Deal>>::unmarshal(&mut iter).unwrap();
+
+    assert_eq!(hmap, new_hmap);
+    assert!(iter.next().is_none());
+    assert!(hmap.marshal_ref().eq(hmap.clone().marshal()));
+}
+
+#[test]
+fn test_marshal_ref() {
+    let s = "Borrowed 🦀".to_string();
+    assert!(s.marshal_ref().eq(s.clone().marshal()));
+    assert!((&s).marshal().eq(s.as_str().marshal()));
+
+    let v = vec![Some(1u64), None, Some(u64::MAX)];
+    assert!(v.marshal_ref().eq(v.clone().marshal()));
+    assert!(v.as_slice().marshal().eq(v.clone().marshal()));
+
+    let b: Box<[u8]> = vec![1, 2, 3].into_boxed_slice();
+    assert!(b.marshal_ref().eq(b.clone().marshal()));
+
+    let t = (true, 'c', -5i16);
+    assert!(t.marshal_ref().eq(t.marshal()));
+}
+
+/// Push `bytes` into a fresh decoder `chunk` bytes at a time, checking that the decoder only
+/// finishes on the last chunk
+fn feed_in_chunks<T: IncrementalUnMarshal>(bytes: &[u8], chunk: usize) -> T {
+    let mut decoder = T::decoder();
+    let mut chunks = bytes.chunks(chunk).peekable();
+    while let Some(mut c) = chunks.next() {
+        match decoder.feed(&mut c).unwrap() {
+            Decoded::NeedMore(n) => {
+                assert!(c.is_empty());
+                assert!(n <= chunks.clone().map(<[u8]>::len).sum());
+            }
+            Decoded::Done(v) => {
+                assert!(c.is_empty() && chunks.peek().is_none());
+                return v;
+            }
+        }
+    }
+    panic!("Decoder never finished")
+}
+
+#[test]
+fn test_incremental() {
+    let s = "Pushed one byte at a time 🐢".to_string();
+    let bytes = s.marshal_ref().collect::<Vec<_>>();
+    for chunk in [1, 3, bytes.len()] {
+        assert_eq!(feed_in_chunks::<String>(&bytes, chunk), s);
+    }
+
+    let v = vec![Some(4u32), None, Some(u32::MAX)];
+    let bytes = v.marshal_ref().collect::<Vec<_>>();
+    for chunk in [1, 2, 5] {
+        assert_eq!(feed_in_chunks::<Vec<Option<u32>>>(&bytes, chunk), v);
+    }
+
+    let t = (true, 'x', -12i64, Box::<[u8]>::from([1, 2, 3]));
+    let bytes = t.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(feed_in_chunks::<(bool, char, i64, Box<[u8]>)>(&bytes, 1), t);
+
+    let mut hmap = HashMap::new();
+    hmap.insert(
+        Salesman {
+            id: 7,
+            name: "Kim".to_string(),
+            email: "kim@company.com".to_string(),
+        },
+        vec![0u16, 1, 2],
+    );
+    let bytes = hmap.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(
+        feed_in_chunks::<HashMap<Salesman, Vec<u16>>>(&bytes, 1),
+        hmap
+    );
+}
+
+#[test]
+fn test_incremental_reuse() {
+    let bytes = [1u32, 2, 3]
+        .iter()
+        .flat_map(|n| n.marshal_ref())
+        .collect::<Vec<_>>();
+
+    let mut data = &bytes[..];
+    let mut decoder = u32::decoder();
+    for expected in 1..=3 {
+        assert_eq!(decoder.feed(&mut data).unwrap(), Decoded::Done(expected));
+    }
+    assert_eq!(decoder.feed(&mut data).unwrap(), Decoded::NeedMore(4));
+
+    let mut data = &[2u8, 1][..];
+    assert!(bool::decoder().feed(&mut data).is_err());
+    assert_eq!(data, [1]);
+}
+
+struct Compact;
+
+impl Config for Compact {
+    const ENDIAN: Endian = Endian::Big;
+    const INT_ENCODING: IntEncoding = IntEncoding::Varint;
+    const LENGTH_PREFIX: LengthPrefix = LengthPrefix::Varint;
+}
+
+struct ShortLengths;
+
+impl Config for ShortLengths {
+    const ENDIAN: Endian = Endian::Big;
+    const LENGTH_PREFIX: LengthPrefix = LengthPrefix::U16;
+}
+
+#[test]
+fn test_config() {
+    let v: Vec<u8> = vec![1, 2, 3];
+    assert_eq!(
+        v.marshal_ref_with::<Compact>().collect::<Vec<_>>(),
+        [3, 1, 2, 3]
+    );
+    assert_eq!(
+        v.marshal_ref_with::<ShortLengths>().collect::<Vec<_>>(),
+        [0, 3, 1, 2, 3]
+    );
+
+    assert_eq!(
+        300u32.marshal_with::<Compact>().collect::<Vec<_>>(),
+        [0xac, 0x02]
+    );
+    assert_eq!((-1i64).marshal_with::<Compact>().collect::<Vec<_>>(), [1]);
+    assert_eq!(
+        1.5f32.marshal_with::<Compact>().collect::<Vec<_>>(),
+        [0x3f, 0xc0, 0, 0]
+    );
+
+    let nums = (u128::MAX, i128::MIN, -300i16, usize::MAX, '🦀', 255u8, -1i8);
+    for bytes in [
+        nums.marshal_with::<Compact>().collect::<Vec<_>>(),
+        nums.marshal_with::<ShortLengths>().collect::<Vec<_>>(),
+    ] {
+        assert_ne!(bytes, nums.marshal().collect::<Vec<_>>());
+    }
+    let bytes = nums.marshal_with::<Compact>().collect::<Vec<_>>();
+    assert_eq!(
+        <(u128, i128, i16, usize, char, u8, i8)>::unmarshal_with::<Compact>(
+            &mut bytes.iter().cloned()
+        )
+        .unwrap(),
+        nums
+    );
+
+    let mut hmap = HashMap::new();
+    hmap.insert("key".to_string(), vec![Some(-5i32), None]);
+    fn round_trip<C: Config>(hmap: &HashMap<String, Vec<Option<i32>>>) {
+        let bytes = hmap.marshal_ref_with::<C>().collect::<Vec<_>>();
+        assert_eq!(
+            HashMap::unmarshal_with::<C>(&mut bytes.iter().cloned())
+                .as_ref()
+                .ok(),
+            Some(hmap)
+        );
+
+        let mut decoder = HashMap::<String, Vec<Option<i32>>>::decoder();
+        let mut decoded = None;
+        for b in &bytes {
+            if let Decoded::Done(v) = decoder.feed_with::<C>(&mut &[*b][..]).unwrap() {
+                decoded = Some(v);
+            }
+        }
+        assert_eq!(decoded.as_ref(), Some(hmap));
+    }
+    round_trip::<Compact>(&hmap);
+    round_trip::<ShortLengths>(&hmap);
+}
+
+#[test]
+fn test_config_errors() {
+    // 70000 doesn't fit in a u16
+    let bytes = 70000u32.marshal_with::<Compact>().collect::<Vec<_>>();
+    assert!(u16::unmarshal_with::<Compact>(&mut bytes.iter().cloned()).is_err());
+    assert!(u16::decoder()
+        .feed_with::<Compact>(&mut &bytes[..])
+        .is_err());
+
+    // Varint that never ends
+    let bytes = [0xff; 20];
+    assert!(u128::unmarshal_with::<Compact>(&mut bytes.iter().cloned()).is_err());
+}
+
+#[test]
+#[should_panic(expected = "doesn't fit")]
+fn test_config_length_overflow() {
+    struct Tiny;
+    impl Config for Tiny {
+        const LENGTH_PREFIX: LengthPrefix = LengthPrefix::U8;
+    }
+
+    let _ = vec![0u8; 256].marshal_with::<Tiny>().count();
+}
+
+/// Check `encoded_len_with` against the bytes actually written, which `size_hint` gives exactly
+fn check_len<T: MarshalRef + ?Sized>(value: &T) {
+    fn check<C: Config, T: MarshalRef + ?Sized>(value: &T) {
+        let mut bytes = value.marshal_ref_with::<C>();
+        let len = value.encoded_len_with::<C>();
+        assert_eq!(bytes.size_hint(), (len, Some(len)));
+        bytes.next();
+        let left = len.saturating_sub(1);
+        assert_eq!(bytes.size_hint(), (left, Some(left)));
+        assert_eq!(bytes.count(), left);
+    }
+    check::<DefaultConfig, T>(value);
+    check::<Compact, T>(value);
+    check::<ShortLengths, T>(value);
+    assert_eq!(value.encoded_len(), value.marshal_ref().count());
+}
+
+#[test]
+fn test_encoded_len() {
+    check_len(&true);
+    for n in [0u64, 127, 128, 300, u64::MAX] {
+        check_len(&n);
+        check_len(&(n as i64));
+        check_len(&VarInt(n));
+    }
+    check_len(&(u128::MAX, i128::MIN, -300i16, usize::MAX, '🦀', 255u8, -1i8));
+    check_len(&1.5f32);
+    check_len("This is a test string 😁");
+    check_len(&vec![Some(-5i32), None, Some(i32::MAX)]);
+    check_len(&vec![vec!["a".to_string()], vec![], vec!["bc".to_string()]]);
+    check_len(&vec![1u8; 200].into_boxed_slice());
+    check_len(&std::marker::PhantomData::<String>);
+
+    let mut hmap = HashMap::new();
+    hmap.insert("key".to_string(), vec![Some(-5i32), None]);
+    hmap.insert("other".to_string(), vec![]);
+    check_len(&hmap);
+
+    // Collecting allocates the exact amount up front
+    let v = vec![300u32; 100];
+    assert_eq!(v.marshal_ref().collect::<Vec<_>>().capacity(), 8 + 400);
+    assert_eq!(
+        Some("text").marshal().collect::<Vec<_>>().capacity(),
+        1 + 8 + 4
+    );
+}
+
+#[test]
+fn test_owned_size_hint() {
+    fn check<T: Marshal + MarshalRef + Clone>(value: &T) {
+        let len = value.encoded_len_with::<Compact>();
+        assert_eq!(value.encoded_len_hint_with::<Compact>(), Some(len));
+        let mut bytes = value.clone().marshal_with::<Compact>();
+        assert_eq!(bytes.size_hint(), (len, Some(len)));
+        bytes.next();
+        assert_eq!(bytes.size_hint(), (len - 1, Some(len - 1)));
+        assert_eq!(
+            value.clone().marshal().size_hint().1,
+            Some(value.encoded_len())
+        );
+    }
+
+    let v = vec!["a".to_string(), "bc".to_string()];
+    check(&v);
+    assert_eq!(v.clone().marshal().size_hint(), (27, Some(27)));
+    assert_eq!(v.marshal().collect::<Vec<_>>().capacity(), 27);
+
+    check(&vec![vec![Some(300u32), None], vec![]]);
+    check(&vec![(VarInt(300u64), -1i16, "t".to_string())]);
+
+    let mut hmap = HashMap::new();
+    hmap.insert("key".to_string(), vec![Some(-5i32), None]);
+    hmap.insert("other".to_string(), vec![]);
+    check(&hmap);
+}
+
+#[test]
+fn test_owned_size_hint_is_lazy() {
+    thread_local! {
+        static HINTS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    #[derive(Clone)]
+    struct Counted(u8);
+
+    impl Marshal for Counted {
+        fn marshal(self) -> impl Iterator<Item = u8> {
+            self.0.marshal()
+        }
+
+        fn encoded_len_hint_with<C: Config>(&self) -> Option<usize> {
+            HINTS.set(HINTS.get() + 1);
+            Some(1)
+        }
+    }
+
+    // Every element is asked once, by the outermost `Vec`, however deep it's nested
+    let nested = vec![vec![vec![Counted(1); 2]; 3]; 4];
+    let bytes = nested.clone().marshal().collect::<Vec<_>>();
+    assert_eq!(bytes.capacity(), bytes.len());
+    assert_eq!(HINTS.get(), 24);
+
+    // Once the elements have started, only the one that's being written is asked
+    HINTS.set(0);
+    let mut bytes = nested.marshal();
+    bytes.nth(8);
+    let (lower, upper) = bytes.size_hint();
+    assert_eq!(lower, 8 + 3 * (8 + 2) - 1);
+    assert_eq!(upper, None);
+    assert_eq!(HINTS.get(), 6);
+}
+
+/// Check `marshal_into` writes the same bytes as the iterators, by value and by reference
+fn check_into<T: Marshal + MarshalRef + Clone>(value: &T) {
+    fn check<C: Config, T: Marshal + MarshalRef + Clone>(value: &T) {
+        let bytes = value.marshal_ref_with::<C>().collect::<Vec<_>>();
+        let mut out = Vec::new();
+        value.marshal_ref_into_with::<C, _>(&mut out).unwrap();
+        assert_eq!(out, bytes);
+        out.clear();
+        value.clone().marshal_into_with::<C, _>(&mut out).unwrap();
+        assert_eq!(out, bytes);
+    }
+    check::<DefaultConfig, T>(value);
+    check::<Compact, T>(value);
+    check::<ShortLengths, T>(value);
+    assert_eq!(
+        value.clone().marshal_to_vec(),
+        value.marshal().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_marshal_into() {
+    check_into(&true);
+    check_into(&(u128::MAX, i128::MIN, -300i16, usize::MAX, '🦀', 255u8, -1i8));
+    check_into(&VarInt(300u32));
+    check_into(&1.5f64);
+    check_into(&"text".to_string());
+    check_into(&vec![1u8, 2, 3]);
+    check_into(&vec![-1i8, 2]);
+    check_into(&vec![Some(-5i32), None]);
+    check_into(&vec![1u8; 300].into_boxed_slice());
+    check_into(&std::marker::PhantomData::<u8>);
+    let mut hmap = HashMap::new();
+    hmap.insert("key".to_string(), vec![Some(-5i32), None]);
+    check_into(&hmap);
+
+    let mut buf = [0; 16];
+    assert_eq!("hi".marshal_to_slice(&mut buf).unwrap(), 10);
+    assert_eq!(buf[8..10], *b"hi");
+    let err = "hi".marshal_to_slice(&mut buf[..9]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::WriteZero);
+}
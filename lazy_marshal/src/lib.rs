@@ -1,14 +1,71 @@
+#[cfg(feature = "tokio")]
+mod async_io;
+mod borrowed;
+#[cfg(feature = "codec")]
+mod codec;
+mod config;
+mod decoders;
 mod error;
 mod impls;
+mod limits;
+mod seq;
+mod source;
+mod stream;
+mod tagged;
 mod traits;
 mod utils;
-pub use error::MarshalError;
+mod varint;
+mod versioned;
+mod view;
+#[cfg(feature = "tokio")]
+pub use async_io::{read_unmarshal, read_unmarshal_with, write_marshal, write_marshal_with};
+#[cfg(feature = "codec")]
+pub use codec::{FrameLength, LazyMarshalCodec};
+pub use config::*;
+pub use error::{ErrorContext, MarshalError};
+pub use limits::{DecodeLimits, Limit};
+pub use seq::{LazySeq, LazyStreamedSeq, Seq, StreamedSeq};
+pub use source::{BorrowedSource, BufReadSource, ByteSource, IterSource, ReadSource};
+pub use stream::{marshal_all, marshal_all_with, UnMarshalStream};
+pub use tagged::{skip_value, WireType};
 pub use traits::*;
+pub use varint::VarInt;
+pub use view::{VecView, VecViewIter, Viewable};
+
+// Lets the derive macros name `::lazy_marshal` from inside this crate too
+extern crate self as lazy_marshal;
+
+/// Used by the code the derive macros generate
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::decoders::{FixedBytesDecoder, NestedDecoder};
+    pub use crate::error::PathSegment;
+    pub use crate::limits::Nested;
+    pub use crate::source::{with_iter, SourceIter};
+    pub use crate::tagged::{
+        expect_wire, marshal_tagged, missing_field, read_key, skip_value_from, tagged_len,
+        write_tagged_key, TaggedDecoder,
+    };
+    pub use crate::utils::{
+        position, readn_to_vec, union_field_has_no_padding, union_is_copy, write_iter, NoPadding,
+    };
+    pub use crate::versioned::{
+        marshal_versioned, versioned_len, write_versioned, Body, BodyDecoder,
+    };
+}
 
 pub mod prelude {
+    pub use crate::config::*;
     pub use crate::error::*;
+    pub use crate::limits::{DecodeLimits, Limit};
+    pub use crate::seq::{LazySeq, LazyStreamedSeq, Seq, StreamedSeq};
+    pub use crate::source::{BorrowedSource, ByteSource};
+    pub use crate::stream::{marshal_all, marshal_all_with, UnMarshalStream};
+    pub use crate::tagged::{skip_value, WireType};
     pub use crate::traits::*;
+    pub use crate::varint::VarInt;
+    pub use crate::view::{VecView, Viewable};
 
     #[cfg(feature = "derive")]
-    pub use lazy_marshal_derive::{Marshal, UnMarshal};
+    pub use lazy_marshal_derive::{Marshal, UnMarshal, UnMarshalBorrowed, Viewable};
 }
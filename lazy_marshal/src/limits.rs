@@ -0,0 +1,251 @@
+use std::{
+    cell::Cell,
+    fmt::{self, Display},
+};
+
+use crate::{config::Config, error::MarshalError, traits::Decoded};
+
+/// Caps on how much a single unmarshal is allowed to do, so a hostile length prefix or deeply nested
+/// input can't exhaust memory or the stack. Set them with [`Config::LIMITS`](crate::Config::LIMITS);
+/// going over any of them fails with [`MarshalError::LimitExceeded`].
+///
+/// - `max_bytes` counts the bytes read while unmarshalling a value, starting again from zero for
+///   every top level value. Push decoders count the bytes fed to the outermost collection or
+///   derived type.
+/// - `max_collection_len` caps the length prefix of `Vec`s, `HashMap`s and `Box<[u8]>`s.
+/// - `max_string_len` caps the length in bytes of a `String`.
+/// - `max_depth` caps how many collections and derived types can be nested inside each other.
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// struct Untrusted;
+///
+/// impl Config for Untrusted {
+///     const LIMITS: DecodeLimits = DecodeLimits::NONE
+///         .max_collection_len(1024)
+///         .max_depth(16);
+/// }
+///
+/// // A length prefix claiming 2^64 - 1 elements
+/// let hostile = [0xff; 8];
+/// let err = Vec::<u32>::unmarshal_with::<Untrusted>(&mut hostile.into_iter()).unwrap_err();
+/// assert!(matches!(
+///     err.kind(),
+///     MarshalError::LimitExceeded { limit: Limit::CollectionLen, max: 1024 }
+/// ));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    pub max_bytes: usize,
+    pub max_collection_len: usize,
+    pub max_string_len: usize,
+    pub max_depth: usize,
+}
+
+impl DecodeLimits {
+    /// Nothing is limited
+    pub const NONE: Self = Self {
+        max_bytes: usize::MAX,
+        max_collection_len: usize::MAX,
+        max_string_len: usize::MAX,
+        max_depth: usize::MAX,
+    };
+
+    pub const fn max_bytes(mut self, max: usize) -> Self {
+        self.max_bytes = max;
+        self
+    }
+
+    pub const fn max_collection_len(mut self, max: usize) -> Self {
+        self.max_collection_len = max;
+        self
+    }
+
+    pub const fn max_string_len(mut self, max: usize) -> Self {
+        self.max_string_len = max;
+        self
+    }
+
+    pub const fn max_depth(mut self, max: usize) -> Self {
+        self.max_depth = max;
+        self
+    }
+
+    pub(crate) fn check_collection_len(&self, len: usize) -> Result<usize, MarshalError> {
+        check(Limit::CollectionLen, len, self.max_collection_len)
+    }
+
+    pub(crate) fn check_string_len(&self, len: usize) -> Result<usize, MarshalError> {
+        check(Limit::StringLen, len, self.max_string_len)
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Which of the [`DecodeLimits`] was exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Bytes,
+    CollectionLen,
+    StringLen,
+    Depth,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Bytes => "byte",
+            Self::CollectionLen => "collection length",
+            Self::StringLen => "string length",
+            Self::Depth => "nesting depth",
+        })
+    }
+}
+
+fn check(limit: Limit, value: usize, max: usize) -> Result<usize, MarshalError> {
+    match value > max {
+        true => Err(MarshalError::LimitExceeded { limit, max }),
+        false => Ok(value),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Tracked {
+    depth: usize,
+    /// How many values that count their bytes together are being unmarshalled
+    values: usize,
+    bytes: usize,
+}
+
+thread_local! {
+    static TRACKED: Cell<Tracked> = const {
+        Cell::new(Tracked {
+            depth: 0,
+            values: 0,
+            bytes: 0,
+        })
+    };
+}
+
+/// Held while a value that reads its input more than once is being unmarshalled, so everything
+/// it reads counts towards the same `max_bytes`. The count starts again from zero once the
+/// outermost one is done. Nothing is tracked unless the config sets `max_bytes`.
+pub(crate) struct Counted(bool);
+
+impl Counted {
+    pub(crate) fn enter<C: Config>() -> Self {
+        if C::LIMITS.max_bytes == usize::MAX {
+            return Self(false);
+        }
+        TRACKED.with(|t| {
+            let mut tracked = t.get();
+            tracked.values += 1;
+            t.set(tracked);
+        });
+        Self(true)
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        if !self.0 {
+            return;
+        }
+        TRACKED.with(|t| {
+            let mut tracked = t.get();
+            tracked.values -= 1;
+            if tracked.values == 0 {
+                tracked.bytes = 0;
+            }
+            t.set(tracked);
+        })
+    }
+}
+
+/// Held while a collection or derived type is being unmarshalled.
+/// Nothing is tracked unless the config sets `max_bytes` or `max_depth`.
+#[doc(hidden)]
+pub struct Nested {
+    depth: bool,
+    _counted: Counted,
+}
+
+impl Nested {
+    pub fn enter<C: Config>() -> Result<Self, MarshalError> {
+        let depth = C::LIMITS.max_depth != usize::MAX;
+        if depth {
+            TRACKED.with(|t| {
+                let mut tracked = t.get();
+                check(Limit::Depth, tracked.depth + 1, C::LIMITS.max_depth)?;
+                tracked.depth += 1;
+                t.set(tracked);
+                Ok::<_, MarshalError>(())
+            })?;
+        }
+        Ok(Self {
+            depth,
+            _counted: Counted::enter::<C>(),
+        })
+    }
+
+    /// Feed `data` to the push decoder of a collection or derived type with `feed`, checking how
+    /// deeply they're nested. The outermost one counts the bytes it's been fed, `fed` of them
+    /// before this call, and is given at most one byte more than `max_bytes` allows, so going over
+    /// it fails without reading any further.
+    pub(crate) fn feed<C: Config, T>(
+        fed: usize,
+        data: &mut &[u8],
+        feed: impl FnOnce(&mut &[u8]) -> Result<Decoded<T>, MarshalError>,
+    ) -> Result<Decoded<T>, MarshalError> {
+        let outermost = C::LIMITS.max_bytes != usize::MAX && TRACKED.with(|t| t.get().values == 0);
+        let _nested = Self::enter::<C>()?;
+        if !outermost {
+            return feed(data);
+        }
+        let left = C::LIMITS.max_bytes.saturating_sub(fed);
+        let mut given = &data[..data.len().min(left.saturating_add(1))];
+        let decoded = feed(&mut given);
+        let used = data.len().min(left.saturating_add(1)) - given.len();
+        *data = &data[used..];
+        let decoded = decoded?;
+        check(Limit::Bytes, fed + used, C::LIMITS.max_bytes)?;
+        Ok(decoded)
+    }
+}
+
+impl Drop for Nested {
+    fn drop(&mut self) {
+        if !self.depth {
+            return;
+        }
+        TRACKED.with(|t| {
+            let mut tracked = t.get();
+            tracked.depth -= 1;
+            t.set(tracked);
+        })
+    }
+}
+
+/// Count `n` bytes that are about to be read towards `max_bytes`
+pub(crate) fn consume<C: Config>(n: usize) -> Result<(), MarshalError> {
+    if C::LIMITS.max_bytes == usize::MAX {
+        return Ok(());
+    }
+    TRACKED.with(|t| {
+        let mut tracked = t.get();
+        let bytes = tracked.bytes.saturating_add(n);
+        // Outside of a value that counts its bytes together, this read is the whole value
+        if tracked.values > 0 {
+            tracked.bytes = bytes;
+            t.set(tracked);
+        }
+        check(Limit::Bytes, bytes, C::LIMITS.max_bytes).map(|_| ())
+    })
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use crate::prelude::*;
+
+struct Strict;
+
+impl Config for Strict {
+    const LIMITS: DecodeLimits = DecodeLimits::NONE
+        .max_bytes(64)
+        .max_collection_len(4)
+        .max_string_len(8)
+        .max_depth(3);
+}
+
+fn exceeded<T>(res: Result<T, MarshalError>) -> Option<Limit> {
+    match res.as_ref().map_err(MarshalError::kind) {
+        Err(MarshalError::LimitExceeded { limit, .. }) => Some(*limit),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_hostile_lengths() {
+    // Every length prefix claims u64::MAX elements, with nothing behind it
+    let hostile = [0xff; 8];
+    let mut data = hostile.iter().cloned();
+    assert_eq!(
+        exceeded(Vec::<u64>::unmarshal_with::<Strict>(&mut data)),
+        Some(Limit::CollectionLen)
+    );
+    let mut data = hostile.iter().cloned();
+    assert_eq!(
+        exceeded(HashMap::<u8, u8>::unmarshal_with::<Strict>(&mut data)),
+        Some(Limit::CollectionLen)
+    );
+    let mut data = hostile.iter().cloned();
+    assert_eq!(
+        exceeded(Box::<[u8]>::unmarshal_with::<Strict>(&mut data)),
+        Some(Limit::CollectionLen)
+    );
+    let mut data = hostile.iter().cloned();
+    assert_eq!(
+        exceeded(String::unmarshal_with::<Strict>(&mut data)),
+        Some(Limit::StringLen)
+    );
+
+    assert_eq!(
+        exceeded(Vec::<u64>::decoder().feed_with::<Strict>(&mut &hostile[..])),
+        Some(Limit::CollectionLen)
+    );
+    assert_eq!(
+        exceeded(String::decoder().feed_with::<Strict>(&mut &hostile[..])),
+        Some(Limit::StringLen)
+    );
+
+    // Without limits the prefix is only trusted as far as the input goes
+    assert!(matches!(
+        HashMap::<u8, u8>::unmarshal(&mut hostile.iter().cloned())
+            .unwrap_err()
+            .kind(),
+        MarshalError::InvalidSizedDecode(0)
+    ));
+    assert!(matches!(
+        String::unmarshal(&mut hostile.iter().cloned()),
+        Err(MarshalError::InvalidSizedDecode(0))
+    ));
+}
+
+#[test]
+fn test_limits_allow_valid_data() {
+    let v = vec![Some("12345678".to_string()), None, Some(String::new())];
+    let bytes = v.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(
+        Vec::<Option<String>>::unmarshal_with::<Strict>(&mut bytes.iter().cloned()).unwrap(),
+        v
+    );
+
+    let too_long = vec!["123456789".to_string()];
+    let bytes = too_long.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(
+        exceeded(Vec::<String>::unmarshal_with::<Strict>(
+            &mut bytes.iter().cloned()
+        )),
+        Some(Limit::StringLen)
+    );
+}
+
+#[test]
+fn test_depth_and_bytes() {
+    let nested = vec![vec![vec![1u8]]];
+    let bytes = nested.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(
+        Vec::<Vec<Vec<u8>>>::unmarshal_with::<Strict>(&mut bytes.iter().cloned()).unwrap(),
+        nested
+    );
+
+    let too_deep = vec![vec![vec![vec![1u8]]]];
+    let bytes = too_deep.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(
+        exceeded(Vec::<Vec<Vec<Vec<u8>>>>::unmarshal_with::<Strict>(
+            &mut bytes.iter().cloned()
+        )),
+        Some(Limit::Depth)
+    );
+
+    // 8 byte length + 4 * (8 byte length + 8 bytes) = 72 bytes
+    let too_big = vec!["8 bytes!".to_string(); 4];
+    let bytes = too_big.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(
+        exceeded(Vec::<String>::unmarshal_with::<Strict>(
+            &mut bytes.iter().cloned()
+        )),
+        Some(Limit::Bytes)
+    );
+
+    // The byte count starts again for every top level value
+    let fits = vec!["8 bytes!".to_string(); 3];
+    let bytes = fits.marshal_ref().collect::<Vec<_>>();
+    for _ in 0..4 {
+        assert_eq!(
+            Vec::<String>::unmarshal_with::<Strict>(&mut bytes.iter().cloned()).unwrap(),
+            fits
+        );
+    }
+}
+
+struct Small;
+
+impl Config for Small {
+    const LIMITS: DecodeLimits = DecodeLimits::NONE.max_bytes(16);
+}
+
+#[test]
+fn test_top_level_bytes() {
+    // 8 byte length + 9 bytes, counted even though nothing is nested
+    let bytes = "9 bytes!!".marshal_ref().collect::<Vec<_>>();
+    assert_eq!(
+        exceeded(String::unmarshal_with::<Small>(&mut bytes.iter().cloned())),
+        Some(Limit::Bytes)
+    );
+    assert_eq!(
+        exceeded(Box::<[u8]>::unmarshal_with::<Small>(
+            &mut bytes.iter().cloned()
+        )),
+        Some(Limit::Bytes)
+    );
+    assert_eq!(
+        exceeded(<&str>::unmarshal_borrowed_with::<Small>(&mut &bytes[..])),
+        Some(Limit::Bytes)
+    );
+
+    let bytes = "8 bytes!".marshal_ref().collect::<Vec<_>>();
+    assert_eq!(
+        String::unmarshal_with::<Small>(&mut bytes.iter().cloned()).unwrap(),
+        "8 bytes!"
+    );
+}
+
+#[test]
+fn test_push_bytes() {
+    // 8 byte length + 8 bytes
+    let fits = vec![1u8; 8];
+    let bytes = fits.marshal_ref().collect::<Vec<_>>();
+    let mut decoder = Vec::<u8>::decoder();
+    // The count is kept between feeds, and starts again once a value is done
+    for _ in 0..2 {
+        for mut chunk in bytes.chunks(3) {
+            match decoder.feed_with::<Small>(&mut chunk).unwrap() {
+                Decoded::Done(v) => assert_eq!(v, fits),
+                Decoded::NeedMore(_) => assert!(chunk.is_empty()),
+            }
+        }
+    }
+
+    let too_big = vec![vec![1u8]; 2];
+    let bytes = too_big.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(
+        exceeded(Vec::<Vec<u8>>::decoder().feed_with::<Small>(&mut &bytes[..])),
+        Some(Limit::Bytes)
+    );
+    let mut decoder = Vec::<Vec<u8>>::decoder();
+    let err = bytes
+        .chunks(1)
+        .find_map(|mut chunk| decoder.feed_with::<Small>(&mut chunk).err())
+        .unwrap();
+    assert_eq!(exceeded::<()>(Err(err)), Some(Limit::Bytes));
+
+    // Reading stops at the first byte past the limit
+    let mut data = &bytes[..];
+    let _ = Vec::<Vec<u8>>::decoder().feed_with::<Small>(&mut data);
+    assert_eq!(data.len(), bytes.len() - 17);
+}
@@ -0,0 +1,519 @@
+//! Sequences that are written and read an element at a time, without a `Vec` holding all of them.
+//! [`Seq`] and [`LazySeq`] are laid out the same as a `Vec`, so either side can use a `Vec` instead.
+//! [`StreamedSeq`] and [`LazyStreamedSeq`] are for when the number of elements isn't known up front.
+
+use std::{
+    io::{self, Write},
+    marker::PhantomData,
+};
+
+use crate::{
+    config::{Config, DefaultConfig},
+    decoders::{NestedDecoder, StreamedSeqDecoder},
+    error::{MarshalError, PathSegment},
+    limits::Nested,
+    source::{ByteSource, IterSource},
+    traits::{IncrementalUnMarshal, Marshal, MarshalRef, UnMarshal},
+    utils::{marshal_len, max_len, position, unmarshal_len},
+};
+
+/// Marshals the elements of an [`ExactSizeIterator`] like a `Vec` of them, as they come out of it.
+///
+/// It panics if the iterator doesn't have as many elements as its `len()` says, since the length
+/// written in front of them would be wrong.
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// let bytes = Seq((1..=3u16).map(|i| i * 100)).marshal_to_vec();
+/// assert_eq!(bytes, vec![100u16, 200, 300].marshal_to_vec());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Seq<I>(pub I);
+
+impl<I> Marshal for Seq<I>
+where
+    I: ExactSizeIterator,
+    I::Item: Marshal,
+{
+    fn marshal(self) -> impl Iterator<Item = u8> {
+        self.marshal_with::<DefaultConfig>()
+    }
+
+    fn marshal_with<C: Config>(self) -> impl Iterator<Item = u8> {
+        let len = self.0.len();
+        let items = Exact::new(self.0, len).flat_map(|item| item.marshal_with::<C>());
+        marshal_len::<C>(len).chain(items)
+    }
+
+    fn marshal_into<W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.marshal_into_with::<DefaultConfig, W>(w)
+    }
+
+    fn marshal_into_with<C: Config, W: Write>(self, w: &mut W) -> io::Result<()> {
+        let len = self.0.len();
+        w.write_all(marshal_len::<C>(len).as_slice())?;
+        Exact::new(self.0, len).try_for_each(|item| item.marshal_into_with::<C, W>(w))
+    }
+}
+
+/// Marshals a copy of the iterator, so it can be a field of a type that's marshalled by reference
+impl<I> MarshalRef for Seq<I>
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: Marshal,
+{
+    fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+        self.clone().marshal()
+    }
+
+    fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
+        self.clone().marshal_with::<C>()
+    }
+
+    fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.clone().marshal_into(w)
+    }
+
+    fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.clone().marshal_into_with::<C, W>(w)
+    }
+}
+
+/// The elements of an iterator that has to have `left` of them
+struct Exact<I> {
+    iter: I,
+    left: usize,
+}
+
+impl<I> Exact<I> {
+    fn new(iter: I, len: usize) -> Self {
+        Self { iter, left: len }
+    }
+}
+
+impl<I: Iterator> Iterator for Exact<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.iter.next();
+        match (&item, self.left) {
+            (Some(_), 0) => panic!("the iterator has more elements than its `len()`"),
+            (Some(_), _) => self.left -= 1,
+            (None, 0) => {}
+            (None, left) => panic!("the iterator has {left} fewer elements than its `len()`"),
+        }
+        item
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.left, Some(self.left))
+    }
+}
+
+/// Reads a sequence marshalled like a `Vec<T>` an element at a time, straight from `data`.
+///
+/// Only the length is read up front. Once every element has been read, `data` is at whatever
+/// comes after the sequence. [`LazySeq::finish()`] reads the ones that are left to get there
+/// early. Decode limits apply to each element on its own.
+///
+/// Nothing is yielded after an element fails to decode, since where the next one starts isn't known.
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// let bytes = (vec!["a", "b", "c"], 7u8).marshal_to_vec();
+/// let mut data = bytes.into_iter();
+/// let mut seq = LazySeq::<String, _>::new(&mut data).unwrap();
+///
+/// assert_eq!(seq.len(), 3);
+/// assert_eq!(seq.next().unwrap().unwrap(), "a");
+/// seq.finish().unwrap();
+/// assert_eq!(u8::unmarshal(&mut data).unwrap(), 7);
+/// ```
+pub struct LazySeq<T, I: Iterator<Item = u8>, C = DefaultConfig> {
+    data: IterSource<I>,
+    index: usize,
+    len: usize,
+    /// Where `data` was before the length, for errors
+    start: Option<usize>,
+    _marker: PhantomData<fn() -> (T, C)>,
+}
+
+impl<T, I: Iterator<Item = u8>> LazySeq<T, I> {
+    /// Read the length of a sequence from `data`, leaving its elements to be read
+    ///
+    /// # Errors
+    /// It errors when the length can't be read or is over the config's limit
+    pub fn new(data: I) -> Result<Self, MarshalError> {
+        Self::new_with::<DefaultConfig>(data)
+    }
+
+    /// [`LazySeq::new()`] with the wire format selected by `C`
+    ///
+    /// # Errors
+    /// It errors when the length can't be read or is over the config's limit
+    pub fn new_with<C: Config>(data: I) -> Result<LazySeq<T, I, C>, MarshalError> {
+        let mut data = IterSource::new(data);
+        let start = position(&data);
+        let len = unmarshal_len::<C>(&mut data)
+            .and_then(|len| C::LIMITS.check_collection_len(len))
+            .map_err(|e| e.within("LazySeq", &[], start, position(&data)))?;
+        Ok(LazySeq {
+            data,
+            index: 0,
+            len,
+            start,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<T, I: Iterator<Item = u8>, C> LazySeq<T, I, C> {
+    /// Give back `data`, after however many elements have been read
+    pub fn into_inner(self) -> I {
+        self.data.into_inner()
+    }
+}
+
+impl<T: UnMarshal, I: Iterator<Item = u8>, C: Config> LazySeq<T, I, C> {
+    /// Read the elements that are left, so `data` is at whatever comes after the sequence, and
+    /// give it back
+    ///
+    /// # Errors
+    /// It errors when one of the elements does
+    pub fn finish(mut self) -> Result<I, MarshalError> {
+        for item in &mut self {
+            item?;
+        }
+        Ok(self.into_inner())
+    }
+}
+
+impl<T: UnMarshal, I: Iterator<Item = u8>, C: Config> Iterator for LazySeq<T, I, C> {
+    type Item = Result<T, MarshalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index == self.len {
+            return None;
+        }
+        let index = self.index;
+        self.index += 1;
+        let item = T::unmarshal_from_with::<C>(&mut self.data).map_err(|e| {
+            e.within(
+                "LazySeq",
+                &[PathSegment::Index(index)],
+                self.start,
+                position(&self.data),
+            )
+        });
+        if item.is_err() {
+            self.index = self.len;
+        }
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.len - self.index;
+        (left, Some(left))
+    }
+}
+
+impl<T: UnMarshal, I: Iterator<Item = u8>, C: Config> ExactSizeIterator for LazySeq<T, I, C> {}
+
+/// How many elements go in a chunk of a [`StreamedSeq`] unless it's told otherwise
+const DEFAULT_CHUNK_LEN: usize = 1024;
+
+/// A sequence of however many elements an iterator has, for when the count isn't known up front.
+///
+/// The elements are written in chunks: the number of elements in the chunk, with the config's
+/// length prefix, then the elements themselves. An empty chunk ends the sequence. Up to
+/// [`chunk_len`](StreamedSeq::chunk_len()) elements (1024 by default) are held at a time, so the
+/// chunk's length can be written before them. The default is cut down to fit the config's length
+/// prefix, so it's 255 with [`LengthPrefix::U8`](crate::config::LengthPrefix::U8).
+///
+/// It unmarshals as a `StreamedSeq<Vec<T>>`, or an element at a time with [`LazyStreamedSeq`].
+/// Decode limits apply to the whole sequence like they do to a `Vec`.
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// let rows = (1..=5u16).filter(|i| i % 2 == 1);
+/// let bytes = StreamedSeq::new(rows).chunk_len(2).marshal_to_vec();
+///
+/// let read = StreamedSeq::<Vec<u16>>::unmarshal(&mut bytes.iter().copied()).unwrap();
+/// assert_eq!(read.into_inner(), [1, 3, 5]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct StreamedSeq<I> {
+    items: I,
+    chunk_len: Option<usize>,
+}
+
+impl<I> StreamedSeq<I> {
+    /// Write the elements of `items`, 1024 to a chunk
+    pub fn new(items: I) -> Self {
+        Self {
+            items,
+            chunk_len: None,
+        }
+    }
+
+    /// Put up to `chunk_len` elements in each chunk instead.
+    ///
+    /// # Panics
+    /// It panics if `chunk_len` is 0, since an empty chunk ends the sequence. Marshalling panics
+    /// if it doesn't fit in the config's length prefix, like more than 255 with
+    /// [`LengthPrefix::U8`](crate::config::LengthPrefix::U8).
+    #[must_use]
+    pub fn chunk_len(mut self, chunk_len: usize) -> Self {
+        assert!(chunk_len > 0, "a chunk has to have at least one element");
+        self.chunk_len = Some(chunk_len);
+        self
+    }
+
+    /// Give back the elements
+    pub fn into_inner(self) -> I {
+        self.items
+    }
+}
+
+impl<I: IntoIterator> StreamedSeq<I> {
+    /// The elements of `items` in chunks that fit `C`'s length prefix, ending with an empty one
+    fn chunks<C: Config>(self) -> impl Iterator<Item = Vec<I::Item>> {
+        let max = max_len::<C>();
+        let chunk_len = match self.chunk_len {
+            Some(chunk_len) => {
+                assert!(
+                    chunk_len <= max,
+                    "A chunk of {chunk_len} elements doesn't fit in the {:?} length prefix",
+                    C::LENGTH_PREFIX
+                );
+                chunk_len
+            }
+            None => DEFAULT_CHUNK_LEN.min(max),
+        };
+        let mut items = self.items.into_iter().fuse();
+        let mut ended = false;
+        std::iter::from_fn(move || {
+            if ended {
+                return None;
+            }
+            let chunk = items.by_ref().take(chunk_len).collect::<Vec<_>>();
+            ended = chunk.is_empty();
+            Some(chunk)
+        })
+    }
+}
+
+impl<I> Marshal for StreamedSeq<I>
+where
+    I: IntoIterator,
+    I::Item: Marshal,
+{
+    fn marshal(self) -> impl Iterator<Item = u8> {
+        self.marshal_with::<DefaultConfig>()
+    }
+
+    fn marshal_with<C: Config>(self) -> impl Iterator<Item = u8> {
+        self.chunks::<C>().flat_map(|chunk| {
+            let len = chunk.len();
+            let items = chunk.into_iter().flat_map(|item| item.marshal_with::<C>());
+            marshal_len::<C>(len).chain(items)
+        })
+    }
+
+    fn marshal_into<W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.marshal_into_with::<DefaultConfig, W>(w)
+    }
+
+    fn marshal_into_with<C: Config, W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.chunks::<C>().try_for_each(|chunk| {
+            w.write_all(marshal_len::<C>(chunk.len()).as_slice())?;
+            I::Item::marshal_vec_into_with::<C, W>(chunk, w)
+        })
+    }
+}
+
+/// Marshals a copy of the elements, so it can be a field of a type that's marshalled by reference
+impl<I> MarshalRef for StreamedSeq<I>
+where
+    I: IntoIterator + Clone,
+    I::Item: Marshal,
+{
+    fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+        self.clone().marshal()
+    }
+
+    fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
+        self.clone().marshal_with::<C>()
+    }
+
+    fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.clone().marshal_into(w)
+    }
+
+    fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.clone().marshal_into_with::<C, W>(w)
+    }
+}
+
+impl<T: UnMarshal> UnMarshal for StreamedSeq<Vec<T>> {
+    fn unmarshal(data: &mut impl Iterator<Item = u8>) -> Result<Self, MarshalError> {
+        Self::unmarshal_with::<DefaultConfig>(data)
+    }
+
+    fn unmarshal_with<C: Config>(
+        data: &mut impl Iterator<Item = u8>,
+    ) -> Result<Self, MarshalError> {
+        Self::unmarshal_from_with::<C>(&mut IterSource::new(data))
+    }
+
+    fn unmarshal_from_with<C: Config>(data: &mut impl ByteSource) -> Result<Self, MarshalError> {
+        let start = position(data);
+        let _nested =
+            Nested::enter::<C>().map_err(|e| e.within("StreamedSeq", &[], start, start))?;
+        let mut items = Vec::new();
+        loop {
+            let len = unmarshal_len::<C>(data)
+                .and_then(|len| {
+                    C::LIMITS.check_collection_len(items.len().saturating_add(len))?;
+                    Ok(len)
+                })
+                .map_err(|e| e.within("StreamedSeq", &[], start, position(data)))?;
+            if len == 0 {
+                return Ok(Self::new(items));
+            }
+            T::unmarshal_vec_from_with::<C>(data, len, &mut items).map_err(|e| {
+                e.within(
+                    "StreamedSeq",
+                    &[PathSegment::Index(items.len())],
+                    start,
+                    position(data),
+                )
+            })?;
+        }
+    }
+}
+
+impl<T: IncrementalUnMarshal> IncrementalUnMarshal for StreamedSeq<Vec<T>> {
+    type Decoder = NestedDecoder<StreamedSeqDecoder<T>>;
+}
+
+/// Reads a [`StreamedSeq`] an element at a time, straight from `data`.
+///
+/// Nothing is read up front; each chunk's length is read when its first element is. Once the
+/// iterator has ended, `data` is at whatever comes after the sequence. [`LazyStreamedSeq::finish()`]
+/// reads the elements that are left to get there early. Decode limits apply to each chunk and
+/// element on its own.
+///
+/// Nothing is yielded after an error, since where the next element starts isn't known.
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// let words = ["a", "b", "c"].into_iter().map(String::from);
+/// let bytes = (StreamedSeq::new(words), 7u8).marshal_to_vec();
+/// let mut data = bytes.into_iter();
+/// let mut seq = LazyStreamedSeq::<String, _>::new(&mut data);
+///
+/// assert_eq!(seq.next().unwrap().unwrap(), "a");
+/// seq.finish().unwrap();
+/// assert_eq!(u8::unmarshal(&mut data).unwrap(), 7);
+/// ```
+pub struct LazyStreamedSeq<T, I: Iterator<Item = u8>, C = DefaultConfig> {
+    data: IterSource<I>,
+    index: usize,
+    /// The elements left in the current chunk
+    left: usize,
+    ended: bool,
+    /// Where `data` was before the sequence, for errors
+    start: Option<usize>,
+    _marker: PhantomData<fn() -> (T, C)>,
+}
+
+impl<T, I: Iterator<Item = u8>> LazyStreamedSeq<T, I> {
+    /// Read a sequence from `data` as its elements are asked for
+    pub fn new(data: I) -> Self {
+        Self::new_with::<DefaultConfig>(data)
+    }
+
+    /// [`LazyStreamedSeq::new()`] with the wire format selected by `C`
+    pub fn new_with<C: Config>(data: I) -> LazyStreamedSeq<T, I, C> {
+        let data = IterSource::new(data);
+        LazyStreamedSeq {
+            start: position(&data),
+            data,
+            index: 0,
+            left: 0,
+            ended: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, I: Iterator<Item = u8>, C> LazyStreamedSeq<T, I, C> {
+    /// Give back `data`, after however many elements have been read
+    pub fn into_inner(self) -> I {
+        self.data.into_inner()
+    }
+}
+
+impl<T: UnMarshal, I: Iterator<Item = u8>, C: Config> LazyStreamedSeq<T, I, C> {
+    /// Read the elements that are left, so `data` is at whatever comes after the sequence, and
+    /// give it back
+    ///
+    /// # Errors
+    /// It errors when one of the elements or chunk lengths does
+    pub fn finish(mut self) -> Result<I, MarshalError> {
+        for item in &mut self {
+            item?;
+        }
+        Ok(self.into_inner())
+    }
+}
+
+impl<T: UnMarshal, I: Iterator<Item = u8>, C: Config> Iterator for LazyStreamedSeq<T, I, C> {
+    type Item = Result<T, MarshalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ended {
+            return None;
+        }
+        if self.left == 0 {
+            let len = unmarshal_len::<C>(&mut self.data)
+                .and_then(|len| C::LIMITS.check_collection_len(len))
+                .map_err(|e| e.within("LazyStreamedSeq", &[], self.start, position(&self.data)));
+            match len {
+                Ok(0) => {
+                    self.ended = true;
+                    return None;
+                }
+                Ok(len) => self.left = len,
+                Err(e) => {
+                    self.ended = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        let index = self.index;
+        self.index += 1;
+        self.left -= 1;
+        let item = T::unmarshal_from_with::<C>(&mut self.data).map_err(|e| {
+            e.within(
+                "LazyStreamedSeq",
+                &[PathSegment::Index(index)],
+                self.start,
+                position(&self.data),
+            )
+        });
+        self.ended = item.is_err();
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.ended {
+            true => (0, Some(0)),
+            false => (self.left, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
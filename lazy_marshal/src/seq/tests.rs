@@ -0,0 +1,231 @@
+use std::panic::catch_unwind;
+
+use super::*;
+use crate::prelude::*;
+
+#[test]
+fn test_seq_round_trip() {
+    let words = ["one", "two", "three"];
+    let bytes = Seq(words.iter()).marshal().collect::<Vec<_>>();
+    assert_eq!(bytes, words.to_vec().marshal_to_vec());
+    assert_eq!(Seq(words.iter()).marshal_to_vec(), bytes);
+    assert_eq!(Seq(words.iter()).encoded_len(), bytes.len());
+    assert_eq!(Seq(words.iter()).marshal_ref().collect::<Vec<_>>(), bytes);
+
+    let seq = LazySeq::<String, _>::new(bytes.iter().copied()).unwrap();
+    assert_eq!(seq.len(), 3);
+    assert_eq!(seq.collect::<Result<Vec<_>, _>>().unwrap(), words);
+
+    let bytes = Seq(0..1000u32)
+        .marshal_with::<Varints>()
+        .collect::<Vec<_>>();
+    assert_eq!(
+        bytes,
+        (0..1000)
+            .collect::<Vec<u32>>()
+            .marshal_with::<Varints>()
+            .collect::<Vec<_>>()
+    );
+    let mut seq = LazySeq::<u32, _>::new_with::<Varints>(bytes.into_iter()).unwrap();
+    assert_eq!(seq.nth(999).unwrap().unwrap(), 999);
+    assert!(seq.next().is_none());
+    assert_eq!(seq.into_inner().len(), 0);
+}
+
+#[test]
+fn test_lazy_seq_errors() {
+    let bytes = vec!["ok", "cut short"].marshal_to_vec();
+    let mut seq = LazySeq::<String, _>::new(bytes[..30].iter().copied()).unwrap();
+    assert_eq!(seq.next().unwrap().unwrap(), "ok");
+    let err = seq.next().unwrap().unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::InvalidSizedDecode(4)));
+    assert_eq!(err.path().as_deref(), Some("LazySeq[1]"));
+    assert_eq!(err.offset(), Some(30));
+    assert!(seq.next().is_none());
+    assert_eq!(seq.len(), 0);
+
+    let bytes = vec!["a"; 3].marshal_to_vec();
+    let seq = LazySeq::<String, _>::new(bytes[..20].iter().copied()).unwrap();
+    assert!(seq.finish().is_err());
+    assert!(LazySeq::<String, _>::new([1, 2].into_iter()).is_err());
+
+    struct Short;
+
+    impl Config for Short {
+        const LIMITS: DecodeLimits = DecodeLimits::NONE.max_collection_len(2);
+    }
+
+    let bytes = vec![0u8; 3].marshal_to_vec();
+    let err = LazySeq::<u8, _>::new_with::<Short>(bytes.into_iter())
+        .err()
+        .unwrap();
+    assert!(matches!(err.kind(), MarshalError::LimitExceeded { .. }));
+}
+
+/// Claims to have `len` elements, whatever it really has
+#[derive(Clone)]
+struct Lying {
+    items: std::ops::Range<u8>,
+    len: usize,
+}
+
+impl Iterator for Lying {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        self.items.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl ExactSizeIterator for Lying {}
+
+#[test]
+fn test_seq_wrong_len() {
+    let short = Lying {
+        items: 0..2,
+        len: 3,
+    };
+    assert!(catch_unwind(|| Seq(short.clone()).marshal().count()).is_err());
+    assert!(catch_unwind(|| Seq(short).marshal_to_vec()).is_err());
+    let long = Lying {
+        items: 0..4,
+        len: 3,
+    };
+    assert!(catch_unwind(|| Seq(long).marshal().count()).is_err());
+    let right = Lying {
+        items: 0..3,
+        len: 3,
+    };
+    assert_eq!(
+        Seq(right).marshal_to_vec(),
+        vec![0u8, 1, 2].marshal_to_vec()
+    );
+}
+
+#[test]
+fn test_streamed_seq_round_trip() {
+    let odd = (1..=5u8).filter(|i| i % 2 == 1);
+    let bytes = StreamedSeq::new(odd.clone())
+        .chunk_len(2)
+        .marshal_with::<Varints>()
+        .collect::<Vec<_>>();
+    assert_eq!(bytes, [2, 1, 3, 1, 5, 0]);
+    let mut written = Vec::new();
+    StreamedSeq::new(odd.clone())
+        .chunk_len(2)
+        .marshal_into_with::<Varints, _>(&mut written)
+        .unwrap();
+    assert_eq!(written, bytes);
+    let seq = StreamedSeq::new(odd).chunk_len(2);
+    assert_eq!(seq.marshal_ref_with::<Varints>().collect::<Vec<_>>(), bytes);
+    assert_eq!(seq.encoded_len_with::<Varints>(), bytes.len());
+
+    let read = StreamedSeq::<Vec<u8>>::unmarshal_with::<Varints>(&mut bytes.iter().copied());
+    assert_eq!(read.unwrap().into_inner(), [1, 3, 5]);
+    let lazy = LazyStreamedSeq::<u8, _>::new_with::<Varints>(bytes.iter().copied());
+    assert_eq!(lazy.collect::<Result<Vec<_>, _>>().unwrap(), [1, 3, 5]);
+
+    let empty = StreamedSeq::new(std::iter::empty::<String>()).marshal_to_vec();
+    assert_eq!(empty, 0u64.marshal_to_vec());
+    let read = StreamedSeq::<Vec<String>>::unmarshal(&mut empty.into_iter()).unwrap();
+    assert!(read.into_inner().is_empty());
+
+    let words = (0..2500).map(|i| i.to_string());
+    let bytes = (StreamedSeq::new(words.clone()), 7u8).marshal_to_vec();
+    let (read, end) =
+        <(StreamedSeq<Vec<String>>, u8)>::unmarshal(&mut bytes.iter().copied()).unwrap();
+    assert!(read.into_inner().into_iter().eq(words.clone()));
+    assert_eq!(end, 7);
+
+    let mut decoder = <(StreamedSeq<Vec<String>>, u8)>::decoder();
+    let mut done = None;
+    for chunk in bytes.chunks(5) {
+        let mut chunk = chunk;
+        if let Decoded::Done(v) = decoder.feed(&mut chunk).unwrap() {
+            done = Some(v);
+        }
+    }
+    let (read, end) = done.unwrap();
+    assert!(read.into_inner().into_iter().eq(words.clone()));
+    assert_eq!(end, 7);
+
+    let mut data = bytes.into_iter();
+    let mut lazy = LazyStreamedSeq::<String, _>::new(&mut data);
+    assert_eq!(lazy.nth(2000).unwrap().unwrap(), "2000");
+    lazy.finish().unwrap();
+    assert_eq!(data.collect::<Vec<_>>(), [7]);
+}
+
+#[test]
+fn test_streamed_seq_errors() {
+    let bytes = StreamedSeq::new(["ok", "cut short"].into_iter()).marshal_to_vec();
+    let err = StreamedSeq::<Vec<String>>::unmarshal(&mut bytes[..30].iter().copied()).unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::InvalidSizedDecode(4)));
+    assert_eq!(err.path().as_deref(), Some("StreamedSeq[1]"));
+    assert_eq!(err.offset(), Some(30));
+
+    let mut lazy = LazyStreamedSeq::<String, _>::new(bytes[..30].iter().copied());
+    assert_eq!(lazy.next().unwrap().unwrap(), "ok");
+    let err = lazy.next().unwrap().unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("LazyStreamedSeq[1]"));
+    assert_eq!(err.offset(), Some(30));
+    assert!(lazy.next().is_none());
+
+    // The terminator is missing
+    let cut = &bytes[..bytes.len() - 1];
+    assert!(StreamedSeq::<Vec<String>>::unmarshal(&mut cut.iter().copied()).is_err());
+    let lazy = LazyStreamedSeq::<String, _>::new(cut.iter().copied());
+    let err = lazy.finish().err().unwrap();
+    assert_eq!(err.path().as_deref(), Some("LazyStreamedSeq"));
+
+    struct Short;
+
+    impl Config for Short {
+        const LIMITS: DecodeLimits = DecodeLimits::NONE.max_collection_len(2);
+    }
+
+    // Every chunk is under the limit, but all of them together aren't
+    let bytes = StreamedSeq::new(0..3u8).chunk_len(1).marshal_to_vec();
+    let err =
+        StreamedSeq::<Vec<u8>>::unmarshal_with::<Short>(&mut bytes.iter().copied()).unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::LimitExceeded { .. }));
+    let mut decoder = StreamedSeq::<Vec<u8>>::decoder();
+    assert!(decoder.feed_with::<Short>(&mut &bytes[..]).is_err());
+    let lazy = LazyStreamedSeq::<u8, _>::new_with::<Short>(bytes.iter().copied());
+    assert_eq!(lazy.count(), 3);
+
+    assert!(catch_unwind(|| StreamedSeq::new(0..3u8).chunk_len(0)).is_err());
+}
+
+#[test]
+fn test_streamed_seq_small_prefix() {
+    struct Bytes;
+
+    impl Config for Bytes {
+        const LENGTH_PREFIX: LengthPrefix = LengthPrefix::U8;
+    }
+
+    // The default chunk is cut down to 255 elements
+    let bytes = StreamedSeq::new(0..600u16)
+        .marshal_with::<Bytes>()
+        .collect::<Vec<_>>();
+    assert_eq!(bytes[0], 255);
+    assert_eq!(bytes[1 + 255 * 2], 255);
+    assert_eq!(bytes[2 + 510 * 2], 90);
+    let mut written = Vec::new();
+    StreamedSeq::new(0..600u16)
+        .marshal_into_with::<Bytes, _>(&mut written)
+        .unwrap();
+    assert_eq!(written, bytes);
+    let read = StreamedSeq::<Vec<u16>>::unmarshal_with::<Bytes>(&mut bytes.iter().copied());
+    assert!(read.unwrap().into_inner().into_iter().eq(0..600));
+
+    let too_big = || StreamedSeq::new(0..600u16).chunk_len(256);
+    assert!(catch_unwind(|| too_big().marshal_with::<Bytes>().count()).is_err());
+    assert!(catch_unwind(|| too_big().marshal_into_with::<Bytes, _>(&mut Vec::new())).is_err());
+    assert_eq!(too_big().marshal_to_vec()[..8], 256u64.marshal_to_vec());
+}
@@ -0,0 +1,481 @@
+//! Where [`UnMarshal::unmarshal_from()`] reads its bytes from. Unlike an iterator, a [`ByteSource`]
+//! can hand over a whole run of bytes at once, so strings and byte buffers are copied straight out
+//! of a slice or a reader instead of a byte at a time.
+//!
+//! [`UnMarshal::unmarshal_from()`]: crate::UnMarshal::unmarshal_from
+
+use std::io::{self, BufRead, Read};
+
+use crate::error::MarshalError;
+
+/// Most bytes reserved up front for a length prefixed run of bytes, since the prefix can't be trusted
+pub(crate) const MAX_PREALLOC: usize = 64 * 1024;
+
+/// Input that [`UnMarshal`](crate::UnMarshal) types decode from.
+///
+/// It's implemented for `&[u8]`, which moves past what's read, and through [`IterSource`],
+/// [`ReadSource`] and [`BufReadSource`] for iterators of bytes, [`Read`]ers and [`BufRead`]ers.
+///
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// let bytes = ("Hello", 7u16).marshal().collect::<Vec<_>>();
+/// let mut data = &bytes[..];
+/// let decoded = <(String, u16)>::unmarshal_from(&mut data).unwrap();
+///
+/// assert_eq!(decoded, ("Hello".to_string(), 7));
+/// assert!(data.is_empty());
+/// ```
+pub trait ByteSource {
+    /// The next byte, or `None` once the input has ended
+    ///
+    /// # Errors
+    /// It errors when reading the input fails
+    fn next_byte(&mut self) -> Result<Option<u8>, MarshalError>;
+
+    /// Fill `buf` from the input
+    ///
+    /// # Errors
+    /// [`MarshalError::InvalidSizedDecode`] with how many bytes there were if the input ends first
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), MarshalError> {
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = self
+                .next_byte()?
+                .ok_or(MarshalError::InvalidSizedDecode(i))?;
+        }
+        Ok(())
+    }
+
+    /// Read `n` bytes onto the end of `v`. `n` can't be trusted, so `v` grows as bytes arrive.
+    ///
+    /// # Errors
+    /// [`MarshalError::InvalidSizedDecode`] with how many bytes there were if the input ends first
+    fn read_to_vec(&mut self, v: &mut Vec<u8>, n: usize) -> Result<(), MarshalError> {
+        let mut done = 0;
+        while done < n {
+            let start = v.len();
+            let chunk = (n - done).min(MAX_PREALLOC);
+            v.resize(start + chunk, 0);
+            if let Err(e) = self.read_exact(&mut v[start..]) {
+                return Err(match e {
+                    MarshalError::InvalidSizedDecode(read) => {
+                        v.truncate(start + read);
+                        MarshalError::InvalidSizedDecode(done + read)
+                    }
+                    e => {
+                        v.truncate(start);
+                        e
+                    }
+                });
+            }
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// Step over the next `n` bytes
+    ///
+    /// # Errors
+    /// [`MarshalError::InvalidSizedDecode`] with how many bytes there were if the input ends first
+    fn skip(&mut self, n: usize) -> Result<(), MarshalError> {
+        for i in 0..n {
+            if self.next_byte()?.is_none() {
+                Err(MarshalError::InvalidSizedDecode(i))?
+            }
+        }
+        Ok(())
+    }
+
+    /// How far into the input the source is, counting from wherever it likes as long as it goes up
+    /// by one for every byte taken out. Errors use it to say where they happened.
+    fn position(&self) -> usize;
+
+    /// How many bytes are left, if the input knows exactly
+    fn remaining(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl<S: ByteSource + ?Sized> ByteSource for &mut S {
+    #[inline]
+    fn next_byte(&mut self) -> Result<Option<u8>, MarshalError> {
+        (**self).next_byte()
+    }
+
+    #[inline]
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), MarshalError> {
+        (**self).read_exact(buf)
+    }
+
+    fn read_to_vec(&mut self, v: &mut Vec<u8>, n: usize) -> Result<(), MarshalError> {
+        (**self).read_to_vec(v, n)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), MarshalError> {
+        (**self).skip(n)
+    }
+
+    fn position(&self) -> usize {
+        (**self).position()
+    }
+
+    fn remaining(&self) -> Option<usize> {
+        (**self).remaining()
+    }
+}
+
+// Running out of bytes uses up the rest of the slice, the same as an iterator would
+impl ByteSource for &[u8] {
+    #[inline]
+    fn next_byte(&mut self) -> Result<Option<u8>, MarshalError> {
+        Ok(self.split_first().map(|(&b, rest)| {
+            *self = rest;
+            b
+        }))
+    }
+
+    #[inline]
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), MarshalError> {
+        let n = self.len();
+        if n < buf.len() {
+            buf[..n].copy_from_slice(self);
+            *self = &self[n..];
+            Err(MarshalError::InvalidSizedDecode(n))?
+        }
+        let (read, rest) = self.split_at(buf.len());
+        buf.copy_from_slice(read);
+        *self = rest;
+        Ok(())
+    }
+
+    fn read_to_vec(&mut self, v: &mut Vec<u8>, n: usize) -> Result<(), MarshalError> {
+        let read = n.min(self.len());
+        v.extend_from_slice(&self[..read]);
+        *self = &self[read..];
+        match read < n {
+            true => Err(MarshalError::InvalidSizedDecode(read)),
+            false => Ok(()),
+        }
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), MarshalError> {
+        let skipped = n.min(self.len());
+        *self = &self[skipped..];
+        match skipped < n {
+            true => Err(MarshalError::InvalidSizedDecode(skipped)),
+            false => Ok(()),
+        }
+    }
+
+    // A slice doesn't know where it started, but it does know how much of it is left
+    fn position(&self) -> usize {
+        usize::MAX - self.len()
+    }
+
+    fn remaining(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+/// A [`ByteSource`] whose bytes live for `'de`, so that [`UnMarshalBorrowed`] values can point
+/// into it instead of copying them. `&'de [u8]` is one.
+///
+/// [`UnMarshalBorrowed`]: crate::UnMarshalBorrowed
+pub trait BorrowedSource<'de>: ByteSource {
+    /// The next `n` bytes
+    ///
+    /// # Errors
+    /// [`MarshalError::InvalidSizedDecode`] with how many bytes there were if the input ends first
+    fn borrow_bytes(&mut self, n: usize) -> Result<&'de [u8], MarshalError>;
+}
+
+impl<'de, S: BorrowedSource<'de> + ?Sized> BorrowedSource<'de> for &mut S {
+    fn borrow_bytes(&mut self, n: usize) -> Result<&'de [u8], MarshalError> {
+        (**self).borrow_bytes(n)
+    }
+}
+
+impl<'de> BorrowedSource<'de> for &'de [u8] {
+    fn borrow_bytes(&mut self, n: usize) -> Result<&'de [u8], MarshalError> {
+        if self.len() < n {
+            let read = self.len();
+            *self = &self[read..];
+            Err(MarshalError::InvalidSizedDecode(read))?
+        }
+        let (bytes, rest) = self.split_at(n);
+        *self = rest;
+        Ok(bytes)
+    }
+}
+
+/// A [`ByteSource`] reading an iterator of bytes a byte at a time
+///
+/// ```
+/// use lazy_marshal::{prelude::*, IterSource};
+///
+/// let mut data = IterSource::new("Hello".marshal());
+/// assert_eq!(String::unmarshal_from(&mut data).unwrap(), "Hello");
+/// ```
+#[derive(Debug, Clone)]
+pub struct IterSource<I> {
+    iter: I,
+    read: usize,
+}
+
+impl<I: Iterator<Item = u8>> IterSource<I> {
+    pub fn new(iter: I) -> Self {
+        Self { iter, read: 0 }
+    }
+
+    /// The iterator, with what hasn't been read still in it
+    pub fn into_inner(self) -> I {
+        self.iter
+    }
+}
+
+impl<I: Iterator<Item = u8>> ByteSource for IterSource<I> {
+    #[inline]
+    fn next_byte(&mut self) -> Result<Option<u8>, MarshalError> {
+        let b = self.iter.next();
+        self.read += b.is_some() as usize;
+        Ok(b)
+    }
+
+    fn read_to_vec(&mut self, v: &mut Vec<u8>, n: usize) -> Result<(), MarshalError> {
+        let start = v.len();
+        v.reserve(n.min(MAX_PREALLOC));
+        v.extend(self.iter.by_ref().take(n));
+        self.read += v.len() - start;
+        match v.len() - start < n {
+            true => Err(MarshalError::InvalidSizedDecode(v.len() - start)),
+            false => Ok(()),
+        }
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), MarshalError> {
+        let skipped = self.iter.by_ref().take(n).count();
+        self.read += skipped;
+        match skipped < n {
+            true => Err(MarshalError::InvalidSizedDecode(skipped)),
+            false => Ok(()),
+        }
+    }
+
+    fn position(&self) -> usize {
+        self.read
+    }
+
+    fn remaining(&self) -> Option<usize> {
+        match self.iter.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(lower),
+            _ => None,
+        }
+    }
+}
+
+/// A [`ByteSource`] reading exactly the bytes a value needs from a [`Read`]er. Numbers written as
+/// varints are read a byte at a time, so an unbuffered reader is better off in a
+/// [`BufReader`](std::io::BufReader) with [`BufReadSource`].
+///
+/// ```
+/// use lazy_marshal::{prelude::*, ReadSource};
+///
+/// let bytes = "Hello".marshal().collect::<Vec<_>>();
+/// let mut data = ReadSource::new(std::io::Cursor::new(bytes));
+/// assert_eq!(String::unmarshal_from(&mut data).unwrap(), "Hello");
+/// ```
+#[derive(Debug)]
+pub struct ReadSource<R> {
+    reader: R,
+    read: usize,
+}
+
+impl<R: Read> ReadSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, read: 0 }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> ByteSource for ReadSource<R> {
+    fn next_byte(&mut self) -> Result<Option<u8>, MarshalError> {
+        let mut b = [0];
+        match self.read_exact(&mut b) {
+            Ok(()) => Ok(Some(b[0])),
+            Err(MarshalError::InvalidSizedDecode(0)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), MarshalError> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.reader.read(&mut buf[read..]) {
+                Ok(0) => Err(MarshalError::InvalidSizedDecode(read))?,
+                Ok(n) => {
+                    read += n;
+                    self.read += n;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => Err(e)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), MarshalError> {
+        let skipped = io::copy(&mut (&mut self.reader).take(n as u64), &mut io::sink())? as usize;
+        self.read += skipped;
+        match skipped < n {
+            true => Err(MarshalError::InvalidSizedDecode(skipped)),
+            false => Ok(()),
+        }
+    }
+
+    fn position(&self) -> usize {
+        self.read
+    }
+}
+
+/// A [`ByteSource`] reading a [`BufRead`]er, which can look at the bytes in its buffer without
+/// taking more than a value needs
+///
+/// ```
+/// use lazy_marshal::{prelude::*, BufReadSource};
+///
+/// let bytes = (VarInt(300u32), "Hello").marshal().collect::<Vec<_>>();
+/// let mut data = BufReadSource::new(&bytes[..]);
+/// assert_eq!(
+///     <(VarInt<u32>, String)>::unmarshal_from(&mut data).unwrap(),
+///     (VarInt(300), "Hello".to_string())
+/// );
+/// ```
+#[derive(Debug)]
+pub struct BufReadSource<R> {
+    reader: R,
+    read: usize,
+}
+
+impl<R: BufRead> BufReadSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, read: 0 }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Take `n` bytes out of the buffer
+    fn consume(&mut self, n: usize) {
+        self.reader.consume(n);
+        self.read += n;
+    }
+
+    /// The buffered bytes, filling the buffer first if it's empty
+    fn fill_buf(&mut self) -> Result<&[u8], MarshalError> {
+        loop {
+            match self.reader.fill_buf() {
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => Err(e)?,
+            }
+        }
+        // The buffer can't be returned from inside the loop, but asking again doesn't read anything
+        Ok(self.reader.fill_buf()?)
+    }
+}
+
+impl<R: BufRead> ByteSource for BufReadSource<R> {
+    fn next_byte(&mut self) -> Result<Option<u8>, MarshalError> {
+        let Some(&b) = self.fill_buf()?.first() else {
+            return Ok(None);
+        };
+        self.consume(1);
+        Ok(Some(b))
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), MarshalError> {
+        let mut read = 0;
+        while read < buf.len() {
+            let available = self.fill_buf()?;
+            if available.is_empty() {
+                Err(MarshalError::InvalidSizedDecode(read))?
+            }
+            let n = available.len().min(buf.len() - read);
+            buf[read..read + n].copy_from_slice(&available[..n]);
+            self.consume(n);
+            read += n;
+        }
+        Ok(())
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), MarshalError> {
+        let mut skipped = 0;
+        while skipped < n {
+            let available = self.fill_buf()?.len();
+            if available == 0 {
+                Err(MarshalError::InvalidSizedDecode(skipped))?
+            }
+            let step = available.min(n - skipped);
+            self.consume(step);
+            skipped += step;
+        }
+        Ok(())
+    }
+
+    fn position(&self) -> usize {
+        self.read
+    }
+}
+
+/// Decode from `data` with `f`, which can only read an iterator, like an [`UnMarshal`] impl
+/// without [`UnMarshal::unmarshal_from_with()`]. If reading `data` fails, that error is returned
+/// instead of whatever `f` made of the input ending early.
+///
+/// [`UnMarshal`]: crate::UnMarshal
+/// [`UnMarshal::unmarshal_from_with()`]: crate::UnMarshal::unmarshal_from_with
+pub fn with_iter<S: ByteSource, T>(
+    data: &mut S,
+    f: impl FnOnce(&mut SourceIter<'_, S>) -> Result<T, MarshalError>,
+) -> Result<T, MarshalError> {
+    let mut iter = SourceIter { data, error: None };
+    let value = f(&mut iter);
+    match iter.error {
+        Some(e) => Err(e),
+        None => value,
+    }
+}
+
+/// The bytes of a [`ByteSource`] as an iterator, for [`with_iter()`]
+pub struct SourceIter<'a, S> {
+    data: &'a mut S,
+    error: Option<MarshalError>,
+}
+
+impl<S: ByteSource> Iterator for SourceIter<'_, S> {
+    type Item = u8;
+
+    #[inline]
+    fn next(&mut self) -> Option<u8> {
+        if self.error.is_some() {
+            return None;
+        }
+        self.data.next_byte().unwrap_or_else(|e| {
+            self.error = Some(e);
+            None
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.data.remaining() {
+            Some(left) => (left, Some(left)),
+            None => (0, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
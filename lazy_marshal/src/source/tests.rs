@@ -0,0 +1,137 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io::{self, BufReader, Cursor, Read},
+};
+
+use super::*;
+use crate::prelude::*;
+
+/// Decode `bytes` through every kind of source, checking they all agree with the iterator
+fn from_every_source<T: UnMarshal + PartialEq + Debug>(bytes: &[u8]) -> T {
+    let expected = T::unmarshal(&mut bytes.iter().cloned()).unwrap();
+
+    let mut slice = bytes;
+    assert_eq!(T::unmarshal_from(&mut slice).unwrap(), expected);
+    assert!(slice.is_empty());
+    let mut iter = IterSource::new(bytes.iter().cloned());
+    assert_eq!(T::unmarshal_from(&mut iter).unwrap(), expected);
+    assert_eq!(iter.remaining(), Some(0));
+    assert_eq!(iter.position(), bytes.len());
+    let mut reader = ReadSource::new(Cursor::new(bytes));
+    assert_eq!(T::unmarshal_from(&mut reader).unwrap(), expected);
+    assert_eq!(reader.position(), bytes.len());
+    assert_eq!(reader.into_inner().position() as usize, bytes.len());
+    // A tiny buffer so that values are split across refills
+    let mut buffered = BufReadSource::new(BufReader::with_capacity(3, bytes));
+    assert_eq!(T::unmarshal_from(&mut buffered).unwrap(), expected);
+    assert_eq!(buffered.position(), bytes.len());
+    expected
+}
+
+#[test]
+fn test_sources_agree() {
+    let bytes = (
+        "Hello, World! 😁",
+        vec![1u8, 2, 3, 4, 5],
+        vec![-1i8, 2],
+        Box::<[u8]>::from([9; 10]),
+        (VarInt(300u32), 7u64, -2.5f32),
+    )
+        .marshal()
+        .collect::<Vec<_>>();
+    from_every_source::<(String, Vec<u8>, Vec<i8>, Box<[u8]>, (VarInt<u32>, u64, f32))>(&bytes);
+
+    let map = HashMap::from([(1u16, Some("one".to_string())), (2, None)]);
+    let bytes = map.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(
+        from_every_source::<HashMap<u16, Option<String>>>(&bytes),
+        map
+    );
+
+    let bytes = "Short".marshal_with::<Varints>().collect::<Vec<_>>();
+    let mut data = &bytes[..];
+    assert_eq!(
+        String::unmarshal_from_with::<Varints>(&mut data).unwrap(),
+        "Short"
+    );
+}
+
+#[test]
+fn test_sources_running_out() {
+    let bytes = vec![7u8; 100].marshal().collect::<Vec<_>>();
+    let short = &bytes[..50];
+    let check = |result: Result<Vec<u8>, MarshalError>| {
+        let e = result.unwrap_err();
+        assert!(matches!(e.kind(), MarshalError::InvalidSizedDecode(0)));
+        assert_eq!(e.path().as_deref(), Some("Vec[42]"));
+    };
+    check(Vec::unmarshal(&mut short.iter().cloned()));
+    check(Vec::unmarshal_from(&mut &short[..]));
+    check(Vec::unmarshal_from(&mut ReadSource::new(short)));
+    check(Vec::unmarshal_from(&mut BufReadSource::new(short)));
+
+    let bytes = "Hello".marshal().collect::<Vec<_>>();
+    let short = &bytes[..10];
+    assert!(matches!(
+        String::unmarshal_from(&mut &short[..]),
+        Err(MarshalError::InvalidSizedDecode(2))
+    ));
+    assert!(matches!(
+        String::unmarshal_from(&mut ReadSource::new(short)),
+        Err(MarshalError::InvalidSizedDecode(2))
+    ));
+    assert!(matches!(
+        String::unmarshal_from(&mut BufReadSource::new(short)),
+        Err(MarshalError::InvalidSizedDecode(2))
+    ));
+
+    // A length that's far longer than the input only allocates as the bytes arrive
+    let bytes = u64::MAX.marshal().chain([1, 2, 3]).collect::<Vec<_>>();
+    assert!(matches!(
+        Box::<[u8]>::unmarshal_from(&mut ReadSource::new(&bytes[..])),
+        Err(MarshalError::InvalidSizedDecode(3))
+    ));
+}
+
+/// Gives `good` bytes of ones and then fails
+struct Failing {
+    good: usize,
+}
+
+impl Read for Failing {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.good == 0 {
+            return Err(io::Error::other("broken"));
+        }
+        let n = buf.len().min(self.good);
+        buf[..n].fill(1);
+        self.good -= n;
+        Ok(n)
+    }
+}
+
+/// Only has `unmarshal`, so reading it from a source goes through an iterator
+#[derive(Debug, PartialEq)]
+struct Pair(u8, u8);
+
+impl UnMarshal for Pair {
+    fn unmarshal(data: &mut impl Iterator<Item = u8>) -> Result<Self, MarshalError> {
+        Ok(Pair(u8::unmarshal(data)?, u8::unmarshal(data)?))
+    }
+}
+
+#[test]
+fn test_read_errors() {
+    let result = u64::unmarshal_from(&mut ReadSource::new(Failing { good: 4 }));
+    assert!(matches!(result, Err(MarshalError::Io(e)) if e.to_string() == "broken"));
+    let result =
+        String::unmarshal_from(&mut BufReadSource::new(BufReader::new(Failing { good: 8 })));
+    assert!(matches!(result, Err(MarshalError::Io(_))));
+
+    // The error reading the source wins over the one the iterator running out gave
+    let result = Pair::unmarshal_from(&mut ReadSource::new(Failing { good: 1 }));
+    assert!(matches!(result, Err(MarshalError::Io(_))));
+    let result = <(u8, Pair)>::unmarshal_from(&mut &[1, 2, 3][..]);
+    assert_eq!(result.unwrap(), (1, Pair(2, 3)));
+}
@@ -0,0 +1,99 @@
+//! Many values marshalled one after another in the same stream of bytes, like messages read from
+//! a socket, with nothing in between them.
+
+use std::{iter::Peekable, marker::PhantomData};
+
+use crate::{
+    config::{Config, DefaultConfig},
+    error::MarshalError,
+    traits::{Marshal, UnMarshal},
+};
+
+/// Marshal every value of `values` one after another, to be read back with [`UnMarshalStream`]
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// let bytes = marshal_all(["a", "b"]).collect::<Vec<_>>();
+/// assert_eq!(bytes, ["a".marshal_to_vec(), "b".marshal_to_vec()].concat());
+/// ```
+pub fn marshal_all<T: Marshal>(values: impl IntoIterator<Item = T>) -> impl Iterator<Item = u8> {
+    marshal_all_with::<DefaultConfig, T>(values)
+}
+
+/// [`marshal_all()`] with the wire format selected by `C`
+pub fn marshal_all_with<C: Config, T: Marshal>(
+    values: impl IntoIterator<Item = T>,
+) -> impl Iterator<Item = u8> {
+    values.into_iter().flat_map(T::marshal_with::<C>)
+}
+
+/// Unmarshal values of `T` from `data` one after another, until it ends.
+///
+/// The stream ends cleanly when `data` runs out between two values. If it runs out partway
+/// through one, that's an error of [kind](MarshalError::kind()) [`MarshalError::EarlyStreamEnd`].
+/// Nothing is yielded after an error, since where the next value starts isn't known.
+///
+/// Types that are marshalled in no bytes at all are yielded forever, as long as `data` isn't empty.
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// let bytes = marshal_all([1u16, 2, 3]).collect::<Vec<_>>();
+/// let values = UnMarshalStream::<u16, _>::new(bytes.into_iter());
+/// assert_eq!(values.collect::<Result<Vec<_>, _>>().unwrap(), [1, 2, 3]);
+///
+/// let cut_short = marshal_all([1u16, 2]).take(3);
+/// let mut values = UnMarshalStream::<u16, _>::new(cut_short);
+/// assert_eq!(values.next().unwrap().unwrap(), 1);
+/// let err = values.next().unwrap().unwrap_err();
+/// assert!(matches!(err.kind(), MarshalError::EarlyStreamEnd));
+/// assert!(values.next().is_none());
+/// ```
+pub struct UnMarshalStream<T, I: Iterator<Item = u8>, C = DefaultConfig> {
+    data: Peekable<I>,
+    failed: bool,
+    _marker: PhantomData<fn() -> (T, C)>,
+}
+
+impl<T, I: Iterator<Item = u8>> UnMarshalStream<T, I> {
+    /// Read values from `data`
+    pub fn new(data: I) -> Self {
+        Self::new_with::<DefaultConfig>(data)
+    }
+
+    /// [`UnMarshalStream::new()`] with the wire format selected by `C`
+    pub fn new_with<C: Config>(data: I) -> UnMarshalStream<T, I, C> {
+        UnMarshalStream {
+            data: data.peekable(),
+            failed: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: UnMarshal, I: Iterator<Item = u8>, C: Config> Iterator for UnMarshalStream<T, I, C> {
+    type Item = Result<T, MarshalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        self.data.peek()?;
+        let mut ended = false;
+        let mut data = std::iter::from_fn(|| {
+            let byte = self.data.next();
+            ended |= byte.is_none();
+            byte
+        });
+        let value = T::unmarshal_with::<C>(&mut data);
+        if value.is_err() {
+            self.failed = true;
+        }
+        Some(value.map_err(|e| match ended {
+            true => e.ended_early(),
+            false => e,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,56 @@
+use super::*;
+use crate::prelude::*;
+
+#[test]
+fn test_stream_round_trip() {
+    let messages = vec![
+        (1u32, "one".to_string()),
+        (2, String::new()),
+        (3, "three".to_string()),
+    ];
+    let bytes = marshal_all(messages.clone()).collect::<Vec<_>>();
+    let stream = UnMarshalStream::<(u32, String), _>::new(bytes.into_iter());
+    assert_eq!(stream.collect::<Result<Vec<_>, _>>().unwrap(), messages);
+
+    let bytes = marshal_all_with::<Varints, _>(&messages).collect::<Vec<_>>();
+    assert_eq!(bytes[..2], [1, 3]);
+    let stream = UnMarshalStream::<(u32, String), _>::new_with::<Varints>(bytes.into_iter());
+    assert_eq!(stream.collect::<Result<Vec<_>, _>>().unwrap(), messages);
+
+    // An empty stream has no values in it
+    let mut stream = UnMarshalStream::<u64, _>::new(std::iter::empty());
+    assert!(stream.next().is_none());
+}
+
+#[test]
+fn test_stream_truncated() {
+    let bytes = marshal_all([vec!["a"], vec!["b", "c"]]).collect::<Vec<_>>();
+    // Cut off at every byte of the second value
+    for end in 18..bytes.len() {
+        let mut stream = UnMarshalStream::<Vec<String>, _>::new(bytes[..end].iter().copied());
+        assert_eq!(stream.next().unwrap().unwrap(), ["a"]);
+        let err = stream.next().unwrap().unwrap_err();
+        assert!(
+            matches!(err.kind(), MarshalError::EarlyStreamEnd),
+            "{end}: {err}"
+        );
+        assert!(stream.next().is_none());
+    }
+
+    let err = UnMarshalStream::<Vec<String>, _>::new(bytes[..40].iter().copied())
+        .nth(1)
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Vec[1]"));
+
+    // Bad data isn't the stream ending, even when it's the last value
+    let bytes = marshal_all([Some(1u8), None])
+        .chain([2])
+        .collect::<Vec<_>>();
+    let mut stream = UnMarshalStream::<Option<u8>, _>::new(bytes.into_iter());
+    assert_eq!(stream.next().unwrap().unwrap(), Some(1));
+    assert_eq!(stream.next().unwrap().unwrap(), None);
+    let err = stream.next().unwrap().unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::InvalidData(_)));
+    assert!(stream.next().is_none());
+}
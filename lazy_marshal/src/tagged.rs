@@ -0,0 +1,325 @@
+//! The layout of derived structs with `#[marshal(tagged)]`, modelled on protobuf: the number of
+//! fields as a [`VarInt`], then every field as a key holding its id and [`WireType`], followed by
+//! its value. Readers look fields up by their id and step over the ones they don't know with
+//! [`skip_value()`], so fields can be added, removed and reordered.
+
+use std::io::{self, Write};
+
+use crate::{
+    config::{Config, IntEncoding},
+    decoders::{PrimitiveDecoder, VarIntDecoder},
+    error::MarshalError,
+    limits::consume,
+    source::{ByteSource, IterSource},
+    traits::{Decoded, Either, Marshal, PushDecoder, UnMarshal},
+    utils::{read_varint, varint_len},
+    varint::VarInt,
+};
+
+/// How a tagged value is laid out, which is all that's needed to skip over it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WireType {
+    /// A LEB128 varint, ending at the first byte without its high bit set
+    Varint = 0,
+    Fixed8 = 1,
+    Fixed16 = 2,
+    Fixed32 = 3,
+    Fixed64 = 4,
+    Fixed128 = 5,
+    /// A [`VarInt`] length followed by that many bytes
+    LengthDelimited = 6,
+}
+
+impl WireType {
+    /// An integer that is `size` bytes wide, which is a varint if the config says so
+    #[doc(hidden)]
+    pub fn int<C: Config>(size: usize) -> Self {
+        match (C::INT_ENCODING, size) {
+            (IntEncoding::Varint, _) => Self::Varint,
+            (IntEncoding::Fixed, 1) => Self::Fixed8,
+            (IntEncoding::Fixed, 2) => Self::Fixed16,
+            (IntEncoding::Fixed, 4) => Self::Fixed32,
+            (IntEncoding::Fixed, 8) => Self::Fixed64,
+            (IntEncoding::Fixed, 16) => Self::Fixed128,
+            (IntEncoding::Fixed, _) => panic!("there are no {size} byte integers"),
+        }
+    }
+
+    /// How many bytes a fixed width value takes up
+    pub fn fixed_len(self) -> Option<usize> {
+        match self {
+            Self::Fixed8 => Some(1),
+            Self::Fixed16 => Some(2),
+            Self::Fixed32 => Some(4),
+            Self::Fixed64 => Some(8),
+            Self::Fixed128 => Some(16),
+            Self::Varint | Self::LengthDelimited => None,
+        }
+    }
+
+    fn from_bits(bits: u64) -> Result<Self, MarshalError> {
+        Ok(match bits {
+            0 => Self::Varint,
+            1 => Self::Fixed8,
+            2 => Self::Fixed16,
+            3 => Self::Fixed32,
+            4 => Self::Fixed64,
+            5 => Self::Fixed128,
+            6 => Self::LengthDelimited,
+            _ => {
+                return Err(MarshalError::InvalidData(format!(
+                    "unknown wire type {bits}"
+                )))
+            }
+        })
+    }
+}
+
+/// Split the key in front of a tagged value into the field's id and its wire type
+fn split_key(key: u64) -> Result<(u32, WireType), MarshalError> {
+    let id = u32::try_from(key >> 3)
+        .map_err(|_| MarshalError::InvalidData(format!("field id {} is too large", key >> 3)))?;
+    Ok((id, WireType::from_bits(key & 0b111)?))
+}
+
+/// Read the length in front of a [`WireType::LengthDelimited`] value
+fn read_delimited_len<C: Config>(data: &mut impl ByteSource) -> Result<usize, MarshalError> {
+    let VarInt(len) = VarInt::<u64>::unmarshal_from_with::<C>(data)?;
+    usize::try_from(len).map_err(|_| MarshalError::VarintOverflow)
+}
+
+/// Step over a value written as `wire` without knowing its type, like a field of a tagged struct
+/// that the reader doesn't have.
+///
+/// ```
+/// use lazy_marshal::{skip_value, DefaultConfig, WireType};
+///
+/// let mut data = [3, 1, 2, 3, 0xac, 0x02, 9].into_iter();
+/// skip_value::<DefaultConfig>(&mut data, WireType::LengthDelimited).unwrap();
+/// skip_value::<DefaultConfig>(&mut data, WireType::Varint).unwrap();
+/// assert_eq!(data.next(), Some(9));
+/// ```
+pub fn skip_value<C: Config>(
+    data: &mut impl Iterator<Item = u8>,
+    wire: WireType,
+) -> Result<(), MarshalError> {
+    skip_value_from::<C>(&mut IterSource::new(data), wire)
+}
+
+/// [`skip_value()`] for a [`ByteSource`]
+pub fn skip_value_from<C: Config>(
+    data: &mut impl ByteSource,
+    wire: WireType,
+) -> Result<(), MarshalError> {
+    let len = match wire.fixed_len() {
+        Some(len) => len,
+        None if wire == WireType::Varint => return read_varint::<C>(data).map(drop),
+        None => read_delimited_len::<C>(data)?,
+    };
+    consume::<C>(len)?;
+    data.skip(len)
+}
+
+/// Write one field of a tagged struct: its key, and then its value with its length in front
+/// if it's [`WireType::LengthDelimited`], in which case it's collected first to find the length.
+pub fn marshal_tagged<C: Config>(
+    id: u32,
+    wire: WireType,
+    value: impl Iterator<Item = u8>,
+) -> impl Iterator<Item = u8> {
+    let key = VarInt((u64::from(id) << 3) | wire as u64).marshal_with::<C>();
+    let value = match wire {
+        WireType::LengthDelimited => {
+            let value = value.collect::<Vec<_>>();
+            Either::Left(VarInt(value.len() as u64).marshal_with::<C>().chain(value))
+        }
+        _ => Either::Right(value),
+    };
+    key.chain(value)
+}
+
+/// How many bytes [`marshal_tagged()`] writes for a value that's `value` bytes long
+pub fn tagged_len<C: Config>(id: u32, wire: WireType, value: usize) -> usize {
+    let key = varint_len((u64::from(id) << 3 | wire as u64).into());
+    match wire {
+        WireType::LengthDelimited => key + varint_len(value as u128) + value,
+        _ => key + value,
+    }
+}
+
+/// Write the key of a field of a tagged struct, and the length of its value if it's
+/// [`WireType::LengthDelimited`], for fields that write their value themselves after it
+pub fn write_tagged_key<C: Config, W: Write>(
+    w: &mut W,
+    id: u32,
+    wire: WireType,
+    value: usize,
+) -> io::Result<()> {
+    VarInt((u64::from(id) << 3) | wire as u64).marshal_into_with::<C, W>(w)?;
+    match wire {
+        WireType::LengthDelimited => VarInt(value as u64).marshal_into_with::<C, W>(w),
+        _ => Ok(()),
+    }
+}
+
+/// Read the key in front of a field of a tagged struct
+pub fn read_key<C: Config>(data: &mut impl ByteSource) -> Result<(u32, WireType), MarshalError> {
+    let VarInt(key) = VarInt::<u64>::unmarshal_from_with::<C>(data)?;
+    split_key(key)
+}
+
+/// Check a field was written the way the reader expects, which it isn't if its type was changed
+pub fn expect_wire(found: WireType, expected: WireType) -> Result<(), MarshalError> {
+    match found == expected {
+        true => Ok(()),
+        false => Err(MarshalError::InvalidData(format!(
+            "expected a {expected:?} value, found {found:?}"
+        ))),
+    }
+}
+
+/// The error for a field missing from a tagged struct that has no `default`
+pub fn missing_field() -> MarshalError {
+    MarshalError::InvalidData("missing field".to_string())
+}
+
+/// Reads the field count and keys of a tagged struct for push decoders,
+/// and skips the values of fields the struct doesn't know
+#[derive(Default)]
+pub struct TaggedDecoder {
+    count_decoder: VarIntDecoder<u32>,
+    /// Fields that are still to come, once the count is known
+    left: Option<u32>,
+    key_decoder: VarIntDecoder<u64>,
+    /// The id and wire type of the field being read
+    field: Option<(u32, WireType)>,
+    len_decoder: VarIntDecoder<u64>,
+    /// Bytes left of a length delimited value being read, or of a value being skipped
+    value_left: Option<usize>,
+    varint: PrimitiveDecoder<u128>,
+}
+
+impl TaggedDecoder {
+    /// Feed the field count or the next key, giving the id of the field to read or skip next, or
+    /// `None` once all of them have been. The same id comes back until that field is done.
+    pub fn next_field<C: Config>(
+        &mut self,
+        data: &mut &[u8],
+    ) -> Result<Decoded<Option<u32>>, MarshalError> {
+        // The rest of the value before, which its decoder didn't need or which is being skipped
+        if self.field.is_none() {
+            if let Decoded::NeedMore(n) = self.skip_rest(data) {
+                return Ok(Decoded::NeedMore(n));
+            }
+        }
+        let left = match self.left {
+            Some(left) => left,
+            None => match self.count_decoder.feed_with::<C>(data)? {
+                Decoded::Done(VarInt(count)) => *self.left.insert(count),
+                Decoded::NeedMore(n) => return Ok(Decoded::NeedMore(n)),
+            },
+        };
+        if let Some((id, _)) = self.field {
+            return Ok(Decoded::Done(Some(id)));
+        }
+        if left == 0 {
+            *self = Self::default();
+            return Ok(Decoded::Done(None));
+        }
+        match self.key_decoder.feed_with::<C>(data)? {
+            Decoded::Done(VarInt(key)) => {
+                let (id, wire) = split_key(key)?;
+                self.field = Some((id, wire));
+                self.left = Some(left - 1);
+                Ok(Decoded::Done(Some(id)))
+            }
+            Decoded::NeedMore(n) => Ok(Decoded::NeedMore(n)),
+        }
+    }
+
+    /// The wire type of the field [`TaggedDecoder::next_field()`] gave
+    fn wire(&self) -> WireType {
+        self.field.expect("next_field() gives the field to read").1
+    }
+
+    /// Feed `decoder` the value of the current field, which the struct expects to be a `wire`
+    pub fn feed<C: Config, D: PushDecoder>(
+        &mut self,
+        decoder: &mut D,
+        wire: WireType,
+        data: &mut &[u8],
+    ) -> Result<Decoded<D::Output>, MarshalError> {
+        expect_wire(self.wire(), wire)?;
+        if wire != WireType::LengthDelimited {
+            let decoded = decoder.feed_with::<C>(data)?;
+            if let Decoded::Done(_) = decoded {
+                self.field = None;
+            }
+            return Ok(decoded);
+        }
+
+        let left = match self.value_left {
+            Some(left) => left,
+            None => match self.len_decoder.feed_with::<C>(data)? {
+                Decoded::Done(VarInt(len)) => {
+                    usize::try_from(len).map_err(|_| MarshalError::VarintOverflow)?
+                }
+                Decoded::NeedMore(n) => return Ok(Decoded::NeedMore(n)),
+            },
+        };
+        let n = data.len().min(left);
+        let mut value = &data[..n];
+        let decoded = decoder.feed_with::<C>(&mut value)?;
+        let used = n - value.len();
+        *data = &data[used..];
+        self.value_left = Some(left - used);
+        match decoded {
+            // The value ended before the decoder did
+            Decoded::NeedMore(_) if left == used => Err(MarshalError::EarlyStreamEnd),
+            Decoded::NeedMore(more) => Ok(Decoded::NeedMore(more.min(left - used))),
+            done => {
+                self.field = None;
+                Ok(done)
+            }
+        }
+    }
+
+    /// Skip the value of the current field, which the struct doesn't know about
+    pub fn skip<C: Config>(&mut self, data: &mut &[u8]) -> Result<Decoded<()>, MarshalError> {
+        let wire = self.wire();
+        if self.value_left.is_none() {
+            let len = match wire.fixed_len() {
+                Some(len) => len,
+                None if wire == WireType::Varint => match self.varint.varint(data)? {
+                    Decoded::Done(_) => 0,
+                    Decoded::NeedMore(n) => return Ok(Decoded::NeedMore(n)),
+                },
+                None => match self.len_decoder.feed_with::<C>(data)? {
+                    Decoded::Done(VarInt(len)) => {
+                        usize::try_from(len).map_err(|_| MarshalError::VarintOverflow)?
+                    }
+                    Decoded::NeedMore(n) => return Ok(Decoded::NeedMore(n)),
+                },
+            };
+            self.value_left = Some(len);
+        }
+        self.field = None;
+        Ok(self.skip_rest(data))
+    }
+
+    /// Skip what's left of the value before
+    fn skip_rest(&mut self, data: &mut &[u8]) -> Decoded<()> {
+        let left = self.value_left.unwrap_or(0);
+        let n = data.len().min(left);
+        *data = &data[n..];
+        if n < left {
+            self.value_left = Some(left - n);
+            return Decoded::NeedMore(left - n);
+        }
+        self.value_left = None;
+        Decoded::Done(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
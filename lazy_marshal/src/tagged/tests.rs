@@ -0,0 +1,128 @@
+use super::*;
+use crate::{config::DefaultConfig, traits::IncrementalUnMarshal};
+
+#[test]
+fn test_skip_value() {
+    let bytes = marshal_tagged::<DefaultConfig>(1, WireType::Fixed16, 7u16.marshal())
+        .chain(marshal_tagged::<DefaultConfig>(
+            300,
+            WireType::LengthDelimited,
+            "hi".to_string().marshal(),
+        ))
+        .chain(marshal_tagged::<DefaultConfig>(
+            2,
+            WireType::Varint,
+            VarInt(1000u32).marshal(),
+        ))
+        .chain([9])
+        .collect::<Vec<_>>();
+    let mut data = &bytes[..];
+    for (id, wire) in [
+        (1, WireType::Fixed16),
+        (300, WireType::LengthDelimited),
+        (2, WireType::Varint),
+    ] {
+        assert_eq!(read_key::<DefaultConfig>(&mut data).unwrap(), (id, wire));
+        skip_value_from::<DefaultConfig>(&mut data, wire).unwrap();
+    }
+    assert_eq!(data, [9]);
+
+    // The input ends before the value does
+    assert!(matches!(
+        skip_value::<DefaultConfig>(&mut [1, 2, 3].into_iter(), WireType::Fixed32),
+        Err(MarshalError::InvalidSizedDecode(3))
+    ));
+    assert!(matches!(
+        skip_value::<DefaultConfig>(&mut [5, 1].into_iter(), WireType::LengthDelimited),
+        Err(MarshalError::InvalidSizedDecode(1))
+    ));
+    assert!(matches!(
+        read_key::<DefaultConfig>(&mut &[0b1111][..]),
+        Err(MarshalError::InvalidData(e)) if e == "unknown wire type 7"
+    ));
+}
+
+#[test]
+fn test_tagged_decoder() {
+    let bytes = VarInt(3u32)
+        .marshal()
+        .chain(marshal_tagged::<DefaultConfig>(
+            9,
+            WireType::LengthDelimited,
+            [1, 2, 3].into_iter(),
+        ))
+        .chain(marshal_tagged::<DefaultConfig>(
+            1,
+            WireType::Fixed32,
+            7u32.marshal(),
+        ))
+        .chain(marshal_tagged::<DefaultConfig>(
+            2,
+            WireType::LengthDelimited,
+            // More than the `u8` read from it
+            [4, 5].into_iter(),
+        ))
+        .collect::<Vec<_>>();
+
+    // Fed one byte at a time, except for the last
+    let mut decoder = TaggedDecoder::default();
+    let mut first = <u32 as IncrementalUnMarshal>::decoder();
+    let mut second = <u8 as IncrementalUnMarshal>::decoder();
+    let (mut a, mut b, mut skipped) = (None, None, vec![]);
+    for i in 0..bytes.len() - 1 {
+        let mut data = &bytes[i..=i];
+        while !data.is_empty() {
+            let Decoded::Done(Some(id)) = decoder.next_field::<DefaultConfig>(&mut data).unwrap()
+            else {
+                continue;
+            };
+            match id {
+                1 => {
+                    let decoded =
+                        decoder.feed::<DefaultConfig, _>(&mut first, WireType::Fixed32, &mut data);
+                    if let Decoded::Done(v) = decoded.unwrap() {
+                        a = Some(v);
+                    }
+                }
+                2 => {
+                    let decoded = decoder.feed::<DefaultConfig, _>(
+                        &mut second,
+                        WireType::LengthDelimited,
+                        &mut data,
+                    );
+                    if let Decoded::Done(v) = decoded.unwrap() {
+                        b = Some(v);
+                    }
+                }
+                id => {
+                    decoder.skip::<DefaultConfig>(&mut data).unwrap();
+                    skipped.push(id);
+                }
+            }
+        }
+    }
+    skipped.dedup();
+    assert_eq!((a, b, skipped), (Some(7), Some(4), vec![9]));
+    // The rest of the last value is skipped before saying it's done
+    assert_eq!(
+        decoder.next_field::<DefaultConfig>(&mut &[][..]).unwrap(),
+        Decoded::NeedMore(1)
+    );
+    assert_eq!(
+        decoder.next_field::<DefaultConfig>(&mut &[5][..]).unwrap(),
+        Decoded::Done(None)
+    );
+
+    // A field written differently than it's read
+    let mut decoder = TaggedDecoder::default();
+    let mut data = &bytes[..];
+    assert_eq!(
+        decoder.next_field::<DefaultConfig>(&mut data).unwrap(),
+        Decoded::Done(Some(9))
+    );
+    assert!(matches!(
+        decoder.feed::<DefaultConfig, _>(&mut first, WireType::Fixed32, &mut data),
+        Err(MarshalError::InvalidData(e))
+            if e == "expected a Fixed32 value, found LengthDelimited"
+    ));
+}
@@ -1,4 +1,11 @@
-use crate::error::MarshalError;
+use std::io::{self, Write};
+
+use crate::{
+    config::{Config, DefaultConfig},
+    error::MarshalError,
+    source::{with_iter, BorrowedSource, ByteSource},
+    utils::write_iter,
+};
 
 pub trait Marshal: Sized {
     /// Marshal the object into an iterator of bytes
@@ -6,9 +13,222 @@ pub trait Marshal: Sized {
     /// use lazy_marshal::prelude::*;
     ///
     /// let i: Vec<u8> = 260u32.marshal().collect();
-    /// assert!(i == vec![0, 0, 1, 4]);
+    /// assert!(i == vec![4, 1, 0, 0]);
     /// ```
     fn marshal(self) -> impl Iterator<Item = u8>;
+
+    /// Marshal the object using the wire format selected by `C`.
+    ///
+    /// Every type in this crate and every derived type honours `C`. The default implementation
+    /// ignores it and falls back to [`Marshal::marshal()`], so types marshalled by hand keep their own format.
+    /// ```
+    /// use lazy_marshal::prelude::*;
+    ///
+    /// struct BigEndian;
+    /// impl Config for BigEndian {
+    ///     const ENDIAN: Endian = Endian::Big;
+    /// }
+    ///
+    /// let i: Vec<u8> = 260u32.marshal_with::<BigEndian>().collect();
+    /// assert!(i == vec![0, 0, 1, 4]);
+    /// ```
+    fn marshal_with<C: Config>(self) -> impl Iterator<Item = u8> {
+        self.marshal()
+    }
+
+    /// How many bytes [`Marshal::marshal_with()`] writes, if that's known without marshalling.
+    ///
+    /// `Vec`s and `HashMap`s of values that know it give it as the size hint of their iterators
+    /// when it's asked for before their elements start, like `collect()` does, so collecting them
+    /// allocates once. It's only worked out then, a single time for the whole value. Every type in
+    /// this crate knows, and so do derived types without type parameters. The default
+    /// implementation doesn't.
+    /// ```
+    /// use lazy_marshal::prelude::*;
+    ///
+    /// let v = vec!["a".to_string(), "bc".to_string()];
+    /// assert_eq!(v.encoded_len_hint_with::<DefaultConfig>(), Some(8 + (8 + 1) + (8 + 2)));
+    /// assert_eq!(v.marshal().size_hint(), (27, Some(27)));
+    /// ```
+    fn encoded_len_hint_with<C: Config>(&self) -> Option<usize> {
+        None
+    }
+
+    /// Write the object straight into `w`, which is faster than going through
+    /// [`Marshal::marshal()`] a byte at a time since numbers and byte slices are written whole.
+    /// The bytes are the same.
+    ///
+    /// The default implementation writes the bytes of [`Marshal::marshal()`] in chunks.
+    ///
+    /// # Errors
+    /// It errors when `w` does
+    /// ```
+    /// use lazy_marshal::prelude::*;
+    ///
+    /// let mut out = Vec::new();
+    /// vec![1u16, 2].marshal_into(&mut out).unwrap();
+    /// assert_eq!(out, vec![1u16, 2].marshal().collect::<Vec<_>>());
+    /// ```
+    fn marshal_into<W: Write>(self, w: &mut W) -> io::Result<()> {
+        write_iter(w, self.marshal())
+    }
+
+    /// Write the object straight into `w` with the wire format selected by `C`.
+    /// See [`Marshal::marshal_into()`]
+    ///
+    /// # Errors
+    /// It errors when `w` does
+    fn marshal_into_with<C: Config, W: Write>(self, w: &mut W) -> io::Result<()> {
+        write_iter(w, self.marshal_with::<C>())
+    }
+
+    /// Write a `Vec` of values one after another, like [`Marshal::marshal_into_with()`] on each.
+    /// Bytes override it to write all of them at once.
+    ///
+    /// # Errors
+    /// It errors when `w` does
+    fn marshal_vec_into_with<C: Config, W: Write>(items: Vec<Self>, w: &mut W) -> io::Result<()> {
+        items
+            .into_iter()
+            .try_for_each(|item| item.marshal_into_with::<C, W>(w))
+    }
+
+    /// Marshal the object into a `Vec` with [`Marshal::marshal_into()`]. It's allocated once
+    /// when [`Marshal::encoded_len_hint_with()`] knows how long it'll be.
+    /// ```
+    /// use lazy_marshal::prelude::*;
+    ///
+    /// assert_eq!("hi".marshal_to_vec(), "hi".marshal().collect::<Vec<_>>());
+    /// assert_eq!(vec![1u16, 2].marshal_to_vec().capacity(), 8 + 2 * 2);
+    /// ```
+    fn marshal_to_vec(self) -> Vec<u8> {
+        let len = self.encoded_len_hint_with::<DefaultConfig>();
+        let mut out = Vec::with_capacity(len.unwrap_or(0));
+        self.marshal_into(&mut out)
+            .expect("writing to a Vec doesn't fail");
+        out
+    }
+
+    /// Marshal the object into the front of `buf` with [`Marshal::marshal_into()`], giving how
+    /// many bytes it took up
+    ///
+    /// # Errors
+    /// It errors with [`io::ErrorKind::WriteZero`] if the object doesn't fit
+    /// ```
+    /// use lazy_marshal::prelude::*;
+    ///
+    /// let mut buf = [0; 8];
+    /// assert_eq!(260u32.marshal_to_slice(&mut buf).unwrap(), 4);
+    /// assert_eq!(buf[..4], [4, 1, 0, 0]);
+    /// assert!(u64::MAX.marshal_to_slice(&mut buf[..4]).is_err());
+    /// ```
+    fn marshal_to_slice(self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len();
+        let mut rest = buf;
+        self.marshal_into(&mut rest)?;
+        Ok(len - rest.len())
+    }
+}
+
+/// Marshal an object from a reference without taking ownership of it.
+///
+/// The bytes produced are identical to [`Marshal::marshal()`], but the returned iterator
+/// borrows from `self`, so large values don't need to be cloned before being sent.
+/// Every `&T` where `T: MarshalRef` also implements [`Marshal`].
+///
+/// That includes `&[T]`, which used to be [`Marshal`] for any `T: Marshal + Clone` and now needs
+/// `T: MarshalRef`. Types that only implement [`Marshal`] by hand can get it back by cloning:
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// #[derive(Clone)]
+/// struct Id(u32);
+///
+/// impl Marshal for Id {
+///     fn marshal(self) -> impl Iterator<Item = u8> {
+///         self.0.marshal()
+///     }
+/// }
+///
+/// impl MarshalRef for Id {
+///     fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+///         self.clone().marshal()
+///     }
+/// }
+///
+/// let ids = [Id(1), Id(2)];
+/// assert_eq!(ids[..].marshal().count(), 8 + 2 * 4);
+/// ```
+pub trait MarshalRef {
+    /// Marshal the object into an iterator of bytes borrowing from `self`
+    /// ```
+    /// use lazy_marshal::prelude::*;
+    ///
+    /// let v = vec![1u16, 2, 3];
+    /// let i: Vec<u8> = v.marshal_ref().collect();
+    /// assert_eq!(i, v.marshal().collect::<Vec<_>>());
+    /// ```
+    fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_;
+
+    /// Marshal the object from a reference using the wire format selected by `C`.
+    /// See [`Marshal::marshal_with()`]
+    fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
+        self.marshal_ref()
+    }
+
+    /// How many bytes [`MarshalRef::marshal_ref()`] writes, without writing them.
+    ///
+    /// Every type in this crate and every derived type works it out without marshalling, and
+    /// their iterators give it as their [`Iterator::size_hint()`], so collecting them allocates
+    /// once. The default implementation counts the bytes.
+    /// ```
+    /// use lazy_marshal::prelude::*;
+    ///
+    /// let v = vec!["a".to_string(), "bc".to_string()];
+    /// assert_eq!(v.encoded_len(), 8 + (8 + 1) + (8 + 2));
+    /// assert_eq!(v.marshal_ref().size_hint(), (27, Some(27)));
+    /// ```
+    fn encoded_len(&self) -> usize {
+        self.marshal_ref().count()
+    }
+
+    /// How many bytes [`MarshalRef::marshal_ref_with()`] writes with the wire format selected by `C`.
+    /// See [`MarshalRef::encoded_len()`]
+    fn encoded_len_with<C: Config>(&self) -> usize {
+        self.marshal_ref_with::<C>().count()
+    }
+
+    /// Write the object straight into `w` without taking ownership of it.
+    /// See [`Marshal::marshal_into()`]
+    ///
+    /// # Errors
+    /// It errors when `w` does
+    fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_iter(w, self.marshal_ref())
+    }
+
+    /// Write the object straight into `w` with the wire format selected by `C`.
+    /// See [`Marshal::marshal_into()`]
+    ///
+    /// # Errors
+    /// It errors when `w` does
+    fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_iter(w, self.marshal_ref_with::<C>())
+    }
+
+    /// Write a slice of values one after another, like [`MarshalRef::marshal_ref_into_with()`]
+    /// on each. Bytes override it to write all of them at once.
+    ///
+    /// # Errors
+    /// It errors when `w` does
+    fn marshal_slice_into_with<C: Config, W: Write>(items: &[Self], w: &mut W) -> io::Result<()>
+    where
+        Self: Sized,
+    {
+        items
+            .iter()
+            .try_for_each(|item| item.marshal_ref_into_with::<C, W>(w))
+    }
 }
 
 pub trait UnMarshal: Sized {
@@ -26,6 +246,265 @@ pub trait UnMarshal: Sized {
     /// assert_eq!("Hello, World!".to_string(), decoded);
     /// ```
     fn unmarshal(data: &mut impl Iterator<Item = u8>) -> Result<Self, MarshalError>;
+
+    /// Unmarshal an iterator of bytes written with the wire format selected by `C`.
+    ///
+    /// Every type in this crate and every derived type honours `C`. The default implementation
+    /// ignores it and falls back to [`UnMarshal::unmarshal()`].
+    ///
+    /// # Errors
+    /// It errors when the data doesn't make sense or can't be decoded to a meaningful output
+    fn unmarshal_with<C: Config>(
+        data: &mut impl Iterator<Item = u8>,
+    ) -> Result<Self, MarshalError> {
+        Self::unmarshal(data)
+    }
+
+    /// Unmarshal `Self` from a [`ByteSource`], like a slice or a reader, which lets strings and
+    /// bytes be copied out of it all at once
+    ///
+    /// # Errors
+    /// It errors when the data doesn't make sense or can't be decoded to a meaningful output,
+    /// or reading `data` fails
+    ///
+    /// ```
+    /// use lazy_marshal::prelude::*;
+    ///
+    /// let bytes = "Hello, World!".marshal().collect::<Vec<_>>();
+    /// let decoded = String::unmarshal_from(&mut &bytes[..]).unwrap();
+    ///
+    /// assert_eq!("Hello, World!".to_string(), decoded);
+    /// ```
+    fn unmarshal_from(data: &mut impl ByteSource) -> Result<Self, MarshalError> {
+        Self::unmarshal_from_with::<DefaultConfig>(data)
+    }
+
+    /// [`UnMarshal::unmarshal_from()`] with the wire format selected by `C`.
+    ///
+    /// Every type in this crate and every derived type reads `data` directly. The default
+    /// implementation reads it a byte at a time through [`UnMarshal::unmarshal_with()`].
+    ///
+    /// # Errors
+    /// It errors when the data doesn't make sense or can't be decoded to a meaningful output,
+    /// or reading `data` fails
+    fn unmarshal_from_with<C: Config>(data: &mut impl ByteSource) -> Result<Self, MarshalError> {
+        with_iter(data, |data| Self::unmarshal_with::<C>(data))
+    }
+
+    /// Read `len` values one after another onto the end of `out`, like
+    /// [`UnMarshal::unmarshal_from_with()`] on each. The values before one that fails are left in
+    /// `out`. Bytes override it to read all of them at once.
+    ///
+    /// # Errors
+    /// It errors when reading any of the values does
+    fn unmarshal_vec_from_with<C: Config>(
+        data: &mut impl ByteSource,
+        len: usize,
+        out: &mut Vec<Self>,
+    ) -> Result<(), MarshalError> {
+        for _ in 0..len {
+            out.push(Self::unmarshal_from_with::<C>(data)?);
+        }
+        Ok(())
+    }
+}
+
+/// Unmarshal a value that can borrow from the bytes it's read from instead of copying them, like
+/// `&'de str`, `&'de [u8]` and `Cow<'de, str>`. The types that [`UnMarshal`] is implemented for
+/// in this crate implement it too, by copying, so borrowed and owned fields can be mixed.
+///
+/// It reads what [`Marshal`] writes, so a `&str` reads what a `String` writes.
+pub trait UnMarshalBorrowed<'de>: Sized {
+    /// Unmarshal `Self`, borrowing from `data` where it can
+    ///
+    /// # Errors
+    /// It errors when the data doesn't make sense or can't be decoded to a meaningful output
+    ///
+    /// ```
+    /// use lazy_marshal::prelude::*;
+    ///
+    /// let bytes = ("Hello", 7u16).marshal_to_vec();
+    /// let (s, n) = <(&str, u16)>::unmarshal_borrowed(&mut &bytes[..]).unwrap();
+    ///
+    /// assert_eq!((s, n), ("Hello", 7));
+    /// assert_eq!(s.as_ptr(), bytes[8..].as_ptr());
+    /// ```
+    fn unmarshal_borrowed(data: &mut impl BorrowedSource<'de>) -> Result<Self, MarshalError> {
+        Self::unmarshal_borrowed_with::<DefaultConfig>(data)
+    }
+
+    /// [`UnMarshalBorrowed::unmarshal_borrowed()`] with the wire format selected by `C`
+    ///
+    /// # Errors
+    /// It errors when the data doesn't make sense or can't be decoded to a meaningful output
+    fn unmarshal_borrowed_with<C: Config>(
+        data: &mut impl BorrowedSource<'de>,
+    ) -> Result<Self, MarshalError>;
+
+    /// Read `len` values one after another onto the end of `out`, like
+    /// [`UnMarshal::unmarshal_vec_from_with()`]
+    ///
+    /// # Errors
+    /// It errors when reading any of the values does
+    fn unmarshal_borrowed_vec_with<C: Config>(
+        data: &mut impl BorrowedSource<'de>,
+        len: usize,
+        out: &mut Vec<Self>,
+    ) -> Result<(), MarshalError> {
+        for _ in 0..len {
+            out.push(Self::unmarshal_borrowed_with::<C>(data)?);
+        }
+        Ok(())
+    }
+}
+
+/// Progress reported by a [`PushDecoder`] after it has been fed some bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decoded<T> {
+    /// At least this many more bytes are needed before the value is complete.
+    /// It is never more than what the value actually needs, so exactly this many bytes
+    /// can be read from a socket without reading into the next message.
+    NeedMore(usize),
+    /// The value finished decoding
+    Done(T),
+}
+
+impl<T> Decoded<T> {
+    /// Convert the finished value, leaving [`Decoded::NeedMore`] as it is
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Decoded<U> {
+        match self {
+            Decoded::NeedMore(n) => Decoded::NeedMore(n),
+            Decoded::Done(v) => Decoded::Done(f(v)),
+        }
+    }
+}
+
+/// A resumable decoder that is pushed bytes as they arrive instead of pulling them from an iterator.
+///
+/// This is what makes unmarshalling work with non-blocking sockets: a short read doesn't lose any
+/// progress, the decoder just asks for more.
+pub trait PushDecoder: Default {
+    type Output;
+
+    /// Feed bytes into the decoder. The bytes that get used are removed from the front of `data`,
+    /// so once [`Decoded::Done`] is returned, whatever is left in `data` belongs to the next value.
+    /// [`Decoded::NeedMore`] is only returned after all of `data` has been used.
+    ///
+    /// After returning [`Decoded::Done`] the decoder is reset and can decode another value.
+    /// After an error the decoder should be thrown away.
+    ///
+    /// # Errors
+    /// It errors when the data doesn't make sense or can't be decoded to a meaningful output
+    fn feed(&mut self, data: &mut &[u8]) -> Result<Decoded<Self::Output>, MarshalError>;
+
+    /// Feed bytes written with the wire format selected by `C` into the decoder.
+    /// The same config has to be used for every call while decoding a value.
+    ///
+    /// The default implementation ignores `C` and falls back to [`PushDecoder::feed()`].
+    ///
+    /// # Errors
+    /// It errors when the data doesn't make sense or can't be decoded to a meaningful output
+    fn feed_with<C: Config>(
+        &mut self,
+        data: &mut &[u8],
+    ) -> Result<Decoded<Self::Output>, MarshalError> {
+        self.feed(data)
+    }
+}
+
+/// Types that can be unmarshalled incrementally with a [`PushDecoder`]
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// let bytes: Vec<u8> = "Hello, World!".marshal().collect();
+/// let (first, second) = bytes.split_at(10);
+///
+/// let mut decoder = String::decoder();
+/// assert_eq!(decoder.feed(&mut &first[..]).unwrap(), Decoded::NeedMore(11));
+/// assert_eq!(
+///     decoder.feed(&mut &second[..]).unwrap(),
+///     Decoded::Done("Hello, World!".to_string())
+/// );
+/// ```
+pub trait IncrementalUnMarshal: UnMarshal {
+    type Decoder: PushDecoder<Output = Self>;
+
+    /// Create a fresh decoder for `Self`
+    fn decoder() -> Self::Decoder {
+        Self::Decoder::default()
+    }
+}
+
+/// Picks the field that's set in a union with `#[marshal(union_as = "discriminant")]`.
+///
+/// The index of the field it picks, in the order they're declared, is written in front of that
+/// field like an enum's tag, and only that field is read.
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// #[derive(Clone, Copy, Marshal)]
+/// #[repr(C)]
+/// struct Key {
+///     kind: u8,
+///     code: u32,
+/// }
+///
+/// #[derive(Clone, Copy, Marshal)]
+/// #[repr(C)]
+/// struct Scroll {
+///     kind: u8,
+///     delta: i16,
+/// }
+///
+/// #[derive(Clone, Copy, Marshal)]
+/// #[marshal(union_as = "discriminant")]
+/// #[repr(C)]
+/// union Event {
+///     key: Key,
+///     scroll: Scroll,
+/// }
+///
+/// // SAFETY: every field starts with `kind`, which is the index of the field that's set
+/// unsafe impl UnionDiscriminant for Event {
+///     fn discriminant(&self) -> usize {
+///         unsafe { self.key.kind.into() }
+///     }
+/// }
+///
+/// let event = Event { scroll: Scroll { kind: 1, delta: -3 } };
+/// assert_eq!(event.marshal().collect::<Vec<_>>(), [1, 1, 253, 255]);
+/// ```
+///
+/// Since the derives trust it, it can't be implemented without `unsafe`:
+/// ```compile_fail,E0200
+/// use lazy_marshal::prelude::*;
+///
+/// #[derive(Clone, Copy, Marshal)]
+/// #[marshal(union_as = "discriminant")]
+/// union Number {
+///     int: u32,
+///     float: f32,
+/// }
+///
+/// impl UnionDiscriminant for Number {
+///     fn discriminant(&self) -> usize {
+///         0
+///     }
+/// }
+/// ```
+///
+/// # Safety
+/// [`discriminant()`](UnionDiscriminant::discriminant) has to return the index of the field
+/// that's set, for every value. Picking another one reads it anyway, which can be uninitialised or
+/// invalid bytes. An index past the last field is safe, marshalling panics.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` doesn't say which of its fields is set",
+    note = "a union with `#[marshal(union_as = \"discriminant\")]` needs an \
+            `unsafe impl UnionDiscriminant`"
+)]
+pub unsafe trait UnionDiscriminant {
+    /// The index of the field that's set
+    fn discriminant(&self) -> usize;
 }
 
 /// Inspired by Rayon's [Either](https://crates.io/crates/either) crate
@@ -48,6 +527,13 @@ where
             Either::Right(iter) => iter.next(),
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Either::Left(iter) => iter.size_hint(),
+            Either::Right(iter) => iter.size_hint(),
+        }
+    }
 }
 
 /// Used as a wrapper when the [`Marshal::marshal()`] types differ on return values.
@@ -101,12 +587,16 @@ where
 ///     }
 /// }
 /// ```
-pub struct MarshalIterator(pub Box<dyn Iterator<Item = u8>>);
+pub struct MarshalIterator<'a>(pub Box<dyn Iterator<Item = u8> + 'a>);
 
-impl Iterator for MarshalIterator {
+impl Iterator for MarshalIterator<'_> {
     type Item = u8;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.0.next()
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
 }
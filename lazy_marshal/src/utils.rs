@@ -1,18 +1,400 @@
-pub(crate) fn readn_to_vec(
-    data: &mut impl Iterator<Item = u8>,
+use std::{
+    cell::OnceCell,
+    io::{self, Write},
+};
+
+use crate::{
+    config::{Config, Endian, IntEncoding, LengthPrefix},
+    error::MarshalError,
+    limits::{consume, Counted},
+    source::ByteSource,
+};
+
+pub fn readn_to_vec<C: Config>(
+    data: &mut impl ByteSource,
     n: usize,
-) -> Result<Vec<u8>, usize> {
-    let v = (0..n).fold(Vec::with_capacity(n), |mut acc, _| match data.next() {
-        Some(d) => {
-            acc.push(d);
-            acc
+) -> Result<Vec<u8>, MarshalError> {
+    consume::<C>(n)?;
+    let mut v = Vec::new();
+    data.read_to_vec(&mut v, n)?;
+    Ok(v)
+}
+
+/// Write `bytes` into `w` a chunk at a time, for values without a faster way to write themselves
+pub fn write_iter(w: &mut impl Write, bytes: impl Iterator<Item = u8>) -> io::Result<()> {
+    let mut buf = [0; 256];
+    let mut n = 0;
+    for b in bytes {
+        buf[n] = b;
+        n += 1;
+        if n == buf.len() {
+            w.write_all(&buf)?;
+            n = 0;
+        }
+    }
+    w.write_all(&buf[..n])
+}
+
+/// Fails to compile for unions that aren't `Copy`, which are the only ones the derives handle
+pub fn union_is_copy<T: Copy>() {}
+
+/// Types whose bytes are all set whatever their value: integers, floats and arrays of them.
+/// They're the only fields a union written as its bytes can have.
+///
+/// ```compile_fail
+/// use lazy_marshal::prelude::*;
+///
+/// // `(u8, u16)` has a byte of padding, which would be written out uninitialised
+/// #[derive(Clone, Copy, Marshal)]
+/// #[marshal(union_as = "bytes")]
+/// union Padded {
+///     pair: (u8, u16),
+///     int: u32,
+/// }
+/// ```
+///
+/// # Safety
+/// The type can't have padding, or anything else that leaves some of its bytes uninitialised.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can have padding, so it can't be in a union marshalled as bytes",
+    note = "the fields of a union with `#[marshal(union_as = \"bytes\")]` can only be integers, \
+            floats and arrays of them"
+)]
+pub unsafe trait NoPadding: Copy {}
+
+macro_rules! no_padding {
+    ($($ty:ty),+) => {$(
+        // SAFETY: every bit pattern of a number is a value, so none of its bytes are left unset
+        unsafe impl NoPadding for $ty {}
+    )+};
+}
+
+no_padding!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+// SAFETY: arrays are their elements one after the other, without any padding between them
+unsafe impl<T: NoPadding, const N: usize> NoPadding for [T; N] {}
+
+/// Fails to compile for union fields that could have padding
+pub fn union_field_has_no_padding<T: NoPadding>() {}
+
+/// Where `data` is, for [`MarshalError::within()`]
+pub fn position(data: &impl ByteSource) -> Option<usize> {
+    Some(data.position())
+}
+
+/// Longest LEB128 encoding of a `u128`
+const MAX_VARINT_LEN: usize = 19;
+
+/// The bytes of a single number, small enough to live on the stack
+pub(crate) struct IntBytes {
+    buf: [u8; MAX_VARINT_LEN],
+    pos: usize,
+    end: usize,
+}
+
+impl IntBytes {
+    /// Fixed width number given in little endian order
+    pub(crate) fn fixed<C: Config, const N: usize>(mut le: [u8; N]) -> Self {
+        if C::ENDIAN == Endian::Big {
+            le.reverse();
+        }
+        let mut buf = [0; MAX_VARINT_LEN];
+        buf[..N].copy_from_slice(&le);
+        Self {
+            buf,
+            pos: 0,
+            end: N,
+        }
+    }
+
+    /// The bytes left to give
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        &self.buf[self.pos..self.end]
+    }
+
+    pub(crate) fn varint(mut v: u128) -> Self {
+        let mut buf = [0; MAX_VARINT_LEN];
+        let mut end = 0;
+        loop {
+            buf[end] = (v & 0x7f) as u8;
+            v >>= 7;
+            end += 1;
+            if v == 0 {
+                break;
+            }
+            buf[end - 1] |= 0x80;
+        }
+        Self { buf, pos: 0, end }
+    }
+}
+
+impl Iterator for IntBytes {
+    type Item = u8;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.end {
+            return None;
+        }
+        self.pos += 1;
+        Some(self.buf[self.pos - 1])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.end - self.pos, Some(self.end - self.pos))
+    }
+}
+
+impl ExactSizeIterator for IntBytes {}
+
+/// How many bytes [`IntBytes::varint()`] writes `v` in
+pub(crate) fn varint_len(v: u128) -> usize {
+    let bits = 128 - v.leading_zeros() as usize;
+    bits.div_ceil(7).max(1)
+}
+
+/// How many bytes a `size` byte integer takes up, which is `varint` if the config makes it a varint
+pub(crate) fn int_len<C: Config>(size: usize, varint: u128) -> usize {
+    match C::INT_ENCODING {
+        IntEncoding::Fixed => size,
+        IntEncoding::Varint => varint_len(varint),
+    }
+}
+
+/// The bytes of a value whose length is only worked out by `len` once something asks for it,
+/// like `collect()`, which then allocates once. Until then it costs nothing.
+pub(crate) struct KnownLen<I, L> {
+    iter: I,
+    len: L,
+    total: OnceCell<usize>,
+    taken: usize,
+}
+
+impl<I, L: Fn() -> usize> KnownLen<I, L> {
+    pub(crate) fn new(iter: I, len: L) -> Self {
+        Self {
+            iter,
+            len,
+            total: OnceCell::new(),
+            taken: 0,
+        }
+    }
+}
+
+impl<I: Iterator<Item = u8>, L: Fn() -> usize> Iterator for KnownLen<I, L> {
+    type Item = u8;
+
+    #[inline]
+    fn next(&mut self) -> Option<u8> {
+        let b = self.iter.next()?;
+        self.taken += 1;
+        Some(b)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.total.get_or_init(&self.len) - self.taken;
+        (left, Some(left))
+    }
+
+    #[inline]
+    fn fold<B, F: FnMut(B, u8) -> B>(self, init: B, f: F) -> B {
+        self.iter.fold(init, f)
+    }
+}
+
+impl<I: Iterator<Item = u8>, L: Fn() -> usize> ExactSizeIterator for KnownLen<I, L> {}
+
+/// The bytes of a collection that's marshalled by value: the `prefix` with its length, then what
+/// `start` makes of its `items`. They're only given to `start` once the prefix has been taken, so
+/// until then `len` can work out how many bytes there are from them if something asks, like
+/// `collect()` after its first byte. Afterwards the size hint is whatever `start`'s iterator says.
+pub(crate) struct OwnedLen<S, I, F, L> {
+    prefix: IntBytes,
+    state: Owned<S, F, I>,
+    len: L,
+    total: OnceCell<Option<usize>>,
+    taken: usize,
+}
+
+enum Owned<S, F, I> {
+    Waiting(S, F),
+    Started(I),
+    /// Only while switching from one to the other
+    Switching,
+}
+
+impl<S, I, F: FnOnce(S) -> I, L: Fn(&S) -> Option<usize>> OwnedLen<S, I, F, L> {
+    pub(crate) fn new(prefix: IntBytes, items: S, len: L, start: F) -> Self {
+        Self {
+            prefix,
+            state: Owned::Waiting(items, start),
+            len,
+            total: OnceCell::new(),
+            taken: 0,
+        }
+    }
+}
+
+impl<S, I, F, L> Iterator for OwnedLen<S, I, F, L>
+where
+    I: Iterator<Item = u8>,
+    F: FnOnce(S) -> I,
+    L: Fn(&S) -> Option<usize>,
+{
+    type Item = u8;
+
+    #[inline]
+    fn next(&mut self) -> Option<u8> {
+        let b = match self.prefix.next() {
+            Some(b) => b,
+            None => {
+                if let Owned::Waiting(..) = self.state {
+                    let Owned::Waiting(items, start) =
+                        std::mem::replace(&mut self.state, Owned::Switching)
+                    else {
+                        unreachable!()
+                    };
+                    self.state = Owned::Started(start(items));
+                }
+                let Owned::Started(iter) = &mut self.state else {
+                    unreachable!("the items were being started")
+                };
+                iter.next()?
+            }
+        };
+        self.taken += 1;
+        Some(b)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let total = match &self.state {
+            Owned::Waiting(items, _) => *self.total.get_or_init(|| (self.len)(items)),
+            _ => self.total.get().copied().flatten(),
+        };
+        match (total, &self.state) {
+            (Some(total), _) => (total - self.taken, Some(total - self.taken)),
+            (None, Owned::Started(iter)) => iter.size_hint(),
+            (None, _) => (self.prefix.len(), None),
+        }
+    }
+
+    #[inline]
+    fn fold<B, G: FnMut(B, u8) -> B>(mut self, init: B, mut f: G) -> B {
+        let acc = self.prefix.by_ref().fold(init, &mut f);
+        match self.state {
+            Owned::Waiting(items, start) => start(items).fold(acc, f),
+            Owned::Started(iter) => iter.fold(acc, f),
+            Owned::Switching => unreachable!("the items were being started"),
+        }
+    }
+}
+
+pub(crate) fn zigzag(v: i128) -> u128 {
+    ((v << 1) ^ (v >> 127)) as u128
+}
+
+pub(crate) fn unzigzag(v: u128) -> i128 {
+    (v >> 1) as i128 ^ -((v & 1) as i128)
+}
+
+/// Read a fixed width number, returning its bytes in little endian order
+pub(crate) fn read_fixed<C: Config, const N: usize>(
+    data: &mut impl ByteSource,
+) -> Result<[u8; N], MarshalError> {
+    consume::<C>(N)?;
+    let mut d = [0; N];
+    data.read_exact(&mut d)?;
+    if C::ENDIAN == Endian::Big {
+        d.reverse();
+    }
+    Ok(d)
+}
+
+/// Add the next byte of a LEB128 number to `acc`. Returns `true` once the number is complete.
+///
+/// Only the shortest encoding of a number is accepted.
+pub(crate) fn varint_step(acc: &mut u128, i: usize, b: u8) -> Result<bool, MarshalError> {
+    let part = (b & 0x7f) as u128;
+    let shift = 7 * i as u32;
+    if shift >= 128 || (part << shift) >> shift != part {
+        Err(MarshalError::VarintOverflow)?
+    }
+    if b == 0 && i > 0 {
+        Err(MarshalError::VarintOverlong)?
+    }
+    *acc |= part << shift;
+    Ok(b & 0x80 == 0)
+}
+
+pub(crate) fn read_varint<C: Config>(data: &mut impl ByteSource) -> Result<u128, MarshalError> {
+    let _counted = Counted::enter::<C>();
+    let mut acc = 0;
+    for i in 0.. {
+        consume::<C>(1)?;
+        let b = match data.next_byte()? {
+            Some(b) => b,
+            None => Err(MarshalError::InvalidSizedDecode(i))?,
+        };
+        if varint_step(&mut acc, i, b)? {
+            break;
         }
-        None => acc,
-    });
+    }
+    Ok(acc)
+}
+
+/// The largest length [`marshal_len()`] can write
+pub(crate) fn max_len<C: Config>() -> usize {
+    match C::LENGTH_PREFIX {
+        LengthPrefix::U8 => u8::MAX as usize,
+        LengthPrefix::U16 => u16::MAX as usize,
+        LengthPrefix::U32 => u32::MAX.try_into().unwrap_or(usize::MAX),
+        LengthPrefix::U64 | LengthPrefix::Varint => usize::MAX,
+    }
+}
+
+/// Write the length prefix of a collection
+pub(crate) fn marshal_len<C: Config>(len: usize) -> IntBytes {
+    fn fit<C: Config, T: TryFrom<usize>>(len: usize) -> T {
+        T::try_from(len).unwrap_or_else(|_| {
+            panic!(
+                "A length of {len} doesn't fit in the {:?} length prefix",
+                C::LENGTH_PREFIX
+            )
+        })
+    }
 
-    if v.len() < n {
-        Err(v.len())
-    } else {
-        Ok(v)
+    match C::LENGTH_PREFIX {
+        LengthPrefix::U8 => IntBytes::fixed::<C, 1>(fit::<C, u8>(len).to_le_bytes()),
+        LengthPrefix::U16 => IntBytes::fixed::<C, 2>(fit::<C, u16>(len).to_le_bytes()),
+        LengthPrefix::U32 => IntBytes::fixed::<C, 4>(fit::<C, u32>(len).to_le_bytes()),
+        LengthPrefix::U64 => IntBytes::fixed::<C, 8>((len as u64).to_le_bytes()),
+        LengthPrefix::Varint => IntBytes::varint(len as u128),
     }
 }
+
+/// How many bytes [`marshal_len()`] writes
+pub(crate) fn len_prefix_len<C: Config>(len: usize) -> usize {
+    match C::LENGTH_PREFIX {
+        LengthPrefix::U8 => 1,
+        LengthPrefix::U16 => 2,
+        LengthPrefix::U32 => 4,
+        LengthPrefix::U64 => 8,
+        LengthPrefix::Varint => varint_len(len as u128),
+    }
+}
+
+/// Read the length prefix of a collection
+pub(crate) fn unmarshal_len<C: Config>(data: &mut impl ByteSource) -> Result<usize, MarshalError> {
+    let len = match C::LENGTH_PREFIX {
+        LengthPrefix::U8 => u8::from_le_bytes(read_fixed::<C, 1>(data)?) as u128,
+        LengthPrefix::U16 => u16::from_le_bytes(read_fixed::<C, 2>(data)?) as u128,
+        LengthPrefix::U32 => u32::from_le_bytes(read_fixed::<C, 4>(data)?) as u128,
+        LengthPrefix::U64 => u64::from_le_bytes(read_fixed::<C, 8>(data)?) as u128,
+        LengthPrefix::Varint => {
+            return read_varint::<C>(data)?
+                .try_into()
+                .map_err(|_| MarshalError::VarintOverflow)
+        }
+    };
+    len.try_into().map_err(|_| MarshalError::InvalidDecode)
+}
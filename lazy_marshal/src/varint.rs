@@ -0,0 +1,128 @@
+use std::io::{self, Write};
+
+use crate::{
+    config::{Config, DefaultConfig, Varints},
+    decoders::VarIntDecoder,
+    error::MarshalError,
+    source::{ByteSource, IterSource},
+    traits::{IncrementalUnMarshal, Marshal, MarshalRef, UnMarshal},
+};
+
+/// An integer that is always written as a varint (LEB128, zigzag encoded first if it's signed),
+/// no matter what [`Config::INT_ENCODING`] says. Useful for fields of a derived type that are usually small.
+///
+/// Decoding fails with [`MarshalError::VarintOverflow`] if the number doesn't fit in `T`, and with
+/// [`MarshalError::VarintOverlong`] if it isn't written in as few bytes as possible.
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// let bytes: Vec<u8> = VarInt(300u64).marshal().collect();
+/// assert_eq!(bytes, vec![0xac, 0x02]);
+/// assert_eq!(VarInt::<i32>(-2).marshal().collect::<Vec<_>>(), vec![3]);
+///
+/// assert_eq!(VarInt::<u64>::unmarshal(&mut bytes.into_iter()).unwrap(), VarInt(300));
+/// assert!(matches!(
+///     VarInt::<u16>::unmarshal(&mut [0x80, 0x80, 0x04].into_iter()),
+///     Err(MarshalError::VarintOverflow)
+/// ));
+/// assert!(matches!(
+///     VarInt::<u64>::unmarshal(&mut [0x81, 0x00].into_iter()),
+///     Err(MarshalError::VarintOverlong)
+/// ));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VarInt<T>(pub T);
+
+impl<T> From<T> for VarInt<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+macro_rules! varint_impl {
+    ($($ty:ident),*) => {$(
+        impl Marshal for VarInt<$ty> {
+            #[inline]
+            fn marshal(self) -> impl Iterator<Item = u8> {
+                self.marshal_with::<DefaultConfig>()
+            }
+
+            #[inline]
+            fn marshal_with<C: Config>(self) -> impl Iterator<Item = u8> {
+                self.0.marshal_with::<Varints<C>>()
+            }
+
+            #[inline]
+            fn encoded_len_hint_with<C: Config>(&self) -> Option<usize> {
+                Some(self.encoded_len_with::<C>())
+            }
+
+            #[inline]
+            fn marshal_into<W: Write>(self, w: &mut W) -> io::Result<()> {
+                self.marshal_into_with::<DefaultConfig, W>(w)
+            }
+
+            #[inline]
+            fn marshal_into_with<C: Config, W: Write>(self, w: &mut W) -> io::Result<()> {
+                self.0.marshal_into_with::<Varints<C>, W>(w)
+            }
+        }
+
+        impl MarshalRef for VarInt<$ty> {
+            #[inline]
+            fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+                (*self).marshal()
+            }
+
+            #[inline]
+            fn marshal_ref_with<C: Config>(&self) -> impl Iterator<Item = u8> + '_ {
+                (*self).marshal_with::<C>()
+            }
+
+            #[inline]
+            fn encoded_len(&self) -> usize {
+                self.encoded_len_with::<DefaultConfig>()
+            }
+
+            #[inline]
+            fn encoded_len_with<C: Config>(&self) -> usize {
+                self.0.encoded_len_with::<Varints<C>>()
+            }
+
+            #[inline]
+            fn marshal_ref_into<W: Write>(&self, w: &mut W) -> io::Result<()> {
+                (*self).marshal_into(w)
+            }
+
+            #[inline]
+            fn marshal_ref_into_with<C: Config, W: Write>(&self, w: &mut W) -> io::Result<()> {
+                (*self).marshal_into_with::<C, W>(w)
+            }
+        }
+
+        impl UnMarshal for VarInt<$ty> {
+            fn unmarshal(data: &mut impl Iterator<Item = u8>) -> Result<Self, MarshalError> {
+                Self::unmarshal_with::<DefaultConfig>(data)
+            }
+
+            fn unmarshal_with<C: Config>(
+                data: &mut impl Iterator<Item = u8>,
+            ) -> Result<Self, MarshalError> {
+                Self::unmarshal_from_with::<C>(&mut IterSource::new(data))
+            }
+
+            fn unmarshal_from_with<C: Config>(data: &mut impl ByteSource) -> Result<Self, MarshalError> {
+                $ty::unmarshal_from_with::<Varints<C>>(data).map(Self)
+            }
+        }
+
+        impl IncrementalUnMarshal for VarInt<$ty> {
+            type Decoder = VarIntDecoder<$ty>;
+        }
+    )*};
+}
+
+varint_impl!(u16, u32, u64, u128, usize, i16, i32, i64, i128, isize);
+
+#[cfg(test)]
+mod tests;
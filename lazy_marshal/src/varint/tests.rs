@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+
+use crate::prelude::*;
+
+#[test]
+fn test_varint_round_trip() {
+    for v in [0u64, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+        let bytes = VarInt(v).marshal().collect::<Vec<_>>();
+        assert_eq!(
+            bytes.len(),
+            (64 - v.leading_zeros() as usize).div_ceil(7).max(1)
+        );
+        assert_eq!(
+            VarInt::<u64>::unmarshal(&mut bytes.iter().cloned()).unwrap(),
+            VarInt(v)
+        );
+    }
+    for v in [0i128, -1, 1, -64, 64, i128::MIN, i128::MAX] {
+        let bytes = VarInt(v).marshal_ref().collect::<Vec<_>>();
+        assert_eq!(
+            VarInt::<i128>::unmarshal(&mut bytes.iter().cloned()).unwrap(),
+            VarInt(v)
+        );
+
+        let mut decoder = VarInt::<i128>::decoder();
+        let (last, rest) = bytes.split_last().unwrap();
+        for b in rest {
+            assert_eq!(decoder.feed(&mut &[*b][..]).unwrap(), Decoded::NeedMore(1));
+        }
+        assert_eq!(
+            decoder.feed(&mut &[*last][..]).unwrap(),
+            Decoded::Done(VarInt(v))
+        );
+    }
+
+    // The config's endianness and length prefix don't change how a `VarInt` is written
+    struct Network;
+    impl Config for Network {
+        const ENDIAN: Endian = Endian::Big;
+        const LENGTH_PREFIX: LengthPrefix = LengthPrefix::U16;
+    }
+    let v = vec![VarInt(1u32), VarInt(300)];
+    assert_eq!(
+        v.marshal_ref_with::<Network>().collect::<Vec<_>>(),
+        [0, 2, 1, 0xac, 0x02]
+    );
+}
+
+#[test]
+fn test_varint_lengths() {
+    let v = vec![1u8, 2, 3];
+    assert_eq!(v.marshal_ref().count(), 11);
+    assert_eq!(v.marshal_ref_with::<Varints>().count(), 4);
+
+    let mut hmap = HashMap::new();
+    hmap.insert("a".to_string(), vec![0u8; 200].into_boxed_slice());
+    let bytes = hmap.marshal_ref_with::<Varints>().collect::<Vec<_>>();
+    assert_eq!(&bytes[..5], [1, 1, b'a', 0xc8, 0x01]);
+    assert_eq!(
+        HashMap::<String, Box<[u8]>>::unmarshal_with::<Varints>(&mut bytes.iter().cloned())
+            .unwrap(),
+        hmap
+    );
+}
+
+#[test]
+fn test_varint_errors() {
+    // Too big for the type
+    let bytes = VarInt(u16::MAX as u32 + 1).marshal().collect::<Vec<_>>();
+    assert!(matches!(
+        VarInt::<u16>::unmarshal(&mut bytes.iter().cloned()),
+        Err(MarshalError::VarintOverflow)
+    ));
+    assert!(matches!(
+        VarInt::<u16>::decoder().feed(&mut &bytes[..]),
+        Err(MarshalError::VarintOverflow)
+    ));
+    assert!(matches!(
+        VarInt::<i16>::unmarshal(&mut [0xff, 0x01].into_iter()),
+        Ok(VarInt(-128))
+    ));
+
+    // Too big for a u128
+    let mut bytes = vec![0xff; 18];
+    bytes.push(0x04);
+    assert!(matches!(
+        u128::unmarshal_with::<Varints>(&mut bytes.iter().cloned()),
+        Err(MarshalError::VarintOverflow)
+    ));
+    bytes[18] = 0x03;
+    assert_eq!(
+        u128::unmarshal_with::<Varints>(&mut bytes.iter().cloned()).unwrap(),
+        u128::MAX
+    );
+
+    // Overlong encodings, for numbers and length prefixes
+    assert!(matches!(
+        VarInt::<u64>::unmarshal(&mut [0x80, 0x80, 0x00].into_iter()),
+        Err(MarshalError::VarintOverlong)
+    ));
+    assert!(matches!(
+        String::decoder().feed_with::<Varints>(&mut &[0x81, 0x00, b'a'][..]),
+        Err(MarshalError::VarintOverlong)
+    ));
+    assert!(matches!(
+        Vec::<u8>::unmarshal_with::<Varints>(&mut [0x81, 0x00, 1].into_iter())
+            .unwrap_err()
+            .kind(),
+        MarshalError::VarintOverlong
+    ));
+
+    // Runs out of bytes mid number
+    assert!(matches!(
+        VarInt::<u64>::unmarshal(&mut [0x80].into_iter()),
+        Err(MarshalError::InvalidSizedDecode(1))
+    ));
+}
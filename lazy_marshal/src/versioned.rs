@@ -0,0 +1,233 @@
+//! The layout of derived structs with `#[marshal(version = N)]`: the version the writer was at as a
+//! [`VarInt`], the length of the fields in bytes, and then the fields. Readers at an older version
+//! skip the fields they don't know about, and readers at a newer one fill in the missing fields
+//! with their defaults.
+
+use std::io::{self, Write};
+
+use crate::{
+    config::Config,
+    decoders::{LenDecoder, VarIntDecoder},
+    error::{MarshalError, PathSegment},
+    limits::consume,
+    source::{BorrowedSource, ByteSource},
+    traits::{Decoded, Marshal, MarshalRef, PushDecoder, UnMarshal},
+    utils::{len_prefix_len, marshal_len, position, unmarshal_len},
+    varint::VarInt,
+};
+
+/// Write the fields of a type at `version`. They're collected first to find their length.
+pub fn marshal_versioned<C: Config>(
+    version: u32,
+    fields: impl Iterator<Item = u8>,
+) -> impl Iterator<Item = u8> {
+    let fields = fields.collect::<Vec<_>>();
+    VarInt(version)
+        .marshal()
+        .chain(marshal_len::<C>(fields.len()))
+        .chain(fields)
+}
+
+/// How many bytes [`marshal_versioned()`] writes for `fields` bytes of fields
+pub fn versioned_len<C: Config>(version: u32, fields: usize) -> usize {
+    VarInt(version).encoded_len() + len_prefix_len::<C>(fields) + fields
+}
+
+/// Write the version and length in front of `fields` bytes of fields, for types that write their
+/// fields themselves after it
+pub fn write_versioned<C: Config, W: Write>(
+    w: &mut W,
+    version: u32,
+    fields: usize,
+) -> io::Result<()> {
+    VarInt(version).marshal_into(w)?;
+    w.write_all(marshal_len::<C>(fields).as_slice())
+}
+
+/// The fields of a versioned type, or the value of a length delimited field of a tagged one,
+/// ending where the length in front of them says
+pub struct Body<'a, S> {
+    data: &'a mut S,
+    version: u32,
+    left: usize,
+}
+
+impl<'a, S: ByteSource> Body<'a, S> {
+    /// Read the version and length in front of the fields
+    pub fn read<C: Config>(data: &'a mut S) -> Result<Self, MarshalError> {
+        let VarInt(version) = VarInt::<u32>::unmarshal_from_with::<C>(data)?;
+        let left = unmarshal_len::<C>(data)?;
+        Ok(Self::new(data, version, left))
+    }
+
+    /// Read the [`VarInt`] length in front of a [`WireType::LengthDelimited`] value
+    ///
+    /// [`WireType::LengthDelimited`]: crate::tagged::WireType::LengthDelimited
+    pub fn delimited<C: Config>(data: &'a mut S) -> Result<Self, MarshalError> {
+        let VarInt(left) = VarInt::<u64>::unmarshal_from_with::<C>(data)?;
+        let left = usize::try_from(left).map_err(|_| MarshalError::VarintOverflow)?;
+        Ok(Self::new(data, 0, left))
+    }
+
+    fn new(data: &'a mut S, version: u32, left: usize) -> Self {
+        Self {
+            data,
+            version,
+            left,
+        }
+    }
+
+    /// The version of the type that wrote the fields
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// [`MarshalError::within()`], for an error from inside the body
+    pub fn within(
+        &self,
+        e: MarshalError,
+        root: &'static str,
+        path: &[PathSegment],
+        start: Option<usize>,
+    ) -> MarshalError {
+        e.within(root, path, start, position(self))
+    }
+
+    /// Skip the fields from newer versions that weren't read, or the rest of a value
+    pub fn finish<C: Config>(self) -> Result<(), MarshalError> {
+        consume::<C>(self.left)?;
+        self.data.skip(self.left)
+    }
+
+    /// Run `read` on the first `n` bytes of the body, or all of it if that's shorter, which then
+    /// errors as if the input had ended there
+    fn limited(
+        &mut self,
+        n: usize,
+        read: impl FnOnce(&mut S, usize) -> Result<(), MarshalError>,
+    ) -> Result<(), MarshalError> {
+        let allowed = n.min(self.left);
+        let result = read(self.data, allowed);
+        self.left -= match &result {
+            Ok(()) => allowed,
+            Err(MarshalError::InvalidSizedDecode(read)) => *read,
+            Err(_) => 0,
+        };
+        match (result, allowed < n) {
+            (Ok(()), true) => Err(MarshalError::InvalidSizedDecode(allowed)),
+            (result, _) => result,
+        }
+    }
+}
+
+impl<S: ByteSource> ByteSource for Body<'_, S> {
+    fn next_byte(&mut self) -> Result<Option<u8>, MarshalError> {
+        if self.left == 0 {
+            return Ok(None);
+        }
+        let b = self.data.next_byte()?;
+        self.left -= b.is_some() as usize;
+        Ok(b)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), MarshalError> {
+        self.limited(buf.len(), |data, n| data.read_exact(&mut buf[..n]))
+    }
+
+    fn read_to_vec(&mut self, v: &mut Vec<u8>, n: usize) -> Result<(), MarshalError> {
+        self.limited(n, |data, n| data.read_to_vec(v, n))
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), MarshalError> {
+        self.limited(n, |data, n| data.skip(n))
+    }
+
+    fn remaining(&self) -> Option<usize> {
+        self.data.remaining().map(|r| r.min(self.left))
+    }
+
+    fn position(&self) -> usize {
+        self.data.position()
+    }
+}
+
+impl<'de, S: BorrowedSource<'de>> BorrowedSource<'de> for Body<'_, S> {
+    fn borrow_bytes(&mut self, n: usize) -> Result<&'de [u8], MarshalError> {
+        let mut bytes: &[u8] = &[];
+        self.limited(n, |data, n| {
+            bytes = data.borrow_bytes(n)?;
+            Ok(())
+        })?;
+        Ok(bytes)
+    }
+}
+
+/// [`Body`] for push decoders
+#[derive(Default)]
+pub struct BodyDecoder {
+    version_decoder: VarIntDecoder<u32>,
+    len: LenDecoder,
+    version: Option<u32>,
+    /// Bytes of the body that haven't been fed yet, once the length is known
+    left: Option<usize>,
+}
+
+impl BodyDecoder {
+    /// Feed the version and length in front of the fields, returning the version once they're done
+    pub fn feed_header<C: Config>(
+        &mut self,
+        data: &mut &[u8],
+    ) -> Result<Decoded<u32>, MarshalError> {
+        let version = match self.version {
+            Some(version) => version,
+            None => match self.version_decoder.feed_with::<C>(data)? {
+                Decoded::Done(VarInt(version)) => *self.version.insert(version),
+                Decoded::NeedMore(n) => return Ok(Decoded::NeedMore(n)),
+            },
+        };
+        if self.left.is_none() {
+            match self.len.feed_with::<C>(data)? {
+                Decoded::Done(len) => self.left = Some(len),
+                Decoded::NeedMore(n) => return Ok(Decoded::NeedMore(n)),
+            }
+        }
+        Ok(Decoded::Done(version))
+    }
+
+    /// Feed `decoder` the part of `data` that's inside the body
+    pub fn feed<C: Config, D: PushDecoder>(
+        &mut self,
+        decoder: &mut D,
+        data: &mut &[u8],
+    ) -> Result<Decoded<D::Output>, MarshalError> {
+        let left = self.left.unwrap_or(0);
+        let n = data.len().min(left);
+        let mut body = &data[..n];
+        let decoded = decoder.feed_with::<C>(&mut body)?;
+        let used = n - body.len();
+        *data = &data[used..];
+        self.left = Some(left - used);
+        match decoded {
+            // The body ended before the field did
+            Decoded::NeedMore(_) if left == used => Err(MarshalError::EarlyStreamEnd),
+            Decoded::NeedMore(more) => Ok(Decoded::NeedMore(more.min(left - used))),
+            done => Ok(done),
+        }
+    }
+
+    /// Skip the fields from newer versions that weren't read, getting ready for the next value
+    pub fn finish(&mut self, data: &mut &[u8]) -> Decoded<()> {
+        let left = self.left.unwrap_or(0);
+        let n = data.len().min(left);
+        *data = &data[n..];
+        if n < left {
+            self.left = Some(left - n);
+            return Decoded::NeedMore(left - n);
+        }
+        *self = Self::default();
+        Decoded::Done(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
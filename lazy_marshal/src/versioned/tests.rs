@@ -0,0 +1,105 @@
+use super::*;
+use crate::{
+    config::{DefaultConfig, Varints},
+    limits::{DecodeLimits, Limit, Nested},
+};
+
+#[test]
+fn test_body() {
+    let bytes = marshal_versioned::<Varints>(300, [1, 2, 3].into_iter())
+        .chain([9])
+        .collect::<Vec<_>>();
+    assert_eq!(bytes, [0xac, 0x02, 3, 1, 2, 3, 9]);
+
+    let mut data = &bytes[..];
+    let mut body = Body::read::<Varints>(&mut data).unwrap();
+    assert_eq!(body.version(), 300);
+    assert_eq!(body.remaining(), Some(3));
+    assert_eq!(body.next_byte().unwrap(), Some(1));
+    // Reads can't go past the end of the body
+    let mut buf = [0; 3];
+    assert!(matches!(
+        body.read_exact(&mut buf),
+        Err(MarshalError::InvalidSizedDecode(2))
+    ));
+    assert_eq!(buf[..2], [2, 3]);
+    assert_eq!(body.remaining(), Some(0));
+    body.finish::<Varints>().unwrap();
+    assert_eq!(data, [9]);
+
+    // The rest is skipped, but nothing after the body
+    let mut data = &bytes[..];
+    let body = Body::read::<Varints>(&mut data).unwrap();
+    body.finish::<Varints>().unwrap();
+    assert_eq!(data, [9]);
+
+    // The input ends before the body does
+    let mut data = &bytes[..4];
+    let body = Body::read::<Varints>(&mut data).unwrap();
+    assert!(matches!(
+        body.finish::<Varints>(),
+        Err(MarshalError::InvalidSizedDecode(1))
+    ));
+}
+
+#[test]
+fn test_skipped_bytes_are_limited() {
+    struct Small;
+
+    impl Config for Small {
+        const LIMITS: DecodeLimits = DecodeLimits::NONE.max_bytes(16);
+    }
+
+    let bytes = marshal_versioned::<Small>(1, [0; 32].into_iter()).collect::<Vec<_>>();
+    let mut data = &bytes[..];
+    let _nested = Nested::enter::<Small>().unwrap();
+    let body = Body::read::<Small>(&mut data).unwrap();
+    assert!(matches!(
+        body.finish::<Small>(),
+        Err(MarshalError::LimitExceeded {
+            limit: Limit::Bytes,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_body_decoder() {
+    let bytes =
+        marshal_versioned::<DefaultConfig>(2, 7u32.marshal().chain([0xff; 3])).collect::<Vec<_>>();
+
+    let mut decoder = BodyDecoder::default();
+    let mut field = <u32 as crate::IncrementalUnMarshal>::decoder();
+    assert_eq!(
+        decoder
+            .feed_header::<DefaultConfig>(&mut &bytes[..3])
+            .unwrap(),
+        Decoded::NeedMore(6)
+    );
+    let mut data = &bytes[3..];
+    assert_eq!(
+        decoder.feed_header::<DefaultConfig>(&mut data).unwrap(),
+        Decoded::Done(2)
+    );
+    assert_eq!(
+        decoder
+            .feed::<DefaultConfig, _>(&mut field, &mut data)
+            .unwrap(),
+        Decoded::Done(7)
+    );
+    // The fields of newer versions
+    assert_eq!(decoder.finish(&mut &data[..1]), Decoded::NeedMore(2));
+    assert_eq!(decoder.finish(&mut &data[1..]), Decoded::Done(()));
+
+    // A field can't run past the end of the body
+    let bytes = marshal_versioned::<DefaultConfig>(1, [1, 2].into_iter())
+        .chain([3, 4])
+        .collect::<Vec<_>>();
+    let mut decoder = BodyDecoder::default();
+    let mut data = &bytes[..];
+    decoder.feed_header::<DefaultConfig>(&mut data).unwrap();
+    assert!(matches!(
+        decoder.feed::<DefaultConfig, _>(&mut field, &mut data),
+        Err(MarshalError::EarlyStreamEnd)
+    ));
+}
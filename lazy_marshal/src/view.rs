@@ -0,0 +1,509 @@
+//! Views look at marshalled bytes in place. A view of a `Vec<Deal>` only works out where each deal
+//! starts, and a deal's fields are only read when they're asked for, so reading one field of one
+//! element doesn't decode the rest.
+
+use std::{
+    borrow::Cow, cell::RefCell, fmt, iter::FusedIterator, marker::PhantomData, mem::size_of,
+};
+
+use crate::{
+    config::{Config, DefaultConfig, IntEncoding},
+    error::{MarshalError, PathSegment},
+    limits::Nested,
+    source::ByteSource,
+    traits::{UnMarshal, UnMarshalBorrowed},
+    utils::{position, unmarshal_len},
+    varint::VarInt,
+};
+
+/// Types whose marshalled bytes can be looked at without decoding all of them. Derive it for a
+/// struct `Deal` to get a `DealView` with a method for each field, which reads only that field.
+///
+/// Numbers are read as they are, strings and bytes borrow from the input, and a `Vec<T>` becomes a
+/// [`VecView`] that finds its elements on demand.
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// let bytes = vec!["a".to_string(), "view".to_string()].marshal_to_vec();
+/// let strings = Vec::<String>::view_from(&mut &bytes[..]).unwrap();
+///
+/// assert_eq!(strings.len(), 2);
+/// assert_eq!(strings.get(1).unwrap(), Some("view"));
+/// ```
+pub trait Viewable {
+    /// What a marshalled value is seen as, which reads the rest of its bytes with `C`
+    type View<'a, C: Config>;
+
+    /// How many bytes every value takes up with `C`, if that's always the same. Elements of a
+    /// [`VecView`] with a fixed length are found without looking at the ones before them.
+    fn fixed_len<C: Config>() -> Option<usize> {
+        None
+    }
+
+    /// Move `data` past a value without decoding it. Only what's needed to find where the value
+    /// ends is checked, so a view can still fail to read the parts it skipped over.
+    ///
+    /// # Errors
+    /// It errors when the data ends before the value does, or its lengths are invalid
+    fn skip_from_with<C: Config>(data: &mut &[u8]) -> Result<(), MarshalError>;
+
+    /// A view of the value at the start of `data`, moving `data` past the parts of it that were
+    /// read straight away. Collections and derived types aren't walked to find where they end, so
+    /// their views take the rest of `data` unless they have a [fixed length](Viewable::fixed_len).
+    /// [`Viewable::skip_from_with()`] moves past all of a value.
+    ///
+    /// # Errors
+    /// It errors when the data ends before the value does, or the parts of it that are read
+    /// straight away are invalid
+    fn view_from<'a>(data: &mut &'a [u8]) -> Result<Self::View<'a, DefaultConfig>, MarshalError> {
+        Self::view_from_with::<DefaultConfig>(data)
+    }
+
+    /// [`Viewable::view_from()`] with the wire format selected by `C`
+    ///
+    /// # Errors
+    /// It errors when the data ends before the value does, or the parts of it that are read
+    /// straight away are invalid
+    fn view_from_with<'a, C: Config>(
+        data: &mut &'a [u8],
+    ) -> Result<Self::View<'a, C>, MarshalError>;
+}
+
+/// How many bytes a number written as `$repr` takes up with `$c`
+macro_rules! num_fixed_len {
+    (int, $c:ident, $repr:ident) => {
+        match $c::INT_ENCODING {
+            IntEncoding::Fixed => Some(size_of::<$repr>()),
+            IntEncoding::Varint => None,
+        }
+    };
+    ($kind:ident, $c:ident, $repr:ident) => {
+        Some(size_of::<$repr>())
+    };
+}
+
+/// `$ty` goes over the wire as `$repr`, and is seen as itself
+macro_rules! viewed_as_is {
+    ($($ty:ty as $repr:ident: $kind:ident),* $(,)?) => {$(
+        impl Viewable for $ty {
+            type View<'a, C: Config> = Self;
+
+            #[inline]
+            fn fixed_len<C: Config>() -> Option<usize> {
+                num_fixed_len!($kind, C, $repr)
+            }
+
+            fn skip_from_with<C: Config>(data: &mut &[u8]) -> Result<(), MarshalError> {
+                match Self::fixed_len::<C>() {
+                    Some(len) => data.skip(len),
+                    None => Self::unmarshal_from_with::<C>(data).map(drop),
+                }
+            }
+
+            #[inline]
+            fn view_from_with<'a, C: Config>(
+                data: &mut &'a [u8],
+            ) -> Result<Self::View<'a, C>, MarshalError> {
+                Self::unmarshal_from_with::<C>(data)
+            }
+        }
+    )*};
+}
+
+viewed_as_is! {
+    bool as u8: byte, u8 as u8: byte, i8 as i8: byte, f32 as f32: float, f64 as f64: float,
+    u16 as u16: int, u32 as u32: int, u64 as u64: int, u128 as u128: int, usize as u64: int,
+    i16 as i16: int, i32 as i32: int, i64 as i64: int, i128 as i128: int, isize as i64: int,
+    char as u32: int,
+}
+viewed_as_is! {
+    VarInt<u16> as u16: varint, VarInt<u32> as u32: varint, VarInt<u64> as u64: varint,
+    VarInt<u128> as u128: varint, VarInt<usize> as u64: varint, VarInt<i16> as i16: varint,
+    VarInt<i32> as i32: varint, VarInt<i64> as i64: varint, VarInt<i128> as i128: varint,
+    VarInt<isize> as i64: varint,
+}
+
+impl<T> Viewable for PhantomData<T> {
+    type View<'a, C: Config> = Self;
+
+    #[inline]
+    fn fixed_len<C: Config>() -> Option<usize> {
+        Some(0)
+    }
+
+    #[inline]
+    fn skip_from_with<C: Config>(_data: &mut &[u8]) -> Result<(), MarshalError> {
+        Ok(())
+    }
+
+    #[inline]
+    fn view_from_with<'a, C: Config>(
+        _data: &mut &'a [u8],
+    ) -> Result<Self::View<'a, C>, MarshalError> {
+        Ok(PhantomData)
+    }
+}
+
+/// `$ty` is a length and then that many bytes, which are seen as a `$view` borrowed from the input
+macro_rules! viewed_borrowed {
+    ($check:ident => $view:ty: $($ty:ty),*) => {$(
+        impl Viewable for $ty {
+            type View<'a, C: Config> = &'a $view;
+
+            fn skip_from_with<C: Config>(data: &mut &[u8]) -> Result<(), MarshalError> {
+                let len = C::LIMITS.$check(unmarshal_len::<C>(data)?)?;
+                data.skip(len)
+            }
+
+            #[inline]
+            fn view_from_with<'a, C: Config>(
+                data: &mut &'a [u8],
+            ) -> Result<Self::View<'a, C>, MarshalError> {
+                <&$view>::unmarshal_borrowed_with::<C>(data)
+            }
+        }
+    )*};
+}
+
+viewed_borrowed!(check_string_len => str: String, &str, Cow<'_, str>);
+viewed_borrowed!(check_collection_len => [u8]: Box<[u8]>, &[u8], Cow<'_, [u8]>);
+
+impl<T: Viewable> Viewable for Vec<T> {
+    type View<'a, C: Config> = VecView<'a, T, C>;
+
+    fn skip_from_with<C: Config>(data: &mut &[u8]) -> Result<(), MarshalError> {
+        let start = position(data);
+        let _nested = Nested::enter::<C>().map_err(|e| e.within("Vec", &[], start, start))?;
+        let len = view_len::<C>(data, start)?;
+        match T::fixed_len::<C>() {
+            Some(size) => data
+                .skip(len.saturating_mul(size))
+                .map_err(|e| e.within("Vec", &[], start, position(data))),
+            None => (0..len).try_for_each(|i| {
+                T::skip_from_with::<C>(data)
+                    .map_err(|e| e.within("Vec", &[PathSegment::Index(i)], start, position(data)))
+            }),
+        }
+    }
+
+    fn view_from_with<'a, C: Config>(
+        data: &mut &'a [u8],
+    ) -> Result<Self::View<'a, C>, MarshalError> {
+        let start = position(data);
+        let _nested = Nested::enter::<C>().map_err(|e| e.within("Vec", &[], start, start))?;
+        let len = view_len::<C>(data, start)?;
+        let elements = *data;
+        // Only elements with a fixed length say where the vector ends without walking them
+        match T::fixed_len::<C>() {
+            Some(size) => data
+                .skip(len.saturating_mul(size))
+                .map_err(|e| e.within("Vec", &[], start, position(data)))?,
+            None => *data = &[],
+        }
+        Ok(VecView {
+            len,
+            bytes: &elements[..elements.len() - data.len()],
+            offsets: RefCell::new(Vec::new()),
+            _marker: PhantomData,
+        })
+    }
+}
+
+/// Read the length in front of a vector's elements
+fn view_len<C: Config>(data: &mut &[u8], start: Option<usize>) -> Result<usize, MarshalError> {
+    unmarshal_len::<C>(data)
+        .and_then(|len| C::LIMITS.check_collection_len(len))
+        .map_err(|e| e.within("Vec", &[], start, position(data)))
+}
+
+impl<T: Viewable> Viewable for Option<T> {
+    type View<'a, C: Config> = Option<T::View<'a, C>>;
+
+    fn skip_from_with<C: Config>(data: &mut &[u8]) -> Result<(), MarshalError> {
+        match is_some::<C>(data)? {
+            true => T::skip_from_with::<C>(data),
+            false => Ok(()),
+        }
+    }
+
+    fn view_from_with<'a, C: Config>(
+        data: &mut &'a [u8],
+    ) -> Result<Self::View<'a, C>, MarshalError> {
+        Ok(match is_some::<C>(data)? {
+            true => Some(T::view_from_with::<C>(data)?),
+            false => None,
+        })
+    }
+}
+
+/// Read the byte in front of an `Option` saying whether it holds a value
+fn is_some<C: Config>(data: &mut &[u8]) -> Result<bool, MarshalError> {
+    match u8::unmarshal_from_with::<C>(data)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        other => Err(MarshalError::InvalidData(format!(
+            "Found '{other}' when unmarshalling the option. Should be either 0 or 1"
+        ))),
+    }
+}
+
+#[cfg(feature = "tuples")]
+macro_rules! tuple_viewed {
+    ($($($t:ident)+;)+) => {$(
+        #[cfg_attr(docsrs, doc(hidden))]
+        impl<$($t: Viewable),+> Viewable for ($($t,)+) {
+            type View<'a, C: Config> = ($($t::View<'a, C>,)+);
+
+            fn fixed_len<C: Config>() -> Option<usize> {
+                Some(0 $(+ $t::fixed_len::<C>()?)+)
+            }
+
+            fn skip_from_with<C: Config>(data: &mut &[u8]) -> Result<(), MarshalError> {
+                $($t::skip_from_with::<C>(data)?;)+
+                Ok(())
+            }
+
+            fn view_from_with<'a, C: Config>(
+                data: &mut &'a [u8],
+            ) -> Result<Self::View<'a, C>, MarshalError> {
+                let count = [$(stringify!($t)),+].len();
+                let mut i = 0;
+                Ok(($({
+                    i += 1;
+                    view_part::<$t, C>(data, i == count)?
+                },)+))
+            }
+        }
+    )+};
+}
+
+/// A view of one part of a value, moving `data` past all of it unless it's the `last` part
+#[cfg(feature = "tuples")]
+fn view_part<'a, T: Viewable, C: Config>(
+    data: &mut &'a [u8],
+    last: bool,
+) -> Result<T::View<'a, C>, MarshalError> {
+    if last {
+        return T::view_from_with::<C>(data);
+    }
+    let view = T::view_from_with::<C>(&mut { *data })?;
+    T::skip_from_with::<C>(data)?;
+    Ok(view)
+}
+
+#[cfg(feature = "tuples")]
+tuple_viewed!(
+    T0;
+    T0 T1;
+    T0 T1 T2;
+    T0 T1 T2 T3;
+    T0 T1 T2 T3 T4;
+    T0 T1 T2 T3 T4 T5;
+    T0 T1 T2 T3 T4 T5 T6;
+    T0 T1 T2 T3 T4 T5 T6 T7;
+);
+
+/// A marshalled `Vec<T>`, whose elements are only read when they're asked for. Elements with a
+/// [fixed length](Viewable::fixed_len) are found straight away, others by skipping the ones before.
+/// Where each element starts is remembered once it's been found, so no element is skipped twice,
+/// and nothing is skipped to make the view.
+///
+/// Errors say where they happened from the start of the elements, after the vector's length.
+/// ```
+/// use lazy_marshal::prelude::*;
+///
+/// let bytes = vec![(1u32, "one"), (2, "two"), (3, "three")].marshal_to_vec();
+/// let view = Vec::<(u32, String)>::view_from(&mut &bytes[..]).unwrap();
+///
+/// assert_eq!(view.get(2).unwrap(), Some((3, "three")));
+/// assert_eq!(view.get(3).unwrap(), None);
+/// let ids = view.iter().map(|pair| pair.map(|(id, _)| id)).collect::<Result<Vec<_>, _>>();
+/// assert_eq!(ids.unwrap(), [1, 2, 3]);
+/// ```
+pub struct VecView<'a, T, C = DefaultConfig> {
+    len: usize,
+    bytes: &'a [u8],
+    /// Where the elements found so far start in `bytes`, for elements without a fixed length
+    offsets: RefCell<Vec<usize>>,
+    _marker: PhantomData<fn() -> (T, C)>,
+}
+
+impl<'a, T: Viewable, C: Config> VecView<'a, T, C> {
+    /// How many elements there are
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether there aren't any elements
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The marshalled elements, without the length in front of them. Unless the elements have a
+    /// fixed length, this goes on to the end of the input the view was made from, since where the
+    /// last element ends isn't looked for.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// A view of the element at `index`, or `None` if it's out of bounds.
+    ///
+    /// Elements without a fixed length are found by skipping over the ones before, starting from
+    /// the furthest one found so far. Getting every element in order costs the same as reading
+    /// them once, and getting one that's already been found doesn't skip anything. Where each
+    /// element starts is kept until the view is dropped, which is a `usize` per element.
+    ///
+    /// # Errors
+    /// It errors when the element, or one it skips over to find it, is invalid
+    pub fn get(&self, index: usize) -> Result<Option<T::View<'a, C>>, MarshalError> {
+        if index >= self.len {
+            return Ok(None);
+        }
+        let start = position(&self.bytes);
+        let mut data = self.bytes;
+        let data = &mut data;
+        match T::fixed_len::<C>() {
+            Some(size) => *data = &self.bytes[index * size..],
+            None => {
+                let mut offsets = self.offsets.borrow_mut();
+                if offsets.is_empty() {
+                    offsets.push(0);
+                }
+                let known = index.min(offsets.len() - 1);
+                *data = &self.bytes[offsets[known]..];
+                for i in known..index {
+                    T::skip_from_with::<C>(data).map_err(|e| {
+                        e.within("Vec", &[PathSegment::Index(i)], start, position(data))
+                    })?;
+                    offsets.push(self.bytes.len() - data.len());
+                }
+            }
+        }
+        T::view_from_with::<C>(data)
+            .map(Some)
+            .map_err(|e| e.within("Vec", &[PathSegment::Index(index)], start, position(data)))
+    }
+
+    /// Views of the elements, in order
+    pub fn iter(&self) -> VecViewIter<'a, T, C> {
+        VecViewIter {
+            index: 0,
+            len: self.len,
+            skip: false,
+            start: position(&self.bytes),
+            data: self.bytes,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, C> Clone for VecView<'_, T, C> {
+    fn clone(&self) -> Self {
+        Self {
+            len: self.len,
+            bytes: self.bytes,
+            offsets: self.offsets.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, C> fmt::Debug for VecView<'_, T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VecView")
+            .field("len", &self.len)
+            .field("bytes", &self.bytes.len())
+            .finish()
+    }
+}
+
+impl<'a, T: Viewable, C: Config> IntoIterator for VecView<'a, T, C> {
+    type Item = Result<T::View<'a, C>, MarshalError>;
+    type IntoIter = VecViewIter<'a, T, C>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T: Viewable, C: Config> IntoIterator for &VecView<'a, T, C> {
+    type Item = Result<T::View<'a, C>, MarshalError>;
+    type IntoIter = VecViewIter<'a, T, C>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// The elements of a [`VecView`], see [`VecView::iter()`]. It stops after an element that
+/// can't be read, since the ones after it can't be found.
+pub struct VecViewIter<'a, T, C = DefaultConfig> {
+    index: usize,
+    len: usize,
+    /// Whether `data` is still at the element before `index`, which is skipped once the next one
+    /// is asked for
+    skip: bool,
+    /// Where the elements start, for errors
+    start: Option<usize>,
+    data: &'a [u8],
+    _marker: PhantomData<fn() -> (T, C)>,
+}
+
+impl<'a, T: Viewable, C: Config> Iterator for VecViewIter<'a, T, C> {
+    type Item = Result<T::View<'a, C>, MarshalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index == self.len {
+            return None;
+        }
+        let index = self.index;
+        self.index += 1;
+        let data = &mut self.data;
+        let start = self.start;
+        // An element that can be viewed can still be cut short, which is found out here
+        let skipped = match std::mem::replace(&mut self.skip, true) {
+            true => T::skip_from_with::<C>(data).map_err(|e| {
+                e.within(
+                    "Vec",
+                    &[PathSegment::Index(index - 1)],
+                    start,
+                    position(data),
+                )
+            }),
+            false => Ok(()),
+        };
+        let view = skipped.and_then(|()| {
+            let element = &mut { *data };
+            T::view_from_with::<C>(element).map_err(|e| {
+                e.within(
+                    "Vec",
+                    &[PathSegment::Index(index)],
+                    start,
+                    position(element),
+                )
+            })
+        });
+        if view.is_err() {
+            self.index = self.len;
+        }
+        Some(view)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.len - self.index;
+        (left, Some(left))
+    }
+}
+
+impl<T: Viewable, C: Config> ExactSizeIterator for VecViewIter<'_, T, C> {}
+
+impl<T: Viewable, C: Config> FusedIterator for VecViewIter<'_, T, C> {}
+
+impl<T, C> Clone for VecViewIter<'_, T, C> {
+    fn clone(&self) -> Self {
+        Self { ..*self }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,132 @@
+use std::borrow::Cow;
+
+use super::*;
+use crate::prelude::*;
+
+#[test]
+fn test_view_values() {
+    let bytes = (7u32, "seven", Some(vec![1u8, 2]), None::<i64>, 'x').marshal_to_vec();
+    let mut data = &bytes[..];
+    let (n, s, bytes_view, none, c) =
+        <(u32, String, Option<Vec<u8>>, Option<i64>, char)>::view_from(&mut data).unwrap();
+    assert!(data.is_empty());
+    assert_eq!((n, s, none, c), (7, "seven", None, 'x'));
+    assert!(bytes.as_ptr_range().contains(&s.as_ptr()));
+    assert_eq!(bytes_view.unwrap().as_bytes(), [1, 2]);
+
+    // Skipping goes past the same bytes as viewing
+    let mut data = &bytes[..];
+    <(u32, Cow<str>, Option<Vec<u8>>, Option<i64>, char)>::skip_from_with::<DefaultConfig>(
+        &mut data,
+    )
+    .unwrap();
+    assert!(data.is_empty());
+
+    assert_eq!(<(u8, u64, f32)>::fixed_len::<DefaultConfig>(), Some(13));
+    assert_eq!(<(u8, u64, f32)>::fixed_len::<Varints>(), None);
+    assert_eq!(<(u8, String)>::fixed_len::<DefaultConfig>(), None);
+}
+
+#[test]
+fn test_vec_view() {
+    let values = vec![1u64, 300, 70000];
+    let bytes = (&values).marshal_to_vec();
+    let view = Vec::<u64>::view_from(&mut &bytes[..]).unwrap();
+    assert_eq!(view.len(), 3);
+    assert_eq!(view.as_bytes(), &bytes[8..]);
+    assert_eq!(view.get(2).unwrap(), Some(70000));
+    assert_eq!(view.get(3).unwrap(), None);
+    assert_eq!(view.iter().len(), 3);
+    assert_eq!(
+        view.into_iter().collect::<Result<Vec<_>, _>>().unwrap(),
+        values
+    );
+
+    // Varints aren't a fixed length, so the elements before are skipped to find one
+    let bytes = values.marshal_ref_with::<Varints>().collect::<Vec<_>>();
+    let view = Vec::<u64>::view_from_with::<Varints>(&mut &bytes[..]).unwrap();
+    assert_eq!(view.get(1).unwrap(), Some(300));
+    assert_eq!(view.get(2).unwrap(), Some(70000));
+    // Elements that have been found already are gone back to straight away
+    assert_eq!(view.offsets.borrow().as_slice(), [0, 1, 3]);
+    assert_eq!(view.get(0).unwrap(), Some(1));
+    assert_eq!(view.clone().get(1).unwrap(), Some(300));
+    assert_eq!(view.offsets.borrow().len(), 3);
+    assert_eq!(
+        (&view).into_iter().collect::<Result<Vec<_>, _>>().unwrap(),
+        values
+    );
+
+    // The outermost view takes the rest of the input, without walking the elements to find its end
+    let nested = vec![vec!["a", "b"], vec![], vec!["c"]];
+    let mut bytes = (&nested).marshal_to_vec();
+    bytes.push(7);
+    let mut data = &bytes[..];
+    let view = Vec::<Vec<String>>::view_from(&mut data).unwrap();
+    assert!(data.is_empty());
+    assert_eq!(view.as_bytes(), &bytes[8..]);
+    assert!(view.offsets.borrow().is_empty());
+    let last = view.get(2).unwrap().unwrap();
+    assert_eq!(last.get(0).unwrap(), Some("c"));
+    assert!(view.get(1).unwrap().unwrap().is_empty());
+    let all = view
+        .iter()
+        .map(|inner| inner?.iter().collect::<Result<Vec<_>, _>>())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(all, nested);
+}
+
+#[test]
+fn test_view_errors() {
+    // Elements without a fixed length aren't looked at until they're asked for
+    let bytes = vec!["ok", "cut short"].marshal_to_vec();
+    let view = Vec::<String>::view_from(&mut &bytes[..30]).unwrap();
+    assert_eq!(view.get(0).unwrap(), Some("ok"));
+    let err = view.get(1).unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::InvalidSizedDecode(4)));
+    assert_eq!(err.path().as_deref(), Some("Vec[1]"));
+    // From the start of the elements
+    assert_eq!(err.offset(), Some(22));
+
+    // An element that's cut short is found when skipping past it to the next one
+    let bytes = vec![vec!["a", "b"], vec!["c"]].marshal_to_vec();
+    let view = Vec::<Vec<String>>::view_from(&mut &bytes[..30]).unwrap();
+    let mut iter = view.iter();
+    assert_eq!(iter.next().unwrap().unwrap().len(), 2);
+    let err = iter.next().unwrap().unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Vec[0][1]"));
+    assert!(iter.next().is_none());
+
+    // Fixed length elements are checked straight away, since that costs nothing
+    let bytes = vec![1u32, 2].marshal_to_vec();
+    let err = Vec::<u32>::view_from(&mut &bytes[..14]).unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::InvalidSizedDecode(6)));
+
+    // But not what's in them, until they're read
+    let bytes = vec![vec![0x61u8], vec![0xff]].marshal_to_vec();
+    let view = Vec::<String>::view_from(&mut &bytes[..]).unwrap();
+    assert_eq!(view.get(0).unwrap(), Some("a"));
+    let err = view.get(1).unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::InvalidSizedDecode(1)));
+    assert_eq!(err.path().as_deref(), Some("Vec[1]"));
+    assert_eq!(err.offset(), Some(18));
+    let mut iter = view.iter();
+    assert!(iter.next().unwrap().is_ok());
+    assert!(iter.next().unwrap().is_err());
+    assert!(iter.next().is_none());
+
+    struct Short;
+
+    impl Config for Short {
+        const LIMITS: DecodeLimits = DecodeLimits::NONE.max_collection_len(2);
+    }
+
+    let bytes = vec![0u8; 3].marshal_to_vec();
+    assert!(matches!(
+        Vec::<u8>::view_from_with::<Short>(&mut &bytes[..])
+            .unwrap_err()
+            .kind(),
+        MarshalError::LimitExceeded { .. }
+    ));
+}
@@ -0,0 +1,279 @@
+use proc_macro2::Span;
+use quote::ToTokens;
+use syn::{Token, meta::ParseNestedMeta, punctuated::Punctuated, spanned::Spanned};
+
+/// `#[marshal(..)]` on the struct or enum itself
+pub(crate) struct ContainerAttrs {
+    /// Where the `lazy_marshal` crate is, `::lazy_marshal` unless it's re-exported
+    pub krate: syn::Path,
+    /// Used instead of the bounds the derives would put on the type parameters
+    pub bound: Option<Punctuated<syn::WherePredicate, Token![,]>>,
+    /// How an enum writes which variant it is
+    pub tag_type: TagType,
+    /// `#[marshal(version = N)]`, which makes a struct write its version and length first
+    pub version: Option<(u32, Span)>,
+    /// `#[marshal(tagged)]`, which writes every field with its id and wire type
+    pub tagged: Option<Span>,
+    /// How a union is written, which it has to say since it can't be matched on
+    pub union_as: Option<(UnionAs, Span)>,
+}
+
+/// `#[marshal(union_as = "bytes")]` or `#[marshal(union_as = "discriminant")]`
+pub(crate) enum UnionAs {
+    /// The union's bytes as they are in memory
+    Bytes,
+    /// The index of the field its `UnionDiscriminant` impl picks, written as a tag followed by
+    /// that field. Only that field is read, which is why the trait is unsafe to implement.
+    Discriminant,
+}
+
+/// `#[marshal(tag_type = "..")]`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum TagType {
+    U8,
+    U16,
+    U32,
+    /// A `u32` that is always written as a varint
+    VarInt,
+}
+
+impl TagType {
+    /// The largest tag that fits
+    pub fn max(self) -> u64 {
+        match self {
+            Self::U8 => u8::MAX.into(),
+            Self::U16 => u16::MAX.into(),
+            Self::U32 | Self::VarInt => u32::MAX.into(),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::U8 => "u8",
+            Self::U16 => "u16",
+            Self::U32 => "u32",
+            Self::VarInt => "varint",
+        }
+    }
+}
+
+/// `default` or `default = "path"`
+pub(crate) enum DefaultValue {
+    Trait,
+    Path(syn::ExprPath),
+}
+
+/// `#[marshal(..)]` on an enum variant
+pub(crate) struct VariantAttrs {
+    /// The number written for the variant, instead of its discriminant or position
+    pub tag: Option<syn::LitInt>,
+    /// Marshalled through a boxed iterator, which breaks the cycle in recursive types
+    pub boxed: bool,
+}
+
+/// `#[marshal(..)]` on a field
+pub(crate) struct FieldAttrs {
+    /// Not written, and filled in with `default` when decoding
+    pub skip: bool,
+    /// What a skipped field, or one missing from an older version, is filled in with
+    pub default: Option<(DefaultValue, Span)>,
+    /// The version of the struct the field was added in
+    pub since: Option<(u32, Span)>,
+    /// A module with `marshal`, `marshal_ref`, `unmarshal` and a `Decoder` type used for the field
+    pub with: Option<syn::Path>,
+    /// The number a field of a tagged struct is written with
+    pub id: Option<(u32, Span)>,
+}
+
+/// Call `f` for every item inside every `#[marshal(..)]` in `attrs`
+fn each_meta(
+    attrs: &[syn::Attribute],
+    mut f: impl FnMut(ParseNestedMeta) -> syn::Result<()>,
+) -> syn::Result<()> {
+    for attr in attrs.iter().filter(|a| a.path().is_ident("marshal")) {
+        attr.parse_nested_meta(&mut f)?;
+    }
+    Ok(())
+}
+
+fn name(meta: &ParseNestedMeta) -> String {
+    meta.path.to_token_stream().to_string().replace(' ', "")
+}
+
+fn unknown(meta: &ParseNestedMeta, on: &str) -> syn::Error {
+    meta.error(format!(
+        "unknown marshal attribute `{}` on {on}",
+        name(meta)
+    ))
+}
+
+/// Store an attribute's value, complaining if it was already given
+fn set<T>(slot: &mut Option<T>, meta: &ParseNestedMeta, value: T) -> syn::Result<()> {
+    if slot.is_some() {
+        return Err(meta.error(format!("duplicate marshal attribute `{}`", name(meta))));
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+/// The number in `name = N`, with its span
+fn number(meta: &ParseNestedMeta) -> syn::Result<(u32, Span)> {
+    let value: syn::LitInt = meta.value()?.parse()?;
+    Ok((value.base10_parse()?, value.span()))
+}
+
+/// The string in `name = "..."`
+fn string(meta: &ParseNestedMeta) -> syn::Result<syn::LitStr> {
+    meta.value()?.parse()
+}
+
+impl ContainerAttrs {
+    pub fn parse(attrs: &[syn::Attribute]) -> syn::Result<Self> {
+        let mut krate = None;
+        let mut bound = None;
+        let mut tag_type = None;
+        let mut version = None;
+        let mut tagged = None;
+        let mut union_as = None;
+        each_meta(attrs, |meta| {
+            if meta.path.is_ident("crate") {
+                set(&mut krate, &meta, string(&meta)?.parse()?)
+            } else if meta.path.is_ident("bound") {
+                let predicates = string(&meta)?.parse_with(Punctuated::parse_terminated)?;
+                set(&mut bound, &meta, predicates)
+            } else if meta.path.is_ident("tag_type") {
+                let ty = string(&meta)?;
+                let parsed = match ty.value().as_str() {
+                    "u8" => TagType::U8,
+                    "u16" => TagType::U16,
+                    "u32" => TagType::U32,
+                    "varint" => TagType::VarInt,
+                    _ => {
+                        return Err(syn::Error::new(
+                            ty.span(),
+                            "expected \"u8\", \"u16\", \"u32\" or \"varint\"",
+                        ));
+                    }
+                };
+                set(&mut tag_type, &meta, parsed)
+            } else if meta.path.is_ident("version") {
+                set(&mut version, &meta, number(&meta)?)
+            } else if meta.path.is_ident("tagged") {
+                set(&mut tagged, &meta, meta.path.span())
+            } else if meta.path.is_ident("union_as") {
+                let value = string(&meta)?;
+                let parsed = match value.value().as_str() {
+                    "bytes" => UnionAs::Bytes,
+                    "discriminant" => UnionAs::Discriminant,
+                    _ => {
+                        return Err(syn::Error::new(
+                            value.span(),
+                            "expected \"bytes\" or \"discriminant\"",
+                        ));
+                    }
+                };
+                set(&mut union_as, &meta, (parsed, value.span()))
+            } else {
+                Err(unknown(&meta, "a type"))
+            }
+        })?;
+        Ok(Self {
+            krate: krate.unwrap_or_else(|| syn::parse_quote! { ::lazy_marshal }),
+            bound,
+            tag_type: tag_type.unwrap_or(TagType::U8),
+            version,
+            tagged,
+            union_as,
+        })
+    }
+}
+
+impl FieldAttrs {
+    pub fn parse(attrs: &[syn::Attribute]) -> syn::Result<Self> {
+        let mut skip = None;
+        let mut default = None;
+        let mut since = None;
+        let mut with = None;
+        let mut id = None;
+        let mut with_span = Span::call_site();
+        each_meta(attrs, |meta| {
+            if meta.path.is_ident("skip") {
+                set(&mut skip, &meta, ())
+            } else if meta.path.is_ident("default") {
+                let span = meta.path.span();
+                let value = match meta.input.peek(Token![=]) {
+                    true => DefaultValue::Path(string(&meta)?.parse()?),
+                    false => DefaultValue::Trait,
+                };
+                set(&mut default, &meta, (value, span))
+            } else if meta.path.is_ident("since") {
+                set(&mut since, &meta, number(&meta)?)
+            } else if meta.path.is_ident("with") {
+                let module = string(&meta)?;
+                with_span = module.span();
+                set(&mut with, &meta, module.parse()?)
+            } else if meta.path.is_ident("id") {
+                set(&mut id, &meta, number(&meta)?)
+            } else {
+                Err(unknown(&meta, "a field"))
+            }
+        })?;
+
+        if let (Some((_, span)), None) = (since, &default) {
+            return Err(syn::Error::new(
+                span,
+                "a field with `since` needs a `default` for payloads from before it was added",
+            ));
+        }
+        if skip.is_some() && with.is_some() {
+            return Err(syn::Error::new(
+                with_span,
+                "a field marked `skip` isn't marshalled, so it can't have `with`",
+            ));
+        }
+        if let (Some(()), Some((_, span))) = (skip, since) {
+            return Err(syn::Error::new(
+                span,
+                "a field marked `skip` isn't marshalled, so it can't have `since`",
+            ));
+        }
+        if let (Some(()), Some((_, span))) = (skip, id) {
+            return Err(syn::Error::new(
+                span,
+                "a field marked `skip` isn't marshalled, so it can't have an `id`",
+            ));
+        }
+        Ok(Self {
+            skip: skip.is_some(),
+            default,
+            since,
+            with,
+            id,
+        })
+    }
+}
+
+impl VariantAttrs {
+    pub fn parse(attrs: &[syn::Attribute]) -> syn::Result<Self> {
+        let mut tag = None;
+        let mut boxed = false;
+        each_meta(attrs, |meta| {
+            if meta.path.is_ident("boxed") {
+                boxed = true;
+                Ok(())
+            } else if meta.path.is_ident("tag") {
+                let value: syn::LitInt = meta.value()?.parse()?;
+                if value.base10_digits().starts_with('-') {
+                    return Err(syn::Error::new(value.span(), "tags can't be negative"));
+                }
+                set(&mut tag, &meta, value)
+            } else {
+                Err(unknown(&meta, "a variant"))
+            }
+        })?;
+        Ok(Self { tag, boxed })
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,168 @@
+use quote::ToTokens;
+use syn::parse_quote;
+
+use super::*;
+
+fn field_error(field: syn::Field) -> String {
+    match FieldAttrs::parse(&field.attrs) {
+        Ok(_) => panic!("{} parsed", field.to_token_stream()),
+        Err(e) => e.to_string(),
+    }
+}
+
+fn container_error(ast: syn::DeriveInput) -> String {
+    match ContainerAttrs::parse(&ast.attrs) {
+        Ok(_) => panic!("{} parsed", ast.to_token_stream()),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn test_field_attrs() {
+    let field: syn::Field = parse_quote! {
+        #[marshal(skip, default = "defaults::port")]
+        #[allow(unused)]
+        port: u16
+    };
+    let attrs = FieldAttrs::parse(&field.attrs).unwrap();
+    assert!(attrs.skip);
+    assert!(matches!(
+        attrs.default,
+        Some((DefaultValue::Path(path), _)) if path.to_token_stream().to_string() == "defaults :: port"
+    ));
+    assert!(attrs.with.is_none());
+
+    let field: syn::Field = parse_quote! { #[marshal(since = 3, default)] email: String };
+    let attrs = FieldAttrs::parse(&field.attrs).unwrap();
+    assert!(matches!(attrs.default, Some((DefaultValue::Trait, _))));
+    assert_eq!(attrs.since.unwrap().0, 3);
+
+    let field: syn::Field = parse_quote! { #[marshal(with = "crate::as_text")] timeout: u32 };
+    let attrs = FieldAttrs::parse(&field.attrs).unwrap();
+    assert!(!attrs.skip);
+    assert_eq!(attrs.with.to_token_stream().to_string(), "crate :: as_text");
+
+    let field: syn::Field = parse_quote! { #[marshal(id = 12, default)] note: String };
+    let attrs = FieldAttrs::parse(&field.attrs).unwrap();
+    assert_eq!(attrs.id.unwrap().0, 12);
+
+    assert_eq!(
+        field_error(parse_quote! { #[marshal(skipp)] a: u8 }),
+        "unknown marshal attribute `skipp` on a field"
+    );
+    assert_eq!(
+        field_error(parse_quote! { #[marshal(skip)] #[marshal(skip)] a: u8 }),
+        "duplicate marshal attribute `skip`"
+    );
+    assert_eq!(
+        field_error(parse_quote! { #[marshal(since = 2)] a: u8 }),
+        "a field with `since` needs a `default` for payloads from before it was added"
+    );
+    assert_eq!(
+        field_error(parse_quote! { #[marshal(skip, since = 2, default)] a: u8 }),
+        "a field marked `skip` isn't marshalled, so it can't have `since`"
+    );
+    assert_eq!(
+        field_error(parse_quote! { #[marshal(skip, with = "text")] a: u8 }),
+        "a field marked `skip` isn't marshalled, so it can't have `with`"
+    );
+    assert_eq!(
+        field_error(parse_quote! { #[marshal(skip, id = 1)] a: u8 }),
+        "a field marked `skip` isn't marshalled, so it can't have an `id`"
+    );
+    assert_eq!(
+        field_error(parse_quote! { #[marshal(with = "not a path")] a: u8 }),
+        "unexpected token"
+    );
+}
+
+#[test]
+fn test_container_attrs() {
+    let ast: syn::DeriveInput = parse_quote! {
+        #[marshal(crate = "my::reexport", bound = "T: Clone, U: Default", version = 4)]
+        struct Thing<T, U>(T, U);
+    };
+    let attrs = ContainerAttrs::parse(&ast.attrs).unwrap();
+    assert_eq!(attrs.krate.to_token_stream().to_string(), "my :: reexport");
+    assert_eq!(attrs.bound.unwrap().len(), 2);
+    assert_eq!(attrs.version.unwrap().0, 4);
+    assert!(attrs.tagged.is_none());
+
+    let ast: syn::DeriveInput = parse_quote! { #[marshal(tagged)] struct Thing; };
+    assert!(ContainerAttrs::parse(&ast.attrs).unwrap().tagged.is_some());
+
+    let ast: syn::DeriveInput = parse_quote! { struct Thing; };
+    let attrs = ContainerAttrs::parse(&ast.attrs).unwrap();
+    assert_eq!(attrs.krate.to_token_stream().to_string(), ":: lazy_marshal");
+    assert!(attrs.bound.is_none());
+
+    assert_eq!(
+        container_error(parse_quote! { #[marshal(skip)] struct Thing; }),
+        "unknown marshal attribute `skip` on a type"
+    );
+    assert_eq!(
+        container_error(parse_quote! { #[marshal(crate = "a", crate = "b")] struct Thing; }),
+        "duplicate marshal attribute `crate`"
+    );
+    assert_eq!(
+        container_error(parse_quote! { #[marshal(bound = "T Clone")] struct Thing<T>(T); }),
+        "expected `:`"
+    );
+
+    let ast: syn::DeriveInput = parse_quote! {
+        #[marshal(union_as = "discriminant")]
+        union Thing { a: u8 }
+    };
+    let attrs = ContainerAttrs::parse(&ast.attrs).unwrap();
+    assert!(matches!(attrs.union_as, Some((UnionAs::Discriminant, _))));
+    assert_eq!(
+        container_error(parse_quote! { #[marshal(union_as = "fields")] union Thing { a: u8 } }),
+        "expected \"bytes\" or \"discriminant\""
+    );
+    assert_eq!(
+        container_error(parse_quote! {
+            #[marshal(union_as = "bytes", union_as = "discriminant")]
+            union Thing { a: u8 }
+        }),
+        "duplicate marshal attribute `union_as`"
+    );
+
+    assert_eq!(
+        container_error(parse_quote! { #[marshal(tag_type = "u64")] enum Thing {} }),
+        "expected \"u8\", \"u16\", \"u32\" or \"varint\""
+    );
+}
+
+#[test]
+fn test_variant_attrs() {
+    let variant: syn::Variant = parse_quote! { #[marshal(tag = 7)] Seven };
+    let attrs = VariantAttrs::parse(&variant.attrs).unwrap();
+    assert_eq!(attrs.tag.unwrap().base10_digits(), "7");
+    assert!(!attrs.boxed);
+    let variant: syn::Variant = parse_quote! { #[marshal(boxed, tag = 1)] Deep(Vec<Other>) };
+    assert!(VariantAttrs::parse(&variant.attrs).unwrap().boxed);
+
+    let ast: syn::DeriveInput = parse_quote! {
+        #[marshal(tag_type = "varint")]
+        enum Thing {}
+    };
+    let attrs = ContainerAttrs::parse(&ast.attrs).unwrap();
+    assert_eq!(attrs.tag_type, TagType::VarInt);
+
+    let variant: syn::Variant = parse_quote! { #[marshal(skip)] Gone };
+    assert_eq!(
+        VariantAttrs::parse(&variant.attrs)
+            .err()
+            .unwrap()
+            .to_string(),
+        "unknown marshal attribute `skip` on a variant"
+    );
+    let variant: syn::Variant = parse_quote! { #[marshal(tag = -1)] Negative };
+    assert_eq!(
+        VariantAttrs::parse(&variant.attrs)
+            .err()
+            .unwrap()
+            .to_string(),
+        "tags can't be negative"
+    );
+}
@@ -1,186 +1,2421 @@
+use attr::{ContainerAttrs, DefaultValue, FieldAttrs, TagType, UnionAs, VariantAttrs};
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{spanned::Spanned, DataEnum, DataStruct, Fields};
+use quote::{ToTokens, format_ident, quote, quote_spanned};
+use syn::{DataEnum, DataStruct, DataUnion, Fields, ext::IdentExt, spanned::Spanned};
 
-#[proc_macro_derive(Marshal)]
+mod attr;
+
+/// The type a derive is generating code for
+struct Container<'a> {
+    ast: &'a syn::DeriveInput,
+    attrs: ContainerAttrs,
+    /// Whether fields are read with `UnMarshalBorrowed`, so they can borrow from the input
+    borrowed: bool,
+}
+
+impl<'a> Container<'a> {
+    fn new(ast: &'a syn::DeriveInput) -> syn::Result<Self> {
+        let attrs = ContainerAttrs::parse(&ast.attrs)?;
+        if let (Some((_, span)), syn::Data::Enum(_) | syn::Data::Union(_)) =
+            (attrs.version, &ast.data)
+        {
+            return Err(syn::Error::new(span, "only structs can be versioned"));
+        }
+        if let Some(span) = attrs.tagged {
+            if !matches!(ast.data, syn::Data::Struct(_)) {
+                return Err(syn::Error::new(span, "only structs can be tagged"));
+            }
+            if attrs.version.is_some() {
+                return Err(syn::Error::new(
+                    span,
+                    "tagged structs can already add and remove fields, they don't need a `version`",
+                ));
+            }
+        }
+        match (&attrs.union_as, &ast.data) {
+            (None, syn::Data::Union(data_union)) => {
+                return Err(syn::Error::new(
+                    data_union.union_token.span(),
+                    "unions need `#[marshal(union_as = \"bytes\")]` or \
+                     `#[marshal(union_as = \"discriminant\")]` to say how they're marshalled",
+                ));
+            }
+            (Some((_, span)), syn::Data::Struct(_) | syn::Data::Enum(_)) => {
+                return Err(syn::Error::new(*span, "only unions take `union_as`"));
+            }
+            _ => {}
+        }
+        Ok(Self {
+            ast,
+            attrs,
+            borrowed: false,
+        })
+    }
+
+    fn name(&self) -> &syn::Ident {
+        &self.ast.ident
+    }
+
+    fn krate(&self) -> &syn::Path {
+        &self.attrs.krate
+    }
+
+    /// Copy the type's generics, requiring the type parameters the written fields use to implement
+    /// `bound`, or adding the `#[marshal(bound = "..")]` predicates instead if there are any
+    fn generics(&self, bound: Option<proc_macro2::TokenStream>) -> syn::Generics {
+        let mut generics = self.ast.generics.clone();
+        if let Some(predicates) = &self.attrs.bound {
+            generics
+                .make_where_clause()
+                .predicates
+                .extend(predicates.iter().cloned());
+        } else if let Some(bound) = bound {
+            let mut bounded = Vec::new();
+            for (field, attrs) in self.all_fields() {
+                if !attrs.skip && attrs.with.is_none() {
+                    self.bounded_types(&field.ty, &mut bounded);
+                }
+            }
+            let where_clause = generics.make_where_clause();
+            for ty in bounded {
+                where_clause
+                    .predicates
+                    .push(syn::parse_quote! { #ty: #bound });
+            }
+        }
+        generics
+    }
+
+    /// [`Container::generics()`] for decoding, where fields that can be filled in with
+    /// `Default::default()` also need their type to implement `Default`
+    fn unmarshal_generics(&self, bound: proc_macro2::TokenStream) -> syn::Generics {
+        let mut generics = self.generics(Some(bound));
+        if self.attrs.bound.is_some() {
+            return generics;
+        }
+        let mut defaulted = Vec::new();
+        for (field, attrs) in self.all_fields() {
+            let by_trait = match &attrs.default {
+                Some((DefaultValue::Trait, _)) => true,
+                Some((DefaultValue::Path(_), _)) => false,
+                None => attrs.skip,
+            };
+            let mut bounded = Vec::new();
+            self.bounded_types(&field.ty, &mut bounded);
+            if by_trait && !bounded.is_empty() {
+                defaulted.push(&field.ty);
+            }
+        }
+        let where_clause = generics.make_where_clause();
+        for ty in defaulted {
+            where_clause
+                .predicates
+                .push(syn::parse_quote! { #ty: ::std::default::Default });
+        }
+        generics
+    }
+
+    /// Every field of the struct, or of every variant, and its attributes.
+    /// Fields with attributes that don't parse are left out, they're reported elsewhere.
+    fn all_fields(&self) -> Vec<(&'a syn::Field, FieldAttrs)> {
+        let fields: Box<dyn Iterator<Item = &syn::Field>> = match &self.ast.data {
+            syn::Data::Struct(data_struct) => Box::new(data_struct.fields.iter()),
+            syn::Data::Enum(data_enum) => {
+                Box::new(data_enum.variants.iter().flat_map(|v| v.fields.iter()))
+            }
+            syn::Data::Union(data_union) => Box::new(data_union.fields.named.iter()),
+        };
+        fields
+            .filter_map(|f| Some((f, FieldAttrs::parse(&f.attrs).ok()?)))
+            .collect()
+    }
+
+    /// Whether a written field mentions the type it's in, whose iterator would then contain
+    /// itself unless it's boxed
+    fn is_recursive(&self, field: &FieldInfo) -> bool {
+        fn mentions(tokens: proc_macro2::TokenStream, name: &syn::Ident) -> bool {
+            tokens.into_iter().any(|token| match token {
+                proc_macro2::TokenTree::Ident(ident) => ident == *name || ident == "Self",
+                proc_macro2::TokenTree::Group(group) => mentions(group.stream(), name),
+                _ => false,
+            })
+        }
+        !field.attrs.skip
+            && field.attrs.with.is_none()
+            && mentions(field.ty.to_token_stream(), self.name())
+    }
+
+    /// Collect the types in `ty` that need a bound for it to implement a trait, without duplicates.
+    /// Those are the type parameters and their associated types like `T::Item`. Nothing inside
+    /// `PhantomData` needs one, since it's marshalled the same whatever it holds.
+    fn bounded_types(&self, ty: &syn::Type, found: &mut Vec<syn::Type>) {
+        let is_param =
+            |ident: &syn::Ident| self.ast.generics.type_params().any(|p| p.ident == *ident);
+        match ty {
+            syn::Type::Path(ty_path) => {
+                let path = &ty_path.path;
+                let qself_has_param = ty_path.qself.as_ref().is_some_and(|qself| {
+                    let mut inner = Vec::new();
+                    self.bounded_types(&qself.ty, &mut inner);
+                    !inner.is_empty()
+                });
+                let starts_with_param = path.leading_colon.is_none()
+                    && path.segments.first().is_some_and(|s| is_param(&s.ident));
+                if qself_has_param || starts_with_param {
+                    let key = ty.to_token_stream().to_string();
+                    if !found.iter().any(|f| f.to_token_stream().to_string() == key) {
+                        found.push(ty.clone());
+                    }
+                    return;
+                }
+                if path
+                    .segments
+                    .last()
+                    .is_some_and(|s| s.ident == "PhantomData")
+                {
+                    return;
+                }
+                for segment in &path.segments {
+                    let syn::PathArguments::AngleBracketed(args) = &segment.arguments else {
+                        continue;
+                    };
+                    for arg in &args.args {
+                        match arg {
+                            syn::GenericArgument::Type(ty) => self.bounded_types(ty, found),
+                            syn::GenericArgument::AssocType(assoc) => {
+                                self.bounded_types(&assoc.ty, found)
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            syn::Type::Reference(r) => self.bounded_types(&r.elem, found),
+            syn::Type::Slice(s) => self.bounded_types(&s.elem, found),
+            syn::Type::Array(a) => self.bounded_types(&a.elem, found),
+            syn::Type::Ptr(p) => self.bounded_types(&p.elem, found),
+            syn::Type::Paren(p) => self.bounded_types(&p.elem, found),
+            syn::Type::Group(g) => self.bounded_types(&g.elem, found),
+            syn::Type::Tuple(t) => t.elems.iter().for_each(|ty| self.bounded_types(ty, found)),
+            _ => {}
+        }
+    }
+
+    /// A closure adding where in the type an error happened, for `map_err`. `path` is a list of
+    /// `PathSegment`s and `__start` has to hold the bytes left in `data` when decoding started.
+    fn error_context(&self, path: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        let root = self.name().unraw().to_string();
+        quote! {
+            |e: #krate::MarshalError| e.within(
+                #root,
+                &[#path],
+                __start,
+                #krate::__private::position(&*data),
+            )
+        }
+    }
+
+    /// The same as [`Container::error_context()`], for the fields of a versioned struct.
+    /// `data` has to be the `Body` they're read from.
+    fn body_error_context(&self, path: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        let root = self.name().unraw().to_string();
+        quote! { |e: #krate::MarshalError| data.within(e, #root, &[#path], __start) }
+    }
+
+    /// The same as [`Container::error_context()`], for push decoders. The decoder wrapping them adds
+    /// how many bytes they'd been fed.
+    fn decoder_error_context(&self, path: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        let root = self.name().unraw().to_string();
+        quote! { |e: #krate::MarshalError| e.within(#root, &[#path], None, None) }
+    }
+
+    /// The `PathSegment` leading to `field` of a struct
+    fn field_path(&self, field: &FieldInfo) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        let field_name = &field.name;
+        quote! { #krate::__private::PathSegment::Field(#field_name) }
+    }
+
+    /// The `PathSegment`s leading to `field` of `var`
+    fn variant_path(&self, var: &syn::Variant, field: &FieldInfo) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        let var_name = var.ident.unraw().to_string();
+        let field = self.field_path(field);
+        quote! {
+            #krate::__private::PathSegment::Variant(#var_name),
+            #field,
+        }
+    }
+
+    /// The fields of the struct or a variant, checking their `since`s against the struct's version
+    /// and their `id`s against each other
+    fn fields<'f>(&self, fields: &'f Fields) -> syn::Result<Vec<FieldInfo<'f>>> {
+        let fields = field_infos(fields)?;
+        let tagged = self.attrs.tagged.is_some();
+        for field in &fields {
+            if let Some((_, span)) = &field.attrs.default
+                && !field.attrs.skip
+                && field.attrs.since.is_none()
+                && !tagged
+            {
+                return Err(syn::Error::new(
+                    *span,
+                    "`default` is only used for fields marked `skip` or `since`, \
+                     or in a `tagged` struct",
+                ));
+            }
+        }
+        // Which field has each id
+        let mut ids = Vec::<(u32, &FieldInfo)>::new();
+        for field in fields.iter().filter(|f| !f.attrs.skip) {
+            match (field.attrs.id, tagged) {
+                (Some((_, span)), false) => {
+                    return Err(syn::Error::new(
+                        span,
+                        "`id` needs `#[marshal(tagged)]` on the struct",
+                    ));
+                }
+                (None, true) => {
+                    return Err(syn::Error::new(
+                        field.span,
+                        "fields of a tagged struct need a `#[marshal(id = N)]`",
+                    ));
+                }
+                (Some((id, span)), true) => {
+                    if let Some((_, other)) = ids.iter().find(|(other, _)| *other == id) {
+                        return Err(syn::Error::new(
+                            span,
+                            format!(
+                                "id {id} of `{}` is already used by `{}`",
+                                field.name, other.name
+                            ),
+                        ));
+                    }
+                    ids.push((id, field));
+                }
+                (None, false) => {}
+            }
+        }
+        // The field that was added last so far, and its version
+        let mut latest: Option<(&FieldInfo, u32)> = None;
+        for field in fields.iter().filter(|f| !f.attrs.skip) {
+            let since = match (field.attrs.since, self.attrs.version) {
+                (Some((_, span)), None) => {
+                    return Err(syn::Error::new(
+                        span,
+                        "`since` needs `#[marshal(version = N)]` on the struct",
+                    ));
+                }
+                (Some((since, span)), Some((version, _))) if since > version => {
+                    return Err(syn::Error::new(
+                        span,
+                        format!("the struct is only at version {version}"),
+                    ));
+                }
+                (since, _) => since.map_or(0, |(since, _)| since),
+            };
+            if let Some((before, before_since)) = latest
+                && since < before_since
+            {
+                return Err(syn::Error::new(
+                    field.span,
+                    format!(
+                        "fields are written in the order they were added, \
+                         so `{}` has to come before `{}` which was added in version {before_since}",
+                        field.name, before.name
+                    ),
+                ));
+            }
+            latest = Some((field, since));
+        }
+        Ok(fields)
+    }
+
+    /// Wrap the bytes of a struct's fields in its version and length, if it's versioned
+    fn versioned(&self, fields: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        match self.attrs.version {
+            Some((version, _)) => {
+                quote! { #krate::__private::marshal_versioned::<__C>(#version, #fields) }
+            }
+            None => fields,
+        }
+    }
+
+    /// Put the number of fields in front of them, if the struct is tagged
+    fn tagged(&self, count: usize, fields: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        match self.attrs.tagged {
+            Some(_) => {
+                let count = count as u32;
+                quote! {
+                    #krate::Marshal::marshal_with::<__C>(#krate::VarInt(#count)).chain(#fields)
+                }
+            }
+            None => fields,
+        }
+    }
+
+    /// Put the key of `field` in front of its bytes, if the struct is tagged
+    fn tagged_field(
+        &self,
+        field: &FieldInfo,
+        bytes: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        match field.attrs.id {
+            Some((id, _)) => {
+                let wire = self.wire_type(field);
+                quote! { #krate::__private::marshal_tagged::<__C>(#id, #wire, #bytes) }
+            }
+            None => bytes,
+        }
+    }
+
+    /// [`Container::versioned()`] for the length of the fields instead of their bytes
+    fn versioned_len(&self, fields: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        match self.attrs.version {
+            Some((version, _)) => {
+                quote! { #krate::__private::versioned_len::<__C>(#version, #fields) }
+            }
+            None => fields,
+        }
+    }
+
+    /// [`Container::tagged()`] for the length of the fields instead of their bytes
+    fn tagged_len(
+        &self,
+        count: usize,
+        fields: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        match self.attrs.tagged {
+            Some(_) => {
+                let count = count as u32;
+                quote! {
+                    #krate::MarshalRef::encoded_len_with::<__C>(&#krate::VarInt(#count)) + #fields
+                }
+            }
+            None => fields,
+        }
+    }
+
+    /// [`Container::tagged_field()`] for the length of the field instead of its bytes
+    fn tagged_field_len(
+        &self,
+        field: &FieldInfo,
+        len: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        match field.attrs.id {
+            Some((id, _)) => {
+                let wire = self.wire_type(field);
+                quote! { #krate::__private::tagged_len::<__C>(#id, #wire, #len) }
+            }
+            None => len,
+        }
+    }
+
+    /// How `field` is laid out in a tagged struct
+    fn wire_type(&self, field: &FieldInfo) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        self.fixed_wire_type(field)
+            .unwrap_or_else(|| quote! { #krate::WireType::LengthDelimited })
+    }
+
+    /// The wire type of a field that's a number, which is written as it is. `None` for everything
+    /// else, which is length delimited since only the field's own impls know where it ends.
+    fn fixed_wire_type(&self, field: &FieldInfo) -> Option<proc_macro2::TokenStream> {
+        let krate = self.krate();
+        let wire = quote! { #krate::WireType };
+        let syn::Type::Path(syn::TypePath {
+            qself: None, path, ..
+        }) = field.ty
+        else {
+            return None;
+        };
+        if field.attrs.with.is_some() {
+            return None;
+        }
+        if path.segments.last().is_some_and(|s| s.ident == "VarInt") {
+            return Some(quote! { #wire::Varint });
+        }
+        Some(match path.get_ident()?.to_string().as_str() {
+            "u8" | "i8" | "bool" => quote! { #wire::Fixed8 },
+            "u16" | "i16" => quote! { #wire::int::<__C>(2) },
+            "u32" | "i32" | "char" => quote! { #wire::int::<__C>(4) },
+            "u64" | "i64" | "usize" | "isize" => quote! { #wire::int::<__C>(8) },
+            "u128" | "i128" => quote! { #wire::int::<__C>(16) },
+            "f32" => quote! { #wire::Fixed32 },
+            "f64" => quote! { #wire::Fixed64 },
+            _ => return None,
+        })
+    }
+
+    /// The value of a field of a tagged struct from its slot `value`, an `Option` that's `None` if
+    /// the field wasn't in the data. That's only an error for fields without a `default`.
+    fn tagged_value(
+        &self,
+        field: &FieldInfo,
+        value: proc_macro2::TokenStream,
+        context: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        let missing = match field.attrs.default {
+            Some(_) => default_value(field),
+            None => quote! { return Err((#context)(#krate::__private::missing_field())) },
+        };
+        quote! {
+            match #value {
+                Some(v) => v,
+                None => #missing,
+            }
+        }
+    }
+
+    /// The bytes of `value`, which is either the field or a reference to it
+    fn marshal_field(
+        &self,
+        field: &FieldInfo,
+        value: proc_macro2::TokenStream,
+        by_ref: bool,
+    ) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        match (&field.attrs.with, by_ref) {
+            (Some(with), false) => quote! { #with::marshal::<__C>(#value) },
+            (Some(with), true) => quote! { #with::marshal_ref::<__C>(#value) },
+            (None, false) => quote! { #krate::Marshal::marshal_with::<__C>(#value) },
+            (None, true) => quote! { #krate::MarshalRef::marshal_ref_with::<__C>(#value) },
+        }
+    }
+
+    /// A statement writing `value`, which is either the field or a reference to it, into `__w`
+    fn write_field(
+        &self,
+        field: &FieldInfo,
+        value: proc_macro2::TokenStream,
+        by_ref: bool,
+    ) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        match (&field.attrs.with, by_ref) {
+            (Some(_), _) => {
+                let bytes = self.marshal_field(field, value, by_ref);
+                quote! { #krate::__private::write_iter(__w, #bytes)?; }
+            }
+            (None, false) => {
+                quote! { #krate::Marshal::marshal_into_with::<__C, __W>(#value, __w)?; }
+            }
+            (None, true) => {
+                quote! { #krate::MarshalRef::marshal_ref_into_with::<__C, __W>(#value, __w)?; }
+            }
+        }
+    }
+
+    /// How many bytes `value`, a reference to the field, is written in. Fields marshalled `with`
+    /// a module are counted, since the module only knows how to write them.
+    fn field_len(
+        &self,
+        field: &FieldInfo,
+        value: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        match &field.attrs.with {
+            Some(with) => quote! { Iterator::count(#with::marshal_ref::<__C>(#value)) },
+            None => quote! { #krate::MarshalRef::encoded_len_with::<__C>(#value) },
+        }
+    }
+
+    /// An expression for the value of `field` read from `data`, with `context` added to errors
+    fn unmarshal_field(
+        &self,
+        field: &FieldInfo,
+        context: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        if field.attrs.skip {
+            return default_value(field);
+        }
+        match &field.attrs.with {
+            Some(with) => quote! {
+                #krate::__private::with_iter(data, |data| #with::unmarshal::<__C>(data))
+                    .map_err(#context)?
+            },
+            None if self.borrowed => quote! {
+                #krate::UnMarshalBorrowed::unmarshal_borrowed_with::<__C>(data).map_err(#context)?
+            },
+            None => quote! {
+                #krate::UnMarshal::unmarshal_from_with::<__C>(data).map_err(#context)?
+            },
+        }
+    }
+
+    /// The type an enum's tag is written as
+    fn tag_type(&self) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        match self.attrs.tag_type {
+            TagType::U8 => quote! { u8 },
+            TagType::U16 => quote! { u16 },
+            TagType::U32 => quote! { u32 },
+            TagType::VarInt => quote! { #krate::VarInt<u32> },
+        }
+    }
+
+    /// `tag` as a value of [`Container::tag_type()`]
+    fn tag_value(&self, tag: u64) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        let literal = self.tag_literal(tag);
+        match self.attrs.tag_type {
+            TagType::VarInt => quote! { #krate::VarInt(#literal) },
+            _ => literal,
+        }
+    }
+
+    /// `tag` as a literal of the integer a decoded tag is matched as
+    fn tag_literal(&self, tag: u64) -> proc_macro2::TokenStream {
+        let suffix = match self.attrs.tag_type {
+            TagType::U8 => "u8",
+            TagType::U16 => "u16",
+            TagType::U32 | TagType::VarInt => "u32",
+        };
+        let literal = syn::LitInt::new(&format!("{tag}{suffix}"), proc_macro2::Span::call_site());
+        quote! { #literal }
+    }
+
+    /// The push decoder used for `field`
+    fn decoder_type(&self, field: &FieldInfo) -> proc_macro2::TokenStream {
+        let krate = self.krate();
+        let ty = field.ty;
+        match &field.attrs.with {
+            Some(with) => quote! { #with::Decoder },
+            None => quote! { <#ty as #krate::IncrementalUnMarshal>::Decoder },
+        }
+    }
+}
+
+/// The fields of a union, none of which can be skipped since one of them holds the value
+fn union_fields<'f>(cx: &Container, fields: &'f Fields) -> syn::Result<Vec<FieldInfo<'f>>> {
+    let fields = cx.fields(fields)?;
+    if let Some(field) = fields.iter().find(|f| f.attrs.skip) {
+        return Err(syn::Error::new(
+            field.span,
+            "the fields of a union can't be skipped",
+        ));
+    }
+    if fields.len() as u64 > cx.attrs.tag_type.max() + 1 {
+        return Err(syn::Error::new(
+            cx.ast.ident.span(),
+            format!(
+                "a union with {} fields doesn't fit in a {} tag, \
+                 use `#[marshal(tag_type = \"..\")]` on the union for a wider one",
+                fields.len(),
+                cx.attrs.tag_type.name()
+            ),
+        ));
+    }
+    Ok(fields)
+}
+
+/// Fails to compile if the union isn't `Copy`, pointing at how it's marshalled
+fn union_is_copy(cx: &Container) -> proc_macro2::TokenStream {
+    let Some((_, span)) = &cx.attrs.union_as else {
+        return quote! {};
+    };
+    let krate = cx.krate();
+    let name = cx.name();
+    let generics = cx.generics(None);
+    let (impl_gen, ty_gen, where_gen) = generics.split_for_impl();
+    quote_spanned! {*span=>
+        const _: () = {
+            #[allow(dead_code)]
+            fn __union_is_copy #impl_gen () #where_gen {
+                #krate::__private::union_is_copy::<#name #ty_gen>();
+            }
+        };
+    }
+}
+
+/// Fails to compile if a union written as its bytes has a field that could have padding, which
+/// would be written out uninitialised, pointing at the field
+fn union_has_no_padding(cx: &Container, data: &syn::Data) -> proc_macro2::TokenStream {
+    let (Some((UnionAs::Bytes, _)), syn::Data::Union(data_union)) = (&cx.attrs.union_as, data)
+    else {
+        return quote! {};
+    };
+    let krate = cx.krate();
+    let generics = cx.generics(None);
+    let (impl_gen, _, where_gen) = generics.split_for_impl();
+    let checks = data_union.fields.named.iter().map(|f| {
+        let ty = &f.ty;
+        quote_spanned! {ty.span()=> #krate::__private::union_field_has_no_padding::<#ty>(); }
+    });
+    quote! {
+        const _: () = {
+            #[allow(dead_code)]
+            fn __union_has_no_padding #impl_gen () #where_gen {
+                #(#checks)*
+            }
+        };
+    }
+}
+
+/// The number written for each variant: its `#[marshal(tag = N)]`, its `= N` discriminant,
+/// or one more than the variant before it, the same way Rust numbers discriminants
+fn variant_tags(cx: &Container, data_enum: &DataEnum) -> syn::Result<Vec<u64>> {
+    let tag_type = cx.attrs.tag_type;
+    let mut tags = Vec::<u64>::new();
+    let mut next = 0;
+    for var in &data_enum.variants {
+        let attrs = VariantAttrs::parse(&var.attrs)?;
+        let (tag, span) = match (&attrs.tag, &var.discriminant) {
+            (Some(tag), _) => (tag.base10_parse()?, tag.span()),
+            (None, Some((_, expr))) => (discriminant(expr)?, expr.span()),
+            (None, None) => (next, var.ident.span()),
+        };
+        if tag > tag_type.max() {
+            return Err(syn::Error::new(
+                span,
+                format!(
+                    "tag {tag} of `{}` doesn't fit in a {} tag, \
+                     use `#[marshal(tag_type = \"..\")]` on the enum for a wider one",
+                    var.ident,
+                    tag_type.name()
+                ),
+            ));
+        }
+        if let Some(other) = tags.iter().position(|&t| t == tag) {
+            return Err(syn::Error::new(
+                span,
+                format!(
+                    "tag {tag} of `{}` is already used by `{}`",
+                    var.ident, data_enum.variants[other].ident
+                ),
+            ));
+        }
+        tags.push(tag);
+        // Can't overflow, tags are at most a `u32`
+        next = tag + 1;
+    }
+    Ok(tags)
+}
+
+/// The value of a `= N` discriminant
+fn discriminant(expr: &syn::Expr) -> syn::Result<u64> {
+    match expr {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Int(int),
+            ..
+        }) => int.base10_parse(),
+        syn::Expr::Group(group) => discriminant(&group.expr),
+        _ => Err(syn::Error::new(
+            expr.span(),
+            "only integer literal discriminants can be used as tags, \
+             use `#[marshal(tag = ..)]` instead",
+        )),
+    }
+}
+
+/// What a skipped field, or one from a newer version, is filled in with
+fn default_value(field: &FieldInfo) -> proc_macro2::TokenStream {
+    match &field.attrs.default {
+        Some((DefaultValue::Path(path), _)) => quote! { #path() },
+        Some((DefaultValue::Trait, _)) | None => quote! { ::std::default::Default::default() },
+    }
+}
+
+/// A field of a struct or enum variant. Bound to `__f{i}` when matching on a variant.
+struct FieldInfo<'a> {
+    binding: syn::Ident,
+    /// `x` for `Moved { x: i32 }`, `0` for `Part(u32)`
+    name: String,
+    member: syn::Member,
+    ty: &'a syn::Type,
+    span: proc_macro2::Span,
+    attrs: FieldAttrs,
+}
+
+fn field_infos(fields: &Fields) -> syn::Result<Vec<FieldInfo<'_>>> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let member = match &field.ident {
+                Some(ident) => syn::Member::Named(ident.clone()),
+                None => syn::Member::Unnamed(i.into()),
+            };
+            Ok(FieldInfo {
+                binding: format_ident!("__f{i}"),
+                name: match &field.ident {
+                    Some(ident) => ident.unraw().to_string(),
+                    None => i.to_string(),
+                },
+                member,
+                ty: &field.ty,
+                span: field.span(),
+                attrs: FieldAttrs::parse(&field.attrs)?,
+            })
+        })
+        .collect()
+}
+
+/// `path { x: value_x, .. }`, `path(value_0, ..)` or `path`, with a value for each field.
+/// Works both as a pattern and as an expression.
+fn construct(
+    path: proc_macro2::TokenStream,
+    kind: &Fields,
+    fields: &[FieldInfo],
+    values: impl IntoIterator<Item = proc_macro2::TokenStream>,
+) -> proc_macro2::TokenStream {
+    let members = fields.iter().map(|f| &f.member);
+    let values = values.into_iter();
+    match kind {
+        Fields::Named(_) => quote! { #path { #(#members: #values),* } },
+        Fields::Unnamed(_) => quote! { #path(#(#values),*) },
+        Fields::Unit => quote! { #path },
+    }
+}
+
+/// Derives `Marshal` and `MarshalRef`. Fields are written in order, and enums write a tag
+/// saying which variant it is before its fields.
+///
+/// Variants are tagged with their `= N` discriminant if they have one, otherwise with one more than
+/// the variant before them, starting at 0. `#[marshal(tag = N)]` on a variant picks its tag instead.
+/// Tags are `u8`s unless the enum has `#[marshal(tag_type = "u16")]`, `"u32"` or `"varint"`.
+///
+/// Enums are marshalled without allocating, except for variants with a field that mentions the
+/// enum itself, like `Node(Vec<Tree>)`, which go through a boxed iterator so that its type isn't
+/// infinitely big. `#[marshal(boxed)]` does the same for variants of types that are only
+/// recursive through another type, like `Sub(Dir)` where `Dir` has a `Vec<Entry>` in it. Without
+/// it those fail to compile with `error[E0275]: overflow evaluating the requirement`, so put
+/// `#[marshal(boxed)]` on the variant the error points through.
+///
+/// Type parameters get the bounds their fields need, like `T: Marshal` for a `Vec<T>` field.
+/// Ones that are only in `PhantomData`, skipped fields or fields with `with` don't get any, and
+/// `UnMarshal` also needs `Default` for fields that are filled in with `Default::default()`.
+///
+/// Fields take these attributes:
+/// - `#[marshal(skip)]` leaves the field out. It's filled in with `Default::default()`
+///   when unmarshalling, or with the function given by `#[marshal(skip, default = "path")]`.
+/// - `#[marshal(with = "module")]` uses `module::marshal`, `module::marshal_ref`,
+///   `module::unmarshal` and `module::Decoder` for the field instead of its own impls. Its
+///   `encoded_len()` counts the bytes `module::marshal_ref` writes.
+/// - `#[marshal(since = N, default)]` marks a field added in version `N` of a versioned struct.
+///   Data from before then gets `Default::default()`, or the function in `default = "path"`.
+/// - `#[marshal(id = N)]` is the number a field of a tagged struct is written with. Data without
+///   the field is an error, unless it has `#[marshal(default)]` or `default = "path"`.
+///
+/// The type itself takes:
+/// - `#[marshal(version = N)]` makes a struct write its version and the length of its fields in front
+///   of them. Older data gets defaults for the fields it doesn't have, and fields from newer data that
+///   the struct doesn't know about are skipped, so both sides can be upgraded one at a time.
+/// - `#[marshal(tagged)]` makes a struct write the number of its fields, and every field with its
+///   `id` and a `WireType` saying how to skip it. Fields can then be added, removed and
+///   reordered, and readers skip the ones they don't know.
+/// - `#[marshal(union_as = "bytes")]` writes a union as its bytes in memory. The union has to be
+///   `Copy`, and every field has to be an integer, a float or an array of them that's as big as
+///   the union, so none of its bytes can be padding.
+/// - `#[marshal(union_as = "discriminant")]` writes a union as the index of the field its
+///   [`UnionDiscriminant`](../lazy_marshal/trait.UnionDiscriminant.html) impl picks, followed by
+///   that field like with an enum. Only that field is read, so the trait is unsafe to implement.
+/// - `#[marshal(bound = "T: Trait, ..")]` replaces the bounds the derives would add.
+/// - `#[marshal(crate = "path")]` for when `lazy_marshal` is only reachable through a re-export.
+///
+/// # Panics
+/// Marshalling a union panics if its `UnionDiscriminant` returns an index past its last field.
+#[proc_macro_derive(Marshal, attributes(marshal))]
 pub fn marshal_derive(input: TokenStream) -> TokenStream {
     let ast = syn::parse(input).unwrap();
 
-    impl_marshal_macro(&ast)
+    impl_marshal_macro(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn marshal_ref_struct(
+    cx: &Container,
+    data_struct: &DataStruct,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = cx.fields(&data_struct.fields)?;
+    let written = fields.iter().filter(|f| !f.attrs.skip).collect::<Vec<_>>();
+    let bytes = written.iter().map(|field| {
+        let f = &field.member;
+        cx.tagged_field(field, cx.marshal_field(field, quote! { &self.#f }, true))
+    });
+    Ok(cx.versioned(cx.tagged(
+        written.len(),
+        quote! { ::std::iter::empty()#(.chain(#bytes))* },
+    )))
+}
+
+fn marshal_struct(
+    cx: &Container,
+    data_struct: &DataStruct,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = cx.fields(&data_struct.fields)?;
+    let written = fields.iter().filter(|f| !f.attrs.skip).collect::<Vec<_>>();
+    let bytes = written.iter().map(|field| {
+        let f = &field.member;
+        let bytes = if let (syn::Type::Reference(_), None) = (field.ty, &field.attrs.with) {
+            cx.marshal_field(
+                field,
+                quote! { ::std::clone::Clone::clone(&self.#f) },
+                false,
+            )
+        } else {
+            cx.marshal_field(field, quote! { self.#f }, false)
+        };
+        cx.tagged_field(field, bytes)
+    });
+    Ok(cx.versioned(cx.tagged(
+        written.len(),
+        quote! { ::std::iter::empty()#(.chain(#bytes))* },
+    )))
+}
+
+fn marshal_enum(
+    cx: &Container,
+    data_enum: &DataEnum,
+    by_ref: bool,
+) -> syn::Result<Option<proc_macro2::TokenStream>> {
+    let krate = cx.krate();
+    let tags = variant_tags(cx, data_enum)?;
+    let mut arms = Vec::new();
+    for (var, tag) in data_enum.variants.iter().zip(tags) {
+        let tag = cx.tag_value(tag);
+        let var_name = &var.ident;
+        let fields = cx.fields(&var.fields)?;
+        let pattern = construct(
+            quote! { Self::#var_name },
+            &var.fields,
+            &fields,
+            fields.iter().map(|f| match f.attrs.skip {
+                true => quote! { _ },
+                false => {
+                    let binding = &f.binding;
+                    quote! { #binding }
+                }
+            }),
+        );
+        let values = fields.iter().filter(|f| !f.attrs.skip).map(|field| {
+            let binding = &field.binding;
+            cx.marshal_field(field, quote! { #binding }, by_ref)
+        });
+        let variant = format_ident!("__V{}", arms.len());
+        let mut bytes = quote! { #krate::Marshal::marshal_with::<__C>(#tag)#(.chain(#values))* };
+        if VariantAttrs::parse(&var.attrs)?.boxed || fields.iter().any(|f| cx.is_recursive(f)) {
+            bytes = quote! { #krate::MarshalIterator(Box::new(#bytes)) };
+        }
+        arms.push(quote! {
+            #pattern => __MarshalIter::#variant(#bytes)
+        });
+    }
+    if arms.is_empty() {
+        return Ok(None);
+    }
+
+    let iter = marshal_iter(arms.len());
+    Ok(Some(quote! {
+        #iter
+        match self {
+            #(#arms,)*
+        }
+    }))
+}
+
+/// An iterator with a variant for each of the `n` iterators an enum or union can be written
+/// with, so which one is used is picked without boxing it
+fn marshal_iter(n: usize) -> proc_macro2::TokenStream {
+    let params = (0..n).map(|i| format_ident!("__I{i}")).collect::<Vec<_>>();
+    let variants = (0..n).map(|i| format_ident!("__V{i}")).collect::<Vec<_>>();
+    quote! {
+        enum __MarshalIter<#(#params),*> {
+            #(#variants(#params),)*
+        }
+
+        impl<#(#params: Iterator<Item = u8>),*> Iterator for __MarshalIter<#(#params),*> {
+            type Item = u8;
+
+            #[inline]
+            fn next(&mut self) -> Option<u8> {
+                match self {
+                    #(Self::#variants(iter) => iter.next(),)*
+                }
+            }
+
+            #[inline]
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                match self {
+                    #(Self::#variants(iter) => iter.size_hint(),)*
+                }
+            }
+
+            #[inline]
+            fn fold<__B, __F: FnMut(__B, u8) -> __B>(self, init: __B, f: __F) -> __B {
+                match self {
+                    #(Self::#variants(iter) => iter.fold(init, f),)*
+                }
+            }
+        }
+    }
+}
+
+/// Write a union as its bytes, or as the index of the field its discriminant picks and that field
+fn marshal_union(
+    cx: &Container,
+    data_union: &DataUnion,
+    by_ref: bool,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let krate = cx.krate();
+    let kind = Fields::Named(data_union.fields.clone());
+    let fields = union_fields(cx, &kind)?;
+    let this = match by_ref {
+        true => quote! { self },
+        false => quote! { &self },
+    };
+
+    let Some((UnionAs::Discriminant, _)) = &cx.attrs.union_as else {
+        let bytes = union_bytes(cx, &fields, &this);
+        let iter = match by_ref {
+            true => quote! { bytes.iter().copied() },
+            false => quote! { bytes.to_vec().into_iter() },
+        };
+        return Ok(quote! {
+            #bytes
+            #iter
+        });
+    };
+
+    let count = fields.len();
+    let arms = fields.iter().enumerate().map(|(i, field)| {
+        let tag = cx.tag_value(i as u64);
+        let member = &field.member;
+        let value = match by_ref {
+            true => quote! { unsafe { &self.#member } },
+            false => quote! { unsafe { self.#member } },
+        };
+        let bytes = cx.marshal_field(field, value, by_ref);
+        let mut bytes = quote! { #krate::Marshal::marshal_with::<__C>(#tag).chain(#bytes) };
+        if cx.is_recursive(field) {
+            bytes = quote! { #krate::MarshalIterator(Box::new(#bytes)) };
+        }
+        let variant = format_ident!("__V{i}");
+        quote! {
+            #i => __MarshalIter::#variant(#bytes)
+        }
+    });
+    let iter = marshal_iter(count);
+    let bad = bad_discriminant(cx, count);
+    Ok(quote! {
+        #iter
+        // SAFETY: the `UnionDiscriminant` impl promises to pick the field that's set, and only
+        // that one is read
+        match #krate::UnionDiscriminant::discriminant(#this) {
+            #(#arms,)*
+            #bad
+        }
+    })
+}
+
+/// Checks that every field fills the union, and sets `bytes` to the union's bytes in memory.
+/// [`union_has_no_padding()`] checks the fields don't have padding.
+fn union_bytes(
+    cx: &Container,
+    fields: &[FieldInfo],
+    this: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let name = cx.name().unraw().to_string();
+    let message = format!("every field of `{name}` has to fill it to be marshalled as bytes");
+    let tys = fields.iter().map(|f| f.ty);
+    quote! {
+        #(const {
+            assert!(::std::mem::size_of::<#tys>() == ::std::mem::size_of::<Self>(), #message)
+        };)*
+        // SAFETY: whichever field was set, it fills the union and has no padding, so all of the
+        // union's bytes are initialised
+        let bytes = unsafe {
+            ::std::slice::from_raw_parts(
+                (#this as *const Self).cast::<u8>(),
+                ::std::mem::size_of::<Self>(),
+            )
+        };
+    }
+}
+
+/// The match arm for a discriminant that picked a field the union doesn't have
+fn bad_discriminant(cx: &Container, count: usize) -> proc_macro2::TokenStream {
+    let name = cx.name().unraw().to_string();
+    quote! {
+        i => panic!(
+            "the discriminant of `{}` picked field {i}, but it only has {}",
+            #name,
+            #count,
+        ),
+    }
+}
+
+/// Write a struct straight into `__w`. Versioned and tagged structs need the length of their
+/// fields first, which is only known by reference, so they're written from their bytes otherwise.
+fn write_struct(
+    cx: &Container,
+    data_struct: &DataStruct,
+    by_ref: bool,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let krate = cx.krate();
+    let fields = cx.fields(&data_struct.fields)?;
+    let written = fields.iter().filter(|f| !f.attrs.skip).collect::<Vec<_>>();
+    let layout = cx.attrs.version.is_some() || cx.attrs.tagged.is_some();
+    if layout && !by_ref {
+        return Ok(quote! {
+            #krate::__private::write_iter(__w, #krate::Marshal::marshal_with::<__C>(self))
+        });
+    }
+
+    let mut statements = Vec::new();
+    if let Some((version, _)) = cx.attrs.version {
+        let lens = written.iter().map(|field| {
+            let f = &field.member;
+            cx.tagged_field_len(field, cx.field_len(field, quote! { &self.#f }))
+        });
+        let len = cx.tagged_len(written.len(), quote! { (0 #(+ #lens)*) });
+        statements.push(quote! {
+            #krate::__private::write_versioned::<__C, __W>(__w, #version, #len)?;
+        });
+    }
+    if cx.attrs.tagged.is_some() {
+        let count = written.len() as u32;
+        statements.push(quote! {
+            #krate::MarshalRef::marshal_ref_into_with::<__C, __W>(&#krate::VarInt(#count), __w)?;
+        });
+    }
+    for field in &written {
+        let f = &field.member;
+        if let Some((id, _)) = field.attrs.id {
+            let wire = cx.wire_type(field);
+            // Only length delimited values have their length in front of them
+            let len = match cx.fixed_wire_type(field) {
+                Some(_) => quote! { 0 },
+                None => cx.field_len(field, quote! { &self.#f }),
+            };
+            statements.push(quote! {
+                #krate::__private::write_tagged_key::<__C, __W>(__w, #id, #wire, #len)?;
+            });
+        }
+        let value = match (by_ref, field.ty, &field.attrs.with) {
+            (true, ..) => quote! { &self.#f },
+            (false, syn::Type::Reference(_), None) => {
+                quote! { ::std::clone::Clone::clone(&self.#f) }
+            }
+            (false, ..) => quote! { self.#f },
+        };
+        statements.push(cx.write_field(field, value, by_ref));
+    }
+    Ok(quote! {
+        #(#statements)*
+        Ok(())
+    })
+}
+
+fn write_enum(
+    cx: &Container,
+    data_enum: &DataEnum,
+    by_ref: bool,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let krate = cx.krate();
+    let tags = variant_tags(cx, data_enum)?;
+    let mut arms = Vec::new();
+    for (var, tag) in data_enum.variants.iter().zip(tags) {
+        let tag = cx.tag_value(tag);
+        let var_name = &var.ident;
+        let fields = cx.fields(&var.fields)?;
+        let pattern = construct(
+            quote! { Self::#var_name },
+            &var.fields,
+            &fields,
+            fields.iter().map(|f| match f.attrs.skip {
+                true => quote! { _ },
+                false => {
+                    let binding = &f.binding;
+                    quote! { #binding }
+                }
+            }),
+        );
+        let writes = fields.iter().filter(|f| !f.attrs.skip).map(|field| {
+            let binding = &field.binding;
+            cx.write_field(field, quote! { #binding }, by_ref)
+        });
+        arms.push(quote! {
+            #pattern => {
+                #krate::Marshal::marshal_into_with::<__C, __W>(#tag, __w)?;
+                #(#writes)*
+            }
+        });
+    }
+    Ok(quote! {
+        match self {
+            #(#arms,)*
+        }
+        Ok(())
+    })
+}
+
+fn write_union(
+    cx: &Container,
+    data_union: &DataUnion,
+    by_ref: bool,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let krate = cx.krate();
+    let kind = Fields::Named(data_union.fields.clone());
+    let fields = union_fields(cx, &kind)?;
+    let this = match by_ref {
+        true => quote! { self },
+        false => quote! { &self },
+    };
+    let Some((UnionAs::Discriminant, _)) = &cx.attrs.union_as else {
+        let bytes = union_bytes(cx, &fields, &this);
+        return Ok(quote! {
+            #bytes
+            __w.write_all(bytes)
+        });
+    };
+
+    let arms = fields.iter().enumerate().map(|(i, field)| {
+        let tag = cx.tag_value(i as u64);
+        let member = &field.member;
+        let value = match by_ref {
+            true => quote! { unsafe { &self.#member } },
+            false => quote! { unsafe { self.#member } },
+        };
+        let write = cx.write_field(field, value, by_ref);
+        quote! {
+            #i => {
+                #krate::Marshal::marshal_into_with::<__C, __W>(#tag, __w)?;
+                #write
+            }
+        }
+    });
+    let bad = bad_discriminant(cx, fields.len());
+    Ok(quote! {
+        // SAFETY: the `UnionDiscriminant` impl promises to pick the field that's set, and only
+        // that one is read
+        match #krate::UnionDiscriminant::discriminant(#this) {
+            #(#arms,)*
+            #bad
+        }
+        Ok(())
+    })
+}
+
+fn encoded_len_struct(
+    cx: &Container,
+    data_struct: &DataStruct,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = cx.fields(&data_struct.fields)?;
+    let written = fields.iter().filter(|f| !f.attrs.skip).collect::<Vec<_>>();
+    let lens = written.iter().map(|field| {
+        let f = &field.member;
+        cx.tagged_field_len(field, cx.field_len(field, quote! { &self.#f }))
+    });
+    Ok(cx.versioned_len(cx.tagged_len(written.len(), quote! { (0 #(+ #lens)*) })))
+}
+
+fn encoded_len_enum(cx: &Container, data_enum: &DataEnum) -> syn::Result<proc_macro2::TokenStream> {
+    let krate = cx.krate();
+    let tags = variant_tags(cx, data_enum)?;
+    let mut arms = Vec::new();
+    for (var, tag) in data_enum.variants.iter().zip(tags) {
+        let tag = cx.tag_value(tag);
+        let var_name = &var.ident;
+        let fields = cx.fields(&var.fields)?;
+        let pattern = construct(
+            quote! { Self::#var_name },
+            &var.fields,
+            &fields,
+            fields.iter().map(|f| match f.attrs.skip {
+                true => quote! { _ },
+                false => {
+                    let binding = &f.binding;
+                    quote! { #binding }
+                }
+            }),
+        );
+        let lens = fields.iter().filter(|f| !f.attrs.skip).map(|field| {
+            let binding = &field.binding;
+            cx.field_len(field, quote! { #binding })
+        });
+        arms.push(quote! {
+            #pattern => #krate::MarshalRef::encoded_len_with::<__C>(&#tag) #(+ #lens)*
+        });
+    }
+    Ok(quote! {
+        match self {
+            #(#arms,)*
+        }
+    })
+}
+
+fn encoded_len_union(
+    cx: &Container,
+    data_union: &DataUnion,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let krate = cx.krate();
+    let kind = Fields::Named(data_union.fields.clone());
+    let fields = union_fields(cx, &kind)?;
+    let Some((UnionAs::Discriminant, _)) = &cx.attrs.union_as else {
+        return Ok(quote! { ::std::mem::size_of::<Self>() });
+    };
+
+    let arms = fields.iter().enumerate().map(|(i, field)| {
+        let tag = cx.tag_value(i as u64);
+        let member = &field.member;
+        let len = cx.field_len(field, quote! { unsafe { &self.#member } });
+        quote! {
+            #i => #krate::MarshalRef::encoded_len_with::<__C>(&#tag) + #len
+        }
+    });
+    // Out of range indexes panic when marshalling, so they're left to that
+    Ok(quote! {
+        // SAFETY: the `UnionDiscriminant` impl promises to pick the field that's set, and only
+        // that one is read
+        match #krate::UnionDiscriminant::discriminant(self) {
+            #(#arms,)*
+            _ => Iterator::count(#krate::MarshalRef::marshal_ref_with::<__C>(self)),
+        }
+    })
+}
+
+fn unmarshal_enum(cx: &Container, data_enum: &DataEnum) -> syn::Result<proc_macro2::TokenStream> {
+    let krate = cx.krate();
+    let tags = variant_tags(cx, data_enum)?;
+    let mut variants = Vec::new();
+    for (var, tag) in data_enum.variants.iter().zip(tags) {
+        let tag = cx.tag_literal(tag);
+        let var_name = &var.ident;
+        let fields = cx.fields(&var.fields)?;
+        let values = fields
+            .iter()
+            .map(|field| cx.unmarshal_field(field, cx.error_context(cx.variant_path(var, field))));
+        let value = construct(quote! { Self::#var_name }, &var.fields, &fields, values);
+        variants.push(quote! { #tag => #value });
+    }
+
+    let context = cx.error_context(quote! {});
+    let tag_type = cx.tag_type();
+    let mut variant = quote! {
+        <#tag_type as #krate::UnMarshal>::unmarshal_from_with::<__C>(data).map_err(#context)?
+    };
+    if cx.attrs.tag_type == TagType::VarInt {
+        variant = quote! { #variant.0 };
+    }
+    Ok(quote! {
+        let variant = #variant;
+        Ok(match variant {
+            #(#variants, )*
+            a => Err((#context)(#krate::MarshalError::InvalidData(format!(
+                "Invalid enum varient: {a}"
+            ))))?,
+        })
+    })
+}
+
+fn impl_marshal_macro(ast: &syn::DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let cx = Container::new(ast)?;
+    let name = cx.name();
+    let krate = cx.krate();
+
+    let data = match &ast.data {
+        syn::Data::Struct(data_struct) => Some((
+            marshal_struct(&cx, data_struct)?,
+            marshal_ref_struct(&cx, data_struct)?,
+            encoded_len_struct(&cx, data_struct)?,
+            write_struct(&cx, data_struct, false)?,
+            write_struct(&cx, data_struct, true)?,
+        )),
+        syn::Data::Enum(data_enum) => {
+            match marshal_enum(&cx, data_enum, false)?.zip(marshal_enum(&cx, data_enum, true)?) {
+                Some((d, d_ref)) => Some((
+                    d,
+                    d_ref,
+                    encoded_len_enum(&cx, data_enum)?,
+                    write_enum(&cx, data_enum, false)?,
+                    write_enum(&cx, data_enum, true)?,
+                )),
+                None => None,
+            }
+        }
+        syn::Data::Union(data_union) => Some((
+            marshal_union(&cx, data_union, false)?,
+            marshal_union(&cx, data_union, true)?,
+            encoded_len_union(&cx, data_union)?,
+            write_union(&cx, data_union, false)?,
+            write_union(&cx, data_union, true)?,
+        )),
+    };
+    let copy = union_is_copy(&cx);
+    let no_padding = union_has_no_padding(&cx, &ast.data);
+
+    let generics = cx.generics(Some(quote! { #krate::Marshal }));
+    let (impl_gen, ty_gen, where_gen) = generics.split_for_impl();
+    // `MarshalRef` needs the type parameters to be borrow-marshallable instead
+    let ref_generics = cx.generics(Some(quote! { #krate::MarshalRef }));
+    let ref_where = &ref_generics.where_clause;
+
+    let Some((d, d_ref, len, write, write_ref)) = data else {
+        return Ok(quote! {});
+    };
+    // Without type parameters the `MarshalRef` impl below always applies, so it can say how long
+    // the owned value is too. With them it might not, and the owned value doesn't know.
+    let len_hint = ast.generics.type_params().next().is_none().then(|| {
+        quote! {
+            fn encoded_len_hint_with<__C: #krate::Config>(&self) -> ::std::option::Option<usize> {
+                ::std::option::Option::Some(#krate::MarshalRef::encoded_len_with::<__C>(self))
+            }
+        }
+    });
+    Ok(quote! {
+        #copy
+        #no_padding
+
+        #[automatically_derived]
+        impl #impl_gen #krate::Marshal for #name #ty_gen #where_gen {
+            fn marshal(self) -> impl Iterator<Item = u8> {
+                #krate::Marshal::marshal_with::<#krate::DefaultConfig>(self)
+            }
+
+            fn marshal_with<__C: #krate::Config>(self) -> impl Iterator<Item = u8> {
+                #d
+            }
+
+            #len_hint
+
+            fn marshal_into<__W: ::std::io::Write>(self, w: &mut __W) -> ::std::io::Result<()> {
+                #krate::Marshal::marshal_into_with::<#krate::DefaultConfig, __W>(self, w)
+            }
+
+            fn marshal_into_with<__C: #krate::Config, __W: ::std::io::Write>(
+                self,
+                __w: &mut __W,
+            ) -> ::std::io::Result<()> {
+                #write
+            }
+        }
+
+        #[automatically_derived]
+        impl #impl_gen #krate::MarshalRef for #name #ty_gen #ref_where {
+            fn marshal_ref(&self) -> impl Iterator<Item = u8> + '_ {
+                #krate::MarshalRef::marshal_ref_with::<#krate::DefaultConfig>(self)
+            }
+
+            fn marshal_ref_with<__C: #krate::Config>(&self) -> impl Iterator<Item = u8> + '_ {
+                #d_ref
+            }
+
+            fn encoded_len(&self) -> usize {
+                #krate::MarshalRef::encoded_len_with::<#krate::DefaultConfig>(self)
+            }
+
+            fn encoded_len_with<__C: #krate::Config>(&self) -> usize {
+                #len
+            }
+
+            fn marshal_ref_into<__W: ::std::io::Write>(&self, w: &mut __W) -> ::std::io::Result<()> {
+                #krate::MarshalRef::marshal_ref_into_with::<#krate::DefaultConfig, __W>(self, w)
+            }
+
+            fn marshal_ref_into_with<__C: #krate::Config, __W: ::std::io::Write>(
+                &self,
+                __w: &mut __W,
+            ) -> ::std::io::Result<()> {
+                #write_ref
+            }
+        }
+    })
 }
 
-fn marshal_struct(data_struct: &DataStruct) -> Option<proc_macro2::TokenStream> {
-    data_struct
-        .fields
-        .clone()
-        .into_iter()
-        .map(|field| {
-            let f = field.ident.unwrap();
-            let ty = &field.ty;
-            if let syn::Type::Reference(_) = ty {
-                quote! {
-                    self.#f.clone().marshal()
-                }
-            } else {
-                quote! {
-                    self.#f.marshal()
+fn unmarshal_struct(
+    cx: &Container,
+    data_struct: &DataStruct,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = cx.fields(&data_struct.fields)?;
+    if cx.attrs.tagged.is_some() {
+        return Ok(unmarshal_tagged(cx, data_struct, &fields));
+    }
+    if cx.attrs.version.is_none() {
+        let values = fields
+            .iter()
+            .map(|field| cx.unmarshal_field(field, cx.error_context(cx.field_path(field))));
+        let value = construct(quote! { Self }, &data_struct.fields, &fields, values);
+        return Ok(quote! {
+            Ok(#value)
+        });
+    }
+
+    // Fields added after the version that wrote the data get their default
+    let krate = cx.krate();
+    let values = fields.iter().map(|field| {
+        let value = cx.unmarshal_field(field, cx.body_error_context(cx.field_path(field)));
+        match field.attrs.since {
+            Some((since, _)) => {
+                let default = default_value(field);
+                quote! { if __version >= #since { #value } else { #default } }
+            }
+            None => value,
+        }
+    });
+    let value = construct(quote! { Self }, &data_struct.fields, &fields, values);
+    let context = cx.error_context(quote! {});
+    Ok(quote! {
+        // Not `map_err`, the error context looks at `data` which an `Ok` body borrows
+        let mut __body = match #krate::__private::Body::read::<__C>(data) {
+            Ok(body) => body,
+            Err(e) => return Err((#context)(e)),
+        };
+        let __version = __body.version();
+        let value = {
+            let data = &mut __body;
+            #value
+        };
+        __body.finish::<__C>().map_err(#context)?;
+        Ok(value)
+    })
+}
+
+/// Reads a tagged struct: the number of fields, and then each field in whatever order they come.
+/// Fields are kept in `__f{i}` until they're all read, and ones the struct doesn't have are skipped.
+fn unmarshal_tagged(
+    cx: &Container,
+    data_struct: &DataStruct,
+    fields: &[FieldInfo],
+) -> proc_macro2::TokenStream {
+    let krate = cx.krate();
+    let context = cx.error_context(quote! {});
+    let decoded = fields.iter().filter(|f| !f.attrs.skip).collect::<Vec<_>>();
+    let slots = decoded.iter().map(|field| {
+        let (slot, ty) = (&field.binding, field.ty);
+        quote! { let mut #slot: Option<#ty> = None; }
+    });
+    let arms = decoded.iter().map(|field| {
+        let (id, _) = field.attrs.id.unwrap();
+        let slot = &field.binding;
+        let field_context = cx.error_context(cx.field_path(field));
+        let Some(wire) = cx.fixed_wire_type(field) else {
+            let value = cx.unmarshal_field(field, cx.body_error_context(cx.field_path(field)));
+            return quote! {
+                #id => {
+                    #krate::__private::expect_wire(__wire, #krate::WireType::LengthDelimited)
+                        .map_err(#field_context)?;
+                    let mut __body = match #krate::__private::Body::delimited::<__C>(data) {
+                        Ok(body) => body,
+                        Err(e) => return Err((#field_context)(e)),
+                    };
+                    let value = {
+                        let data = &mut __body;
+                        #value
+                    };
+                    __body.finish::<__C>().map_err(#field_context)?;
+                    #slot = Some(value);
                 }
+            };
+        };
+        let value = cx.unmarshal_field(field, field_context.clone());
+        quote! {
+            #id => {
+                #krate::__private::expect_wire(__wire, #wire).map_err(#field_context)?;
+                #slot = Some(#value);
             }
-        })
-        .reduce(|acc, n| {
-            quote! {
-                #acc.chain(#n)
+        }
+    });
+    let values = fields.iter().map(|field| match field.attrs.skip {
+        true => default_value(field),
+        false => {
+            let slot = &field.binding;
+            cx.tagged_value(
+                field,
+                quote! { #slot },
+                cx.error_context(cx.field_path(field)),
+            )
+        }
+    });
+    let value = construct(quote! { Self }, &data_struct.fields, fields, values);
+    quote! {
+        let #krate::VarInt(__count) =
+            <#krate::VarInt<u32> as #krate::UnMarshal>::unmarshal_from_with::<__C>(data)
+                .map_err(#context)?;
+        #(#slots)*
+        for _ in 0..__count {
+            let (__id, __wire) = #krate::__private::read_key::<__C>(data).map_err(#context)?;
+            match __id {
+                #(#arms)*
+                _ => #krate::__private::skip_value_from::<__C>(data, __wire).map_err(#context)?,
             }
+        }
+        Ok(#value)
+    }
+}
+
+/// Read a union's bytes, or the index of the field that's set and then that field
+fn unmarshal_union(
+    cx: &Container,
+    data_union: &DataUnion,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let krate = cx.krate();
+    let kind = Fields::Named(data_union.fields.clone());
+    let fields = union_fields(cx, &kind)?;
+    let context = cx.error_context(quote! {});
+    if let Some((UnionAs::Bytes, _)) = cx.attrs.union_as {
+        return Ok(quote! {
+            let bytes = #krate::__private::readn_to_vec::<__C>(data, ::std::mem::size_of::<Self>())
+                .map_err(#context)?;
+            // SAFETY: `bytes` is as long as the union, and a union can hold any bytes.
+            // It's reading its fields that needs the right ones.
+            Ok(unsafe { ::std::ptr::read_unaligned(bytes.as_ptr().cast::<Self>()) })
+        });
+    }
+
+    let values = fields.iter().enumerate().map(|(i, field)| {
+        let tag = cx.tag_literal(i as u64);
+        let value = cx.unmarshal_field(field, cx.error_context(cx.field_path(field)));
+        let value = construct(quote! { Self }, &kind, std::slice::from_ref(field), [value]);
+        quote! { #tag => #value }
+    });
+    let tag_type = cx.tag_type();
+    let mut tag = quote! {
+        <#tag_type as #krate::UnMarshal>::unmarshal_from_with::<__C>(data).map_err(#context)?
+    };
+    if cx.attrs.tag_type == TagType::VarInt {
+        tag = quote! { #tag.0 };
+    }
+    Ok(quote! {
+        Ok(match #tag {
+            #(#values,)*
+            a => Err((#context)(#krate::MarshalError::InvalidData(format!(
+                "Invalid union field: {a}"
+            ))))?,
         })
+    })
 }
 
-fn marshal_enum(data_enum: &DataEnum) -> Option<proc_macro2::TokenStream> {
-    let data = data_enum.variants.iter()
-        .map(|var | {
-            let args = match &var.fields {
-                Fields::Named(a) => Some(syn::Error::new(a.span(), "Named fields are not supported").to_compile_error()),
-                Fields::Unit => None,
-                Fields::Unnamed(fields) => {
-                    if fields.unnamed.len() > 1 {
-                        Some(syn::Error::new(fields.unnamed.span(), "Only single unnamed fields are supported").to_compile_error())
-                    } else {
-                        let a = &fields.unnamed.first().unwrap().ty;
-                        match a {
-                            syn::Type::Verbatim(_) => panic!("Verbatim"),
-                            _ => Some(quote! { })
+/// Generates the [`PushDecoder`] for a union. One that's written as bytes collects them, and one
+/// with a discriminant reads the index of its field and then switches to decoding that field.
+fn incremental_union(
+    cx: &Container,
+    data_union: &DataUnion,
+    decoder: &syn::Ident,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let name = cx.name();
+    let krate = cx.krate();
+    let vis = &cx.ast.vis;
+    let generics = cx.unmarshal_generics(quote! { #krate::IncrementalUnMarshal });
+    let (impl_gen, ty_gen, where_gen) = generics.split_for_impl();
+    let (_, name_ty_gen, _) = cx.ast.generics.split_for_impl();
+    let params = &generics.params;
+    let kind = Fields::Named(data_union.fields.clone());
+    let fields = union_fields(cx, &kind)?;
+    let context = cx.decoder_error_context(quote! {});
+
+    let (state, default, feed) = if let Some((UnionAs::Bytes, _)) = cx.attrs.union_as {
+        (
+            quote! {
+                struct #decoder <#params> #where_gen (
+                    #krate::__private::FixedBytesDecoder,
+                    ::std::marker::PhantomData<fn() -> #name #name_ty_gen>,
+                );
+            },
+            quote! { Self(Default::default(), ::std::marker::PhantomData) },
+            quote! {
+                let size = ::std::mem::size_of::<#name #name_ty_gen>();
+                Ok(match self.0.feed(size, data) {
+                    // SAFETY: `bytes` is as long as the union, and a union can hold any bytes.
+                    // It's reading its fields that needs the right ones.
+                    #krate::Decoded::Done(bytes) => #krate::Decoded::Done(unsafe {
+                        ::std::ptr::read_unaligned(bytes.as_ptr().cast::<#name #name_ty_gen>())
+                    }),
+                    #krate::Decoded::NeedMore(n) => #krate::Decoded::NeedMore(n),
+                })
+            },
+        )
+    } else {
+        let variants = (0..fields.len())
+            .map(|i| format_ident!("__F{i}"))
+            .collect::<Vec<_>>();
+        let decoder_tys = fields.iter().map(|f| cx.decoder_type(f));
+        let starts = variants.iter().enumerate().map(|(i, var)| {
+            let tag = cx.tag_literal(i as u64);
+            quote! { #krate::Decoded::Done(#tag) => *self = Self::#var(Default::default()) }
+        });
+        let continues = fields.iter().zip(&variants).map(|(field, var)| {
+            let context = cx.decoder_error_context(cx.field_path(field));
+            let value = construct(
+                quote! { #name },
+                &kind,
+                std::slice::from_ref(field),
+                [quote! { v }],
+            );
+            quote! {
+                Self::#var(decoder) => {
+                    match #krate::PushDecoder::feed_with::<__C>(decoder, data).map_err(#context)? {
+                        #krate::Decoded::Done(v) => {
+                            *self = Self::default();
+                            #krate::Decoded::Done(#value)
                         }
+                        #krate::Decoded::NeedMore(n) => #krate::Decoded::NeedMore(n),
                     }
                 }
-            };
-            (&var.ident, args)
-        })
-        .enumerate()
-        .map(|(i, (var_name, args))| {
-                let i = i as u8;
-                match args {
-                    Some(args) => quote! { Self::#var_name(args) => {#args;MarshalIterator(Box::new(#i.marshal().chain(args.marshal())))} },
-                    None =>  quote! {Self::#var_name =>  MarshalIterator(Box::new(#i.marshal()))},
-                }
-                
-            }   
+            }
+        });
+        let tag_type = cx.tag_type();
+        let mut tag =
+            quote! { #krate::PushDecoder::feed_with::<__C>(tag, data).map_err(#context)? };
+        if cx.attrs.tag_type == TagType::VarInt {
+            tag = quote! { #tag.map(|tag| tag.0) };
+        }
+        (
+            quote! {
+                enum #decoder <#params> #where_gen {
+                    __Tag(
+                        <#tag_type as #krate::IncrementalUnMarshal>::Decoder,
+                        ::std::marker::PhantomData<fn() -> #name #name_ty_gen>,
+                    ),
+                    #(#variants(#decoder_tys),)*
+                }
+            },
+            quote! { Self::__Tag(Default::default(), ::std::marker::PhantomData) },
+            quote! {
+                if let Self::__Tag(tag, _) = self {
+                    match #tag {
+                        #krate::Decoded::NeedMore(n) => return Ok(#krate::Decoded::NeedMore(n)),
+                        #(#starts,)*
+                        #krate::Decoded::Done(a) => Err((#context)(#krate::MarshalError::InvalidData(
+                            format!("Invalid union field: {a}")
+                        )))?,
+                    }
+                }
+
+                Ok(match self {
+                    #(#continues,)*
+                    Self::__Tag(..) => unreachable!(),
+                })
+            },
         )
-        .reduce(|acc, val| quote! {
-            #acc, #val
-        })?;
+    };
 
-    Some(quote! {
-        match self {
-            #data
+    Ok(quote! {
+        #[doc(hidden)]
+        #vis #state
+
+        #[automatically_derived]
+        impl #impl_gen Default for #decoder #ty_gen #where_gen {
+            fn default() -> Self {
+                #default
+            }
+        }
+
+        #[automatically_derived]
+        impl #impl_gen #krate::PushDecoder for #decoder #ty_gen #where_gen {
+            type Output = #name #name_ty_gen;
+
+            fn feed(
+                &mut self,
+                data: &mut &[u8],
+            ) -> Result<#krate::Decoded<Self::Output>, #krate::MarshalError> {
+                #krate::PushDecoder::feed_with::<#krate::DefaultConfig>(self, data)
+            }
+
+            fn feed_with<__C: #krate::Config>(
+                &mut self,
+                data: &mut &[u8],
+            ) -> Result<#krate::Decoded<Self::Output>, #krate::MarshalError> {
+                #feed
+            }
+        }
+
+        #[automatically_derived]
+        impl #impl_gen #krate::IncrementalUnMarshal for #name #ty_gen #where_gen {
+            type Decoder = #krate::__private::NestedDecoder<#decoder #ty_gen>;
         }
     })
 }
-fn unmarshal_enum(data_enum: &DataEnum) -> proc_macro2::TokenStream {
-    let variants = data_enum.variants.iter()
-        .map(|var| {
-            let f = match &var.fields {
-                Fields::Named(fields_named) => syn::Error::new(fields_named.span(), "Named fields are not supported").to_compile_error(),
-                Fields::Unnamed(_fields_unnamed) => quote! {(UnMarshal::unmarshal(data)?)},
-                Fields::Unit => quote! {},
+
+/// Generates the [`PushDecoder`] state machine for a struct. Each field gets its own decoder
+/// and a slot to hold the value once it's done, and `stage` tracks which field is being decoded.
+fn incremental_struct(
+    cx: &Container,
+    data_struct: &DataStruct,
+    decoder: &syn::Ident,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let name = cx.name();
+    let krate = cx.krate();
+    let vis = &cx.ast.vis;
+    let generics = cx.unmarshal_generics(quote! { #krate::IncrementalUnMarshal });
+    let (impl_gen, ty_gen, where_gen) = generics.split_for_impl();
+    let (_, name_ty_gen, _) = cx.ast.generics.split_for_impl();
+    let params = &generics.params;
+
+    let fields = cx.fields(&data_struct.fields)?;
+    let decoded = fields.iter().filter(|f| !f.attrs.skip).collect::<Vec<_>>();
+    let tys = decoded.iter().map(|field| field.ty).collect::<Vec<_>>();
+    let decoder_tys = decoded
+        .iter()
+        .map(|field| cx.decoder_type(field))
+        .collect::<Vec<_>>();
+    let decoders = (0..decoded.len())
+        .map(|i| format_ident!("__d{i}"))
+        .collect::<Vec<_>>();
+    let values = (0..decoded.len())
+        .map(|i| format_ident!("__v{i}"))
+        .collect::<Vec<_>>();
+    let versioned = cx.attrs.version.is_some();
+    let stages = decoded.iter().enumerate().map(|(stage, field)| {
+        let (d, v) = (&decoders[stage], &values[stage]);
+        let context = cx.decoder_error_context(cx.field_path(field));
+        let feed = match versioned {
+            true => quote! { self.__body.feed::<__C, _>(&mut self.#d, data) },
+            false => quote! { #krate::PushDecoder::feed_with::<__C>(&mut self.#d, data) },
+        };
+        let mut decode = quote! {
+            match #feed.map_err(#context)? {
+                #krate::Decoded::Done(v) => self.#v = Some(v),
+                #krate::Decoded::NeedMore(n) => return Ok(#krate::Decoded::NeedMore(n)),
+            }
+        };
+        // Fields added after the version that wrote the data get their default
+        if let Some((since, _)) = field.attrs.since {
+            let default = default_value(field);
+            decode = quote! {
+                if __version >= #since {
+                    #decode
+                } else {
+                    self.#v = Some(#default);
+                }
             };
-            (&var.ident, f)
-        })
-        .enumerate()
-        .map(|(i, (id, field))| {
-            let ii = i as u8;
-            quote! { #ii => Self::#id #field}
-        });
+        }
+        quote! {
+            if self.__stage == #stage {
+                #decode
+                self.__stage += 1;
+            }
+        }
+    });
+    // Versioned structs read their version and length first, and skip fields they don't know last
+    let body = versioned.then(|| quote! { __body: #krate::__private::BodyDecoder, });
+    let body_default = versioned.then(|| quote! { __body: Default::default(), });
+    let header = versioned.then(|| {
+        let context = cx.decoder_error_context(quote! {});
+        quote! {
+            let __version = match self.__body.feed_header::<__C>(data).map_err(#context)? {
+                #krate::Decoded::Done(version) => version,
+                #krate::Decoded::NeedMore(n) => return Ok(#krate::Decoded::NeedMore(n)),
+            };
+        }
+    });
+    let finish = versioned.then(|| {
+        quote! {
+            if let #krate::Decoded::NeedMore(n) = self.__body.finish(data) {
+                return Ok(#krate::Decoded::NeedMore(n));
+            }
+        }
+    });
+    let mut feed = quote! {
+        #header
+        #(#stages)*
+        #finish
+        self.__stage = 0;
+    };
+    let mut next_value = values.iter();
+    let mut value = construct(
+        quote! { #name },
+        &data_struct.fields,
+        &fields,
+        fields.iter().map(|field| match field.attrs.skip {
+            true => default_value(field),
+            false => {
+                let v = next_value.next().unwrap();
+                quote! { self.#v.take().unwrap() }
+            }
+        }),
+    );
+    let mut state = quote! { __stage: usize, };
+    let mut state_default = quote! { __stage: 0, };
 
-    quote! {
-        let variant = u8::unmarshal(data)?;
-        Ok(match variant {
-            #(#variants, )*
-            a => Err(MarshalError::InvalidData(format!(
-                "Invalid enum varient: {a}"
-            )))?,
-        })
+    // Tagged structs read fields in whatever order they come, skipping the ones they don't know
+    if cx.attrs.tagged.is_some() {
+        state = quote! { __tagged: #krate::__private::TaggedDecoder, };
+        state_default = quote! { __tagged: Default::default(), };
+        let context = cx.decoder_error_context(quote! {});
+        let arms = decoded.iter().enumerate().map(|(i, field)| {
+            let (id, _) = field.attrs.id.unwrap();
+            let (d, v) = (&decoders[i], &values[i]);
+            let wire = cx.wire_type(field);
+            let context = cx.decoder_error_context(cx.field_path(field));
+            quote! {
+                #id => match self
+                    .__tagged
+                    .feed::<__C, _>(&mut self.#d, #wire, data)
+                    .map_err(#context)?
+                {
+                    #krate::Decoded::Done(v) => {
+                        self.#v = Some(v);
+                        #krate::Decoded::Done(())
+                    }
+                    #krate::Decoded::NeedMore(n) => #krate::Decoded::NeedMore(n),
+                },
+            }
+        });
+        feed = quote! {
+            loop {
+                let __id = match self.__tagged.next_field::<__C>(data).map_err(#context)? {
+                    #krate::Decoded::Done(Some(id)) => id,
+                    #krate::Decoded::Done(None) => break,
+                    #krate::Decoded::NeedMore(n) => return Ok(#krate::Decoded::NeedMore(n)),
+                };
+                let __decoded = match __id {
+                    #(#arms)*
+                    _ => self.__tagged.skip::<__C>(data).map_err(#context)?,
+                };
+                if let #krate::Decoded::NeedMore(n) = __decoded {
+                    return Ok(#krate::Decoded::NeedMore(n));
+                }
+            }
+            // Emptied before anything can fail, ready for the next value
+            #(let #values = self.#values.take();)*
+        };
+        let mut next_value = values.iter();
+        value = construct(
+            quote! { #name },
+            &data_struct.fields,
+            &fields,
+            fields.iter().map(|field| match field.attrs.skip {
+                true => default_value(field),
+                false => {
+                    let v = next_value.next().unwrap();
+                    let context = cx.decoder_error_context(cx.field_path(field));
+                    cx.tagged_value(field, quote! { #v }, context)
+                }
+            }),
+        );
     }
+
+    Ok(quote! {
+        #[doc(hidden)]
+        #vis struct #decoder <#params> #where_gen {
+            #state
+            #body
+            #(#decoders: #decoder_tys,)*
+            #(#values: Option<#tys>,)*
+            __marker: ::std::marker::PhantomData<fn() -> #name #name_ty_gen>,
+        }
+
+        #[automatically_derived]
+        impl #impl_gen Default for #decoder #ty_gen #where_gen {
+            fn default() -> Self {
+                Self {
+                    #state_default
+                    #body_default
+                    #(#decoders: Default::default(),)*
+                    #(#values: None,)*
+                    __marker: ::std::marker::PhantomData,
+                }
+            }
+        }
+
+        #[automatically_derived]
+        impl #impl_gen #krate::PushDecoder for #decoder #ty_gen #where_gen {
+            type Output = #name #name_ty_gen;
+
+            fn feed(
+                &mut self,
+                data: &mut &[u8],
+            ) -> Result<#krate::Decoded<Self::Output>, #krate::MarshalError> {
+                #krate::PushDecoder::feed_with::<#krate::DefaultConfig>(self, data)
+            }
+
+            fn feed_with<__C: #krate::Config>(
+                &mut self,
+                data: &mut &[u8],
+            ) -> Result<#krate::Decoded<Self::Output>, #krate::MarshalError> {
+                #feed
+                Ok(#krate::Decoded::Done(#value))
+            }
+        }
+
+        #[automatically_derived]
+        impl #impl_gen #krate::IncrementalUnMarshal for #name #ty_gen #where_gen {
+            type Decoder = #krate::__private::NestedDecoder<#decoder #ty_gen>;
+        }
+    })
 }
 
-fn impl_marshal_macro(ast: &syn::DeriveInput) -> TokenStream {
-    let name = &ast.ident;
-    let (impl_gen, ty_gen, where_gen) = &ast.generics.split_for_impl();
+/// Generates the [`PushDecoder`] state machine for an enum. The decoder starts out reading the
+/// tag and then switches to decoding the fields of that variant, one after another.
+fn incremental_enum(
+    cx: &Container,
+    data_enum: &DataEnum,
+    decoder: &syn::Ident,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let name = cx.name();
+    let krate = cx.krate();
+    let vis = &cx.ast.vis;
+    let generics = cx.unmarshal_generics(quote! { #krate::IncrementalUnMarshal });
+    let (impl_gen, ty_gen, where_gen) = generics.split_for_impl();
+    let (_, name_ty_gen, _) = cx.ast.generics.split_for_impl();
+    let params = &generics.params;
 
-    let data = match &ast.data {
-        syn::Data::Struct(data_struct) => marshal_struct(data_struct),
-        syn::Data::Enum(data_enum) => marshal_enum(data_enum),
-        // {
-        //     return syn::Error::new(
-        //         data_enum.enum_token.span(),
-        //         "Marshalling enums with the derive macro isn't supported yet",
-        //     )
-        //     .into_compile_error()
-        //     .into()
-        // }
-        syn::Data::Union(data_union) => {
-            return syn::Error::new(
-                data_union.union_token.span(),
-                "Marshalling unions with the derive macro isn't supported yet",
+    let tags = variant_tags(cx, data_enum)?;
+    let mut states = Vec::new();
+    let mut starts = Vec::new();
+    let mut continues = Vec::new();
+    for (var, tag) in data_enum.variants.iter().zip(tags) {
+        let i = cx.tag_literal(tag);
+        let var_name = &var.ident;
+        let fields = cx.fields(&var.fields)?;
+        let decoded = fields.iter().filter(|f| !f.attrs.skip).collect::<Vec<_>>();
+        let idx = (0..decoded.len()).map(syn::Index::from).collect::<Vec<_>>();
+        let mut next_idx = idx.iter();
+        let value = construct(
+            quote! { #name::#var_name },
+            &var.fields,
+            &fields,
+            fields.iter().map(|field| match field.attrs.skip {
+                true => default_value(field),
+                false => {
+                    let i = next_idx.next().unwrap();
+                    quote! { values.#i.take().unwrap() }
+                }
+            }),
+        );
+        if decoded.is_empty() {
+            starts.push(quote! {
+                #krate::Decoded::Done(#i) => return Ok(#krate::Decoded::Done(#value))
+            });
+            continue;
+        }
+
+        // Each variant with fields keeps the stage it's at, a decoder per field,
+        // and the values of the fields decoded so far
+        let tys = decoded.iter().map(|f| f.ty).collect::<Vec<_>>();
+        let decoder_tys = decoded.iter().map(|f| cx.decoder_type(f));
+        let defaults = decoded.iter().map(|_| quote! { Default::default() });
+        let nones = decoded.iter().map(|_| quote! { None });
+        let stages = 0..decoded.len();
+        let contexts = decoded
+            .iter()
+            .map(|field| cx.decoder_error_context(cx.variant_path(var, field)));
+
+        states.push(quote! {
+            #var_name(
+                usize,
+                (#(#decoder_tys,)*),
+                (#(Option<#tys>,)*),
             )
-            .into_compile_error()
-            .into()
+        });
+        starts.push(quote! {
+            #krate::Decoded::Done(#i) => *self = Self::#var_name(0, (#(#defaults,)*), (#(#nones,)*))
+        });
+        continues.push(quote! {
+            Self::#var_name(stage, decoders, values) => {
+                #(
+                    if *stage == #stages {
+                        match #krate::PushDecoder::feed_with::<__C>(&mut decoders.#idx, data)
+                            .map_err(#contexts)?
+                        {
+                            #krate::Decoded::Done(v) => {
+                                values.#idx = Some(v);
+                                *stage += 1;
+                            }
+                            #krate::Decoded::NeedMore(n) => {
+                                return Ok(#krate::Decoded::NeedMore(n));
+                            }
+                        }
+                    }
+                )*
+                let value = #value;
+                *self = Self::default();
+                #krate::Decoded::Done(value)
+            }
+        });
+    }
+
+    let context = cx.decoder_error_context(quote! {});
+    let tag_type = cx.tag_type();
+    let mut tag = quote! { #krate::PushDecoder::feed_with::<__C>(tag, data).map_err(#context)? };
+    if cx.attrs.tag_type == TagType::VarInt {
+        tag = quote! { #tag.map(|tag| tag.0) };
+    }
+
+    Ok(quote! {
+        #[doc(hidden)]
+        #vis enum #decoder <#params> #where_gen {
+            __Tag(
+                <#tag_type as #krate::IncrementalUnMarshal>::Decoder,
+                ::std::marker::PhantomData<fn() -> #name #name_ty_gen>,
+            ),
+            #(#states,)*
         }
-    };
 
-    if let Some(d) = data {
-        quote! {
-            #[automatically_derived]
-            impl #impl_gen Marshal for #name #ty_gen #where_gen {
-                fn marshal(self) -> impl Iterator<Item = u8> {
-                    #d
+        #[automatically_derived]
+        impl #impl_gen Default for #decoder #ty_gen #where_gen {
+            fn default() -> Self {
+                Self::__Tag(Default::default(), ::std::marker::PhantomData)
+            }
+        }
+
+        #[automatically_derived]
+        impl #impl_gen #krate::PushDecoder for #decoder #ty_gen #where_gen {
+            type Output = #name #name_ty_gen;
+
+            fn feed(
+                &mut self,
+                data: &mut &[u8],
+            ) -> Result<#krate::Decoded<Self::Output>, #krate::MarshalError> {
+                #krate::PushDecoder::feed_with::<#krate::DefaultConfig>(self, data)
+            }
+
+            // When every variant is fieldless, decoding the tag always returns
+            #[allow(unreachable_code)]
+            fn feed_with<__C: #krate::Config>(
+                &mut self,
+                data: &mut &[u8],
+            ) -> Result<#krate::Decoded<Self::Output>, #krate::MarshalError> {
+                if let Self::__Tag(tag, _) = self {
+                    match #tag {
+                        #krate::Decoded::NeedMore(n) => return Ok(#krate::Decoded::NeedMore(n)),
+                        #(#starts,)*
+                        #krate::Decoded::Done(a) => Err((#context)(#krate::MarshalError::InvalidData(
+                            format!("Invalid enum varient: {a}")
+                        )))?,
+                    }
                 }
+
+                Ok(match self {
+                    #(#continues,)*
+                    Self::__Tag(..) => unreachable!(),
+                })
             }
         }
-        .into()
-    } else {
-        quote! {}.into()
-    }
-}
 
-fn unmarshal_struct(data_struct: &DataStruct) -> proc_macro2::TokenStream {
-    let fields =  data_struct.fields.iter().map(|field| {
-        field.ident.as_ref().unwrap()
-    });
-    quote! {
-        Ok(Self {
-            #(#fields: UnMarshal::unmarshal(data)?),*
-        })
-    }
+        #[automatically_derived]
+        impl #impl_gen #krate::IncrementalUnMarshal for #name #ty_gen #where_gen {
+            type Decoder = #krate::__private::NestedDecoder<#decoder #ty_gen>;
+        }
+    })
 }
 
-#[proc_macro_derive(UnMarshal)]
+/// Derives `UnMarshal` and `IncrementalUnMarshal`, reading what `Marshal` writes.
+/// It takes the same attributes as `Marshal`.
+#[proc_macro_derive(UnMarshal, attributes(marshal))]
 pub fn unmarshal_derive(input: TokenStream) -> TokenStream {
     let ast = syn::parse(input).unwrap();
 
     impl_unmarshal_macro(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
 }
 
-fn impl_unmarshal_macro(ast: &syn::DeriveInput) -> TokenStream {
-    let name = &ast.ident;
-    let (impl_gen, ty_gen, where_gen) = &ast.generics.split_for_impl();
-    let unmarshal_body = match &ast.data {
-        syn::Data::Struct(data_struct) => unmarshal_struct(data_struct),
-        syn::Data::Enum(data_enum) => unmarshal_enum(data_enum),
-        syn::Data::Union(data_union) => {
-            syn::Error::new(
-                data_union.union_token.span(),
-                "Unmarshalling unions with the derive macro isn't supported",
-            )
-            .into_compile_error()
-        }
+fn impl_unmarshal_macro(ast: &syn::DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let cx = Container::new(ast)?;
+    let name = cx.name();
+    let krate = cx.krate();
+    let generics = cx.unmarshal_generics(quote! { #krate::UnMarshal });
+    let (impl_gen, ty_gen, where_gen) = generics.split_for_impl();
+    let decoder = format_ident!("__{}PushDecoder", name);
+    let (unmarshal_body, incremental) = match &ast.data {
+        syn::Data::Struct(data_struct) => (
+            unmarshal_struct(&cx, data_struct)?,
+            incremental_struct(&cx, data_struct, &decoder)?,
+        ),
+        syn::Data::Enum(data_enum) => (
+            unmarshal_enum(&cx, data_enum)?,
+            incremental_enum(&cx, data_enum, &decoder)?,
+        ),
+        syn::Data::Union(data_union) => (
+            unmarshal_union(&cx, data_union)?,
+            incremental_union(&cx, data_union, &decoder)?,
+        ),
     };
 
-    let generated = quote! {
+    let context = cx.error_context(quote! {});
+    let copy = union_is_copy(&cx);
+
+    Ok(quote! {
+        #copy
+
         #[automatically_derived]
-        impl #impl_gen UnMarshal for #name #ty_gen #where_gen {
-            fn unmarshal(data: &mut impl Iterator<Item = u8>) -> Result<Self, MarshalError> {
+        impl #impl_gen #krate::UnMarshal for #name #ty_gen #where_gen {
+            fn unmarshal(
+                data: &mut impl Iterator<Item = u8>,
+            ) -> Result<Self, #krate::MarshalError> {
+                <Self as #krate::UnMarshal>::unmarshal_with::<#krate::DefaultConfig>(data)
+            }
+
+            fn unmarshal_with<__C: #krate::Config>(
+                data: &mut impl Iterator<Item = u8>,
+            ) -> Result<Self, #krate::MarshalError> {
+                <Self as #krate::UnMarshal>::unmarshal_from_with::<__C>(
+                    &mut #krate::IterSource::new(data),
+                )
+            }
+
+            fn unmarshal_from_with<__C: #krate::Config>(
+                data: &mut impl #krate::ByteSource,
+            ) -> Result<Self, #krate::MarshalError> {
+                let __start = #krate::__private::position(&*data);
+                let _nested = #krate::__private::Nested::enter::<__C>().map_err(#context)?;
                 #unmarshal_body
             }
         }
+
+        #incremental
+    })
+}
+
+/// Derives `UnMarshalBorrowed`, for types with fields like `&'a str`, `&'a [u8]` or `Cow<'a, str>`
+/// that point into the bytes they're read from instead of copying them. It reads what `Marshal`
+/// writes and takes the same attributes, but fields with `with` are read through
+/// `module::unmarshal`, so they can't borrow.
+#[proc_macro_derive(UnMarshalBorrowed, attributes(marshal))]
+pub fn unmarshal_borrowed_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse(input).unwrap();
+
+    impl_unmarshal_borrowed_macro(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn impl_unmarshal_borrowed_macro(ast: &syn::DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let mut cx = Container::new(ast)?;
+    cx.borrowed = true;
+    let name = cx.name();
+    let krate = cx.krate();
+    let generics = cx.unmarshal_generics(quote! { #krate::UnMarshalBorrowed<'__de> });
+    let (_, ty_gen, _) = generics.split_for_impl();
+    // The input has to outlive everything the type borrows from it
+    let mut impl_generics = generics.clone();
+    let mut de: syn::LifetimeParam = syn::parse_quote! { '__de };
+    for param in generics.lifetimes() {
+        de.bounds.push(param.lifetime.clone());
+    }
+    impl_generics
+        .params
+        .insert(0, syn::GenericParam::Lifetime(de));
+    let (impl_gen, _, where_gen) = impl_generics.split_for_impl();
+    let body = match &ast.data {
+        syn::Data::Struct(data_struct) => unmarshal_struct(&cx, data_struct)?,
+        syn::Data::Enum(data_enum) => unmarshal_enum(&cx, data_enum)?,
+        syn::Data::Union(data_union) => unmarshal_union(&cx, data_union)?,
+    };
+
+    let context = cx.error_context(quote! {});
+    let copy = union_is_copy(&cx);
+
+    Ok(quote! {
+        #copy
+
+        #[automatically_derived]
+        impl #impl_gen #krate::UnMarshalBorrowed<'__de> for #name #ty_gen #where_gen {
+            fn unmarshal_borrowed_with<__C: #krate::Config>(
+                data: &mut impl #krate::BorrowedSource<'__de>,
+            ) -> Result<Self, #krate::MarshalError> {
+                let __start = #krate::__private::position(&*data);
+                let _nested = #krate::__private::Nested::enter::<__C>().map_err(#context)?;
+                #body
+            }
+        }
+    })
+}
+
+/// Derives `Viewable` for a struct `Name`, along with a `NameView` of its marshalled bytes that has
+/// a method for each field, which reads only that field. Fields marshalled `with` a module are read
+/// through `module::unmarshal`. Where the fields of versioned and tagged structs are depends on
+/// what's in the data, so they can't be viewed.
+#[proc_macro_derive(Viewable, attributes(marshal))]
+pub fn viewable_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse(input).unwrap();
+
+    impl_viewable_macro(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn impl_viewable_macro(ast: &syn::DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let cx = Container::new(ast)?;
+    let syn::Data::Struct(data_struct) = &ast.data else {
+        return Err(syn::Error::new(
+            ast.ident.span(),
+            "only structs can be viewed",
+        ));
+    };
+    if let Some((_, span)) = cx.attrs.version {
+        return Err(syn::Error::new(
+            span,
+            "versioned structs can't be viewed, their fields depend on the version in the data",
+        ));
+    }
+    if let Some(span) = cx.attrs.tagged {
+        return Err(syn::Error::new(
+            span,
+            "tagged structs can't be viewed, their fields can be in any order",
+        ));
+    }
+    let name = cx.name();
+    let krate = cx.krate();
+    let view = format_ident!("{}View", name.unraw());
+    let fields = cx.fields(&data_struct.fields)?;
+    let written = data_struct
+        .fields
+        .iter()
+        .zip(&fields)
+        .filter(|(_, f)| !f.attrs.skip)
+        .collect::<Vec<_>>();
+
+    // The view has the type's generics between the input's lifetime and the config
+    let with_view_params = |generics: &syn::Generics, config: syn::GenericParam| {
+        let mut generics = generics.clone();
+        generics.params.insert(0, syn::parse_quote! { '__v });
+        generics.params.push(config);
+        generics
+    };
+    let (_, name_ty_gen, _) = ast.generics.split_for_impl();
+    let view_generics = with_view_params(
+        &ast.generics,
+        syn::parse_quote! { __C = #krate::DefaultConfig },
+    );
+    let view_where = &view_generics.where_clause;
+    let plain_generics = with_view_params(&ast.generics, syn::parse_quote! { __C });
+    let (plain_impl_gen, plain_ty_gen, plain_where_gen) = plain_generics.split_for_impl();
+    let generics = cx.generics(Some(quote! { #krate::Viewable }));
+    let (impl_gen, ty_gen, where_gen) = generics.split_for_impl();
+    let view_impl_generics = with_view_params(&generics, syn::parse_quote! { __C: #krate::Config });
+    let (view_impl_gen, view_ty_gen, view_where_gen) = view_impl_generics.split_for_impl();
+
+    let skip = |field: &FieldInfo| {
+        let ty = field.ty;
+        match &field.attrs.with {
+            Some(with) => quote! {
+                #krate::__private::with_iter(data, |data| #with::unmarshal::<__C>(data)).map(drop)
+            },
+            None => quote! { <#ty as #krate::Viewable>::skip_from_with::<__C>(data) },
+        }
+    };
+    let skips = written.iter().map(|(_, field)| {
+        let skip = skip(field);
+        let context = cx.error_context(cx.field_path(field));
+        quote! { #skip.map_err(#context)?; }
+    });
+    let fixed_len = match written.iter().any(|(_, f)| f.attrs.with.is_some()) {
+        true => quote! { None },
+        false => {
+            let lens = written.iter().map(|(_, field)| {
+                let ty = field.ty;
+                quote! { <#ty as #krate::Viewable>::fixed_len::<__C>()? }
+            });
+            quote! { Some(0 #(+ #lens)*) }
+        }
     };
-    generated.into()
+    let accessors = written.iter().enumerate().map(|(i, (syn_field, field))| {
+        let vis = &syn_field.vis;
+        let method = match &field.member {
+            syn::Member::Named(ident) => ident.clone(),
+            syn::Member::Unnamed(index) => format_ident!("_{}", index.index),
+        };
+        let doc = format!("Read `{}` from the marshalled bytes", field.name);
+        let before = written[..i].iter().map(|(_, before)| {
+            let skip = skip(before);
+            let context = cx.error_context(cx.field_path(before));
+            quote! { #skip.map_err(#context)?; }
+        });
+        let ty = field.ty;
+        let (view_ty, value) = match &field.attrs.with {
+            Some(with) => (
+                quote! { #ty },
+                quote! { #krate::__private::with_iter(data, |data| #with::unmarshal::<__C>(data)) },
+            ),
+            None => (
+                quote! { <#ty as #krate::Viewable>::View<'__v, __C> },
+                quote! { <#ty as #krate::Viewable>::view_from_with::<__C>(data) },
+            ),
+        };
+        let context = cx.error_context(cx.field_path(field));
+        quote! {
+            #[doc = #doc]
+            #vis fn #method(&self) -> Result<#view_ty, #krate::MarshalError> {
+                let mut __bytes = self.bytes;
+                let data = &mut __bytes;
+                let __start = #krate::__private::position(&*data);
+                #(#before)*
+                #value.map_err(#context)
+            }
+        }
+    });
+
+    let doc = format!(
+        "A view of a marshalled `{}`, with a method reading each of its fields",
+        name.unraw()
+    );
+    let vis = &ast.vis;
+    let context = cx.error_context(quote! {});
+
+    Ok(quote! {
+        #[doc = #doc]
+        #vis struct #view #view_generics #view_where {
+            bytes: &'__v [u8],
+            _marker: ::std::marker::PhantomData<fn() -> (#name #name_ty_gen, __C)>,
+        }
+
+        #[automatically_derived]
+        impl #plain_impl_gen ::std::clone::Clone for #view #plain_ty_gen #plain_where_gen {
+            fn clone(&self) -> Self {
+                *self
+            }
+        }
+
+        #[automatically_derived]
+        impl #plain_impl_gen ::std::marker::Copy for #view #plain_ty_gen #plain_where_gen {}
+
+        #[automatically_derived]
+        impl #view_impl_gen #view #view_ty_gen #view_where_gen {
+            #(#accessors)*
+        }
+
+        #[automatically_derived]
+        impl #impl_gen #krate::Viewable for #name #ty_gen #where_gen {
+            type View<'__v, __C: #krate::Config> = #view #view_ty_gen;
+
+            fn fixed_len<__C: #krate::Config>() -> Option<usize> {
+                #fixed_len
+            }
+
+            fn skip_from_with<__C: #krate::Config>(
+                data: &mut &[u8],
+            ) -> Result<(), #krate::MarshalError> {
+                let __start = #krate::__private::position(&*data);
+                let _nested = #krate::__private::Nested::enter::<__C>().map_err(#context)?;
+                #(#skips)*
+                Ok(())
+            }
+
+            fn view_from_with<'__v, __C: #krate::Config>(
+                data: &mut &'__v [u8],
+            ) -> Result<Self::View<'__v, __C>, #krate::MarshalError> {
+                let __bytes = *data;
+                // The fields are found when they're read, so only a fixed length says where the
+                // value ends without walking them
+                match <Self as #krate::Viewable>::fixed_len::<__C>() {
+                    Some(len) => {
+                        let __start = #krate::__private::position(&*data);
+                        #krate::ByteSource::skip(data, len).map_err(#context)?;
+                    }
+                    None => *data = &[],
+                }
+                Ok(#view {
+                    bytes: &__bytes[..__bytes.len() - data.len()],
+                    _marker: ::std::marker::PhantomData,
+                })
+            }
+        }
+    })
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,305 @@
+use quote::ToTokens;
+use syn::parse_quote;
+
+use super::*;
+
+fn tags(ast: syn::DeriveInput) -> syn::Result<Vec<u64>> {
+    let cx = Container::new(&ast)?;
+    let syn::Data::Enum(data_enum) = &ast.data else {
+        panic!("not an enum");
+    };
+    variant_tags(&cx, data_enum)
+}
+
+fn tag_error(ast: syn::DeriveInput) -> String {
+    tags(ast).unwrap_err().to_string()
+}
+
+#[test]
+fn test_variant_tags() {
+    assert_eq!(
+        tags(parse_quote! { enum E { A, B, C } }).unwrap(),
+        [0, 1, 2]
+    );
+    assert_eq!(
+        tags(parse_quote! { enum E { A = 4, B, #[marshal(tag = 0)] C, D } }).unwrap(),
+        [4, 5, 0, 1]
+    );
+    assert_eq!(
+        tags(parse_quote! {
+            #[marshal(tag_type = "u16")]
+            enum E { #[marshal(tag = 65535)] A, B = 0 }
+        })
+        .unwrap(),
+        [65535, 0]
+    );
+
+    assert_eq!(
+        tag_error(parse_quote! { enum E { A = 1, B, #[marshal(tag = 2)] C } }),
+        "tag 2 of `C` is already used by `B`"
+    );
+    assert_eq!(
+        tag_error(parse_quote! { enum E { A = 255, B } }),
+        "tag 256 of `B` doesn't fit in a u8 tag, \
+         use `#[marshal(tag_type = \"..\")]` on the enum for a wider one"
+    );
+    assert_eq!(
+        tag_error(parse_quote! {
+            #[marshal(tag_type = "varint")]
+            enum E { #[marshal(tag = 4294967296)] A }
+        }),
+        "tag 4294967296 of `A` doesn't fit in a varint tag, \
+         use `#[marshal(tag_type = \"..\")]` on the enum for a wider one"
+    );
+    assert_eq!(
+        tag_error(parse_quote! { enum E { A = -1 } }),
+        "only integer literal discriminants can be used as tags, \
+         use `#[marshal(tag = ..)]` instead"
+    );
+    assert_eq!(
+        tag_error(parse_quote! { enum E { A = FIRST } }),
+        "only integer literal discriminants can be used as tags, \
+         use `#[marshal(tag = ..)]` instead"
+    );
+}
+
+#[test]
+fn test_many_variants() {
+    let variants = (0..300).map(|i| format_ident!("V{i}"));
+    let ast: syn::DeriveInput = parse_quote! { enum E { #(#variants,)* } };
+    assert_eq!(
+        tag_error(ast.clone()),
+        "tag 256 of `V256` doesn't fit in a u8 tag, \
+         use `#[marshal(tag_type = \"..\")]` on the enum for a wider one"
+    );
+
+    let mut wide = ast;
+    wide.attrs
+        .push(parse_quote! { #[marshal(tag_type = "u16")] });
+    assert_eq!(tags(wide).unwrap(), (0..300).collect::<Vec<_>>());
+}
+
+fn struct_error(ast: syn::DeriveInput) -> String {
+    let syn::Data::Struct(data_struct) = &ast.data else {
+        panic!("not a struct");
+    };
+    match Container::new(&ast).and_then(|cx| cx.fields(&data_struct.fields).map(|_| ())) {
+        Ok(()) => panic!("no error"),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn test_versions() {
+    let ast: syn::DeriveInput = parse_quote! {
+        #[marshal(version = 3)]
+        struct S {
+            a: u8,
+            #[marshal(skip)]
+            b: u8,
+            #[marshal(since = 2, default)]
+            c: u8,
+            #[marshal(since = 2, default)]
+            d: u8,
+            #[marshal(since = 3, default = "three")]
+            e: u8,
+        }
+    };
+    let syn::Data::Struct(data_struct) = &ast.data else {
+        unreachable!()
+    };
+    let cx = Container::new(&ast).unwrap();
+    assert_eq!(cx.fields(&data_struct.fields).unwrap().len(), 5);
+
+    assert_eq!(
+        struct_error(parse_quote! { struct S { #[marshal(since = 2, default)] a: u8 } }),
+        "`since` needs `#[marshal(version = N)]` on the struct"
+    );
+    assert_eq!(
+        struct_error(parse_quote! {
+            #[marshal(version = 2)]
+            struct S { #[marshal(since = 3, default)] a: u8 }
+        }),
+        "the struct is only at version 2"
+    );
+    assert_eq!(
+        struct_error(parse_quote! {
+            #[marshal(version = 3)]
+            struct S {
+                #[marshal(since = 3, default)]
+                a: u8,
+                #[marshal(since = 2, default)]
+                b: u8,
+            }
+        }),
+        "fields are written in the order they were added, \
+         so `b` has to come before `a` which was added in version 3"
+    );
+    assert_eq!(
+        struct_error(parse_quote! {
+            #[marshal(version = 2)]
+            struct S(#[marshal(since = 2, default)] u8, u8);
+        }),
+        "fields are written in the order they were added, \
+         so `1` has to come before `0` which was added in version 2"
+    );
+
+    let ast: syn::DeriveInput = parse_quote! { #[marshal(version = 2)] enum E { A } };
+    assert_eq!(
+        Container::new(&ast).err().unwrap().to_string(),
+        "only structs can be versioned"
+    );
+}
+
+#[test]
+fn test_tagged_fields() {
+    let ast: syn::DeriveInput = parse_quote! {
+        #[marshal(tagged)]
+        struct S {
+            #[marshal(id = 2)]
+            a: u8,
+            #[marshal(skip)]
+            b: u8,
+            #[marshal(id = 1, default)]
+            c: u8,
+        }
+    };
+    let syn::Data::Struct(data_struct) = &ast.data else {
+        unreachable!()
+    };
+    let cx = Container::new(&ast).unwrap();
+    assert_eq!(cx.fields(&data_struct.fields).unwrap().len(), 3);
+
+    assert_eq!(
+        struct_error(parse_quote! { struct S { #[marshal(id = 1)] a: u8 } }),
+        "`id` needs `#[marshal(tagged)]` on the struct"
+    );
+    assert_eq!(
+        struct_error(
+            parse_quote! { #[marshal(tagged)] struct S { #[marshal(id = 1)] a: u8, b: u8 } }
+        ),
+        "fields of a tagged struct need a `#[marshal(id = N)]`"
+    );
+    assert_eq!(
+        struct_error(parse_quote! {
+            #[marshal(tagged)]
+            struct S(#[marshal(id = 3)] u8, #[marshal(id = 3)] u8);
+        }),
+        "id 3 of `1` is already used by `0`"
+    );
+    assert_eq!(
+        struct_error(parse_quote! { struct S { #[marshal(default = "zero")] a: u8 } }),
+        "`default` is only used for fields marked `skip` or `since`, or in a `tagged` struct"
+    );
+    assert_eq!(
+        struct_error(parse_quote! { #[marshal(tagged, version = 2)] struct S; }),
+        "tagged structs can already add and remove fields, they don't need a `version`"
+    );
+
+    let ast: syn::DeriveInput = parse_quote! { #[marshal(tagged)] enum E { A } };
+    assert_eq!(
+        Container::new(&ast).err().unwrap().to_string(),
+        "only structs can be tagged"
+    );
+}
+
+#[test]
+fn test_unions() {
+    let ast: syn::DeriveInput = parse_quote! { union U { a: u8 } };
+    assert_eq!(
+        Container::new(&ast).err().unwrap().to_string(),
+        "unions need `#[marshal(union_as = \"bytes\")]` or \
+         `#[marshal(union_as = \"discriminant\")]` to say how they're marshalled"
+    );
+    let ast: syn::DeriveInput = parse_quote! { #[marshal(union_as = "bytes")] struct S; };
+    assert_eq!(
+        Container::new(&ast).err().unwrap().to_string(),
+        "only unions take `union_as`"
+    );
+
+    let ast: syn::DeriveInput = parse_quote! {
+        #[marshal(union_as = "discriminant")]
+        union U { a: u8, #[marshal(skip)] b: u8 }
+    };
+    let syn::Data::Union(data_union) = &ast.data else {
+        unreachable!()
+    };
+    let cx = Container::new(&ast).unwrap();
+    let fields = Fields::Named(data_union.fields.clone());
+    assert_eq!(
+        union_fields(&cx, &fields).err().unwrap().to_string(),
+        "the fields of a union can't be skipped"
+    );
+}
+
+fn where_clause(generics: syn::Generics) -> String {
+    generics.where_clause.to_token_stream().to_string()
+}
+
+#[test]
+fn test_inferred_bounds() {
+    let ast: syn::DeriveInput = parse_quote! {
+        struct S<T, U: Iterator, V, W> where W: Clone {
+            a: Vec<T>,
+            b: (T, [U::Item; 2]),
+            c: ::std::marker::PhantomData<V>,
+            #[marshal(skip)]
+            d: Option<W>,
+            #[marshal(with = "text")]
+            e: V,
+        }
+    };
+    let cx = Container::new(&ast).unwrap();
+    assert_eq!(
+        where_clause(cx.generics(Some(quote! { Marshal }))),
+        "where W : Clone , T : Marshal , U :: Item : Marshal"
+    );
+    assert_eq!(
+        where_clause(cx.unmarshal_generics(quote! { UnMarshal })),
+        "where W : Clone , T : UnMarshal , U :: Item : UnMarshal , Option < W > : :: std :: default :: Default"
+    );
+
+    // `bound` replaces all of them
+    let ast: syn::DeriveInput = parse_quote! {
+        #[marshal(bound = "T: Marshal + Send")]
+        struct S<T> { #[marshal(skip)] a: T }
+    };
+    let cx = Container::new(&ast).unwrap();
+    assert_eq!(
+        where_clause(cx.unmarshal_generics(quote! { UnMarshal })),
+        "where T : Marshal + Send"
+    );
+}
+
+fn view_error(ast: syn::DeriveInput) -> String {
+    impl_viewable_macro(&ast).err().unwrap().to_string()
+}
+
+#[test]
+fn test_views() {
+    assert_eq!(
+        view_error(parse_quote! { enum E { A } }),
+        "only structs can be viewed"
+    );
+    assert_eq!(
+        view_error(parse_quote! { #[marshal(version = 2)] struct S { a: u8 } }),
+        "versioned structs can't be viewed, their fields depend on the version in the data"
+    );
+    assert_eq!(
+        view_error(parse_quote! { #[marshal(tagged)] struct S { #[marshal(id = 1)] a: u8 } }),
+        "tagged structs can't be viewed, their fields can be in any order"
+    );
+
+    // Tuple fields are read with `_0`, `_1` and so on, and skipped fields can't be read
+    let tokens = impl_viewable_macro(&parse_quote! {
+        pub struct S<T>(pub T, #[marshal(skip)] u8, String);
+    })
+    .unwrap()
+    .to_string();
+    assert!(
+        tokens.contains("pub struct SView < '__v , T , __C = :: lazy_marshal :: DefaultConfig >")
+    );
+    assert!(tokens.contains("pub fn _0 (& self)"));
+    assert!(!tokens.contains("fn _1"));
+    assert!(tokens.contains("fn _2 (& self)"));
+}
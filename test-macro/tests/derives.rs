@@ -2,7 +2,7 @@ use lazy_marshal::*;
 use lazy_marshal_derive::*;
 
 #[derive(Clone, Debug, Marshal, UnMarshal)]
-pub struct Thing<T: Marshal + UnMarshal> {
+pub struct Thing<T> {
     a: Vec<Option<String>>,
     b: String,
     c: T,
@@ -57,6 +57,7 @@ enum TestEnum {
 // }
 
 #[test]
+#[allow(clippy::match_like_matches_macro, clippy::useless_format)]
 fn test_enum() {
     let e = TestEnum::NoPart;
     assert!(
@@ -77,3 +78,1465 @@ fn test_enum() {
         }
     );
 }
+
+#[test]
+fn test_marshal_ref() {
+    let a = Thing {
+        a: vec![Some("😉".to_string()), None],
+        b: "Borrowed cows".to_string(),
+        c: 21u8,
+    };
+    assert!(a.marshal_ref().eq(a.clone().marshal()));
+
+    let e = TestEnum::Part2(("test string".to_string(), 69));
+    assert!(e.marshal_ref().eq(e.clone().marshal()));
+    assert!(TestEnum::NoPart
+        .marshal_ref()
+        .eq(TestEnum::NoPart.marshal()));
+}
+
+#[test]
+fn test_incremental() {
+    let a = Thing {
+        a: vec![None, Some("🦀".to_string())],
+        b: "Arrives slowly".to_string(),
+        c: 21u64,
+    };
+    let bytes = a.marshal_ref().collect::<Vec<_>>();
+
+    let mut decoder = Thing::<u64>::decoder();
+    let (last, rest) = bytes.split_last().unwrap();
+    for b in rest {
+        assert!(matches!(
+            decoder.feed(&mut &[*b][..]).unwrap(),
+            Decoded::NeedMore(_)
+        ));
+    }
+    let Decoded::Done(decoded) = decoder.feed(&mut &[*last][..]).unwrap() else {
+        panic!("Decoder didn't finish on the last byte")
+    };
+    assert!(a.a == decoded.a);
+    assert!(a.b == decoded.b);
+    assert!(a.c == decoded.c);
+
+    let e = TestEnum::Part2(("split".to_string(), 5));
+    let bytes = e.marshal_ref().collect::<Vec<_>>();
+    let mut decoder = TestEnum::decoder();
+    assert!(matches!(
+        decoder.feed(&mut &bytes[..3]).unwrap(),
+        Decoded::NeedMore(6)
+    ));
+    assert!(matches!(
+        decoder.feed(&mut &bytes[3..]).unwrap(),
+        Decoded::Done(TestEnum::Part2((s, 5))) if s == "split"
+    ));
+
+    let mut data = &[2u8, 0][..];
+    assert!(matches!(
+        TestEnum::decoder().feed(&mut data).unwrap(),
+        Decoded::Done(TestEnum::NoPart)
+    ));
+    assert_eq!(data, [0]);
+}
+
+struct Network;
+
+impl Config for Network {
+    const ENDIAN: Endian = Endian::Big;
+    const INT_ENCODING: IntEncoding = IntEncoding::Varint;
+    const LENGTH_PREFIX: LengthPrefix = LengthPrefix::U16;
+}
+
+#[test]
+fn test_config() {
+    let a = Thing {
+        a: vec![Some("config".to_string())],
+        b: String::new(),
+        c: TestEnum::Part1(300),
+    };
+    let bytes = a.marshal_ref_with::<Network>().collect::<Vec<_>>();
+    assert!(a
+        .clone()
+        .marshal_with::<Network>()
+        .eq(bytes.iter().cloned()));
+    assert_eq!(
+        bytes,
+        [0, 1, 1, 0, 6, b'c', b'o', b'n', b'f', b'i', b'g', 0, 0, 0, 0xac, 0x02]
+    );
+
+    let decoded = Thing::<TestEnum>::unmarshal_with::<Network>(&mut bytes.iter().cloned()).unwrap();
+    assert!(a.a == decoded.a);
+    assert!(matches!(decoded.c, TestEnum::Part1(300)));
+
+    let mut decoder = Thing::<TestEnum>::decoder();
+    let Decoded::NeedMore(_) = decoder.feed_with::<Network>(&mut &bytes[..7]).unwrap() else {
+        panic!("Decoder finished early")
+    };
+    let Decoded::Done(decoded) = decoder.feed_with::<Network>(&mut &bytes[7..]).unwrap() else {
+        panic!("Decoder didn't finish")
+    };
+    assert!(a.a == decoded.a);
+    assert!(matches!(decoded.c, TestEnum::Part1(300)));
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+struct Compact {
+    id: VarInt<u64>,
+    delta: VarInt<i32>,
+    raw: u32,
+}
+
+#[test]
+fn test_varint_fields() {
+    let c = Compact {
+        id: VarInt(300),
+        delta: VarInt(-1),
+        raw: 1,
+    };
+    let bytes = c.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(bytes, [0xac, 0x02, 1, 1, 0, 0, 0]);
+    assert_eq!(Compact::unmarshal(&mut bytes.iter().cloned()).unwrap(), c);
+
+    let mut decoder = Compact::decoder();
+    assert!(matches!(
+        decoder.feed(&mut &bytes[..1]).unwrap(),
+        Decoded::NeedMore(1)
+    ));
+    assert_eq!(decoder.feed(&mut &bytes[1..]).unwrap(), Decoded::Done(c));
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+enum Tree {
+    Leaf(u32),
+    Node(Vec<Tree>),
+}
+
+struct Shallow;
+
+impl Config for Shallow {
+    const LIMITS: DecodeLimits = DecodeLimits::NONE.max_depth(8);
+}
+
+#[test]
+fn test_limits() {
+    fn nest(depth: usize) -> Tree {
+        (0..depth).fold(Tree::Leaf(1), |t, _| Tree::Node(vec![t]))
+    }
+
+    // Each level is a `Tree` and a `Vec`
+    let fits = nest(3);
+    let bytes = fits.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(
+        Tree::unmarshal_with::<Shallow>(&mut bytes.iter().cloned()).unwrap(),
+        fits
+    );
+
+    let bytes = nest(4).marshal_ref().collect::<Vec<_>>();
+    assert!(matches!(
+        Tree::unmarshal_with::<Shallow>(&mut bytes.iter().cloned())
+            .unwrap_err()
+            .kind(),
+        MarshalError::LimitExceeded {
+            limit: Limit::Depth,
+            max: 8
+        }
+    ));
+
+    // Far deeper than the stack could handle without the limit
+    let mut bytes = [1u8, 1, 0, 0, 0, 0, 0, 0, 0].repeat(1_000_000);
+    bytes.extend([0, 1, 0, 0, 0]);
+    assert!(matches!(
+        Tree::unmarshal_with::<Shallow>(&mut bytes.into_iter())
+            .unwrap_err()
+            .kind(),
+        MarshalError::LimitExceeded {
+            limit: Limit::Depth,
+            ..
+        }
+    ));
+
+    // Push decoders count the depth they're fed at the same way
+    let bytes = fits.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(
+        Tree::decoder()
+            .feed_with::<Shallow>(&mut &bytes[..])
+            .unwrap(),
+        Decoded::Done(fits)
+    );
+
+    let bytes = nest(4).marshal_ref().collect::<Vec<_>>();
+    assert!(matches!(
+        Tree::decoder()
+            .feed_with::<Shallow>(&mut &bytes[..])
+            .unwrap_err()
+            .kind(),
+        MarshalError::LimitExceeded {
+            limit: Limit::Depth,
+            max: 8
+        }
+    ));
+
+    let mut bytes = [1u8, 1, 0, 0, 0, 0, 0, 0, 0].repeat(1_000_000);
+    bytes.extend([0, 1, 0, 0, 0]);
+    let mut decoder = Tree::decoder();
+    // Fed a byte at a time, so every level is returned to before going deeper
+    let err = bytes
+        .chunks(1)
+        .find_map(|mut chunk| decoder.feed_with::<Shallow>(&mut chunk).err())
+        .unwrap();
+    assert!(matches!(
+        err.kind(),
+        MarshalError::LimitExceeded {
+            limit: Limit::Depth,
+            ..
+        }
+    ));
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+enum Event {
+    Moved { x: i32, y: i32 },
+    Renamed { name: String },
+    Tagged(Vec<String>),
+    Empty {},
+    Closed,
+}
+
+#[test]
+fn test_named_variants() {
+    let events = vec![
+        Event::Moved { x: -1, y: 2 },
+        Event::Renamed {
+            name: "new".to_string(),
+        },
+        Event::Tagged(vec!["a".to_string()]),
+        Event::Empty {},
+        Event::Closed,
+    ];
+
+    // Same layout as a tuple variant: the tag, then each field in order
+    assert_eq!(
+        events[0].marshal_ref().collect::<Vec<_>>(),
+        [0, 255, 255, 255, 255, 2, 0, 0, 0]
+    );
+    assert_eq!(events[3].marshal_ref().collect::<Vec<_>>(), [3]);
+
+    let bytes = events.marshal_ref().collect::<Vec<_>>();
+    assert!(events.clone().marshal().eq(bytes.iter().cloned()));
+    assert_eq!(
+        Vec::<Event>::unmarshal(&mut bytes.iter().cloned()).unwrap(),
+        events
+    );
+
+    let mut decoder = Vec::<Event>::decoder();
+    let (last, rest) = bytes.split_last().unwrap();
+    for b in rest {
+        assert!(matches!(
+            decoder.feed(&mut &[*b][..]).unwrap(),
+            Decoded::NeedMore(_)
+        ));
+    }
+    assert_eq!(
+        decoder.feed(&mut &[*last][..]).unwrap(),
+        Decoded::Done(events.clone())
+    );
+
+    let bytes = Event::Moved { x: 1, y: 2 }.marshal().collect::<Vec<_>>();
+    let err = Event::unmarshal(&mut bytes[..7].iter().cloned()).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Event::Moved.y"));
+    let err = Event::decoder().feed(&mut &[1, 1, 0, 0, 0, 0, 0, 0, 0, 0xff][..]);
+    assert_eq!(
+        err.unwrap_err().path().as_deref(),
+        Some("Event::Renamed.name")
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Marshal, UnMarshal)]
+struct Id(u64);
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+struct Pair(u8, String);
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+struct Marker;
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+struct Nothing {}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+enum Shape {
+    Point,
+    Circle(Id, u32),
+    Rect(Pair, Marker, Nothing, i16),
+}
+
+#[test]
+fn test_tuple_and_unit() {
+    assert_eq!(
+        Id(7).marshal().collect::<Vec<_>>(),
+        [7, 0, 0, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(Marker.marshal().count(), 0);
+    assert_eq!(Nothing {}.marshal_ref().count(), 0);
+    assert_eq!(Marker::unmarshal(&mut [].into_iter()).unwrap(), Marker);
+
+    let shapes = vec![
+        Shape::Point,
+        Shape::Circle(Id(1), 5),
+        Shape::Rect(Pair(2, "ab".to_string()), Marker, Nothing {}, -3),
+    ];
+    // The tag, then each field in order
+    assert_eq!(
+        shapes[1].marshal_ref().collect::<Vec<_>>(),
+        [1, 1, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0]
+    );
+
+    let bytes = shapes.marshal_ref().collect::<Vec<_>>();
+    assert!(shapes.clone().marshal().eq(bytes.iter().cloned()));
+    assert_eq!(
+        Vec::<Shape>::unmarshal(&mut bytes.iter().cloned()).unwrap(),
+        shapes
+    );
+
+    let mut decoder = Vec::<Shape>::decoder();
+    let (last, rest) = bytes.split_last().unwrap();
+    for b in rest {
+        assert!(matches!(
+            decoder.feed(&mut &[*b][..]).unwrap(),
+            Decoded::NeedMore(_)
+        ));
+    }
+    assert_eq!(
+        decoder.feed(&mut &[*last][..]).unwrap(),
+        Decoded::Done(shapes.clone())
+    );
+    assert_eq!(
+        Marker::decoder().feed(&mut &[][..]).unwrap(),
+        Decoded::Done(Marker)
+    );
+
+    let bytes = shapes[2].marshal_ref().collect::<Vec<_>>();
+    let err = Shape::unmarshal(&mut bytes[..bytes.len() - 1].iter().cloned()).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Shape::Rect.3"));
+    let err = Shape::unmarshal(&mut bytes[..5].iter().cloned()).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Shape::Rect.0.1"));
+    let err = Pair::decoder().feed(&mut &[1, 1, 0, 0, 0, 0, 0, 0, 0, 0xff][..]);
+    assert_eq!(err.unwrap_err().path().as_deref(), Some("Pair.1"));
+}
+
+fn unknown_port() -> u16 {
+    8080
+}
+
+/// Writes a `u32` as its decimal digits
+mod as_text {
+    use lazy_marshal::*;
+
+    pub fn marshal<C: Config>(value: u32) -> impl Iterator<Item = u8> {
+        value.to_string().marshal_with::<C>()
+    }
+
+    pub fn marshal_ref<C: Config>(value: &u32) -> impl Iterator<Item = u8> + '_ {
+        marshal::<C>(*value)
+    }
+
+    pub fn unmarshal<C: Config>(data: &mut impl Iterator<Item = u8>) -> Result<u32, MarshalError> {
+        parse(String::unmarshal_with::<C>(data)?)
+    }
+
+    fn parse(text: String) -> Result<u32, MarshalError> {
+        text.parse()
+            .map_err(|_| MarshalError::InvalidData(format!("{text:?} isn't a number")))
+    }
+
+    #[derive(Default)]
+    pub struct Decoder(<String as IncrementalUnMarshal>::Decoder);
+
+    impl PushDecoder for Decoder {
+        type Output = u32;
+
+        fn feed(&mut self, data: &mut &[u8]) -> Result<Decoded<u32>, MarshalError> {
+            self.feed_with::<DefaultConfig>(data)
+        }
+
+        fn feed_with<C: Config>(&mut self, data: &mut &[u8]) -> Result<Decoded<u32>, MarshalError> {
+            match self.0.feed_with::<C>(data)? {
+                Decoded::Done(text) => parse(text).map(Decoded::Done),
+                Decoded::NeedMore(n) => Ok(Decoded::NeedMore(n)),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal, Viewable)]
+struct Connection {
+    host: String,
+    #[marshal(skip)]
+    retries: u8,
+    #[marshal(skip, default = "unknown_port")]
+    port: u16,
+    #[marshal(with = "as_text")]
+    timeout: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+enum Request {
+    Connect(Connection),
+    Wait(#[marshal(with = "as_text")] u32, #[marshal(skip)] Vec<u8>),
+    Cached {
+        #[marshal(skip)]
+        hits: u64,
+    },
+}
+
+#[test]
+fn test_field_attributes() {
+    let conn = Connection {
+        host: "h".to_string(),
+        retries: 3,
+        port: 22,
+        timeout: 15,
+    };
+    let bytes = conn.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(
+        bytes,
+        [1, 0, 0, 0, 0, 0, 0, 0, b'h', 2, 0, 0, 0, 0, 0, 0, 0, b'1', b'5']
+    );
+    assert!(conn.clone().marshal().eq(bytes.iter().cloned()));
+
+    let decoded = Connection {
+        retries: 0,
+        port: 8080,
+        ..conn
+    };
+    assert_eq!(
+        Connection::unmarshal(&mut bytes.iter().cloned()).unwrap(),
+        decoded
+    );
+    assert_eq!(
+        Connection::decoder().feed(&mut &bytes[..]).unwrap(),
+        Decoded::Done(decoded.clone())
+    );
+
+    let requests = vec![
+        Request::Connect(decoded),
+        Request::Wait(7, vec![1, 2]),
+        Request::Cached { hits: 9 },
+    ];
+    let bytes = requests.marshal_ref().collect::<Vec<_>>();
+    assert!(requests.clone().marshal().eq(bytes.iter().cloned()));
+    let expected = vec![
+        requests[0].clone(),
+        Request::Wait(7, Vec::new()),
+        Request::Cached { hits: 0 },
+    ];
+    assert_eq!(
+        Vec::<Request>::unmarshal(&mut bytes.iter().cloned()).unwrap(),
+        expected
+    );
+    let mut decoder = Vec::<Request>::decoder();
+    let (last, rest) = bytes.split_last().unwrap();
+    for b in rest {
+        assert!(matches!(
+            decoder.feed(&mut &[*b][..]).unwrap(),
+            Decoded::NeedMore(_)
+        ));
+    }
+    assert_eq!(
+        decoder.feed(&mut &[*last][..]).unwrap(),
+        Decoded::Done(expected)
+    );
+
+    // Errors from a `with` module get the field's path like any other
+    let bytes = Request::Wait(1, Vec::new()).marshal().collect::<Vec<_>>();
+    let mut bad = bytes.clone();
+    *bad.last_mut().unwrap() = b'x';
+    let err = Request::unmarshal(&mut bad.iter().cloned()).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Request::Wait.0"));
+    assert!(matches!(err.kind(), MarshalError::InvalidData(_)));
+}
+
+/// Doesn't need `T: Marshal` since `T` is never marshalled
+#[derive(Debug, PartialEq, Marshal, UnMarshal)]
+#[marshal(bound = "")]
+struct Count<T> {
+    count: u32,
+    #[marshal(skip)]
+    kind: std::marker::PhantomData<T>,
+}
+
+#[derive(Debug, PartialEq)]
+struct NotMarshal;
+
+/// Nothing from `lazy_marshal` is in scope here, the derives only go through the re-export
+mod reexported {
+    pub use lazy_marshal as marshal_lib;
+
+    #[derive(Debug, PartialEq, lazy_marshal_derive::Marshal, lazy_marshal_derive::UnMarshal)]
+    #[marshal(crate = "crate::reexported::marshal_lib")]
+    pub enum Renamed {
+        Empty,
+        Full(Vec<u8>, #[marshal(skip)] bool),
+    }
+}
+
+#[test]
+fn test_container_attributes() {
+    let count = Count::<NotMarshal> {
+        count: 2,
+        kind: std::marker::PhantomData,
+    };
+    let bytes = count.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(bytes, [2, 0, 0, 0]);
+    assert_eq!(
+        Count::<NotMarshal>::unmarshal(&mut bytes.iter().cloned()).unwrap(),
+        count
+    );
+    assert_eq!(
+        Count::<NotMarshal>::decoder()
+            .feed(&mut &bytes[..])
+            .unwrap(),
+        Decoded::Done(count)
+    );
+
+    let renamed = reexported::Renamed::Full(vec![1], false);
+    let bytes = renamed.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(
+        reexported::Renamed::unmarshal(&mut bytes.iter().cloned()).unwrap(),
+        renamed
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Marshal, UnMarshal)]
+enum Level {
+    Low = 1,
+    // Tagged 2, like its discriminant
+    Medium,
+    High = 10,
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+#[marshal(tag_type = "u16")]
+enum Message {
+    #[marshal(tag = 300)]
+    Ping,
+    Data(Vec<u8>),
+    #[marshal(tag = 7)]
+    Level(Level),
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+#[marshal(tag_type = "varint")]
+enum Versioned {
+    V1(u8),
+    #[marshal(tag = 1000)]
+    V1000(u8),
+}
+
+fn round_trip<T>(value: T) -> Vec<u8>
+where
+    T: Marshal + MarshalRef + IncrementalUnMarshal + Clone + PartialEq + std::fmt::Debug,
+{
+    let bytes = value.marshal_ref().collect::<Vec<_>>();
+    assert!(value.clone().marshal().eq(bytes.iter().cloned()));
+    assert_eq!(value.encoded_len(), bytes.len());
+    assert_eq!(value.clone().marshal_to_vec(), bytes);
+    assert_eq!(T::unmarshal(&mut bytes.iter().cloned()).unwrap(), value);
+    assert_eq!(T::unmarshal_from(&mut &bytes[..]).unwrap(), value);
+    let mut decoder = T::decoder();
+    let (last, rest) = bytes.split_last().unwrap();
+    for b in rest {
+        assert!(matches!(
+            decoder.feed(&mut &[*b][..]).unwrap(),
+            Decoded::NeedMore(_)
+        ));
+    }
+    assert_eq!(
+        decoder.feed(&mut &[*last][..]).unwrap(),
+        Decoded::Done(value)
+    );
+    bytes
+}
+
+#[test]
+fn test_tags() {
+    assert_eq!(round_trip(Level::Low), [1]);
+    assert_eq!(round_trip(Level::Medium), [2]);
+    assert_eq!(round_trip(Level::High), [10]);
+    assert!(Level::unmarshal(&mut [0].into_iter()).is_err());
+
+    assert_eq!(round_trip(Message::Ping), [44, 1]);
+    assert_eq!(
+        round_trip(Message::Data(vec![9])),
+        [45, 1, 1, 0, 0, 0, 0, 0, 0, 0, 9]
+    );
+    assert_eq!(round_trip(Message::Level(Level::High)), [7, 0, 10]);
+    // The tag follows the config like any other `u16`
+    assert_eq!(
+        Message::Ping.marshal_with::<Varints>().collect::<Vec<_>>(),
+        [0xac, 0x02]
+    );
+
+    assert_eq!(round_trip(Versioned::V1(5)), [0, 5]);
+    assert_eq!(round_trip(Versioned::V1000(5)), [0xe8, 0x07, 5]);
+    let err = Versioned::decoder()
+        .feed(&mut &[0xe9, 0x07][..])
+        .unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Versioned"));
+    assert!(matches!(err.kind(), MarshalError::InvalidData(_)));
+}
+
+/// The same struct as three services at different versions would have it
+mod v1 {
+    use lazy_marshal_derive::*;
+
+    #[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+    #[marshal(version = 1)]
+    pub struct Profile {
+        pub id: u32,
+        pub name: String,
+    }
+}
+
+mod v2 {
+    use lazy_marshal_derive::*;
+
+    #[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+    #[marshal(version = 2)]
+    pub struct Profile {
+        pub id: u32,
+        pub name: String,
+        #[marshal(since = 2, default)]
+        pub email: String,
+    }
+}
+
+mod v3 {
+    use lazy_marshal_derive::*;
+
+    pub fn adult() -> u8 {
+        18
+    }
+
+    #[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+    #[marshal(version = 3)]
+    pub struct Profile {
+        pub id: u32,
+        pub name: String,
+        #[marshal(since = 2, default)]
+        pub email: String,
+        #[marshal(since = 3, default = "adult")]
+        pub age: u8,
+    }
+}
+
+/// Read `from` as a `T` both from an iterator and byte by byte, with another value after it
+/// to check the whole of `from` was read
+fn convert<F: MarshalRef, T: IncrementalUnMarshal + PartialEq + std::fmt::Debug>(from: &F) -> T {
+    let bytes = from
+        .marshal_ref()
+        .chain(0xabcdu16.marshal())
+        .collect::<Vec<_>>();
+    assert_eq!(from.encoded_len(), bytes.len() - 2);
+    let mut written = Vec::new();
+    from.marshal_ref_into(&mut written).unwrap();
+    assert_eq!(written, bytes[..bytes.len() - 2]);
+    let mut data = bytes.iter().cloned();
+    let value = T::unmarshal(&mut data).unwrap();
+    assert_eq!(u16::unmarshal(&mut data).unwrap(), 0xabcd);
+    let mut slice = &bytes[..];
+    assert_eq!(T::unmarshal_from(&mut slice).unwrap(), value);
+    assert_eq!(u16::unmarshal_from(&mut slice).unwrap(), 0xabcd);
+
+    let mut decoder = T::decoder();
+    let mut rest = &bytes[..];
+    let decoded = loop {
+        let (byte, more) = rest.split_first().unwrap();
+        rest = more;
+        if let Decoded::Done(v) = decoder.feed(&mut &[*byte][..]).unwrap() {
+            break v;
+        }
+    };
+    assert_eq!(rest, 0xabcdu16.marshal().collect::<Vec<_>>());
+    assert_eq!(decoded, value);
+    value
+}
+
+#[test]
+fn test_versioning() {
+    let p1 = v1::Profile {
+        id: 1,
+        name: "ann".to_string(),
+    };
+    let p2 = v2::Profile {
+        id: 2,
+        name: "bob".to_string(),
+        email: "bob@example.com".to_string(),
+    };
+    let p3 = v3::Profile {
+        id: 3,
+        name: "cy".to_string(),
+        email: "cy@example.com".to_string(),
+        age: 40,
+    };
+
+    // The version, the length of the fields and then the fields
+    assert_eq!(
+        p1.marshal_ref().collect::<Vec<_>>(),
+        [1, 15, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, b'a', b'n', b'n']
+    );
+    assert!(p3.clone().marshal().eq(p3.marshal_ref()));
+    assert_eq!(convert::<_, v3::Profile>(&p3), p3);
+
+    // Upgrades fill in what older versions didn't have
+    assert_eq!(
+        convert::<_, v2::Profile>(&p1),
+        v2::Profile {
+            id: 1,
+            name: "ann".to_string(),
+            email: String::new(),
+        }
+    );
+    assert_eq!(
+        convert::<_, v3::Profile>(&p1),
+        v3::Profile {
+            id: 1,
+            name: "ann".to_string(),
+            email: String::new(),
+            age: 18,
+        }
+    );
+    assert_eq!(
+        convert::<_, v3::Profile>(&p2),
+        v3::Profile {
+            id: 2,
+            name: "bob".to_string(),
+            email: "bob@example.com".to_string(),
+            age: 18,
+        }
+    );
+
+    // Downgrades skip what they don't know about
+    assert_eq!(
+        convert::<_, v1::Profile>(&p3),
+        v1::Profile {
+            id: 3,
+            name: "cy".to_string(),
+        }
+    );
+    assert_eq!(
+        convert::<_, v2::Profile>(&p3),
+        v2::Profile {
+            id: 3,
+            name: "cy".to_string(),
+            email: "cy@example.com".to_string(),
+        }
+    );
+    assert_eq!(
+        convert::<_, v1::Profile>(&p2),
+        v1::Profile {
+            id: 2,
+            name: "bob".to_string(),
+        }
+    );
+
+    // Inside collections too
+    let all = vec![p3.clone(), p3.clone()];
+    let old = convert::<_, Vec<v1::Profile>>(&all);
+    assert_eq!(old.len(), 2);
+    assert_eq!(convert::<_, Vec<v3::Profile>>(&old)[1].age, 18);
+}
+
+#[test]
+fn test_versioning_errors() {
+    let p3 = v3::Profile {
+        id: 3,
+        name: "cy".to_string(),
+        email: "cy@example.com".to_string(),
+        age: 40,
+    };
+    let bytes = vec![p3.clone(), p3].marshal().collect::<Vec<_>>();
+
+    // Cut off in the middle of the second profile's email
+    let cut = bytes.len() - 5;
+    let err = Vec::<v3::Profile>::unmarshal(&mut bytes[..cut].iter().cloned()).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Vec[1].email"));
+    assert_eq!(err.offset(), Some(cut));
+    let err = Vec::<v3::Profile>::unmarshal_from(&mut &bytes[..cut]).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Vec[1].email"));
+    assert_eq!(err.offset(), Some(cut));
+    let err = Vec::<v3::Profile>::unmarshal_from(&mut ReadSource::new(&bytes[..cut])).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Vec[1].email"));
+    assert_eq!(err.offset(), Some(cut));
+    let mut decoder = Vec::<v3::Profile>::decoder();
+    assert!(matches!(
+        decoder.feed(&mut &bytes[..cut]).unwrap(),
+        Decoded::NeedMore(_)
+    ));
+
+    // Older readers still need the whole body before the next value starts
+    let err = Vec::<v1::Profile>::unmarshal(&mut bytes[..cut].iter().cloned()).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Vec[1]"));
+    assert_eq!(err.offset(), Some(cut));
+
+    // A length that ends inside a field
+    let mut short = v2::Profile {
+        id: 2,
+        name: "bob".to_string(),
+        email: "bob@example.com".to_string(),
+    }
+    .marshal()
+    .collect::<Vec<_>>();
+    short[1] = 10;
+    let err = v2::Profile::unmarshal(&mut short.iter().cloned()).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Profile.name"));
+    let err = v2::Profile::unmarshal_from(&mut &short[..]).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Profile.name"));
+    let err = v2::Profile::decoder().feed(&mut &short[..]).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Profile.name"));
+    assert!(matches!(err.kind(), MarshalError::EarlyStreamEnd));
+}
+
+mod tagged_v1 {
+    use lazy_marshal_derive::*;
+
+    #[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+    #[marshal(tagged)]
+    pub struct Order {
+        #[marshal(id = 1)]
+        pub id: u64,
+        #[marshal(id = 2)]
+        pub item: String,
+        #[marshal(id = 3)]
+        pub note: Option<String>,
+        #[marshal(id = 4)]
+        pub rush: bool,
+    }
+}
+
+mod tagged_v2 {
+    use lazy_marshal::VarInt;
+    use lazy_marshal_derive::*;
+
+    /// `note` is gone, the fields are in a different order and there are new ones
+    #[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+    #[marshal(tagged)]
+    pub struct Order {
+        #[marshal(id = 4)]
+        pub rush: bool,
+        #[marshal(id = 5, default)]
+        pub quantity: u32,
+        #[marshal(id = 1)]
+        pub id: u64,
+        #[marshal(id = 6, default)]
+        pub price: VarInt<u64>,
+        #[marshal(skip)]
+        pub cached: Vec<u8>,
+        #[marshal(id = 2)]
+        pub item: String,
+    }
+}
+
+mod tagged_v3 {
+    use lazy_marshal_derive::*;
+
+    /// `id` changed type, which readers notice from its wire type
+    #[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+    #[marshal(tagged)]
+    pub struct Order {
+        #[marshal(id = 1)]
+        pub id: String,
+    }
+}
+
+#[test]
+fn test_tagged() {
+    let o1 = tagged_v1::Order {
+        id: 7,
+        item: "ab".to_string(),
+        note: None,
+        rush: true,
+    };
+    let o2 = tagged_v2::Order {
+        rush: false,
+        quantity: 3,
+        id: 8,
+        price: VarInt(300),
+        cached: vec![1, 2],
+        item: "cd".to_string(),
+    };
+
+    // The number of fields, then each field's id and wire type, and its value
+    assert_eq!(
+        o1.marshal_ref().collect::<Vec<_>>(),
+        [
+            [4].as_slice(),
+            &[1 << 3 | 4, 7, 0, 0, 0, 0, 0, 0, 0],
+            &[2 << 3 | 6, 10, 2, 0, 0, 0, 0, 0, 0, 0, b'a', b'b'],
+            &[3 << 3 | 6, 1, 0],
+            &[4 << 3 | 1, 1],
+        ]
+        .concat()
+    );
+    assert!(o2.clone().marshal().eq(o2.marshal_ref()));
+    assert_eq!(convert::<_, tagged_v1::Order>(&o1), o1);
+
+    // Fields that are new get their default, and ones that are gone are skipped
+    assert_eq!(
+        convert::<_, tagged_v2::Order>(&o1),
+        tagged_v2::Order {
+            rush: true,
+            quantity: 0,
+            id: 7,
+            price: VarInt(0),
+            cached: vec![],
+            item: "ab".to_string(),
+        }
+    );
+    assert_eq!(
+        convert::<_, tagged_v2::Order>(&o2),
+        tagged_v2::Order {
+            cached: vec![],
+            ..o2.clone()
+        }
+    );
+    let mut decoder = tagged_v1::Order::decoder();
+    let bytes = o2.marshal_ref().collect::<Vec<_>>();
+    let err = decoder.feed(&mut &bytes[..]).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Order.note"));
+    assert!(matches!(err.kind(), MarshalError::InvalidData(e) if e == "missing field"));
+    let err = tagged_v1::Order::unmarshal(&mut bytes.iter().cloned()).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Order.note"));
+
+    // Numbers follow the config
+    let bytes = o2.marshal_ref_with::<Varints>().collect::<Vec<_>>();
+    assert_eq!(&bytes[..5], [5, 4 << 3 | 1, 0, 5 << 3, 3]);
+    let read = tagged_v2::Order::unmarshal_with::<Varints>(&mut bytes.iter().cloned());
+    assert_eq!(read.unwrap().quantity, 3);
+
+    // A field read as a different type than it was written
+    let bytes = o1.marshal_ref().collect::<Vec<_>>();
+    let err = tagged_v3::Order::unmarshal(&mut bytes.iter().cloned()).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Order.id"));
+    assert!(matches!(
+        err.kind(),
+        MarshalError::InvalidData(e) if e == "expected a LengthDelimited value, found Fixed64"
+    ));
+    let err = tagged_v3::Order::decoder()
+        .feed(&mut &bytes[..])
+        .unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Order.id"));
+}
+
+#[derive(Clone, Copy, Marshal, UnMarshal)]
+#[marshal(union_as = "bytes")]
+union Raw {
+    int: u32,
+    float: f32,
+    bytes: [u8; 4],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Marshal, UnMarshal)]
+#[repr(C)]
+struct Key {
+    kind: u8,
+    code: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Marshal, UnMarshal)]
+#[repr(C)]
+struct Click {
+    kind: u8,
+    x: i16,
+    y: i16,
+}
+
+/// Like a C event union, where every field starts with the same `kind`
+#[derive(Clone, Copy, Marshal, UnMarshal)]
+#[marshal(union_as = "discriminant")]
+#[repr(C)]
+union InputEvent {
+    key: Key,
+    click: Click,
+}
+
+// SAFETY: `kind` is the index of the field that's set
+unsafe impl UnionDiscriminant for InputEvent {
+    fn discriminant(&self) -> usize {
+        // Both fields start with `kind`, so it can be read through either
+        unsafe { self.key.kind.into() }
+    }
+}
+
+/// Decode `bytes` as a `T` from an iterator and byte by byte
+fn both_ways<T: IncrementalUnMarshal>(bytes: &[u8]) -> (T, T) {
+    let value = T::unmarshal(&mut bytes.iter().cloned()).unwrap();
+    let mut decoder = T::decoder();
+    let (last, rest) = bytes.split_last().unwrap();
+    for byte in rest {
+        assert!(matches!(
+            decoder.feed(&mut &[*byte][..]).unwrap(),
+            Decoded::NeedMore(_)
+        ));
+    }
+    match decoder.feed(&mut &[*last][..]).unwrap() {
+        Decoded::Done(decoded) => (value, decoded),
+        Decoded::NeedMore(_) => panic!("not done"),
+    }
+}
+
+#[test]
+fn test_unions() {
+    // The bytes as they are in memory
+    let raw = Raw { float: 1.5 };
+    let bytes = raw.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(bytes, 1.5f32.to_ne_bytes());
+    assert!(raw.marshal().eq(bytes.iter().cloned()));
+    assert_eq!(raw.marshal_to_vec(), bytes);
+    let (a, b) = both_ways::<Raw>(&bytes);
+    assert_eq!(unsafe { (a.float, b.int) }, (1.5, 1.5f32.to_bits()));
+    assert_eq!(unsafe { a.bytes }, 1.5f32.to_ne_bytes());
+    assert!(matches!(
+        Raw::unmarshal(&mut [1, 2].into_iter()),
+        Err(MarshalError::Context(_))
+    ));
+
+    // The field the discriminant picks, and then that field
+    let click = InputEvent {
+        click: Click {
+            kind: 1,
+            x: -4,
+            y: 9,
+        },
+    };
+    let bytes = click.marshal_ref().collect::<Vec<_>>();
+    assert_eq!(bytes[0], 1);
+    assert!(click.marshal().eq(bytes.iter().cloned()));
+    assert_eq!(click.marshal_to_vec(), bytes);
+    assert_eq!((&click).marshal_to_vec(), bytes);
+    let (a, b) = both_ways::<InputEvent>(&bytes);
+    assert_eq!(unsafe { (a.click, b.click) }, unsafe {
+        (click.click, click.click)
+    });
+    let key = InputEvent {
+        key: Key { kind: 0, code: 42 },
+    };
+    let (a, _) = both_ways::<InputEvent>(&key.marshal_ref().collect::<Vec<_>>());
+    assert_eq!(unsafe { a.key.code }, 42);
+
+    let err = InputEvent::unmarshal(&mut [2].into_iter()).err().unwrap();
+    assert_eq!(err.path().as_deref(), Some("InputEvent"));
+    assert!(matches!(err.kind(), MarshalError::InvalidData(e) if e == "Invalid union field: 2"));
+    let err = InputEvent::decoder().feed(&mut &[2][..]).err().unwrap();
+    assert!(matches!(err.kind(), MarshalError::InvalidData(e) if e == "Invalid union field: 2"));
+}
+
+#[test]
+#[should_panic(expected = "the discriminant of `InputEvent` picked field 7, but it only has 2")]
+fn test_bad_discriminant() {
+    let event = InputEvent {
+        key: Key { kind: 7, code: 0 },
+    };
+    event.marshal_ref().for_each(drop);
+}
+
+/// No bounds on the type parameters, the derives add the ones the fields need
+#[derive(Debug, PartialEq, Marshal, UnMarshal)]
+struct Inferred<T, U, M> {
+    items: Vec<T>,
+    pair: Option<(T, U)>,
+    // Marshalled the same whatever `M` is
+    marker: std::marker::PhantomData<M>,
+    #[marshal(skip)]
+    cache: Vec<U>,
+}
+
+/// The associated type is what's marshalled, not `I`
+#[derive(Marshal, UnMarshal)]
+struct Peeked<I: Iterator> {
+    next: Option<I::Item>,
+}
+
+#[test]
+fn test_inferred_bounds() {
+    let value = Inferred::<u8, String, NotMarshal> {
+        items: vec![1, 2],
+        pair: Some((3, "x".to_string())),
+        marker: std::marker::PhantomData,
+        cache: vec![],
+    };
+    assert_eq!(
+        convert::<_, Inferred<u8, String, NotMarshal>>(&value),
+        value
+    );
+
+    let peeked = Peeked::<std::vec::IntoIter<u32>> { next: Some(9) };
+    let bytes = peeked.marshal_ref().collect::<Vec<_>>();
+    let read = Peeked::<std::ops::Range<u32>>::unmarshal(&mut bytes.into_iter()).unwrap();
+    assert_eq!(read.next, Some(9));
+}
+
+/// Generic enums are marshalled without boxing, so their parameters needn't be `'static`
+#[derive(Debug, PartialEq, Marshal, UnMarshal)]
+enum OneOf<L, R> {
+    Left(L),
+    Right { right: R },
+}
+
+#[test]
+fn test_generic_enum() {
+    for value in [
+        OneOf::Left(7u16),
+        OneOf::Right {
+            right: vec![1u8, 2],
+        },
+    ] {
+        assert_eq!(convert::<_, OneOf<u16, Vec<u8>>>(&value), value);
+    }
+    let right = OneOf::<u16, Vec<u8>>::Right { right: vec![3] };
+    let bytes = right.marshal().collect::<Vec<_>>();
+    assert_eq!(bytes.len(), 1 + 8 + 1);
+    // The iterator says how many bytes are left when its variant's does
+    let left = OneOf::<u16, Vec<u8>>::Left(1);
+    assert_eq!(left.marshal_ref().size_hint(), (3, Some(3)));
+}
+
+/// Only recursive through `Entry`, so its variant is boxed by hand
+#[derive(Debug, PartialEq, Marshal, UnMarshal)]
+struct Dir {
+    name: String,
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, PartialEq, Marshal, UnMarshal)]
+enum Entry {
+    File(String),
+    #[marshal(boxed)]
+    Dir(Dir),
+}
+
+#[test]
+fn test_recursive_enums() {
+    let tree = Tree::Node(vec![Tree::Leaf(1), Tree::Node(vec![Tree::Leaf(2)])]);
+    assert_eq!(convert::<_, Tree>(&tree), tree);
+
+    let dir = Dir {
+        name: "src".to_string(),
+        entries: vec![
+            Entry::File("lib.rs".to_string()),
+            Entry::Dir(Dir {
+                name: "bin".to_string(),
+                entries: vec![],
+            }),
+        ],
+    };
+    assert_eq!(convert::<_, Dir>(&dir), dir);
+}
+
+/// The length a value is written in, which its iterator gives as its size hint
+fn exact_len<T: MarshalRef, C: Config>(value: &T) -> usize {
+    let len = value.encoded_len_with::<C>();
+    assert_eq!(value.marshal_ref_with::<C>().size_hint(), (len, Some(len)));
+    assert_eq!(value.marshal_ref_with::<C>().count(), len);
+    len
+}
+
+#[test]
+fn test_encoded_len() {
+    let event = Event::Tagged(vec!["a".to_string(), "bc".to_string()]);
+    assert_eq!(exact_len::<_, DefaultConfig>(&event), 1 + 8 + 9 + 10);
+    assert_eq!(exact_len::<_, Network>(&event), 1 + 2 + 3 + 4);
+    assert_eq!(exact_len::<_, DefaultConfig>(&Message::Ping), 2);
+    assert_eq!(exact_len::<_, DefaultConfig>(&Versioned::V1000(1)), 2 + 1);
+
+    let profile = v3::Profile {
+        id: 1,
+        name: "ann".to_string(),
+        email: "a@b".to_string(),
+        age: 30,
+    };
+    assert_eq!(
+        exact_len::<_, DefaultConfig>(&profile),
+        1 + 8 + 4 + 11 + 11 + 1
+    );
+    // So does a `Vec` of them marshalled by value
+    let profiles = vec![profile.clone(), profile];
+    let len = profiles.encoded_len();
+    assert_eq!(profiles.clone().marshal().size_hint(), (len, Some(len)));
+    assert_eq!(profiles.marshal().collect::<Vec<_>>().capacity(), len);
+
+    let order = tagged_v2::Order {
+        rush: false,
+        quantity: 3,
+        id: 8,
+        price: VarInt(300),
+        cached: vec![1, 2],
+        item: "cd".to_string(),
+    };
+    exact_len::<_, DefaultConfig>(&order);
+    exact_len::<_, Network>(&order);
+
+    assert_eq!(exact_len::<_, DefaultConfig>(&Raw { int: 7 }), 4);
+    let event = InputEvent {
+        click: Click {
+            kind: 1,
+            x: 2,
+            y: 3,
+        },
+    };
+    assert_eq!(exact_len::<_, DefaultConfig>(&event), 1 + 5);
+
+    // Fields written `with` a module are counted
+    let connection = Connection {
+        host: "h".to_string(),
+        retries: 0,
+        port: 0,
+        timeout: 250,
+    };
+    assert_eq!(connection.encoded_len(), connection.marshal_ref().count());
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshalBorrowed, Viewable)]
+struct Header<'a> {
+    id: u32,
+    name: &'a str,
+    body: &'a [u8],
+    note: Option<std::borrow::Cow<'a, str>>,
+    tags: Vec<&'a str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshalBorrowed)]
+#[marshal(version = 2)]
+struct VersionedHeader<'a> {
+    name: &'a str,
+    #[marshal(since = 2, default)]
+    alias: &'a str,
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshalBorrowed)]
+#[marshal(tagged)]
+struct TaggedHeader<'a> {
+    #[marshal(id = 1)]
+    name: &'a str,
+    #[marshal(id = 2, default)]
+    size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshalBorrowed)]
+enum Frame<'a, T> {
+    Data(T, &'a [u8]),
+    Text { text: &'a str },
+    Close,
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+#[marshal(version = 1)]
+struct OldHeader {
+    name: String,
+}
+
+/// An owned version of `Header`, written by a sender that doesn't borrow
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal)]
+struct OwnedHeader {
+    id: u32,
+    name: String,
+    body: Vec<u8>,
+    note: Option<String>,
+    tags: Vec<String>,
+}
+
+#[test]
+fn test_borrowed() {
+    let owned = OwnedHeader {
+        id: 7,
+        name: "header".to_string(),
+        body: vec![1, 2, 3],
+        note: Some("note".to_string()),
+        tags: vec!["a".to_string(), "b".to_string()],
+    };
+    let bytes = owned.marshal_to_vec();
+    let mut data = &bytes[..];
+    let header = Header::unmarshal_borrowed(&mut data).unwrap();
+    assert!(data.is_empty());
+    assert_eq!(header.name, "header");
+    assert_eq!(header.body, [1, 2, 3]);
+    assert!(matches!(
+        &header.note,
+        Some(std::borrow::Cow::Borrowed("note"))
+    ));
+    assert_eq!(header.tags, ["a", "b"]);
+    assert!(bytes.as_ptr_range().contains(&header.name.as_ptr()));
+    assert_eq!(header.marshal_to_vec(), bytes);
+
+    let bytes = VersionedHeader {
+        name: "v2",
+        alias: "two",
+    }
+    .marshal_to_vec();
+    let decoded = VersionedHeader::unmarshal_borrowed(&mut &bytes[..]).unwrap();
+    assert_eq!(decoded.alias, "two");
+    // Older writers don't have the alias
+    let bytes = (
+        OldHeader {
+            name: "v1".to_string(),
+        },
+        9u8,
+    )
+        .marshal_to_vec();
+    let mut data = &bytes[..];
+    let decoded = VersionedHeader::unmarshal_borrowed(&mut data).unwrap();
+    assert_eq!((decoded.name, decoded.alias), ("v1", ""));
+    assert_eq!(data, [9]);
+    let bytes = TaggedHeader {
+        name: "tagged",
+        size: 3,
+    }
+    .marshal_to_vec();
+    let decoded = TaggedHeader::unmarshal_borrowed(&mut &bytes[..]).unwrap();
+    assert_eq!(decoded.name, "tagged");
+
+    for frame in [
+        Frame::Data(5u16, &[9, 9][..]),
+        Frame::Text { text: "hi" },
+        Frame::Close,
+    ] {
+        let bytes = frame.clone().marshal_to_vec();
+        assert_eq!(Frame::unmarshal_borrowed(&mut &bytes[..]).unwrap(), frame);
+    }
+
+    // Errors say where they happened, like with owned types
+    let bytes = vec![Frame::<u8>::Close, Frame::Text { text: "cut" }].marshal_to_vec();
+    let err = Vec::<Frame<u8>>::unmarshal_borrowed(&mut &bytes[..bytes.len() - 1]).unwrap_err();
+    assert_eq!(err.path().as_deref(), Some("Vec[1]::Text.text"));
+    assert_eq!(err.offset(), Some(bytes.len() - 1));
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal, Viewable)]
+struct Leg {
+    price: u64,
+    quantity: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal, Viewable)]
+struct Deal {
+    id: u64,
+    salesman: String,
+    legs: Vec<Leg>,
+    #[marshal(skip)]
+    cached: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Marshal, UnMarshal, Viewable)]
+struct Point<T>(T, T, String);
+
+#[test]
+fn test_views() {
+    let deals = (0..100)
+        .map(|i| Deal {
+            id: i,
+            salesman: format!("salesman {i}"),
+            legs: (0..i)
+                .map(|j| Leg {
+                    price: j * 10,
+                    quantity: -(j as i32),
+                })
+                .collect(),
+            cached: None,
+        })
+        .collect::<Vec<_>>();
+    let bytes = (&deals).marshal_to_vec();
+    let mut data = &bytes[..];
+    let view = Vec::<Deal>::view_from(&mut data).unwrap();
+    assert!(data.is_empty());
+    assert_eq!(view.len(), 100);
+
+    let deal = view.get(42).unwrap().unwrap();
+    assert_eq!(deal.id().unwrap(), 42);
+    assert_eq!(deal.salesman().unwrap(), "salesman 42");
+    let legs = deal.legs().unwrap();
+    assert_eq!(legs.len(), 42);
+    let leg = legs.get(7).unwrap().unwrap();
+    assert_eq!((leg.price().unwrap(), leg.quantity().unwrap()), (70, -7));
+    for (deal, view) in deals.iter().zip(&view) {
+        let view = view.unwrap();
+        assert_eq!(view.id().unwrap(), deal.id);
+        assert_eq!(view.salesman().unwrap(), deal.salesman);
+        assert_eq!(view.legs().unwrap().len(), deal.legs.len());
+    }
+
+    // Legs are always 12 bytes, so they're found without skipping the ones before
+    assert_eq!(Leg::fixed_len::<DefaultConfig>(), Some(12));
+    assert_eq!(Leg::fixed_len::<Varints>(), None);
+    assert_eq!(Deal::fixed_len::<DefaultConfig>(), None);
+    assert_eq!(<Point<u8>>::fixed_len::<DefaultConfig>(), None);
+
+    let point = Point(3u16, 4, "p".to_string());
+    let bytes = point.marshal_ref_with::<Varints>().collect::<Vec<_>>();
+    let view = Point::<u16>::view_from_with::<Varints>(&mut &bytes[..]).unwrap();
+    assert_eq!((view._0().unwrap(), view._1().unwrap()), (3, 4));
+    assert_eq!(view._2().unwrap(), "p");
+
+    // Skipped fields aren't there, and ones marshalled `with` a module are read through it
+    let connection = Connection {
+        host: "localhost".to_string(),
+        retries: 3,
+        port: 1,
+        timeout: 30,
+    };
+    let bytes = connection.marshal_ref().collect::<Vec<_>>();
+    let view = Connection::view_from(&mut &bytes[..]).unwrap();
+    assert_eq!(view.host().unwrap(), "localhost");
+    assert_eq!(view.timeout().unwrap(), 30);
+    assert_eq!(Connection::fixed_len::<DefaultConfig>(), None);
+
+    let header = Header {
+        id: 1,
+        name: "header",
+        body: &[1, 2],
+        note: Some("note".into()),
+        tags: vec!["a", "b"],
+    };
+    let bytes = header.marshal_to_vec();
+    let view = Header::view_from(&mut &bytes[..]).unwrap();
+    assert_eq!(view.body().unwrap(), [1, 2]);
+    assert_eq!(view.note().unwrap(), Some("note"));
+    assert_eq!(view.tags().unwrap().get(1).unwrap(), Some("b"));
+}
+
+#[test]
+fn test_view_errors() {
+    // The salesman isn't valid UTF-8, which is only found when it's read
+    let bytes = (1u64, vec![0xffu8], Vec::<Leg>::new()).marshal_to_vec();
+    let view = Deal::view_from(&mut &bytes[..]).unwrap();
+    assert_eq!(view.id().unwrap(), 1);
+    assert!(view.legs().unwrap().is_empty());
+    let err = view.salesman().unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::InvalidSizedDecode(1)));
+    assert_eq!(err.path().as_deref(), Some("Deal.salesman"));
+    assert_eq!(err.offset(), Some(17));
+
+    // A deal that's cut short is found when the field that's cut short is read, or when looking
+    // for where it ends
+    let deals = vec![Deal {
+        id: 1,
+        salesman: "s".to_string(),
+        legs: vec![Leg {
+            price: 1,
+            quantity: 1,
+        }],
+        cached: None,
+    }];
+    let bytes = deals.marshal_to_vec();
+    let cut = &bytes[..bytes.len() - 1];
+    let view = Vec::<Deal>::view_from(&mut &cut[..]).unwrap();
+    let deal = view.get(0).unwrap().unwrap();
+    assert_eq!(deal.salesman().unwrap(), "s");
+    let err = deal.legs().unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::InvalidSizedDecode(11)));
+    assert_eq!(err.path().as_deref(), Some("Deal.legs"));
+    assert_eq!(err.offset(), Some(cut.len() - 8));
+    let err = Vec::<Deal>::skip_from_with::<DefaultConfig>(&mut &cut[..]).unwrap_err();
+    assert!(matches!(err.kind(), MarshalError::InvalidSizedDecode(11)));
+    assert_eq!(err.path().as_deref(), Some("Vec[0].legs"));
+    assert_eq!(err.offset(), Some(cut.len()));
+}